@@ -0,0 +1,217 @@
+//! The OpenAI Assistants API, for organizations that keep their prompts, tools, and
+//! files in assistants rather than sending them with each chat request.
+//!
+//! A conversation is a thread kept by the server. Each turn adds the user's message to
+//! the thread and starts a run of the assistant on it, whose answer either streams as
+//! server-sent events or is read from the thread once polling finds the run finished.
+
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::sse::{SseEvent, SseParser};
+use crate::{api_url, QllmClient, Result};
+
+/// how often a run is checked on when it is not streamed
+const POLL: Duration = Duration::from_millis(1000);
+
+/// a client of the Assistants API
+#[derive(Clone, Debug)]
+pub struct Assistants {
+    client: QllmClient,
+}
+
+/// what a run was told, besides the assistant's own settings
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    /// a model to use instead of the assistant's
+    pub model: Option<String>,
+    /// added to the assistant's instructions for this run
+    pub additional_instructions: Option<String>,
+}
+
+impl RunOptions {
+    fn to_json(&self, assistant_id: &str, stream: bool) -> Value {
+        let mut body = json!({ "assistant_id": assistant_id, "stream": stream });
+        if let Some(model) = &self.model {
+            body["model"] = json!(model);
+        }
+        if let Some(instructions) = &self.additional_instructions {
+            body["additional_instructions"] = json!(instructions);
+        }
+        body
+    }
+}
+
+/// a run, as the server last reported it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Run {
+    pub id: String,
+    pub thread_id: String,
+    /// queued, in_progress, requires_action, cancelling, cancelled, failed, completed, incomplete, or expired
+    pub status: String,
+    pub usage: Option<Value>,
+}
+
+impl Run {
+    pub fn from_json(value: &Value) -> Result<Run> {
+        Ok(Run {
+            id: value["id"].as_str().ok_or("the run has no id")?.to_string(),
+            thread_id: value["thread_id"].as_str().unwrap_or_default().to_string(),
+            status: value["status"].as_str().unwrap_or_default().to_string(),
+            usage: Some(value["usage"].clone()).filter(|usage| !usage.is_null()),
+        })
+    }
+}
+
+/// the error of a run that did not finish, from the run object
+fn run_error(run: &Value) -> crate::Error {
+    let status = run["status"].as_str().unwrap_or("unknown");
+    let reason = run["last_error"]["message"].as_str().or(run["incomplete_details"]["reason"].as_str());
+    match (status, reason) {
+        ("requires_action", _) => "The assistant asked to call functions, which qllm can not run for assistants; the run was cancelled.".into(),
+        (_, Some(reason)) => format!("The run stopped ({}): {}", status, reason).into(),
+        _ => format!("The run stopped ({}).", status).into(),
+    }
+}
+
+impl Assistants {
+    /// a client for the Assistants API of the endpoint, which needs its beta header
+    pub fn new(client: QllmClient) -> Result<Assistants> {
+        if client.provider().name() != "openai" {
+            return Err(format!("The {} provider has no Assistants API.", client.provider().name()).into());
+        }
+        Ok(Assistants { client: client.with_header("OpenAI-Beta", "assistants=v2")? })
+    }
+
+    fn url(&self, path: &str) -> String {
+        api_url(self.client.endpoint(), path)
+    }
+
+    /// start an empty thread, returning its id
+    pub async fn create_thread(&self) -> Result<String> {
+        let thread: Value = self.client.post_once(&self.url("threads"), &json!({})).await?.json().await?;
+        Ok(thread["id"].as_str().ok_or("the thread has no id")?.to_string())
+    }
+
+    /// add a user message to a thread
+    pub async fn add_message(&self, thread_id: &str, text: &str) -> Result<()> {
+        let body = json!({ "role": "user", "content": text });
+        self.client.post_once(&self.url(&format!("threads/{}/messages", thread_id)), &body).await?;
+        Ok(())
+    }
+
+    /// run the assistant on a thread, streaming the answer
+    pub async fn stream_run(&self, thread_id: &str, assistant_id: &str, options: &RunOptions) -> Result<RunStream> {
+        let url = self.url(&format!("threads/{}/runs", thread_id));
+        let response = self.client.post_once(&url, &options.to_json(assistant_id, true)).await?;
+        Ok(RunStream { assistants: self.clone(), response, parser: SseParser::new(), pending: VecDeque::new(), run: None })
+    }
+
+    /// run the assistant on a thread and wait for it to finish, returning the run and
+    /// the text of the messages it added
+    pub async fn run(&self, thread_id: &str, assistant_id: &str, options: &RunOptions) -> Result<(Run, String)> {
+        let url = self.url(&format!("threads/{}/runs", thread_id));
+        let mut run: Value = self.client.post_once(&url, &options.to_json(assistant_id, false)).await?.json().await?;
+        let id = run["id"].as_str().ok_or("the run has no id")?.to_string();
+        while matches!(run["status"].as_str(), Some("queued" | "in_progress" | "cancelling")) {
+            tokio::time::sleep(POLL).await;
+            run = self.client.get(&self.url(&format!("threads/{}/runs/{}", thread_id, id))).await?.json().await?;
+        }
+        match run["status"].as_str() {
+            Some("completed") => {}
+            Some("requires_action") => {
+                self.cancel_run(thread_id, &id).await?;
+                return Err(run_error(&run));
+            }
+            _ => return Err(run_error(&run)),
+        }
+        let url = self.url(&format!("threads/{}/messages?run_id={}&order=asc", thread_id, id));
+        let messages: Value = self.client.get(&url).await?.json().await?;
+        let text = messages["data"].as_array().into_iter().flatten()
+            .filter(|message| message["role"] == "assistant")
+            .map(|message| message_text(&message["content"]))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        Ok((Run::from_json(&run)?, text))
+    }
+
+    /// stop a run, so that the thread takes new messages again
+    pub async fn cancel_run(&self, thread_id: &str, run_id: &str) -> Result<()> {
+        self.client.post_once(&self.url(&format!("threads/{}/runs/{}/cancel", thread_id, run_id)), &json!({})).await?;
+        Ok(())
+    }
+}
+
+/// the text parts of a message's content, or of a delta of it
+fn message_text(content: &Value) -> String {
+    content.as_array().into_iter().flatten()
+        .filter(|part| part["type"] == "text")
+        .filter_map(|part| part["text"]["value"].as_str())
+        .collect()
+}
+
+/// the events of a streamed run
+pub struct RunStream {
+    assistants: Assistants,
+    response: reqwest::Response,
+    parser: SseParser,
+    pending: VecDeque<SseEvent>,
+    /// the run, once it has finished
+    run: Option<Run>,
+}
+
+impl RunStream {
+    async fn next_event(&mut self) -> Result<Option<SseEvent>> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(Some(event));
+            }
+            match self.response.chunk().await? {
+                Some(bytes) => self.pending.extend(self.parser.feed(&bytes)),
+                None => return Ok(self.parser.finish()),
+            }
+        }
+    }
+
+    /// the next piece of the answer, or None once the run has finished
+    pub async fn next_delta(&mut self) -> Result<Option<String>> {
+        while let Some(event) = self.next_event().await? {
+            let name = event.event.as_deref().unwrap_or_default();
+            if name == "done" || event.data == "[DONE]" {
+                break;
+            }
+            let data: Value = serde_json::from_str(&event.data)
+                .map_err(|e| format!("the {} event of the run is not valid JSON: {}", name, e))?;
+            match name {
+                "thread.message.delta" => {
+                    let text = message_text(&data["delta"]["content"]);
+                    if !text.is_empty() {
+                        return Ok(Some(text));
+                    }
+                }
+                "thread.run.completed" => self.run = Some(Run::from_json(&data)?),
+                "thread.run.requires_action" => {
+                    let run = Run::from_json(&data)?;
+                    self.assistants.cancel_run(&run.thread_id, &run.id).await?;
+                    return Err(run_error(&data));
+                }
+                "thread.run.failed" | "thread.run.cancelled" | "thread.run.expired" | "thread.run.incomplete" => return Err(run_error(&data)),
+                "error" => {
+                    let message = data["message"].as_str().or(data["error"]["message"].as_str()).unwrap_or("unknown error");
+                    return Err(format!("The run failed: {}", message).into());
+                }
+                other => tracing::trace!(event = other, "assistant event"),
+            }
+        }
+        match self.run {
+            Some(_) => Ok(None),
+            None => Err("The stream of the run ended before it finished.".into()),
+        }
+    }
+
+    /// the run, once it has finished
+    pub fn run(&self) -> Option<&Run> {
+        self.run.as_ref()
+    }
+}
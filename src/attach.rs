@@ -0,0 +1,143 @@
+//! Files attached to a prompt, each under a header with its name in a fenced code block.
+//!
+//! Directories and globs are walked the way ripgrep walks them, leaving out what
+//! `.gitignore` files exclude and hidden files.
+
+use std::path::{Path, PathBuf};
+
+use crate::tokens::{Cut, Tokenizer};
+use crate::Result;
+
+/// a file read for attaching to a prompt
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attachment {
+    /// the path as it was given
+    pub path: String,
+    pub text: String,
+    /// the tokens of the text as attached
+    pub tokens: u64,
+    /// the tokens of the whole file, when it was cut to fit the limit
+    pub cut_from: Option<u64>,
+}
+
+impl Attachment {
+    /// read a text file, cut to its first `max_tokens` tokens; None for a binary file
+    pub fn read(path: &Path, tokenizer: &Tokenizer, max_tokens: u64) -> Result<Option<Self>> {
+        let bytes = std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        // a NUL byte near the start is what git takes as the sign of a binary file too
+        if bytes.iter().take(8000).any(|&b| b == 0) {
+            return Ok(None);
+        }
+        let Ok(text) = String::from_utf8(bytes) else {
+            return Ok(None);
+        };
+        let tokens = tokenizer.count(&text);
+        let (text, cut_from) = if tokens > max_tokens {
+            (tokenizer.truncate(&text, max_tokens, Cut::Tail), Some(tokens))
+        } else {
+            (text, None)
+        };
+        Ok(Some(Attachment {
+            path: path.display().to_string(),
+            tokens: if cut_from.is_some() { tokenizer.count(&text) } else { tokens },
+            text,
+            cut_from,
+        }))
+    }
+
+    /// the file under a header with its name, in a fenced block long enough that no run of
+    /// backticks in the file closes it early
+    pub fn to_markdown(&self) -> String {
+        let fence = fence(&self.text);
+        let note = match self.cut_from {
+            Some(tokens) => format!(" (the first {} of {} tokens)", self.tokens, tokens),
+            None => String::new(),
+        };
+        format!("File: {}{}\n{}{}\n{}\n{}", self.path, note, fence, language(&self.path), self.text.trim_end(), fence)
+    }
+}
+
+/// a fence for a block of `text`, longer than any run of backticks in it
+pub fn fence(text: &str) -> String {
+    let mut longest = 0;
+    let mut run = 0;
+    for c in text.chars() {
+        run = if c == '`' { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    "`".repeat(longest.max(2) + 1)
+}
+
+/// the language of a fenced block for a file, from its extension
+pub fn language(path: &str) -> &str {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let extension = match name.rsplit_once('.') {
+        Some((_, extension)) => extension,
+        None => return if name == "Makefile" || name == "Dockerfile" { name } else { "" },
+    };
+    match extension {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" => "typescript",
+        "go" => "go",
+        "c" | "h" => "c",
+        "cpp" | "cc" | "hpp" => "cpp",
+        "cs" => "csharp",
+        "sh" | "bash" => "bash",
+        "rb" => "ruby",
+        "md" => "markdown",
+        "yml" | "yaml" => "yaml",
+        "kt" => "kotlin",
+        "txt" => "",
+        other => other,
+    }
+}
+
+/// the files under `dir`, in order
+pub fn walk(dir: &Path) -> Result<Vec<PathBuf>> {
+    walk_matching(dir, None)
+}
+
+/// the files matching a glob such as `src/**/*.rs`, in order; `*` stays within a
+/// directory and `**` crosses them
+pub fn glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    let parts: Vec<&str> = pattern.split('/').collect();
+    // walk from the directories before the first part with a wildcard
+    let literal = parts.iter().take_while(|part| !part.contains(['*', '?', '[', '{'])).count();
+    if literal == parts.len() {
+        let path = PathBuf::from(pattern);
+        return match path.is_file() {
+            true => Ok(vec![path]),
+            false => Err(format!("{} is not a file.", pattern).into()),
+        };
+    }
+    let root = match parts[..literal].join("/") {
+        root if root.is_empty() && pattern.starts_with('/') => "/".to_string(),
+        root if root.is_empty() => ".".to_string(),
+        root => root,
+    };
+    // a leading slash anchors the rest at the root, as in a .gitignore
+    walk_matching(Path::new(&root), Some(&format!("/{}", parts[literal..].join("/"))))
+}
+
+fn walk_matching(root: &Path, glob: Option<&str>) -> Result<Vec<PathBuf>> {
+    let mut builder = ignore::WalkBuilder::new(root);
+    // .gitignore files count outside of git repositories too
+    builder.require_git(false);
+    if let Some(glob) = glob {
+        let mut overrides = ignore::overrides::OverrideBuilder::new(root);
+        overrides.add(glob)?;
+        builder.overrides(overrides.build()?);
+    }
+    let mut files = Vec::new();
+    for entry in builder.build() {
+        let entry = entry?;
+        if entry.file_type().is_some_and(|kind| kind.is_file()) {
+            let path = entry.into_path();
+            files.push(path.strip_prefix("./").map(Path::to_path_buf).unwrap_or(path));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
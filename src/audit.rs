@@ -0,0 +1,133 @@
+//! An opt-in audit log of every chat and completion request, for compliance and later
+//! analysis.
+//!
+//! Each request is appended as one JSON object per line once its answer is complete,
+//! or once it has failed: when it was sent, where to, the model and parameters, the
+//! messages, the answer, the usage, and how long it took. The text of the messages and
+//! the answer is kept as a SHA-256 hash unless the full text is asked for, so that the
+//! log can show what was sent without holding it.
+
+use serde_json::{json, Map, Value};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::{sha256, time, ChatRequest, CompletionRequest, Result};
+
+/// the append-only file of audited requests
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    /// keep the text of prompts and answers rather than their hashes
+    full_text: bool,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path, full_text: bool) -> Self {
+        AuditLog { path: path.to_path_buf(), full_text }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// every logged request, oldest first
+    pub fn entries(&self) -> Result<Vec<Value>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Could not read the audit log {}: {}", self.path.display(), e).into()),
+        };
+        Ok(content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+    }
+
+    fn append(&self, entry: &Value) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", entry)?;
+        Ok(())
+    }
+
+    /// text as it is kept in the log: itself, or an object with its hash
+    fn text(&self, text: &str) -> Value {
+        match self.full_text {
+            true => json!(text),
+            false => json!({ "sha256": sha256::hex(&sha256::digest(text.as_bytes())) }),
+        }
+    }
+
+    /// start the entry of a chat request about to be sent
+    pub fn chat(&self, endpoint: &str, provider: &str, request: &ChatRequest) -> Pending {
+        let mut params = request.to_json();
+        if let Some(params) = params.as_object_mut() {
+            params.remove("messages");
+            params.remove("model");
+        }
+        let messages: Vec<Value> = request.messages.iter()
+            .map(|message| json!({ "role": message.role, "content": self.text(&message.content) }))
+            .collect();
+        let mut pending = self.pending("chat", endpoint, provider, &request.model, params);
+        pending.entry.insert("messages".into(), json!(messages));
+        pending
+    }
+
+    /// start the entry of a text completion request about to be sent
+    pub fn completion(&self, endpoint: &str, provider: &str, request: &CompletionRequest) -> Pending {
+        let mut params = request.to_json();
+        if let Some(params) = params.as_object_mut() {
+            params.remove("prompt");
+            params.remove("model");
+        }
+        let mut pending = self.pending("completion", endpoint, provider, &request.model, params);
+        pending.entry.insert("prompt".into(), self.text(&request.prompt));
+        pending
+    }
+
+    fn pending(&self, kind: &str, endpoint: &str, provider: &str, model: &str, params: Value) -> Pending {
+        let mut entry = Map::new();
+        entry.insert("timestamp".into(), json!(time::now()));
+        entry.insert("kind".into(), json!(kind));
+        entry.insert("endpoint".into(), json!(endpoint));
+        entry.insert("provider".into(), json!(provider));
+        entry.insert("model".into(), json!(model));
+        entry.insert("params".into(), params);
+        Pending { log: self.clone(), entry, answer: String::new(), started: Instant::now() }
+    }
+}
+
+/// the entry of a request that is still being answered
+#[derive(Debug)]
+pub struct Pending {
+    log: AuditLog,
+    entry: Map<String, Value>,
+    answer: String,
+    started: Instant,
+}
+
+impl Pending {
+    /// add a piece of the answer
+    pub fn push(&mut self, text: &str) {
+        self.answer.push_str(text);
+    }
+
+    /// complete the entry and append it to the log; a log that can not be written is
+    /// warned about rather than failing the request it describes
+    pub fn finish(mut self, usage: Option<&Value>, finish_reason: Option<&str>, first_token: Option<Instant>, error: Option<&str>) {
+        let response = match error.is_some() && self.answer.is_empty() {
+            true => Value::Null,
+            false => self.log.text(&self.answer),
+        };
+        self.entry.insert("response".into(), response);
+        self.entry.insert("usage".into(), usage.cloned().unwrap_or(Value::Null));
+        self.entry.insert("finish_reason".into(), json!(finish_reason));
+        self.entry.insert("latency_ms".into(), json!(self.started.elapsed().as_millis() as u64));
+        self.entry.insert("first_token_ms".into(), json!(first_token.map(|at| at.duration_since(self.started).as_millis() as u64)));
+        self.entry.insert("error".into(), json!(error));
+        if let Err(e) = self.log.append(&Value::Object(self.entry)) {
+            tracing::warn!("could not write to the audit log {}: {}", self.log.path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! The OpenAI Batch API, which runs a file of requests within a day at half the price,
+//! writing their responses to another file.
+//!
+//! A job's input is a JSONL file uploaded with the purpose `batch`, one request a line
+//! with a `custom_id` to find its response by, since the responses come in any order.
+
+use serde_json::{json, Value};
+
+use crate::{api_url, QllmClient, Result};
+
+/// a batch job, as the server last reported it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Batch {
+    pub id: String,
+    /// validating, in_progress, finalizing, completed, failed, expired, cancelling, or cancelled
+    pub status: String,
+    /// the route each request is sent to, such as `/v1/chat/completions`
+    pub endpoint: String,
+    pub input_file_id: String,
+    /// the responses of the requests that succeeded, once there are any
+    pub output_file_id: Option<String>,
+    /// the responses of the requests that failed
+    pub error_file_id: Option<String>,
+    /// unix seconds
+    pub created_at: u64,
+    pub total: u64,
+    pub completed: u64,
+    pub failed: u64,
+    /// why the job as a whole failed, such as input lines that are not valid
+    pub errors: Vec<String>,
+}
+
+impl Batch {
+    pub fn from_json(value: &Value) -> Result<Batch> {
+        let file = |name: &str| value[name].as_str().map(str::to_string);
+        let count = |name: &str| value["request_counts"][name].as_u64().unwrap_or(0);
+        let errors = value["errors"]["data"].as_array().into_iter().flatten()
+            .map(|error| {
+                let message = error["message"].as_str().or(error["code"].as_str()).unwrap_or("unknown error");
+                match error["line"].as_u64() {
+                    Some(line) => format!("line {}: {}", line, message),
+                    None => message.to_string(),
+                }
+            })
+            .collect();
+        Ok(Batch {
+            id: value["id"].as_str().ok_or("the batch object has no id")?.to_string(),
+            status: value["status"].as_str().unwrap_or_default().to_string(),
+            endpoint: value["endpoint"].as_str().unwrap_or_default().to_string(),
+            input_file_id: value["input_file_id"].as_str().unwrap_or_default().to_string(),
+            output_file_id: file("output_file_id"),
+            error_file_id: file("error_file_id"),
+            created_at: value["created_at"].as_u64().unwrap_or(0),
+            total: count("total"),
+            completed: count("completed"),
+            failed: count("failed"),
+            errors,
+        })
+    }
+
+    /// whether the job has stopped, and its output files, if any, are final
+    pub fn is_done(&self) -> bool {
+        matches!(self.status.as_str(), "completed" | "failed" | "expired" | "cancelled")
+    }
+}
+
+/// start a job running the requests of an uploaded file against `endpoint`, such as
+/// `/v1/chat/completions`
+pub async fn create(client: &QllmClient, input_file_id: &str, endpoint: &str, completion_window: &str) -> Result<Batch> {
+    let body = json!({ "input_file_id": input_file_id, "endpoint": endpoint, "completion_window": completion_window });
+    let response: Value = client.post_once(&api_url(client.endpoint(), "batches"), &body).await?.json().await?;
+    Batch::from_json(&response)
+}
+
+/// the job as it is now
+pub async fn retrieve(client: &QllmClient, id: &str) -> Result<Batch> {
+    let response: Value = client.get(&api_url(client.endpoint(), &format!("batches/{}", id))).await?.json().await?;
+    Batch::from_json(&response)
+}
+
+/// the most recent jobs, newest first
+pub async fn list(client: &QllmClient, limit: u32) -> Result<Vec<Batch>> {
+    let url = api_url(client.endpoint(), &format!("batches?limit={}", limit));
+    let response: Value = client.get(&url).await?.json().await?;
+    let data = response["data"].as_array().ok_or("the batch list has no data array")?;
+    data.iter().map(Batch::from_json).collect()
+}
@@ -0,0 +1,127 @@
+//! Pick the fenced code blocks out of a streamed answer.
+
+use std::path::{Path, PathBuf};
+
+/// a fenced code block and the language from its info string, if any
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CodeBlock {
+    pub language: String,
+    pub code: String,
+}
+
+/// the fence characters and the info string of a line that opens a code block
+pub fn fence(line: &str) -> Option<(String, &str)> {
+    let trimmed = line.trim_start();
+    let marker = trimmed.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let length = trimmed.chars().take_while(|&c| c == marker).count();
+    if length < 3 {
+        return None;
+    }
+    Some((marker.to_string().repeat(length), trimmed[length..].trim()))
+}
+
+/// whether `line` closes the block opened by `fence`
+pub fn closes(line: &str, fence: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.starts_with(fence) && trimmed.trim_start_matches(['`', '~']).is_empty()
+}
+
+/// collects code blocks a line at a time, passing on the code of the wanted ones
+#[derive(Debug, Default)]
+pub struct BlockFilter {
+    /// the 1-based block to pass on, or every block
+    want: Option<usize>,
+    line: String,
+    fence: Option<String>,
+    blocks: Vec<CodeBlock>,
+}
+
+impl BlockFilter {
+    pub fn new(want: Option<usize>) -> Self {
+        BlockFilter { want, ..BlockFilter::default() }
+    }
+
+    /// add streamed text, returning the code lines it completed
+    pub fn push(&mut self, text: &str) -> String {
+        self.line.push_str(text);
+        let mut out = String::new();
+        while let Some(end) = self.line.find('\n') {
+            let line: String = self.line.drain(..=end).collect();
+            self.take_line(&line, &mut out);
+        }
+        out
+    }
+
+    /// handle the last line once the stream has ended
+    pub fn finish(&mut self) -> String {
+        let mut out = String::new();
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.take_line(&line, &mut out);
+        }
+        out
+    }
+
+    fn take_line(&mut self, line: &str, out: &mut String) {
+        let bare = line.trim_end_matches(['\n', '\r']);
+        match &self.fence {
+            Some(fence) if closes(bare, fence) => self.fence = None,
+            Some(_) => {
+                let block = self.blocks.last_mut().expect("a block is open");
+                block.code.push_str(line);
+                if self.want.is_none_or(|want| want == self.blocks.len()) {
+                    out.push_str(line);
+                }
+            }
+            None => {
+                if let Some((fence, info)) = fence(bare) {
+                    let language = info.split(|c: char| c.is_whitespace() || c == ',' || c == '{').next().unwrap_or_default();
+                    self.blocks.push(CodeBlock { language: language.to_string(), code: String::new() });
+                    self.fence = Some(fence);
+                }
+            }
+        }
+    }
+}
+
+/// the code blocks of a complete answer
+pub fn blocks(text: &str) -> Vec<CodeBlock> {
+    let mut filter = BlockFilter::new(None);
+    filter.push(text);
+    filter.finish();
+    filter.blocks
+}
+
+/// the file extension for a block's language
+fn extension(language: &str) -> &str {
+    match language.to_lowercase().as_str() {
+        "" | "text" | "plaintext" => "txt",
+        "rust" => "rs",
+        "python" => "py",
+        "javascript" => "js",
+        "typescript" => "ts",
+        "golang" => "go",
+        "c++" => "cpp",
+        "csharp" => "cs",
+        "bash" | "shell" | "zsh" | "console" => "sh",
+        "ruby" => "rb",
+        "markdown" => "md",
+        "yml" => "yaml",
+        "kotlin" => "kt",
+        _ => language,
+    }
+}
+
+/// write each block to `dir` as `block-<n>.<extension>`, returning the paths written
+pub fn save(blocks: &[CodeBlock], dir: &Path) -> Result<Vec<PathBuf>, qllm::Error> {
+    std::fs::create_dir_all(dir)?;
+    let mut paths = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        let extension: String = extension(&block.language).chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+        let extension = if extension.is_empty() { "txt".to_string() } else { extension.to_lowercase() };
+        let path = dir.join(format!("block-{}.{}", i + 1, extension));
+        std::fs::write(&path, &block.code)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
@@ -0,0 +1,208 @@
+//! A disk cache of chat answers, keyed by a hash of the request.
+//!
+//! Each answer is a small JSON file named after the SHA-256 of the endpoint and
+//! request body, in `responses/` under the qllm cache directory. Entries older
+//! than the time to live are ignored and replaced on the next store.
+
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::{dirs, sha256, time, ChatRequest, Result};
+
+/// a cached answer
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Entry {
+    /// unix time in seconds at which the answer was stored
+    pub created: u64,
+    pub content: String,
+    pub usage: Option<Value>,
+    pub finish_reason: Option<String>,
+}
+
+impl Entry {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "created": self.created,
+            "content": self.content,
+            "usage": self.usage,
+            "finish_reason": self.finish_reason,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Option<Self> {
+        Some(Entry {
+            created: value["created"].as_u64()?,
+            content: value["content"].as_str()?.to_string(),
+            usage: Some(value["usage"].clone()).filter(|u| !u.is_null()),
+            finish_reason: value["finish_reason"].as_str().map(str::to_string),
+        })
+    }
+
+    /// whether the entry is older than `ttl`; no ttl means entries never expire
+    pub fn expired(&self, ttl: Option<Duration>) -> bool {
+        ttl.is_some_and(|ttl| time::now().saturating_sub(self.created) >= ttl.as_secs())
+    }
+}
+
+/// what `qllm cache stats` reports
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub entries: usize,
+    pub expired: usize,
+    pub bytes: u64,
+}
+
+/// the on-disk store of answers
+#[derive(Clone, Debug)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn open(dir: &Path) -> Self {
+        Cache { dir: dir.to_path_buf() }
+    }
+
+    /// the `responses` directory in the qllm cache directory
+    pub fn open_default() -> Self {
+        Cache::open(&dirs::cache_dir().join("responses"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// the key of a request sent to `url`; whether it streams does not change the answer
+    pub fn key(url: &str, request: &ChatRequest) -> String {
+        let mut body = request.to_json();
+        if let Some(body) = body.as_object_mut() {
+            body.remove("stream");
+            body.remove("stream_options");
+        }
+        let text = json!({ "url": url, "body": body }).to_string();
+        sha256::hex(&sha256::digest(text.as_bytes()))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// the answer stored under `key`, unless it is missing, unreadable, or expired
+    pub fn get(&self, key: &str, ttl: Option<Duration>) -> Option<Entry> {
+        let entry = read(&self.path(key))?;
+        (!entry.expired(ttl)).then_some(entry)
+    }
+
+    /// store an answer, written to a temporary file first so readers never see half of it
+    pub fn put(&self, key: &str, entry: &Entry) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let temp = self.dir.join(format!("{}.tmp", key));
+        fs::write(&temp, entry.to_json().to_string())?;
+        fs::rename(&temp, self.path(key))?;
+        Ok(())
+    }
+
+    /// the cached files, or none when the directory does not exist yet
+    fn files(&self) -> Result<Vec<PathBuf>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut files = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "json" || extension == "tmp") {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+
+    /// delete every entry, or only the expired ones, returning how many were deleted
+    pub fn clear(&self, expired_only: Option<Duration>) -> Result<usize> {
+        let mut deleted = 0;
+        for path in self.files()? {
+            if let Some(ttl) = expired_only {
+                if read(&path).is_some_and(|entry| !entry.expired(Some(ttl))) {
+                    continue;
+                }
+            }
+            fs::remove_file(&path)?;
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+
+    /// count the entries and their size, and how many have outlived `ttl`
+    pub fn stats(&self, ttl: Option<Duration>) -> Result<Stats> {
+        let mut stats = Stats::default();
+        for path in self.files()? {
+            stats.entries += 1;
+            stats.bytes += fs::metadata(&path)?.len();
+            if read(&path).is_none_or(|entry| entry.expired(ttl)) {
+                stats.expired += 1;
+            }
+        }
+        Ok(stats)
+    }
+}
+
+/// the entry in a cache file, if it can be read
+fn read(path: &Path) -> Option<Entry> {
+    let text = fs::read_to_string(path).ok()?;
+    Entry::from_json(&serde_json::from_str(&text).ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Message;
+
+    fn request() -> ChatRequest {
+        ChatRequest::new("gpt-4o-mini", vec![Message::user("What is the capital of France?")])
+    }
+
+    #[test]
+    fn keys_requests_by_what_changes_the_answer() {
+        let url = "https://api.openai.com/v1/chat/completions";
+        let key = Cache::key(url, &request());
+        assert_eq!(key.len(), 64);
+        assert!(key.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(Cache::key(url, &ChatRequest { stream: false, ..request() }), key, "streaming does not change the answer");
+
+        let mut warmer = request();
+        warmer.params.temperature = 1.0;
+        let others = [
+            Cache::key("http://localhost:8080/v1/chat/completions", &request()),
+            Cache::key(url, &ChatRequest { model: "gpt-4o".to_string(), ..request() }),
+            Cache::key(url, &ChatRequest::new("gpt-4o-mini", vec![Message::user("What is the capital of Spain?")])),
+            Cache::key(url, &warmer),
+        ];
+        for other in others {
+            assert_ne!(other, key);
+        }
+    }
+
+    #[test]
+    fn stores_answers_until_they_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::open(&dir.path().join("responses"));
+        let key = Cache::key("http://localhost", &request());
+        assert_eq!(cache.get(&key, None), None);
+
+        let entry = Entry { created: time::now(), content: "Paris.".to_string(), usage: Some(json!({ "prompt_tokens": 14 })), finish_reason: Some("stop".to_string()) };
+        cache.put(&key, &entry).unwrap();
+        assert_eq!(cache.get(&key, Some(Duration::from_secs(60))), Some(entry.clone()));
+
+        let old = Entry { created: entry.created - 120, ..entry };
+        cache.put(&key, &old).unwrap();
+        assert_eq!(cache.get(&key, Some(Duration::from_secs(60))), None);
+        assert_eq!(cache.get(&key, None), Some(old));
+        assert_eq!(cache.stats(Some(Duration::from_secs(60))).unwrap().expired, 1);
+        assert_eq!(cache.clear(Some(Duration::from_secs(60))).unwrap(), 1);
+        assert_eq!(cache.stats(None).unwrap().entries, 0);
+    }
+}
@@ -0,0 +1,223 @@
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use qllm::tokens::Tokenizer;
+use qllm::{ChatRequest, Config, Exchange, History, Message, QllmClient, StreamEvent};
+
+use crate::cli::ChatArgs;
+use crate::{context_length, exchange_for, print_output, print_stream, read_prompt, read_stdin, spinner, ChatOptions};
+
+/// the size of a chunk when the model's context window is not known
+const DEFAULT_CHUNK: u64 = 4096;
+
+/// run the prompt over each chunk of stdin at once, then combine the partial answers into
+/// one, in rounds of their own while they are too long to combine in a single request
+pub async fn run(client: &QllmClient, args: &ChatArgs, options: &ChatOptions, history: &History, config: &Config) -> Result<(), qllm::Error> {
+    let input = read_stdin(true).await?.unwrap_or_default();
+    let mut prompt = read_prompt(&args.prompt, None, args.file.as_deref()).await?;
+    if let Some(lead) = &options.template_prompt {
+        prompt = if prompt.is_empty() { lead.clone() } else { format!("{}\n{}", lead, prompt) };
+    }
+    if prompt.is_empty() {
+        return Err("--chunked needs a prompt to run over each chunk of the input.".into());
+    }
+    let tokenizer = Tokenizer::for_model(&options.model, config).unwrap_or(Tokenizer::Estimate);
+    let size = chunk_size(client, config, &options.model, args.chunk_tokens).await;
+    let chunks = tokenizer.chunks(&input, size);
+    if chunks.is_empty() {
+        return Err("There is nothing on stdin to run --chunked over.".into());
+    }
+
+    let timestamp = qllm::time::now();
+    let started = Instant::now();
+    eprintln!("Running the prompt over {} chunks of up to {} tokens.", chunks.len(), size);
+    let (partials, combined, mut cost) = map_reduce(client, options, &tokenizer, &chunks, &prompt, size, args.concurrency as usize).await?;
+    let (request, answer) = if let [only] = combined.as_slice() {
+        // the input fit in a single chunk, whose answer needs no combining
+        let mut replay = client.replay(vec![StreamEvent::Content(only.clone())]);
+        (part(options, &prompt, 0, 1, &chunks[0]), print_stream(&mut replay, options.display, options.wrap, false, None).await?)
+    } else {
+        let request = combine(options, &prompt, &combined);
+        options.check_budget(&request)?;
+        spinner::start(&format!("waiting for {}", request.model));
+        let mut stream = client.chat(&request).await?;
+        let answer = print_stream(&mut stream, options.display, options.wrap, options.show_reasoning, None).await?;
+        if let Some(last) = stream.usage().and_then(|usage| options.prices.cost(&request.model, usage)) {
+            cost = Some(cost.unwrap_or(0.0) + last);
+        }
+        (request, answer)
+    };
+
+    let mut output = json!({
+        "model": request.model,
+        "params": request.params.to_json(),
+        "chunks": chunks.len(),
+        "partials": partials,
+        "content": answer,
+        "cost": cost,
+    });
+    if !args.no_history {
+        let mut exchange = Exchange {
+            conversation: history.new_conversation()?,
+            timestamp,
+            duration_ms: started.elapsed().as_millis() as u64,
+            cost,
+            ..exchange_for(client, &request, &answer, None, &options.prices)
+        };
+        history.record(&mut exchange)?;
+        output["id"] = exchange.id.into();
+        output["conversation"] = exchange.conversation.into();
+    }
+    print_output(args.output, output);
+    Ok(())
+}
+
+/// the size of the chunks: `flag` if given, else half the model's context window
+pub async fn chunk_size(client: &QllmClient, config: &Config, model: &str, flag: Option<u64>) -> u64 {
+    match flag {
+        Some(size) => size,
+        None => context_length(client, config, model).await.map(|length| length / 2).unwrap_or(DEFAULT_CHUNK),
+    }
+}
+
+/// answer the prompt for each chunk, then combine the partial answers in rounds while
+/// there are too many to combine at once; returns the answers for the chunks and those
+/// left for a last [`combine`], which is a single answer when there was a single chunk,
+/// along with what they cost
+pub async fn map_reduce(
+    client: &QllmClient,
+    options: &ChatOptions,
+    tokenizer: &Tokenizer,
+    chunks: &[String],
+    prompt: &str,
+    size: u64,
+    concurrency: usize,
+) -> Result<(Vec<String>, Vec<String>, Option<f64>), qllm::Error> {
+    let mut cost = None;
+    let requests = chunks.iter().enumerate().map(|(i, chunk)| part(options, prompt, i, chunks.len(), chunk)).collect();
+    let partials = answer_all(client, options, requests, concurrency, &mut cost).await?;
+    let mut combined = partials.clone();
+    loop {
+        let groups = group(tokenizer, &combined, size);
+        // partial answers too long to pair up are combined all at once
+        if groups.len() <= 1 || groups.len() == combined.len() {
+            break;
+        }
+        eprintln!("Combining {} partial answers in {} groups.", combined.len(), groups.len());
+        let requests = groups.iter().map(|group| combine(options, prompt, group)).collect();
+        combined = answer_all(client, options, requests, concurrency, &mut cost).await?;
+    }
+    Ok((partials, combined, cost))
+}
+
+/// the request for the `i`th of `count` chunks
+fn part(options: &ChatOptions, prompt: &str, i: usize, count: usize, chunk: &str) -> ChatRequest {
+    options.request(vec![
+        Message::system(&options.system),
+        Message::user(&format!("Part {} of {} of the input:\n{}\n{}", i + 1, count, chunk.trim_end(), prompt)),
+    ])
+}
+
+/// the request that combines the answers for consecutive parts of the input into one
+fn combine(options: &ChatOptions, prompt: &str, partials: &[String]) -> ChatRequest {
+    let parts: Vec<String> = partials.iter().enumerate()
+        .map(|(i, partial)| format!("Answer for part {}:\n{}", i + 1, partial.trim()))
+        .collect();
+    options.request(vec![
+        Message::system(&options.system),
+        Message::user(&format!(
+            "The input was too long to read at once, so the request below was answered for each of {} consecutive parts of it.\n\n{}\n\n\
+             Combine these into a single answer to the request for the whole input, without mentioning the parts.\n\nRequest: {}",
+            partials.len(),
+            parts.join("\n\n"),
+            prompt,
+        )),
+    ])
+}
+
+/// the partial answers in consecutive groups of at most about `size` tokens each
+fn group(tokenizer: &Tokenizer, partials: &[String], size: u64) -> Vec<Vec<String>> {
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut tokens = 0;
+    for partial in partials {
+        let count = tokenizer.count(partial);
+        match groups.last_mut() {
+            Some(group) if tokens + count <= size => group.push(partial.clone()),
+            _ => {
+                groups.push(vec![partial.clone()]);
+                tokens = 0;
+            }
+        }
+        tokens += count;
+    }
+    groups
+}
+
+/// send the requests, up to `concurrency` at once, and return their answers in order,
+/// adding what they cost to `cost`
+async fn answer_all(client: &QllmClient, options: &ChatOptions, requests: Vec<ChatRequest>, concurrency: usize, cost: &mut Option<f64>) -> Result<Vec<String>, qllm::Error> {
+    for request in &requests {
+        options.check_budget(request)?;
+    }
+    let count = requests.len();
+    let permits = Arc::new(Semaphore::new(concurrency));
+    let mut tasks = JoinSet::new();
+    for (index, request) in requests.into_iter().enumerate() {
+        // the clients share one connection pool
+        let client = client.clone();
+        let permits = permits.clone();
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let result = async {
+                let mut stream = client.chat(&request).await?;
+                let mut answer = String::new();
+                while let Some(delta) = stream.next_delta().await? {
+                    answer.push_str(&delta);
+                }
+                Ok::<_, qllm::Error>((answer, stream.usage().cloned()))
+            };
+            (index, result.await)
+        });
+    }
+    let mut answers = vec![String::new(); count];
+    while let Some(result) = tasks.join_next().await {
+        let (index, result) = result?;
+        let (answer, usage) = result.map_err(|e| format!("Part {} of {} failed: {}", index + 1, count, e))?;
+        if let Some(part) = usage.and_then(|usage| options.prices.cost(&options.model, &usage)) {
+            *cost = Some(cost.unwrap_or(0.0) + part);
+        }
+        answers[index] = answer;
+    }
+    Ok(answers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a partial answer of `tokens` estimated tokens
+    fn partial(tokens: usize) -> String {
+        "x".repeat(tokens * 4)
+    }
+
+    /// the tokens of each partial of each group
+    fn sizes(groups: &[Vec<String>]) -> Vec<Vec<u64>> {
+        groups.iter().map(|group| group.iter().map(|partial| Tokenizer::Estimate.count(partial)).collect()).collect()
+    }
+
+    #[test]
+    fn groups_consecutive_partials_up_to_the_size() {
+        let partials = [3, 4, 3, 5, 1, 1].map(partial);
+        assert_eq!(sizes(&group(&Tokenizer::Estimate, &partials, 10)), [vec![3, 4, 3], vec![5, 1, 1]]);
+        assert_eq!(sizes(&group(&Tokenizer::Estimate, &partials, 7)), [vec![3, 4], vec![3], vec![5, 1, 1]]);
+    }
+
+    #[test]
+    fn gives_a_partial_over_the_size_a_group_of_its_own() {
+        let partials = [2, 20, 2].map(partial);
+        assert_eq!(sizes(&group(&Tokenizer::Estimate, &partials, 10)), [vec![2], vec![20], vec![2]]);
+        assert!(group(&Tokenizer::Estimate, &[], 10).is_empty());
+    }
+}
@@ -0,0 +1,1614 @@
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::audit::{AuditLog, Pending};
+use crate::framing::Framer;
+use crate::image::Image;
+use crate::logprobs::TokenLogprob;
+use crate::oauth::OAuth;
+use crate::provider::{self, Provider, StreamEvent};
+use crate::think::ThinkSplitter;
+use crate::tls::Tls;
+use crate::tools::{ToolCall, ToolCalls};
+use crate::utf8::Utf8Decoder;
+use crate::{realtime, time, unix, Result};
+
+/// a single role-tagged chat message
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub role: String,
+    pub content: String,
+    /// the tools an assistant message called
+    pub tool_calls: Vec<ToolCall>,
+    /// the call a tool message answers
+    pub tool_call_id: Option<String>,
+    /// images shown to a vision model along with the text
+    pub images: Vec<Image>,
+}
+
+impl Message {
+    pub fn new(role: &str, content: &str) -> Self {
+        Message {
+            role: role.to_string(),
+            content: content.to_string(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn system(content: &str) -> Self {
+        Message::new("system", content)
+    }
+
+    pub fn user(content: &str) -> Self {
+        Message::new("user", content)
+    }
+
+    pub fn assistant(content: &str) -> Self {
+        Message::new("assistant", content)
+    }
+
+    /// an assistant turn that called tools, possibly with some text alongside
+    pub fn tool_calls(content: &str, calls: Vec<ToolCall>) -> Self {
+        Message { tool_calls: calls, ..Message::assistant(content) }
+    }
+
+    /// the result of the tool call `id`
+    pub fn tool(id: &str, content: &str) -> Self {
+        Message { tool_call_id: Some(id.to_string()), ..Message::new("tool", content) }
+    }
+
+    /// a message from its OpenAI shape, where the content may also be an array of text parts
+    pub fn from_json(value: &Value) -> Result<Self> {
+        let role = value["role"].as_str().ok_or("a message needs a `role` string")?;
+        let content = match &value["content"] {
+            Value::String(text) => text.clone(),
+            Value::Array(parts) => parts.iter().filter_map(|part| part["text"].as_str()).collect::<Vec<_>>().join("\n"),
+            Value::Null => String::new(),
+            other => return Err(format!("the content of a message must be a string, not {}", other).into()),
+        };
+        let tool_calls = value["tool_calls"].as_array().into_iter().flatten()
+            .map(|call| ToolCall {
+                id: call["id"].as_str().unwrap_or_default().to_string(),
+                name: call["function"]["name"].as_str().unwrap_or_default().to_string(),
+                arguments: match &call["function"]["arguments"] {
+                    Value::String(arguments) => arguments.clone(),
+                    arguments => arguments.to_string(),
+                },
+            })
+            .collect();
+        Ok(Message {
+            tool_calls,
+            tool_call_id: value["tool_call_id"].as_str().map(str::to_string),
+            ..Message::new(role, &content)
+        })
+    }
+
+    /// the message in the OpenAI shape, with images making the content an array of parts
+    pub fn to_json(&self) -> Value {
+        let mut message = json!({ "role": self.role, "content": self.content });
+        if !self.images.is_empty() {
+            let mut parts = vec![json!({ "type": "text", "text": self.content })];
+            parts.extend(self.images.iter().map(|image| json!({ "type": "image_url", "image_url": { "url": image.url() } })));
+            message["content"] = Value::Array(parts);
+        }
+        if !self.tool_calls.is_empty() {
+            message["tool_calls"] = self.tool_calls.iter().map(ToolCall::to_json).collect();
+        }
+        if let Some(id) = &self.tool_call_id {
+            message["tool_call_id"] = json!(id);
+        }
+        message
+    }
+}
+
+/*
+    repeat_last_n = 64, repeat_penalty = 1.100, frequency_penalty = 0.000, presence_penalty = 0.000
+    top_k = 40, tfs_z = 1.000, top_p = 0.950, min_p = 0.050, typical_p = 1.000, temp = 0.800
+    mirostat = 0, mirostat_lr = 0.100, mirostat_ent = 5.000
+*/
+
+/// the sampler settings sent along with every request
+#[derive(Clone, Debug, PartialEq)]
+pub struct SamplerParams {
+    pub max_tokens: i64,
+    pub temperature: f64,
+    pub top_p: f64,
+    pub min_p: f64,
+    pub top_k: usize,
+    pub repetition_penalty: f64,
+    pub repetition_penalty_last: usize,
+    pub presence_penalty: f64,
+    pub frequency_penalty: f64,
+    pub typical_p: f64,
+    pub mirostat_mode: u8,
+    pub mirostat_tau: f64,
+    pub mirostat_eta: f64,
+    /// the sampling seed, for servers that can repeat a generation
+    pub seed: Option<u64>,
+    /// a map from token ids to a bias between -100 and 100, for OpenAI-compatible servers
+    pub logit_bias: Option<Value>,
+    /// a GBNF grammar the output must follow, for llama.cpp servers
+    pub grammar: Option<String>,
+}
+
+impl Default for SamplerParams {
+    fn default() -> Self {
+        SamplerParams {
+            max_tokens: -1,
+            temperature: 0.8,
+            top_p: 0.95,
+            min_p: 0.05,
+            top_k: 40,
+            repetition_penalty: 1.1,
+            repetition_penalty_last: 64,
+            presence_penalty: 0.0,
+            frequency_penalty: 0.0,
+            typical_p: 1.0,
+            mirostat_mode: 0,
+            mirostat_tau: 5.0,
+            mirostat_eta: 0.1,
+            seed: None,
+            logit_bias: None,
+            grammar: None,
+        }
+    }
+}
+
+impl SamplerParams {
+    /// write the sampler fields into a request body
+    pub fn apply(&self, body: &mut Map<String, Value>) {
+        body.insert("max_tokens".into(), json!(self.max_tokens));
+        body.insert("temperature".into(), json!(self.temperature));
+        body.insert("top_p".into(), json!(self.top_p));
+        body.insert("top_k".into(), json!(self.top_k));
+        body.insert("min_p".into(), json!(self.min_p));
+        body.insert("repetition_penalty".into(), json!(self.repetition_penalty));
+        body.insert("repetition_penalty_last".into(), json!(self.repetition_penalty_last));
+        body.insert("presence_penalty".into(), json!(self.presence_penalty));
+        body.insert("frequency_penalty".into(), json!(self.frequency_penalty));
+        body.insert("typical_p".into(), json!(self.typical_p));
+        body.insert("mirostat_mode".into(), json!(self.mirostat_mode));
+        body.insert("mirostat_tau".into(), json!(self.mirostat_tau));
+        body.insert("mirostat_eta".into(), json!(self.mirostat_eta));
+        if let Some(seed) = self.seed {
+            body.insert("seed".into(), json!(seed));
+        }
+        if let Some(bias) = &self.logit_bias {
+            body.insert("logit_bias".into(), bias.clone());
+        }
+        if let Some(grammar) = &self.grammar {
+            body.insert("grammar".into(), json!(grammar));
+        }
+    }
+
+    /// settings that make the output as repeatable as the server allows: greedy
+    /// sampling, no penalties, and a fixed seed
+    pub fn deterministic(&mut self) {
+        self.temperature = 0.0;
+        self.top_p = 1.0;
+        self.top_k = 1;
+        self.repetition_penalty = 1.0;
+        self.presence_penalty = 0.0;
+        self.frequency_penalty = 0.0;
+        self.mirostat_mode = 0;
+        self.seed = Some(self.seed.unwrap_or(0));
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut body = Map::new();
+        self.apply(&mut body);
+        Value::Object(body)
+    }
+
+    /// override the fields present in a table such as the config's `[sampler]`
+    pub fn update(&mut self, table: &Value) {
+        let float = |key: &str, field: &mut f64| {
+            if let Some(v) = table.get(key).and_then(Value::as_f64) {
+                *field = v;
+            }
+        };
+        float("temperature", &mut self.temperature);
+        float("top_p", &mut self.top_p);
+        float("min_p", &mut self.min_p);
+        float("repetition_penalty", &mut self.repetition_penalty);
+        float("presence_penalty", &mut self.presence_penalty);
+        float("frequency_penalty", &mut self.frequency_penalty);
+        float("typical_p", &mut self.typical_p);
+        float("mirostat_tau", &mut self.mirostat_tau);
+        float("mirostat_eta", &mut self.mirostat_eta);
+        if let Some(v) = table.get("max_tokens").and_then(Value::as_i64) {
+            self.max_tokens = v;
+        }
+        if let Some(v) = table.get("top_k").and_then(Value::as_u64) {
+            self.top_k = v as usize;
+        }
+        if let Some(v) = table.get("repetition_penalty_last").and_then(Value::as_u64) {
+            self.repetition_penalty_last = v as usize;
+        }
+        if let Some(v) = table.get("mirostat_mode").and_then(Value::as_u64) {
+            self.mirostat_mode = v as u8;
+        }
+        if let Some(v) = table.get("seed").and_then(Value::as_u64) {
+            self.seed = Some(v);
+        }
+        if let Some(v) = table.get("logit_bias").filter(|v| v.is_object()) {
+            self.logit_bias = Some(v.clone());
+        }
+        if let Some(v) = table.get("grammar").and_then(Value::as_str) {
+            self.grammar = Some(v.to_string());
+        }
+    }
+}
+
+/// a constraint on the shape of the answer
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResponseFormat {
+    /// any valid JSON
+    Json,
+    /// JSON matching a schema
+    Schema { name: String, schema: Value },
+}
+
+impl ResponseFormat {
+    /// the OpenAI `response_format` field
+    pub fn to_json(&self) -> Value {
+        match self {
+            ResponseFormat::Json => json!({ "type": "json_object" }),
+            ResponseFormat::Schema { name, schema } => json!({
+                "type": "json_schema",
+                "json_schema": { "name": name, "schema": schema },
+            }),
+        }
+    }
+}
+
+/// a chat completion request against an OpenAI-compatible endpoint
+#[derive(Clone, Debug)]
+pub struct ChatRequest {
+    /// the model name, "default" leaves the choice to the server
+    pub model: String,
+    pub messages: Vec<Message>,
+    pub params: SamplerParams,
+    /// ask for a streamed response, otherwise the whole answer arrives at once
+    pub stream: bool,
+    /// OpenAI-style tool definitions the model may call
+    pub tools: Vec<Value>,
+    /// the number of answers to generate, see [`ChatStream::choices`]
+    pub n: usize,
+    /// ask for token log probabilities with this many alternatives per token
+    pub logprobs: Option<usize>,
+    /// constrain the answer to JSON
+    pub response_format: Option<ResponseFormat>,
+    /// OpenRouter's routing fields, such as fallback `models` and `provider`
+    /// preferences, which only OpenRouter is sent
+    pub routing: Option<Value>,
+}
+
+impl ChatRequest {
+    pub fn new(model: &str, messages: Vec<Message>) -> Self {
+        ChatRequest { model: model.to_string(), messages, params: SamplerParams::default(), stream: true, tools: Vec::new(), n: 1, logprobs: None, response_format: None, routing: None }
+    }
+
+    /// build the JSON request body
+    pub fn to_json(&self) -> Value {
+        let mut body = Map::new();
+        if self.model != "default" {
+            body.insert("model".into(), json!(self.model));
+        }
+        body.insert(
+            "messages".into(),
+            Value::Array(self.messages.iter().map(Message::to_json).collect()),
+        );
+        self.params.apply(&mut body);
+        if !self.tools.is_empty() {
+            body.insert("tools".into(), json!(self.tools));
+        }
+        if self.n > 1 {
+            body.insert("n".into(), json!(self.n));
+        }
+        if let Some(format) = &self.response_format {
+            body.insert("response_format".into(), format.to_json());
+        }
+        if let Some(top) = self.logprobs {
+            body.insert("logprobs".into(), json!(true));
+            if top > 0 {
+                body.insert("top_logprobs".into(), json!(top));
+            }
+        }
+        body.insert("stream".into(), json!(self.stream));
+        if self.stream {
+            // ask for a trailing usage chunk, which streams otherwise leave out
+            body.insert("stream_options".into(), json!({ "include_usage": true }));
+        }
+        Value::Object(body)
+    }
+}
+
+/// a plain text completion request, sent to the `/completions` endpoint
+#[derive(Clone, Debug)]
+pub struct CompletionRequest {
+    pub model: String,
+    pub prompt: String,
+    pub params: SamplerParams,
+    pub stream: bool,
+}
+
+impl CompletionRequest {
+    pub fn new(model: &str, prompt: &str) -> Self {
+        CompletionRequest { model: model.to_string(), prompt: prompt.to_string(), params: SamplerParams::default(), stream: true }
+    }
+
+    /// build the JSON request body
+    pub fn to_json(&self) -> Value {
+        let mut body = Map::new();
+        if self.model != "default" {
+            body.insert("model".into(), json!(self.model));
+        }
+        body.insert("prompt".into(), json!(self.prompt));
+        self.params.apply(&mut body);
+        body.insert("stream".into(), json!(self.stream));
+        if self.stream {
+            body.insert("stream_options".into(), json!({ "include_usage": true }));
+        }
+        Value::Object(body)
+    }
+}
+
+/// the verdict of a moderation endpoint on one input
+#[derive(Clone, Debug, PartialEq)]
+pub struct Moderation {
+    /// whether the server found the input breaks its policy
+    pub flagged: bool,
+    /// the score of each category, from 0 to 1, highest first
+    pub scores: Vec<(String, f64)>,
+    /// the categories the server flagged
+    pub categories: Vec<String>,
+    /// the result as the server sent it
+    pub raw: Value,
+}
+
+/// derive the url of another API route from a configured endpoint
+///
+/// The endpoint may be a full route such as `http://host/v1/chat/completions` or
+/// just the API base `http://host/v1`; either way the known route suffix is
+/// replaced by `path`.
+pub fn api_url(endpoint: &str, path: &str) -> String {
+    let mut base = endpoint.trim_end_matches('/');
+    for suffix in ["/chat/completions", "/completions", "/embeddings", "/rerank", "/moderations", "/models"] {
+        if let Some(stripped) = base.strip_suffix(suffix) {
+            base = stripped;
+            break;
+        }
+    }
+    format!("{}/{}", base, path.trim_start_matches('/'))
+}
+
+/// the url of a proxy as reqwest takes it: `socks://` is SOCKS5 with host names resolved
+/// by the proxy, as `socks5h://` is, and SOCKS4 is refused rather than misread as SOCKS5
+fn proxy_url(url: &str) -> Result<String> {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    match scheme.to_ascii_lowercase().as_str() {
+        "socks" => Ok(format!("socks5h://{}", rest)),
+        "socks4" | "socks4a" => Err(format!("{} is a SOCKS4 proxy; only SOCKS5 and HTTP proxies are supported", url).into()),
+        _ => Ok(url.to_string()),
+    }
+}
+
+/// how often and how patiently failed requests are retried
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// the number of retries after the first attempt
+    pub retries: u32,
+    /// the wait before the first retry, doubled for each further one
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { retries: 2, delay: Duration::from_secs(1) }
+    }
+}
+
+/// the longest a single backoff may wait
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+impl RetryPolicy {
+    /// the exponential backoff before retry number `attempt` (counting from 0),
+    /// with up to a quarter of jitter so parallel clients spread out
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let base = self.delay.saturating_mul(2u32.saturating_pow(attempt)).min(MAX_BACKOFF);
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        base + base.mul_f64((nanos % 1000) as f64 / 4000.0)
+    }
+}
+
+/// rate limiting and overload statuses that are worth trying again
+fn retryable_status(status: reqwest::StatusCode) -> bool {
+    matches!(status.as_u16(), 408 | 429 | 500 | 502 | 503 | 504)
+}
+
+/// connection failures and timeouts, as opposed to errors in the request itself
+fn transient(error: &crate::Error) -> bool {
+    if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+        return error.is_connect() || error.is_timeout() || error.is_request();
+    }
+    // requests over a Unix socket fail with these instead
+    error.is::<std::io::Error>() || error.is::<hyper::Error>()
+}
+
+/// failures in which the request surely never reached the server, so that even a request
+/// that creates something can be sent again
+fn unsent(error: &crate::Error) -> bool {
+    if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+        return error.is_connect();
+    }
+    // a Unix socket that could not be connected to, as opposed to a request that timed out
+    match error.downcast_ref::<std::io::Error>() {
+        Some(error) => error.kind() != std::io::ErrorKind::TimedOut,
+        None => error.downcast_ref::<hyper::Error>().is_some_and(hyper::Error::is_connect),
+    }
+}
+
+/// the wait requested by a Retry-After header, in seconds or as an HTTP date
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get("retry-after")?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<f64>() {
+        return Some(Duration::from_secs_f64(secs.max(0.0)).min(MAX_BACKOFF));
+    }
+    let at = time::parse_http_date(value)?;
+    Some(Duration::from_secs(at.saturating_sub(time::now())).min(MAX_BACKOFF))
+}
+
+/// how long to wait on the server before giving up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timeouts {
+    /// establishing the connection
+    pub connect: Option<Duration>,
+    /// the whole request, from sending it to the end of the response
+    pub request: Option<Duration>,
+    /// the silence between two pieces of a streamed response
+    pub stall: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Some(Duration::from_secs(10)),
+            request: None,
+            stall: Some(Duration::from_secs(120)),
+        }
+    }
+}
+
+/// how requests choose among several keys
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rotation {
+    /// each request uses the next key in turn
+    RoundRobin,
+    /// requests keep to one key until it is rate limited
+    OnRateLimit,
+}
+
+/// several api keys for one endpoint, for rate limits sharded across keys; a key that
+/// is answered with 429 rests until the server's Retry-After or the backoff has passed
+#[derive(Debug)]
+pub struct KeyPool {
+    keys: Vec<String>,
+    rotation: Rotation,
+    state: Mutex<PoolState>,
+}
+
+#[derive(Debug)]
+struct PoolState {
+    /// the key the next request starts looking from
+    next: usize,
+    /// when each key may be used again after being rate limited
+    resting: Vec<Option<Instant>>,
+}
+
+impl KeyPool {
+    pub fn new(keys: Vec<String>, rotation: Rotation) -> Self {
+        // a round starts at a different key in each process, so that short runs of a
+        // single request do not all fall on the first key
+        let next = match rotation {
+            Rotation::RoundRobin => (time::now_ms() % keys.len().max(1) as u64) as usize,
+            Rotation::OnRateLimit => 0,
+        };
+        let resting = vec![None; keys.len()];
+        KeyPool { keys, rotation, state: Mutex::new(PoolState { next, resting }) }
+    }
+
+    /// the index and key for a request: the next key that is not resting, or the one
+    /// whose rest ends first when all are
+    fn pick(&self) -> (usize, &str) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let count = self.keys.len();
+        let index = (0..count)
+            .map(|offset| (state.next + offset) % count)
+            .find(|&index| state.resting[index].is_none_or(|until| until <= now))
+            .or_else(|| (0..count).min_by_key(|&index| state.resting[index]))
+            .unwrap_or_default();
+        state.resting[index] = None;
+        state.next = match self.rotation {
+            Rotation::RoundRobin => (index + 1) % count,
+            Rotation::OnRateLimit => index,
+        };
+        (index, &self.keys[index])
+    }
+
+    /// rest a key that was rate limited for `wait`
+    fn rate_limited(&self, index: usize, wait: Duration) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.resting[index] = Some(Instant::now() + wait);
+        tracing::info!(key = index + 1, of = self.keys.len(), wait_ms = wait.as_millis() as u64, "key rate limited");
+    }
+
+    /// whether some key is not resting
+    fn any_ready(&self) -> bool {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        state.resting.iter().any(|until| until.is_none_or(|until| until <= now))
+    }
+}
+
+/// a reusable client for a single endpoint
+#[derive(Clone, Debug)]
+pub struct QllmClient {
+    http: reqwest::Client,
+    endpoint: String,
+    key: Option<String>,
+    provider: Arc<dyn Provider>,
+    retry: RetryPolicy,
+    timeouts: Timeouts,
+    proxy: Option<String>,
+    tls: Tls,
+    headers: reqwest::header::HeaderMap,
+    /// print requests and response headers to stderr
+    dump: bool,
+    /// where chat and completion requests are logged, if anywhere
+    audit: Option<AuditLog>,
+    /// keys used in turn in place of `key`
+    keys: Option<Arc<KeyPool>>,
+    /// where bearer tokens come from in place of a key
+    oauth: Option<Arc<OAuth>>,
+    /// the socket requests are sent over, for a `unix://` endpoint
+    socket: Option<PathBuf>,
+}
+
+impl QllmClient {
+    /// a client for `endpoint`, with the provider guessed from the url
+    pub fn new(endpoint: &str, key: Option<String>) -> Self {
+        let (socket, http_endpoint) = match unix::split(endpoint) {
+            Some((socket, http_endpoint)) => (Some(socket), http_endpoint),
+            None => (None, endpoint.to_string()),
+        };
+        let mut client = QllmClient {
+            http: reqwest::Client::new(),
+            endpoint: http_endpoint,
+            key,
+            provider: provider::detect(endpoint),
+            retry: RetryPolicy::default(),
+            timeouts: Timeouts::default(),
+            proxy: None,
+            tls: Tls::default(),
+            headers: reqwest::header::HeaderMap::new(),
+            dump: false,
+            audit: None,
+            keys: None,
+            oauth: None,
+            socket,
+        };
+        if let Ok(http) = client.http_client() {
+            client.http = http;
+        }
+        client
+    }
+
+    /// the http client for the current timeouts and proxy
+    fn http_client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(connect) = self.timeouts.connect {
+            builder = builder.connect_timeout(connect);
+        }
+        if let Some(request) = self.timeouts.request {
+            builder = builder.timeout(request);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy_url(proxy)?)?);
+        }
+        builder = self.tls.configure(builder)?;
+        Ok(builder.build()?)
+    }
+
+    /// give up on unresponsive servers according to `timeouts`
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Result<Self> {
+        self.timeouts = timeouts;
+        self.http = self.http_client()?;
+        Ok(self)
+    }
+
+    /// add a header to every request, replacing any the provider sets under the same name
+    pub fn with_header(mut self, name: &str, value: &str) -> Result<Self> {
+        let name = reqwest::header::HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("{:?} is not a valid header name", name.trim()))?;
+        let value = reqwest::header::HeaderValue::from_str(value.trim())
+            .map_err(|_| format!("the value of the {} header is not valid", name))?;
+        self.headers.insert(name, value);
+        Ok(self)
+    }
+
+    /// send every request through the proxy at `url`, an HTTP or SOCKS5 proxy which may
+    /// carry `user:password@` credentials; without one, the HTTP_PROXY, HTTPS_PROXY, and
+    /// ALL_PROXY variables apply
+    pub fn with_proxy(mut self, url: &str) -> Result<Self> {
+        self.proxy = Some(url.to_string());
+        self.http = self.http_client()?;
+        Ok(self)
+    }
+
+    /// trust a private CA or none, or present a client certificate to the server for
+    /// gateways that authenticate with mutual TLS
+    pub fn with_tls(mut self, tls: Tls) -> Result<Self> {
+        self.tls = tls;
+        self.http = self.http_client()?;
+        Ok(self)
+    }
+
+    /// print every request, with its key redacted, and the status and headers of every
+    /// response to stderr, for finding out what a gateway or provider objects to
+    pub fn with_dump(mut self, dump: bool) -> Self {
+        self.dump = dump;
+        self
+    }
+
+    /// append every chat and completion request to an audit log
+    pub fn with_audit(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// use the keys of a pool in turn instead of a single key
+    pub fn with_keys(mut self, keys: KeyPool) -> Self {
+        if !keys.keys.is_empty() {
+            self.keys = Some(Arc::new(keys));
+        }
+        self
+    }
+
+    /// authorize requests with OAuth tokens instead of a key, fetching a new one
+    /// when the server rejects the last
+    pub fn with_oauth(mut self, oauth: OAuth) -> Self {
+        self.oauth = Some(Arc::new(oauth));
+        self
+    }
+
+    /// retry failed requests according to `retry`
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// use the given provider instead of the detected one
+    pub fn with_provider(mut self, provider: Arc<dyn Provider>) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub fn provider(&self) -> &dyn Provider {
+        self.provider.as_ref()
+    }
+
+    /// a request with the provider's authorization, the extra headers, and any signature
+    fn build(&self, builder: reqwest::RequestBuilder) -> Result<reqwest::Request> {
+        let key = self.key.as_deref().or_else(|| self.keys.as_ref().and_then(|keys| keys.keys.first()).map(String::as_str));
+        self.build_with(builder, key)
+    }
+
+    fn build_with(&self, builder: reqwest::RequestBuilder, key: Option<&str>) -> Result<reqwest::Request> {
+        let builder = self.provider.authorize(builder, key).headers(self.headers.clone());
+        let mut request = builder.build()?;
+        self.provider.sign(&mut request, key)?;
+        Ok(request)
+    }
+
+    /// authorize, sign, and send a request, retrying transient failures, and moving on
+    /// from a key of the pool that is rate limited
+    ///
+    /// A request that is not `idempotent`, such as one creating a job, is only sent again
+    /// when the server surely did not act on it: when it could not be connected to, or
+    /// refused the request as rate limited or unauthorized. A timeout or a server error
+    /// may come after the server did its work, so sending it again could do it twice.
+    async fn send(&self, builder: reqwest::RequestBuilder, idempotent: bool) -> Result<reqwest::Response> {
+        let mut builder = Some(builder);
+        let mut attempt = 0;
+        // keys tried since the last wait, so that keys rested only briefly are not
+        // switched between for ever
+        let mut switches = 0;
+        // a rejected OAuth token is replaced only once, in case the new one is too
+        let mut refreshed = false;
+        loop {
+            let retry = attempt < self.retry.retries;
+            // a copy for another attempt or another key, unless the body can not be replayed
+            let again = builder.as_ref().filter(|_| retry || self.keys.is_some() || self.oauth.is_some()).and_then(reqwest::RequestBuilder::try_clone);
+            let last = again.is_none();
+            let this = match again {
+                Some(this) => this,
+                None => builder.take().ok_or("the request can not be sent again")?,
+            };
+            let key = self.keys.as_ref().map(|keys| keys.pick());
+            let token = match &self.oauth {
+                Some(oauth) => Some(oauth.token(&self.http).await?),
+                None => None,
+            };
+            let request = self.build_with(this, token.as_deref().or(key.map(|(_, key)| key)).or(self.key.as_deref()))?;
+            if self.dump {
+                dump_request(&request);
+            }
+            tracing::debug!(method = %request.method(), url = %request.url(), attempt = attempt + 1, key = key.map(|(index, _)| index + 1), "sending request");
+            let started = Instant::now();
+            // responses from the socket do not know their url
+            let url = request.url().clone();
+            let result = match &self.socket {
+                Some(socket) => {
+                    let sending = unix::execute(socket, request, self.timeouts.connect);
+                    match self.timeouts.request {
+                        Some(limit) => tokio::time::timeout(limit, sending).await
+                            .unwrap_or_else(|_| Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "the request timed out").into())),
+                        None => sending.await,
+                    }
+                }
+                None => self.http.execute(request).await.map_err(Into::into),
+            };
+            if let (Ok(response), true) = (&result, self.dump) {
+                dump_response(response);
+            }
+            let response = match result {
+                Ok(response) => response,
+                Err(e) if transient(&e) && (idempotent || unsent(&e)) && retry && !last => {
+                    let wait = self.retry.backoff(attempt);
+                    tracing::warn!("request failed ({}), retrying in {:.1}s", e, wait.as_secs_f64());
+                    tokio::time::sleep(wait).await;
+                    attempt += 1;
+                    switches = 0;
+                    continue;
+                }
+                // the message of a socket that can not be reached already names it
+                Err(e) if e.is::<std::io::Error>() => return Err(e.to_string().into()),
+                Err(e) => return Err(e),
+            };
+            let status = response.status();
+            let wait = retry_after(&response).unwrap_or_else(|| self.retry.backoff(attempt));
+            if let (Some(keys), Some((index, _)), reqwest::StatusCode::TOO_MANY_REQUESTS) = (&self.keys, key, status) {
+                keys.rate_limited(index, wait);
+                if !last && switches + 1 < keys.keys.len() && keys.any_ready() {
+                    tracing::warn!("{} returned {} for key {} of {}, trying another key", url, status, index + 1, keys.keys.len());
+                    switches += 1;
+                    continue;
+                }
+            }
+            if let (Some(oauth), Some(token), reqwest::StatusCode::UNAUTHORIZED) = (&self.oauth, &token, status) {
+                if !last && !refreshed {
+                    tracing::warn!("{} rejected the OAuth token, fetching a new one", url);
+                    oauth.invalidate(token).await;
+                    refreshed = true;
+                    continue;
+                }
+            }
+            let resend = idempotent || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+            if retryable_status(status) && resend && retry && !last {
+                tracing::warn!("{} returned {}, retrying in {:.1}s", url, status, wait.as_secs_f64());
+                tokio::time::sleep(wait).await;
+                attempt += 1;
+                switches = 0;
+                continue;
+            }
+            tracing::info!(status = %status, url = %url, ms = started.elapsed().as_millis() as u64, "response");
+            return check_status(response, &url).await;
+        }
+    }
+
+    pub(crate) async fn post(&self, url: &str, body: &Value) -> Result<reqwest::Response> {
+        self.send(self.http.post(url).json(body), true).await
+    }
+
+    /// post a request that creates or changes something billed or kept, such as a job, a
+    /// file, or an image, which is not sent again once the server may have acted on it
+    pub(crate) async fn post_once(&self, url: &str, body: &Value) -> Result<reqwest::Response> {
+        self.send(self.http.post(url).json(body), false).await
+    }
+
+    pub(crate) async fn get(&self, url: &str) -> Result<reqwest::Response> {
+        self.send(self.http.get(url), true).await
+    }
+
+    pub(crate) async fn delete(&self, url: &str) -> Result<reqwest::Response> {
+        self.send(self.http.delete(url), true).await
+    }
+
+    /// post a body already encoded, such as a multipart upload, like [`Self::post_once`];
+    /// it is kept whole in memory so that it can be sent again when it did not get through
+    pub(crate) async fn post_bytes_once(&self, url: &str, content_type: &str, body: Vec<u8>) -> Result<reqwest::Response> {
+        self.send(self.http.post(url).header(reqwest::header::CONTENT_TYPE, content_type).body(body), false).await
+    }
+
+    /// fetch a file the server pointed to, such as a generated image, without the key
+    /// or headers meant for the server itself
+    pub(crate) async fn download(&self, url: &str) -> Result<Vec<u8>> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| format!("{} is not a valid url: {}", url, e))?;
+        let response = self.http.get(parsed.clone()).send().await?;
+        Ok(check_status(response, &parsed).await?.bytes().await?.to_vec())
+    }
+
+    /// the url chat requests are sent to
+    pub fn chat_url(&self) -> String {
+        self.chat_url_for(&ChatRequest::new("default", Vec::new()))
+    }
+
+    fn chat_url_for(&self, request: &ChatRequest) -> String {
+        self.provider.chat_url(&self.endpoint, request)
+    }
+
+    /// refuse a request for something the provider can not do, before it is sent
+    fn check_supported(&self, request: &ChatRequest) -> Result<()> {
+        if !request.tools.is_empty() && !self.provider.supports_tools() {
+            return Err(format!("The {} provider does not support tools.", self.provider.name()).into());
+        }
+        if request.n > 1 && !self.provider.supports_choices() {
+            return Err(format!("The {} provider can not generate several answers at once.", self.provider.name()).into());
+        }
+        if request.response_format.is_some() && !self.provider.supports_json() {
+            return Err(format!("The {} provider can not be asked for JSON.", self.provider.name()).into());
+        }
+        if request.logprobs.is_some() && !self.provider.supports_logprobs() {
+            return Err(format!("The {} provider does not report log probabilities.", self.provider.name()).into());
+        }
+        Ok(())
+    }
+
+    /// send a chat request and return the stream of response deltas
+    pub async fn chat(&self, request: &ChatRequest) -> Result<ChatStream> {
+        self.check_supported(request)?;
+        let body = self.provider.chat_body(request);
+        let audit = self.audit.as_ref().map(|log| log.chat(&self.endpoint, self.provider.name(), request));
+        let response = self.post(&self.chat_url_for(request), &body).await;
+        self.audited(response, request.stream, audit).await
+    }
+
+    /// open a realtime session with `model` over a WebSocket to the endpoint, with the
+    /// key requests would be sent with
+    pub async fn realtime(&self, model: &str) -> Result<realtime::Session> {
+        if self.provider.name() != "openai" {
+            return Err(format!("The {} provider has no realtime API.", self.provider.name()).into());
+        }
+        if self.socket.is_some() {
+            return Err("Realtime sessions can not be held over a Unix socket.".into());
+        }
+        let key = match &self.oauth {
+            Some(oauth) => Some(oauth.token(&self.http).await?),
+            None => self.keys.as_ref().map(|keys| keys.pick().1.to_string()).or_else(|| self.key.clone()),
+        };
+        realtime::Session::connect(&realtime::url(&self.endpoint, model)?, key.as_deref(), &self.headers).await
+    }
+
+    /// send a chat request and return the HTTP response unparsed, for inspecting
+    /// what a provider actually sends
+    pub async fn chat_response(&self, request: &ChatRequest) -> Result<RawResponse> {
+        self.check_supported(request)?;
+        let body = self.provider.chat_body(request);
+        let audit = self.audit.as_ref().map(|log| log.chat(&self.endpoint, self.provider.name(), request));
+        match self.post(&self.chat_url_for(request), &body).await {
+            Ok(response) => Ok(RawResponse { response, audit, first_chunk: None, decoder: Utf8Decoder::new() }),
+            Err(e) => {
+                if let Some(audit) = audit {
+                    audit.finish(None, None, None, Some(&e.to_string()));
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// a `curl` command that sends the same chat request, with `$QLLM_KEY` in place of
+    /// the key, for reproducing a problem outside qllm
+    ///
+    /// A request signed with AWS credentials is refused, since its signature expires
+    /// minutes after it is made and can not stand for a key.
+    pub fn chat_curl(&self, request: &ChatRequest) -> Result<String> {
+        if self.provider.name() == "bedrock" && self.key.is_none() && self.keys.is_none() {
+            return Err("Bedrock requests are signed with AWS credentials for the moment they are sent, so they can not be \
+                        written as a curl command; set a Bedrock API key, or use curl's --aws-sigv4."
+                .into());
+        }
+        if self.oauth.is_some() {
+            tracing::warn!("the endpoint takes an OAuth token, so $QLLM_KEY in the command must be a current token, not a key");
+        } else if let Some(keys) = self.keys.as_ref().filter(|keys| keys.keys.len() > 1) {
+            tracing::warn!("$QLLM_KEY in the command stands for one of the {} keys of the pool", keys.keys.len());
+        }
+        let body = self.provider.chat_body(request);
+        let built = self.build(self.http.post(self.chat_url_for(request)).json(&body))?;
+        Ok(curl_command(&built, request.stream))
+    }
+
+    /// wrap a streamed response, or parse a complete one into the same events
+    async fn stream_or_whole(&self, response: reqwest::Response, stream: bool) -> Result<ChatStream> {
+        if stream {
+            return Ok(ChatStream::new(response, self.provider.clone(), self.timeouts.stall));
+        }
+        let text = response.text().await?;
+        let parsed: Value = serde_json::from_str(&text)
+            .map_err(|e| format!("could not parse the response as JSON ({}): {}", e, text.trim()))?;
+        let events = self.provider.parse_response(&parsed)?;
+        Ok(ChatStream::from_events(events, self.provider.clone()))
+    }
+
+    /// the stream of a response, whose audit entry is written once it is read to the
+    /// end, or at once for a request that failed
+    async fn audited(&self, response: Result<reqwest::Response>, stream: bool, audit: Option<Pending>) -> Result<ChatStream> {
+        let result = match response {
+            Ok(response) => self.stream_or_whole(response, stream).await,
+            Err(e) => Err(e),
+        };
+        match (result, audit) {
+            (Ok(mut stream), audit) => {
+                stream.audit = audit;
+                Ok(stream)
+            }
+            (Err(e), Some(audit)) => {
+                audit.finish(None, None, None, Some(&e.to_string()));
+                Err(e)
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    /// a stream that plays back an answer received earlier, such as one from the cache
+    pub fn replay(&self, events: Vec<StreamEvent>) -> ChatStream {
+        ChatStream::from_events(events, self.provider.clone())
+    }
+
+    /// send a text completion request and return the stream of response deltas
+    pub async fn complete(&self, request: &CompletionRequest) -> Result<ChatStream> {
+        let (url, body) = self.provider.completion(&self.endpoint, request)
+            .ok_or_else(|| format!("The {} provider has no text completion API.", self.provider.name()))?;
+        let audit = self.audit.as_ref().map(|log| log.completion(&self.endpoint, self.provider.name(), request));
+        let response = self.post(&url, &body).await;
+        self.audited(response, request.stream, audit).await
+    }
+
+    /// embed each of the inputs, returning one vector per input
+    pub async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f64>>> {
+        let url = api_url(&self.endpoint, "embeddings");
+        let mut body = json!({ "input": inputs });
+        if model != "default" {
+            body["model"] = json!(model);
+        }
+        let response = self.post(&url, &body).await?;
+        let parsed: Value = response.json().await?;
+        let data = parsed["data"].as_array().ok_or("embedding response has no data array")?;
+        let mut vectors = vec![Vec::new(); inputs.len()];
+        for (i, item) in data.iter().enumerate() {
+            let index = item["index"].as_u64().map(|n| n as usize).unwrap_or(i);
+            let embedding = item["embedding"].as_array().ok_or("embedding response item has no vector")?;
+            if let Some(slot) = vectors.get_mut(index) {
+                *slot = embedding.iter().filter_map(Value::as_f64).collect();
+            }
+        }
+        Ok(vectors)
+    }
+
+    /// score each document for relevance to the query with a rerank endpoint, as served by
+    /// Cohere, Jina, vLLM and llama.cpp, returning the index and score of each, best first
+    pub async fn rerank(&self, model: &str, query: &str, documents: &[String]) -> Result<Vec<(usize, f64)>> {
+        let url = api_url(&self.endpoint, "rerank");
+        let mut body = json!({ "query": query, "documents": documents });
+        if model != "default" {
+            body["model"] = json!(model);
+        }
+        let response = self.post(&url, &body).await?;
+        let parsed: Value = response.json().await?;
+        let results = parsed["results"].as_array().ok_or("rerank response has no results array")?;
+        let mut scores = Vec::with_capacity(results.len());
+        for item in results {
+            let index = item["index"].as_u64().ok_or("rerank result has no index")? as usize;
+            // older servers call it score
+            let score = item["relevance_score"].as_f64().or_else(|| item["score"].as_f64()).ok_or("rerank result has no score")?;
+            scores.push((index, score));
+        }
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        Ok(scores)
+    }
+
+    /// classify each input with the `/moderations` endpoint of OpenAI or Mistral
+    pub async fn moderate(&self, model: &str, inputs: &[String]) -> Result<Vec<Moderation>> {
+        let url = api_url(&self.endpoint, "moderations");
+        let mut body = json!({ "input": inputs });
+        if model != "default" {
+            body["model"] = json!(model);
+        }
+        let response = self.post(&url, &body).await?;
+        let parsed: Value = response.json().await?;
+        let results = parsed["results"].as_array().ok_or("moderation response has no results array")?;
+        let mut moderations = Vec::with_capacity(results.len());
+        for result in results {
+            let mut scores: Vec<(String, f64)> = result["category_scores"].as_object()
+                .ok_or("moderation result has no category scores")?
+                .iter()
+                .filter_map(|(name, score)| Some((name.clone(), score.as_f64()?)))
+                .collect();
+            scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+            let categories = result["categories"].as_object().into_iter().flatten()
+                .filter(|(_, flagged)| flagged.as_bool() == Some(true))
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+            // Mistral sends no overall verdict, only the categories
+            let flagged = result["flagged"].as_bool().unwrap_or(!categories.is_empty());
+            moderations.push(Moderation { flagged, scores, categories, raw: result.clone() });
+        }
+        Ok(moderations)
+    }
+
+    /// list the model ids served by the endpoint
+    pub async fn models(&self) -> Result<Vec<String>> {
+        Ok(self.model_entries().await?.into_iter().map(|(id, _)| id).collect())
+    }
+
+    /// list the models served by the endpoint, each id with what the server says about it
+    pub async fn model_entries(&self) -> Result<Vec<(String, Value)>> {
+        let url = self.provider.models_url(&self.endpoint);
+        let response = self.get(&url).await?;
+        let parsed: Value = response.json().await?;
+        Ok(self.provider.parse_models(&parsed))
+    }
+
+    /// the context window of `model` in tokens, when the server says what it is
+    pub async fn context_length(&self, model: &str) -> Result<Option<u64>> {
+        if let Some((url, body)) = self.provider.model_info(&self.endpoint, model) {
+            let info: Value = self.post(&url, &body).await?.json().await?;
+            return Ok(self.provider.context_length(&info));
+        }
+        let entries = self.model_entries().await?;
+        // a server with a single model answers to any name
+        let info = match entries.iter().find(|(id, _)| id == model.trim_start_matches("models/")) {
+            Some((_, info)) => info,
+            None if entries.len() == 1 => &entries[0].1,
+            None => return Ok(None),
+        };
+        // llama.cpp lists the context the model was trained with, /props the one it was started with
+        if info["owned_by"] == "llamacpp" {
+            let root = api_url(&self.endpoint, "");
+            let root = root.trim_end_matches('/');
+            let url = format!("{}/props", root.strip_suffix("/v1").unwrap_or(root));
+            let props: Value = self.get(&url).await?.json().await?;
+            if let Some(n_ctx) = props["default_generation_settings"]["n_ctx"].as_u64() {
+                return Ok(Some(n_ctx));
+            }
+        }
+        Ok(self.provider.context_length(info))
+    }
+
+    /// send a chat request and collect the whole streamed answer
+    pub async fn chat_text(&self, request: &ChatRequest) -> Result<String> {
+        let mut stream = self.chat(request).await?;
+        let mut text = String::new();
+        while let Some(delta) = stream.next_delta().await? {
+            text.push_str(&delta);
+        }
+        Ok(text)
+    }
+}
+
+/// turn an unsuccessful response into an error carrying the server's message
+async fn check_status(response: reqwest::Response, url: &reqwest::Url) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<Value>(&body).ok()
+        .and_then(|v| v["error"]["message"].as_str().or(v["error"].as_str()).or(v["message"].as_str()).map(str::to_string))
+        .unwrap_or(body);
+    Err(Box::new(HttpError { url: url.to_string(), status, message: message.trim().to_string() }))
+}
+
+/// a response with an error status, for callers that act on the status
+#[derive(Clone)]
+pub struct HttpError {
+    pub url: String,
+    pub status: reqwest::StatusCode,
+    /// the server's message, from the error object of the body if it has one
+    pub message: String,
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} returned {}: {}", self.url, self.status, self.message)
+    }
+}
+
+// shown like the message it is, as errors built from strings are
+impl std::fmt::Debug for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// the streamed answer of a chat request
+pub struct ChatStream {
+    /// the response still being read, None once it is exhausted or for a whole response
+    response: Option<reqwest::Response>,
+    /// give up when no data arrives for this long
+    stall: Option<Duration>,
+    provider: Arc<dyn Provider>,
+    framer: Framer,
+    events: VecDeque<StreamEvent>,
+    first: bool,
+    done: bool,
+    usage: Option<Value>,
+    finish_reason: Option<String>,
+    tool_calls: ToolCalls,
+    /// when the first piece of content arrived
+    first_token: Option<Instant>,
+    /// the log probabilities of the first choice's tokens so far
+    logprobs: Vec<TokenLogprob>,
+    /// the choice the events being read belong to
+    choice: usize,
+    /// the text of the choices after the first, when more than one was asked for
+    choices: BTreeMap<usize, String>,
+    /// splits a leading `<think>` span out of the first choice's content as events are queued
+    think: ThinkSplitter,
+    /// the choice the events being queued belong to
+    queued_choice: usize,
+    /// the reasoning read so far
+    reasoning: String,
+    /// text to return before the answer, for a prefilled assistant message
+    prefill: Option<String>,
+    /// when the response began to be read, until the stream's stats are logged
+    opened: Option<Instant>,
+    /// the audit log entry of the request, written once the stream is finished
+    audit: Option<Pending>,
+}
+
+/// a piece of streamed text
+#[derive(Clone, Debug, PartialEq)]
+pub enum Delta {
+    Answer(String),
+    Reasoning(String),
+}
+
+impl ChatStream {
+    fn new(response: reqwest::Response, provider: Arc<dyn Provider>, stall: Option<Duration>) -> Self {
+        ChatStream {
+            response: Some(response),
+            stall,
+            framer: Framer::new(provider.framing()),
+            provider,
+            events: VecDeque::new(),
+            first: true,
+            done: false,
+            usage: None,
+            finish_reason: None,
+            tool_calls: ToolCalls::default(),
+            first_token: None,
+            logprobs: Vec::new(),
+            choice: 0,
+            choices: BTreeMap::new(),
+            think: ThinkSplitter::new(),
+            queued_choice: 0,
+            reasoning: String::new(),
+            prefill: None,
+            opened: Some(Instant::now()),
+            audit: None,
+        }
+    }
+
+    /// a stream over the events of a response that has already been received in full
+    fn from_events(events: Vec<StreamEvent>, provider: Arc<dyn Provider>) -> Self {
+        let mut stream = ChatStream {
+            response: None,
+            stall: None,
+            framer: Framer::new(provider.framing()),
+            provider,
+            events: VecDeque::new(),
+            first: true,
+            done: true,
+            usage: None,
+            finish_reason: None,
+            tool_calls: ToolCalls::default(),
+            first_token: None,
+            logprobs: Vec::new(),
+            choice: 0,
+            choices: BTreeMap::new(),
+            think: ThinkSplitter::new(),
+            queued_choice: 0,
+            reasoning: String::new(),
+            prefill: None,
+            opened: None,
+            audit: None,
+        };
+        stream.queue(events, true);
+        stream
+    }
+
+    /// queue parsed events, splitting a leading `<think>` span out of the first choice's content;
+    /// `end` releases any text held back in case it started a tag
+    fn queue(&mut self, events: Vec<StreamEvent>, end: bool) {
+        let mut split = Vec::new();
+        for event in events {
+            match event {
+                StreamEvent::Choice(index) => {
+                    self.queued_choice = index;
+                    split.push(event);
+                }
+                StreamEvent::Content(text) if self.queued_choice == 0 => self.think.push(&text, &mut split),
+                StreamEvent::Done => {
+                    self.think.finish(&mut split);
+                    split.push(event);
+                }
+                _ => split.push(event),
+            }
+        }
+        if end {
+            self.think.finish(&mut split);
+        }
+        self.events.extend(split);
+    }
+
+    /// return `text` as the first piece of the answer, so that the continuation of a
+    /// prefilled assistant message reads as a whole
+    pub fn prefill(&mut self, text: &str) {
+        self.prefill = Some(text.to_string());
+    }
+
+    /// the model's reasoning read so far, kept apart from the answer
+    pub fn reasoning(&self) -> &str {
+        &self.reasoning
+    }
+
+    /// the token usage reported by the server, once it has been received
+    pub fn usage(&self) -> Option<&Value> {
+        self.usage.as_ref()
+    }
+
+    /// why generation stopped, once the server has said
+    pub fn finish_reason(&self) -> Option<&str> {
+        self.finish_reason.as_deref()
+    }
+
+    /// when the first piece of content arrived, for measuring the time to first token
+    pub fn first_token_at(&self) -> Option<Instant> {
+        self.first_token
+    }
+
+    /// the tool calls the model has made so far, complete once the stream is finished
+    pub fn tool_calls(&self) -> Vec<ToolCall> {
+        self.tool_calls.calls()
+    }
+
+    /// the log probabilities of the tokens read so far, when the request asked for them
+    pub fn logprobs(&self) -> &[TokenLogprob] {
+        &self.logprobs
+    }
+
+    /// the text of every choice after the first, by index, complete once the stream is
+    /// finished; the first is what [`ChatStream::next_delta`] returns
+    pub fn choices(&self) -> &BTreeMap<usize, String> {
+        &self.choices
+    }
+
+    /// return the next parsed event, or None once the stream is finished
+    pub async fn next_event(&mut self) -> Result<Option<StreamEvent>> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                if self.choice > 0 {
+                    // later choices are collected, not passed on
+                    match event {
+                        StreamEvent::Content(text) => {
+                            self.choices.entry(self.choice).or_default().push_str(&text);
+                            continue;
+                        }
+                        StreamEvent::Reasoning(_) | StreamEvent::ToolCall(_) | StreamEvent::Logprobs(_) | StreamEvent::Finish(_) => continue,
+                        _ => {}
+                    }
+                }
+                match &event {
+                    StreamEvent::Usage(usage) => merge_usage(&mut self.usage, usage),
+                    StreamEvent::Finish(reason) => self.finish_reason = Some(reason.clone()),
+                    StreamEvent::ToolCall(delta) => self.tool_calls.push(delta),
+                    StreamEvent::Done => {
+                        self.done = true;
+                        self.response = None;
+                    }
+                    StreamEvent::Content(text) => {
+                        self.first_token.get_or_insert_with(Instant::now);
+                        if let Some(audit) = &mut self.audit {
+                            audit.push(text);
+                        }
+                    }
+                    StreamEvent::Reasoning(text) => {
+                        self.first_token.get_or_insert_with(Instant::now);
+                        self.reasoning.push_str(text);
+                    }
+                    StreamEvent::Choice(index) => self.choice = *index,
+                    StreamEvent::Logprobs(logprobs) => self.logprobs.extend(logprobs.iter().cloned()),
+                }
+                return Ok(Some(event));
+            }
+            if self.done {
+                if let Some(opened) = self.opened.take() {
+                    let (prompt, completion) = self.usage.as_ref().map(token_counts).unwrap_or_default();
+                    tracing::info!(
+                        finish_reason = self.finish_reason.as_deref(),
+                        prompt_tokens = prompt,
+                        completion_tokens = completion,
+                        first_token_ms = self.first_token.map(|at| at.duration_since(opened).as_millis() as u64),
+                        ms = opened.elapsed().as_millis() as u64,
+                        "stream finished"
+                    );
+                }
+                if let Some(audit) = self.audit.take() {
+                    audit.finish(self.usage.as_ref(), self.finish_reason.as_deref(), self.first_token, None);
+                }
+                return Ok(None);
+            }
+            let chunk = match self.response.as_mut() {
+                Some(response) => match self.stall {
+                    Some(stall) => tokio::time::timeout(stall, response.chunk()).await
+                        .map_err(|_| format!("the server sent nothing for {}s, giving up", stall.as_secs_f64()))??,
+                    None => response.chunk().await?,
+                },
+                None => None,
+            };
+            let payloads = match chunk {
+                Some(bytes) => self.framer.feed(&bytes),
+                None => {
+                    self.done = true;
+                    self.response = None;
+                    self.framer.finish()
+                }
+            };
+            for data in payloads {
+                let events = self.provider.parse_event(&data)?;
+                self.queue(events, false);
+            }
+            if self.done {
+                self.queue(Vec::new(), true);
+            }
+        }
+    }
+
+    /// return the next piece of the answer or the reasoning, or None once the stream is finished
+    pub async fn next_piece(&mut self) -> Result<Option<Delta>> {
+        if let Some(text) = self.prefill.take() {
+            // the continuation keeps its leading space
+            self.first = false;
+            return Ok(Some(Delta::Answer(text)));
+        }
+        while let Some(event) = self.next_event().await? {
+            match event {
+                StreamEvent::Content(text) => {
+                    let mut text = text.as_str();
+                    if self.first {
+                        // trim the leading space from the first response
+                        text = text.trim_start();
+                        if text.is_empty() {
+                            continue;
+                        }
+                        self.first = false;
+                    }
+                    return Ok(Some(Delta::Answer(text.to_string())));
+                }
+                StreamEvent::Reasoning(text) => return Ok(Some(Delta::Reasoning(text))),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// return the next piece of content text, or None once the stream is finished
+    pub async fn next_delta(&mut self) -> Result<Option<String>> {
+        while let Some(piece) = self.next_piece().await? {
+            if let Delta::Answer(text) = piece {
+                return Ok(Some(text));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// a chat response as the provider sent it, whose body is audited once it is read to the end
+#[derive(Debug)]
+pub struct RawResponse {
+    response: reqwest::Response,
+    audit: Option<Pending>,
+    /// when the first chunk of the body arrived
+    first_chunk: Option<Instant>,
+    decoder: Utf8Decoder,
+}
+
+impl RawResponse {
+    /// the next chunk of the body, or None at its end
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        let chunk = match self.response.chunk().await {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                if let Some(audit) = self.audit.take() {
+                    audit.finish(None, None, self.first_chunk, Some(&e.to_string()));
+                }
+                return Err(e.into());
+            }
+        };
+        match chunk {
+            Some(chunk) => {
+                self.first_chunk.get_or_insert_with(Instant::now);
+                if let Some(audit) = &mut self.audit {
+                    // the body is kept as the answer, events and all
+                    audit.push(&self.decoder.decode(&chunk));
+                }
+                Ok(Some(chunk.to_vec()))
+            }
+            None => {
+                if let Some(mut audit) = self.audit.take() {
+                    audit.push(&self.decoder.finish());
+                    audit.finish(None, None, self.first_chunk, None);
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Drop for RawResponse {
+    fn drop(&mut self) {
+        if let Some(audit) = self.audit.take() {
+            audit.finish(None, None, self.first_chunk, Some("the answer was not read to the end"));
+        }
+    }
+}
+
+impl Drop for ChatStream {
+    /// a stream given up on before its end, after an error or because the rest of the
+    /// answer was not wanted, is still audited
+    fn drop(&mut self) {
+        if let Some(audit) = self.audit.take() {
+            audit.finish(self.usage.as_ref(), self.finish_reason.as_deref(), self.first_token, Some("the answer was not read to the end"));
+        }
+    }
+}
+
+/// headers that carry credentials, whose values `--dump` leaves out
+const SECRET_HEADERS: &[&str] = &[
+    "authorization", "proxy-authorization", "x-api-key", "api-key", "x-goog-api-key", "x-amz-security-token", "cookie",
+];
+
+/// print a request to stderr as `> ` lines, then its body, pretty when it is JSON
+fn dump_request(request: &reqwest::Request) {
+    let mut url = request.url().clone();
+    // Gemini can take the key as a query parameter
+    if let Some(query) = url.query().map(str::to_string) {
+        let query: Vec<&str> = query.split('&').map(|pair| if pair.starts_with("key=") { "key=[redacted]" } else { pair }).collect();
+        url.set_query(Some(&query.join("&")));
+    }
+    let mut out = format!("> {} {}\n", request.method(), url);
+    for (name, value) in request.headers() {
+        let value = value.to_str().unwrap_or("[not text]");
+        let value = if SECRET_HEADERS.contains(&name.as_str()) {
+            // keep the scheme, as in `Bearer [redacted]`
+            match value.split_once(' ') {
+                Some((scheme, _)) => format!("{} [redacted]", scheme),
+                None => "[redacted]".to_string(),
+            }
+        } else {
+            value.to_string()
+        };
+        out.push_str(&format!("> {}: {}\n", name, value));
+    }
+    if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+        let text = match serde_json::from_slice::<Value>(body) {
+            Ok(json) => serde_json::to_string_pretty(&json).unwrap_or_default(),
+            Err(_) => String::from_utf8_lossy(body).into_owned(),
+        };
+        out.push_str(&format!(">\n{}\n", text));
+    }
+    eprint!("{}", out);
+}
+
+/// a request as a `curl` command, with the values of the credential headers and a `key`
+/// in the url left to the shell as `$QLLM_KEY`
+fn curl_command(request: &reqwest::Request, stream: bool) -> String {
+    // single quotes keep everything literal, so only they need escaping
+    let quote = |text: &str| format!("'{}'", text.replace('\'', "'\\''"));
+    let mut url = request.url().to_string();
+    if let Some(query) = request.url().query() {
+        let redacted: Vec<&str> = query.split('&').map(|pair| if pair.starts_with("key=") { "key=$QLLM_KEY" } else { pair }).collect();
+        url = url.replace(query, &redacted.join("&"));
+    }
+    let url = if url.contains("$QLLM_KEY") { format!("\"{}\"", url) } else { quote(&url) };
+    let mut lines = vec![format!("curl{} -X {} {}", if stream { " -N" } else { "" }, request.method(), url)];
+    for (name, value) in request.headers() {
+        let value = value.to_str().unwrap_or_default();
+        lines.push(match (SECRET_HEADERS.contains(&name.as_str()), value.split_once(' ')) {
+            (true, Some((scheme, _))) => format!("  -H \"{}: {} $QLLM_KEY\"", name, scheme),
+            (true, None) => format!("  -H \"{}: $QLLM_KEY\"", name),
+            (false, _) => format!("  -H {}", quote(&format!("{}: {}", name, value))),
+        });
+    }
+    if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+        let text = match serde_json::from_slice::<Value>(body) {
+            Ok(json) => serde_json::to_string_pretty(&json).unwrap_or_default(),
+            Err(_) => String::from_utf8_lossy(body).into_owned(),
+        };
+        lines.push(format!("  --data-raw {}", quote(&text)));
+    }
+    lines.join(" \\\n")
+}
+
+/// print the status and headers of a response to stderr as `< ` lines
+fn dump_response(response: &reqwest::Response) {
+    let mut out = format!("< {:?} {}\n", response.version(), response.status());
+    for (name, value) in response.headers() {
+        out.push_str(&format!("< {}: {}\n", name, value.to_str().unwrap_or("[not text]")));
+    }
+    eprint!("{}", out);
+}
+
+/// the prompt and completion token counts in a usage report, whichever
+/// provider's names it uses
+pub fn token_counts(usage: &Value) -> (Option<u64>, Option<u64>) {
+    let count = |names: &[&str]| names.iter().find_map(|name| usage[*name].as_u64());
+    (
+        count(&["prompt_tokens", "input_tokens", "promptTokenCount", "inputTokens"]),
+        count(&["completion_tokens", "output_tokens", "candidatesTokenCount", "outputTokens"]),
+    )
+}
+
+/// providers report usage piecemeal (Anthropic sends input and output tokens in
+/// separate events), so later fields are merged over earlier ones
+fn merge_usage(usage: &mut Option<Value>, update: &Value) {
+    match (usage.as_mut().and_then(Value::as_object_mut), update.as_object()) {
+        (Some(current), Some(update)) => {
+            for (key, value) in update {
+                current.insert(key.clone(), value.clone());
+            }
+        }
+        _ => *usage = Some(update.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proxy_urls() {
+        assert_eq!(proxy_url("http://user:pw@proxy:3128").unwrap(), "http://user:pw@proxy:3128");
+        assert_eq!(proxy_url("socks5://127.0.0.1:1080").unwrap(), "socks5://127.0.0.1:1080");
+        assert_eq!(proxy_url("socks://127.0.0.1:1080").unwrap(), "socks5h://127.0.0.1:1080");
+        assert!(proxy_url("socks4://127.0.0.1:1080").is_err());
+        for url in ["socks://127.0.0.1:1080", "socks5h://user:pw@127.0.0.1:1080"] {
+            assert!(reqwest::Proxy::all(proxy_url(url).unwrap()).is_ok(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn knows_which_failures_never_reached_the_server() {
+        let refused: crate::Error = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused").into();
+        assert!(unsent(&refused));
+        let timed_out: crate::Error = std::io::Error::new(std::io::ErrorKind::TimedOut, "the request timed out").into();
+        assert!(!unsent(&timed_out));
+        assert!(!unsent(&"the server broke".into()));
+    }
+}
@@ -0,0 +1,37 @@
+//! The system clipboard, for `--paste` and `--copy`, through arboard on X11, Wayland,
+//! macOS and Windows.
+//!
+//! On X11 and Wayland the clipboard belongs to the program that copied, so text copied
+//! by qllm outlives it only where a clipboard manager takes it over as qllm exits, as
+//! most desktops have one do.
+
+use crate::Result;
+
+/// the text on the clipboard
+#[cfg(feature = "clipboard")]
+pub fn paste() -> Result<String> {
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| format!("Could not open the clipboard: {}", e))?;
+    match clipboard.get_text() {
+        Ok(text) => Ok(text),
+        Err(arboard::Error::ContentNotAvailable) => Err("There is no text on the clipboard.".into()),
+        Err(e) => Err(format!("Could not read the clipboard: {}", e).into()),
+    }
+}
+
+/// put `text` on the clipboard
+#[cfg(feature = "clipboard")]
+pub fn copy(text: &str) -> Result<()> {
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| format!("Could not open the clipboard: {}", e))?;
+    clipboard.set_text(text).map_err(|e| format!("Could not copy to the clipboard: {}", e))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn paste() -> Result<String> {
+    Err("This qllm was built without the clipboard feature.".into())
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn copy(_text: &str) -> Result<()> {
+    Err("This qllm was built without the clipboard feature.".into())
+}
@@ -0,0 +1,176 @@
+use serde_json::{json, Value};
+use std::time::Instant;
+use qllm::mcp::{self, McpServer};
+use qllm::pricing::Prices;
+use qllm::{History, Message, ToolCall};
+
+use crate::cli::AgentArgs;
+use crate::{client, config, confirm, exchange_for, model, print_stream, read_prompt, read_stdin, routing, spinner, stream, Budget, ChatOptions, Display};
+
+const AGENT_SYSTEM: &str = "Help the user with their task. You can run shell commands with the run_shell tool; \
+the user confirms each one before it runs. When the task is done, answer without calling a tool.";
+
+/// tool output beyond this many bytes is cut off before it goes back to the model
+const MAX_OUTPUT: usize = 16 * 1024;
+
+/// the definition of the builtin shell tool
+fn run_shell_tool() -> Value {
+    json!({
+        "type": "function",
+        "function": {
+            "name": "run_shell",
+            "description": "Run a command with `sh -c` and return its exit status, stdout, and stderr.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "command": { "type": "string", "description": "the shell command to run" },
+                },
+                "required": ["command"],
+            },
+        },
+    })
+}
+
+/// loop between the model and its tools until the model answers without a tool call
+pub async fn run(args: AgentArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let servers = match config.get("mcp_servers") {
+        Some(servers) if !args.no_mcp => mcp::connect_all(servers).await?,
+        _ => Vec::new(),
+    };
+    let mut tools = vec![run_shell_tool()];
+    tools.extend(servers.iter().flat_map(McpServer::functions));
+    let mut agent = Agent { servers, yes: args.yes };
+    let options = ChatOptions {
+        model: model(&args.common, &config),
+        system: args.system.clone().or_else(|| args.system_file.clone()).unwrap_or_else(|| AGENT_SYSTEM.to_string()),
+        template_prompt: None,
+        params: args.sampler.to_params(config.sampler(args.sampler.preset.as_deref())?),
+        stream: stream(args.no_stream, &config),
+        tools,
+        logprobs: None,
+        response_format: None,
+        display: if args.render { Display::Render } else { Display::Raw },
+        show_reasoning: args.show_reasoning,
+        wrap: None,
+        routing: routing(&[], &[], &config),
+        prices: Prices::new(config.get("prices")),
+        budget: Budget::new(args.max_cost, &config),
+    };
+    let task = read_prompt(&args.prompt, read_stdin(args.stdin).await?.as_deref(), args.file.as_deref()).await?;
+    let mut messages = vec![Message::system(&options.system), Message::user(&task)];
+
+    let started = Instant::now();
+    let timestamp = qllm::time::now();
+    // the cost of the turns before the last, which the history would otherwise miss
+    let mut cost: Option<f64> = None;
+    for _ in 0..args.max_iterations {
+        let request = options.request(messages.clone());
+        options.check_budget(&request)?;
+        spinner::start(&format!("waiting for {}", request.model));
+        let mut stream = client.chat(&request).await?;
+        let answer = print_stream(&mut stream, options.display, options.wrap, options.show_reasoning, None).await?;
+        let calls = stream.tool_calls();
+        if calls.is_empty() {
+            println!();
+            if !args.no_history {
+                let history = History::open_default();
+                let mut exchange = exchange_for(&client, &request, &answer, stream.usage(), &options.prices);
+                exchange.prompt = task;
+                exchange.cost = match (cost, exchange.cost) {
+                    (Some(earlier), Some(last)) => Some(earlier + last),
+                    (earlier, last) => earlier.or(last),
+                };
+                exchange.conversation = history.new_conversation()?;
+                exchange.timestamp = timestamp;
+                exchange.duration_ms = started.elapsed().as_millis() as u64;
+                history.record(&mut exchange)?;
+            }
+            return Ok(());
+        }
+        if !answer.is_empty() {
+            println!();
+        }
+        if let Some(turn) = stream.usage().and_then(|usage| options.prices.cost(&request.model, usage)) {
+            cost = Some(cost.unwrap_or(0.0) + turn);
+        }
+
+        messages.push(Message::tool_calls(&answer, calls.clone()));
+        for call in calls {
+            let result = agent.handle(&call).await;
+            messages.push(Message::tool(&call.id, &result));
+        }
+    }
+    Err(format!("Gave up after {} iterations without a final answer.", args.max_iterations).into())
+}
+
+/// the agent with its tools, the builtin shell and those of the configured MCP servers
+struct Agent {
+    servers: Vec<McpServer>,
+    yes: bool,
+}
+
+impl Agent {
+    /// run one tool call, returning the text the model gets back
+    async fn handle(&mut self, call: &ToolCall) -> String {
+        if call.name == "run_shell" {
+            return run_shell(call, self.yes).await;
+        }
+        let Some((server, tool)) = mcp::find(&mut self.servers, &call.name) else {
+            return format!("error: there is no tool named {}", call.name);
+        };
+        let arguments = match call.parsed_arguments() {
+            Ok(arguments) => arguments,
+            Err(e) => return format!("error: {}", e),
+        };
+        eprintln!("{} {}", call.name, arguments);
+        if !self.yes && !confirm("call this tool? [y/N] ") {
+            return "the user declined this tool call".to_string();
+        }
+        match server.call_tool(&tool, arguments).await {
+            Ok(text) => {
+                eprintln!("{}", text);
+                truncate(text)
+            }
+            Err(e) => format!("error: {}", e),
+        }
+    }
+}
+
+/// run a command for the builtin shell tool
+async fn run_shell(call: &ToolCall, yes: bool) -> String {
+    let command = match call.parsed_arguments() {
+        Ok(arguments) => match arguments["command"].as_str() {
+            Some(command) => command.to_string(),
+            None => return "error: run_shell needs a `command` string".to_string(),
+        },
+        Err(e) => return format!("error: {}", e),
+    };
+
+    eprintln!("$ {}", command);
+    if !yes && !confirm("run this command? [y/N] ") {
+        return "the user declined to run this command".to_string();
+    }
+    let output = match tokio::process::Command::new("sh").arg("-c").arg(&command).output().await {
+        Ok(output) => output,
+        Err(e) => return format!("error: could not run the command: {}", e),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    eprint!("{}{}", stdout, stderr);
+    let result = format!("exit status: {}\nstdout:\n{}\nstderr:\n{}", output.status, stdout, stderr);
+    truncate(result)
+}
+
+fn truncate(mut text: String) -> String {
+    if text.len() > MAX_OUTPUT {
+        let mut end = MAX_OUTPUT;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("\n[output truncated]");
+    }
+    text
+}
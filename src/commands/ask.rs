@@ -0,0 +1,48 @@
+use qllm::attach;
+use qllm::index::{Chunk, Index};
+
+use crate::cli::AskArgs;
+use crate::{chat, client, config};
+
+/// embed the question, find the passages of the index nearest to it, and ask the model to
+/// answer from them, with the passages listed on stderr as the sources cited
+pub async fn run(args: AskArgs) -> Result<(), qllm::Error> {
+    let AskArgs { index, passages, chat: mut args } = args;
+    let question = args.prompt.join(" ");
+    if question.trim().is_empty() {
+        return Err("Ask a question, as in `qllm ask --index NAME \"question\"`.".into());
+    }
+    let index = Index::load(&index)?;
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let vector = client.embed(&index.model, std::slice::from_ref(&question)).await?.into_iter().next().unwrap_or_default();
+    let found: Vec<Chunk> = index.search(&vector, passages)?.into_iter()
+        .enumerate()
+        .map(|(i, (score, chunk))| {
+            eprintln!("[{}] {} ({:.2})", i + 1, chunk.source(), score);
+            chunk
+        })
+        .collect();
+    if found.is_empty() {
+        return Err(format!("The index {} is empty.", index.name).into());
+    }
+    args.prompt = vec![prompt(&question, &found)];
+    chat(args).await
+}
+
+/// the question after the passages, numbered for citing
+fn prompt(question: &str, passages: &[Chunk]) -> String {
+    let blocks: Vec<String> = passages.iter().enumerate()
+        .map(|(i, chunk)| {
+            let fence = attach::fence(&chunk.text);
+            let language = attach::language(&chunk.path);
+            format!("[{}] {}\n{}{}\n{}\n{}", i + 1, chunk.source(), fence, language, chunk.text.trim_end(), fence)
+        })
+        .collect();
+    format!(
+        "Answer the question from the numbered passages below, citing the ones you use as [1], [2] and so on. \
+         If they do not hold the answer, say so.\n\n{}\n\nQuestion: {}",
+        blocks.join("\n\n"),
+        question,
+    )
+}
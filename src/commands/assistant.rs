@@ -0,0 +1,60 @@
+use std::io::Write;
+use std::time::Instant;
+use qllm::assistants::{Assistants, RunOptions};
+
+use crate::cli::AssistantArgs;
+use crate::{client, config, read_prompt, read_stdin, stream, theme};
+
+/// add the prompt to a thread and print the assistant's answer to it
+pub async fn run(args: AssistantArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let assistant_id = args.assistant_id.clone()
+        .or_else(|| config.str("assistant_id").map(str::to_string))
+        .ok_or("Which assistant? Give its id with --assistant-id or as `assistant_id` in the config.")?;
+    let prompt = read_prompt(&args.prompt, read_stdin(args.stdin).await?.as_deref(), None).await?;
+    if prompt.trim().is_empty() {
+        return Err("Nothing to ask. Give the message as arguments or pass -c to read it from stdin.".into());
+    }
+    let assistants = Assistants::new(client(&args.common, &config)?)?;
+    // the assistant has its own model, which only -m replaces
+    let options = RunOptions { model: args.common.model.clone(), additional_instructions: args.system.clone() };
+
+    let thread = match &args.thread {
+        Some(thread) => thread.clone(),
+        None => {
+            let thread = assistants.create_thread().await?;
+            eprintln!("{}Continue this conversation with --thread {}{}", theme::err().stats, thread, theme::err().reset);
+            thread
+        }
+    };
+    assistants.add_message(&thread, prompt.trim()).await?;
+
+    let started = Instant::now();
+    let mut first_token = None;
+    let mut stdout = std::io::stdout();
+    let run = if stream(args.no_stream, &config) {
+        let mut events = assistants.stream_run(&thread, &assistant_id, &options).await?;
+        while let Some(delta) = events.next_delta().await? {
+            first_token.get_or_insert_with(Instant::now);
+            write!(stdout, "{}", delta)?;
+            stdout.flush()?;
+        }
+        writeln!(stdout)?;
+        events.run().cloned()
+    } else {
+        let (run, text) = assistants.run(&thread, &assistant_id, &options).await?;
+        writeln!(stdout, "{}", text)?;
+        Some(run)
+    };
+
+    if args.stats {
+        let usage = run.as_ref().and_then(|run| run.usage.as_ref());
+        let count = |name: &str| usage.and_then(|usage| usage[name].as_u64()).map(|n| n.to_string()).unwrap_or_else(|| "?".to_string());
+        let mut stats = format!("{} prompt + {} completion tokens, {:.2}s", count("prompt_tokens"), count("completion_tokens"), started.elapsed().as_secs_f64());
+        if let Some(first) = first_token {
+            stats.push_str(&format!(", {:.2}s to first token", first.duration_since(started).as_secs_f64()));
+        }
+        eprintln!("{}{}{}", theme::err().stats, stats, theme::err().reset);
+    }
+    Ok(())
+}
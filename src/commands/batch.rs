@@ -0,0 +1,235 @@
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::AsyncReadExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use qllm::pricing::Prices;
+use qllm::{ChatRequest, Exchange, Message, QllmClient};
+
+use crate::cli::BatchArgs;
+use crate::{client, config, exchange_for, model, record_spend, stream, Budget, DEFAULT_SYSTEM};
+
+/// run one chat request per input line, several at a time, writing a result line for each
+/// as soon as it is done
+pub async fn run(args: BatchArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let prices = Arc::new(Prices::new(config.get("prices")));
+    let defaults = Defaults {
+        model: model(&args.common, &config),
+        system: args.system.clone()
+            .or_else(|| config.str("system").map(str::to_string))
+            .unwrap_or_else(|| DEFAULT_SYSTEM.to_string()),
+        params: args.sampler.to_params(config.sampler(args.sampler.preset.as_deref())?),
+        stream: stream(false, &config),
+    };
+
+    let mut requests = defaults.requests(&read_input(&args.input).await?)?;
+
+    let journal_path = args.journal.clone().or_else(|| args.out.as_ref().map(|path| {
+        let mut path = path.clone().into_os_string();
+        path.push(".journal");
+        PathBuf::from(path)
+    }));
+    if args.resume {
+        let path = journal_path.as_ref().ok_or("--resume needs a --journal or an --out file to find the journal by.")?;
+        let finished = match std::fs::read_to_string(path) {
+            Ok(text) => text.lines().map(str::to_string).collect::<HashSet<_>>(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e).into()),
+        };
+        let before = requests.len();
+        requests.retain(|(line, id, _)| !finished.contains(&journal_key(*line, id.as_ref())));
+        eprintln!("Skipping {} requests that are already finished.", before - requests.len());
+    }
+    let all: Vec<ChatRequest> = requests.iter().map(|(_, _, request)| request.clone()).collect();
+    Budget::new(args.max_cost, &config).check_all(&prices, &all)?;
+    // a resumed run adds to what the interrupted one wrote
+    let open = |path: &PathBuf| {
+        OpenOptions::new().create(true).write(true).append(args.resume).truncate(!args.resume).open(path)
+            .map_err(|e| format!("Could not open {}: {}", path.display(), e))
+    };
+    let mut out: Box<dyn Write> = match &args.out {
+        Some(path) => Box::new(open(path)?),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut journal = journal_path.as_ref().map(open).transpose()?;
+    let total = requests.len();
+    let permits = Arc::new(Semaphore::new(args.concurrency as usize));
+    let mut tasks = JoinSet::new();
+    for (line, id, request) in requests {
+        // the clients share one connection pool
+        let client = client.clone();
+        let permits = permits.clone();
+        let prices = prices.clone();
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let started = Instant::now();
+            let (mut result, exchange) = match send(&client, &request, &prices).await {
+                Ok((result, exchange)) => (result, Some(exchange)),
+                Err(e) => (json!({ "error": e.to_string() }), None),
+            };
+            let key = journal_key(line, id.as_ref());
+            result["line"] = line.into();
+            if let Some(id) = id {
+                result["id"] = id;
+            }
+            result["model"] = request.model.into();
+            (key, result, exchange, started)
+        });
+    }
+
+    let mut failed = 0;
+    while let Some(result) = tasks.join_next().await {
+        let (key, result, exchange, started) = result?;
+        if let Some(exchange) = exchange {
+            record_spend(exchange, started);
+        }
+        writeln!(out, "{}", result)?;
+        out.flush()?;
+        if result.get("error").is_some() {
+            failed += 1;
+        } else if let Some(journal) = journal.as_mut() {
+            // only once the result is written, so that an interrupted run loses nothing
+            writeln!(journal, "{}", key)?;
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} requests failed.", failed, total).into());
+    }
+    Ok(())
+}
+
+/// the text of the input file, or of stdin for `-`
+pub(crate) async fn read_input(path: &Path) -> Result<String, qllm::Error> {
+    if path.as_os_str() == "-" {
+        let mut input = String::new();
+        tokio::io::stdin().read_to_string(&mut input).await?;
+        return Ok(input);
+    }
+    std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e).into())
+}
+
+/// how the journal names a request: by its `id`, or by its line without one
+fn journal_key(line: usize, id: Option<&Value>) -> String {
+    match id {
+        Some(id) => json!({ "id": id }).to_string(),
+        None => json!({ "line": line }).to_string(),
+    }
+}
+
+/// what a record does not say for itself
+pub(crate) struct Defaults {
+    pub(crate) model: String,
+    pub(crate) system: String,
+    pub(crate) params: qllm::SamplerParams,
+    pub(crate) stream: bool,
+}
+
+impl Defaults {
+    /// the line number, `id`, and request of each record of the input, every line checked
+    /// before anything is sent
+    pub(crate) fn requests(&self, input: &str) -> Result<Vec<(usize, Option<Value>, ChatRequest)>, qllm::Error> {
+        let mut requests = Vec::new();
+        for (number, line) in input.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let record: Value = serde_json::from_str(line).map_err(|e| format!("line {} is not valid JSON: {}", number + 1, e))?;
+            let request = self.request(&record).map_err(|e| format!("line {}: {}", number + 1, e))?;
+            requests.push((number + 1, record.get("id").cloned(), request));
+        }
+        Ok(requests)
+    }
+
+    /// the request of an input record: a JSON string is the prompt, an object has a `prompt`
+    /// or `messages` and may set `system`, `model`, and sampler parameters, at the top or in `params`
+    fn request(&self, record: &Value) -> Result<ChatRequest, qllm::Error> {
+        let empty = json!({});
+        let (record, prompt) = match record {
+            Value::String(prompt) => (&empty, Some(prompt.as_str())),
+            Value::Object(_) => (record, record["prompt"].as_str()),
+            _ => return Err("expected a prompt string or an object".into()),
+        };
+        let system = record["system"].as_str().unwrap_or(&self.system);
+        let messages = match (record["messages"].as_array(), prompt) {
+            (Some(list), _) => {
+                let mut messages = list.iter().map(Message::from_json).collect::<Result<Vec<_>, _>>()?;
+                if !messages.iter().any(|m| m.role == "system") {
+                    messages.insert(0, Message::system(system));
+                }
+                if let Some(prompt) = prompt {
+                    messages.push(Message::user(prompt));
+                }
+                messages
+            }
+            (None, Some(prompt)) => vec![Message::system(system), Message::user(prompt)],
+            (None, None) => return Err("a record needs a `prompt` string or a `messages` array".into()),
+        };
+        let mut request = ChatRequest::new(record["model"].as_str().unwrap_or(&self.model), messages);
+        request.params = self.params.clone();
+        request.params.update(record);
+        request.params.update(&record["params"]);
+        request.stream = self.stream;
+        Ok(request)
+    }
+}
+
+/// send a request and describe its answer, for the output and the history
+async fn send(client: &QllmClient, request: &ChatRequest, prices: &Prices) -> Result<(Value, Exchange), qllm::Error> {
+    let mut stream = client.chat(request).await?;
+    let mut content = String::new();
+    while let Some(delta) = stream.next_delta().await? {
+        content.push_str(&delta);
+    }
+    let mut result = json!({
+        "content": content,
+        "finish_reason": stream.finish_reason(),
+        "usage": stream.usage(),
+        "cost": stream.usage().and_then(|usage| prices.cost(&request.model, usage)),
+    });
+    if !stream.reasoning().is_empty() {
+        result["reasoning"] = stream.reasoning().into();
+    }
+    Ok((result, exchange_for(client, request, &content, stream.usage(), prices)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> Defaults {
+        Defaults { model: "m".to_string(), system: "be brief".to_string(), params: qllm::SamplerParams::default(), stream: false }
+    }
+
+    #[test]
+    fn names_requests_in_the_journal_by_id_or_line() {
+        assert_eq!(journal_key(3, None), r#"{"line":3}"#);
+        assert_eq!(journal_key(3, Some(&json!("a"))), r#"{"id":"a"}"#);
+        assert_eq!(journal_key(3, Some(&json!(3))), r#"{"id":3}"#);
+        // the same id names the same request wherever it moves in the input
+        assert_eq!(journal_key(1, Some(&json!({ "n": 1 }))), journal_key(9, Some(&json!({ "n": 1 }))));
+    }
+
+    #[test]
+    fn reads_the_records_of_the_input() {
+        let input = "\"plain\"\n\n{\"id\": 7, \"prompt\": \"hi\", \"model\": \"x\", \"temperature\": 0.1, \"params\": {\"top_k\": 5}}\n\
+                     {\"messages\": [{\"role\": \"system\", \"content\": \"s\"}, {\"role\": \"user\", \"content\": \"u\"}]}\n";
+        let requests = defaults().requests(input).unwrap();
+        let lines: Vec<(usize, Option<Value>)> = requests.iter().map(|(line, id, _)| (*line, id.clone())).collect();
+        assert_eq!(lines, [(1, None), (3, Some(json!(7))), (4, None)]);
+        assert_eq!(requests[0].2.messages, [Message::system("be brief"), Message::user("plain")]);
+        let request = &requests[1].2;
+        assert_eq!((request.model.as_str(), request.params.temperature, request.params.top_k), ("x", 0.1, 5));
+        assert_eq!(requests[2].2.messages, [Message::system("s"), Message::user("u")]);
+    }
+
+    #[test]
+    fn refuses_a_bad_record_by_its_line() {
+        let error = defaults().requests("\"fine\"\n{\"prompt\": 1}\n").unwrap_err().to_string();
+        assert_eq!(error, "line 2: a record needs a `prompt` string or a `messages` array");
+        assert!(defaults().requests("\"fine\"\n{oops\n").unwrap_err().to_string().starts_with("line 2 is not valid JSON"));
+    }
+}
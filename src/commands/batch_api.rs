@@ -0,0 +1,189 @@
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use qllm::batches::{self, Batch};
+use qllm::files;
+use qllm::pricing::Prices;
+use qllm::time::format_timestamp;
+
+use crate::cli::{BatchApiArgs, BatchApiCommand, BatchSubmitArgs};
+use crate::commands::batch::{read_input, Defaults};
+use crate::{client, config, model, DEFAULT_SYSTEM};
+
+/// the route the requests of a job are sent to
+const ENDPOINT: &str = "/v1/chat/completions";
+
+/// how often `fetch --wait` asks whether a job is done
+const POLL: Duration = Duration::from_secs(30);
+
+/// submit a job, report on jobs, or fetch the results of one
+pub async fn run(args: BatchApiArgs) -> Result<(), qllm::Error> {
+    match args.command {
+        BatchApiCommand::Submit(args) => submit(*args).await?,
+        BatchApiCommand::Status { common, id } => {
+            let client = client(&common, &config(&common)?)?;
+            match id {
+                Some(id) => print_batch(&batches::retrieve(&client, &id).await?),
+                None => {
+                    for batch in batches::list(&client, 20).await? {
+                        println!("{}  {}  {:<11}  {}/{}", batch.id, format_timestamp(batch.created_at), batch.status, batch.completed + batch.failed, batch.total);
+                    }
+                }
+            }
+        }
+        BatchApiCommand::Fetch { common, id, out, wait } => {
+            let config = config(&common)?;
+            let client = client(&common, &config)?;
+            let mut batch = batches::retrieve(&client, &id).await?;
+            while !batch.is_done() {
+                if !wait {
+                    return Err(format!(
+                        "The job {} is {}, with {} of {} requests done; fetch it once it has finished, or with --wait.",
+                        id, batch.status, batch.completed + batch.failed, batch.total
+                    ).into());
+                }
+                eprintln!("{}: {}, {} of {} requests done", id, batch.status, batch.completed + batch.failed, batch.total);
+                tokio::time::sleep(POLL).await;
+                batch = batches::retrieve(&client, &id).await?;
+            }
+            if batch.output_file_id.is_none() && batch.error_file_id.is_none() {
+                let errors = if batch.errors.is_empty() { String::new() } else { format!(": {}", batch.errors.join("; ")) };
+                return Err(format!("The job {} is {} without results{}", id, batch.status, errors).into());
+            }
+            fetch(&client, &batch, &Prices::new(config.get("prices")), out.as_deref()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// upload the requests of the input file and start a job running them
+async fn submit(args: BatchSubmitArgs) -> Result<(), qllm::Error> {
+    let BatchSubmitArgs { common, input, system, completion_window, sampler } = args;
+    let config = config(&common)?;
+    let client = client(&common, &config)?;
+    let defaults = Defaults {
+        model: model(&common, &config),
+        system: system
+            .or_else(|| config.str("system").map(str::to_string))
+            .unwrap_or_else(|| DEFAULT_SYSTEM.to_string()),
+        params: sampler.to_params(config.sampler(sampler.preset.as_deref())?),
+        stream: false,
+    };
+    if client.provider().name() != "openai" {
+        return Err(format!("The {} provider has no Batch API.", client.provider().name()).into());
+    }
+    let lines = request_lines(&defaults, &read_input(&input).await?)?;
+    let count = lines.len();
+    let filename = match input.as_os_str() == "-" {
+        true => "batch.jsonl",
+        false => input.file_name().and_then(|name| name.to_str()).unwrap_or("batch.jsonl"),
+    };
+    let file = files::upload_content(&client, filename, lines.join("\n").as_bytes(), "batch").await?;
+    let batch = batches::create(&client, &file.id, ENDPOINT, &completion_window).await?;
+    let requests = if count == 1 { "request" } else { "requests" };
+    eprintln!("Uploaded {} {} as {}; the job is {}.", count, requests, file.id, batch.status);
+    println!("{}", batch.id);
+    Ok(())
+}
+
+/// the request lines of a job's input file, one for each record of the input, named by
+/// the record's `id` or else by its line
+fn request_lines(defaults: &Defaults, input: &str) -> Result<Vec<String>, qllm::Error> {
+    let mut seen = HashSet::new();
+    let mut lines = Vec::new();
+    for (line, id, request) in defaults.requests(input)? {
+        if request.model == "default" {
+            return Err(format!("line {}: the Batch API needs a model, give it with -m or a `model` in the record", line).into());
+        }
+        let custom_id = match id {
+            Some(Value::String(id)) => id,
+            Some(Value::Number(id)) => id.to_string(),
+            Some(_) => return Err(format!("line {}: the Batch API needs an `id` that is a string or a number", line).into()),
+            None => format!("line-{}", line),
+        };
+        if !seen.insert(custom_id.clone()) {
+            return Err(format!("line {}: the id {} is used by another line; the Batch API needs each to be unique", line, custom_id).into());
+        }
+        lines.push(json!({ "custom_id": custom_id, "method": "POST", "url": ENDPOINT, "body": request.to_json() }).to_string());
+    }
+    if lines.is_empty() {
+        return Err("The input has no requests.".into());
+    }
+    Ok(lines)
+}
+
+fn print_batch(batch: &Batch) {
+    println!("id:        {}", batch.id);
+    println!("status:    {}", batch.status);
+    println!("created:   {}", format_timestamp(batch.created_at));
+    println!("requests:  {} total, {} completed, {} failed", batch.total, batch.completed, batch.failed);
+    println!("input:     {}", batch.input_file_id);
+    if let Some(file) = &batch.output_file_id {
+        println!("output:    {}", file);
+    }
+    if let Some(file) = &batch.error_file_id {
+        println!("errors:    {}", file);
+    }
+    for error in &batch.errors {
+        println!("error:     {}", error);
+    }
+}
+
+/// write a result line for each response of a finished job, failing if any request did
+async fn fetch(client: &qllm::QllmClient, batch: &Batch, prices: &Prices, out: Option<&Path>) -> Result<(), qllm::Error> {
+    let mut writer: Box<dyn Write> = match out {
+        Some(path) => Box::new(std::fs::File::create(path).map_err(|e| format!("Could not open {}: {}", path.display(), e))?),
+        None => Box::new(std::io::stdout()),
+    };
+    let (mut total, mut failed) = (0, 0);
+    for file in batch.output_file_id.iter().chain(&batch.error_file_id) {
+        let content = files::content(client, file).await?;
+        for line in String::from_utf8_lossy(&content).lines().filter(|line| !line.trim().is_empty()) {
+            let response: Value = serde_json::from_str(line).map_err(|e| format!("{} has a line that is not valid JSON: {}", file, e))?;
+            let result = result(&response, prices);
+            total += 1;
+            if result.get("error").is_some() {
+                failed += 1;
+            }
+            writeln!(writer, "{}", result)?;
+        }
+    }
+    writer.flush()?;
+    if failed > 0 {
+        return Err(format!("{} of {} requests failed.", failed, total).into());
+    }
+    Ok(())
+}
+
+/// the result of one response line of a job, as `qllm batch` would have written it
+fn result(response: &Value, prices: &Prices) -> Value {
+    let custom_id = response["custom_id"].as_str().unwrap_or_default();
+    let mut result = match custom_id.strip_prefix("line-").and_then(|line| line.parse::<u64>().ok()) {
+        Some(line) => json!({ "line": line }),
+        None => json!({ "id": custom_id }),
+    };
+    let body = &response["response"]["body"];
+    if let Some(model) = body["model"].as_str() {
+        result["model"] = model.into();
+    }
+    if response["response"]["status_code"] != 200 {
+        let error = &response["error"]["message"];
+        let message = error.as_str().or(body["error"]["message"].as_str()).map(str::to_string)
+            .unwrap_or_else(|| format!("the request failed with status {}", response["response"]["status_code"]));
+        result["error"] = message.into();
+        return result;
+    }
+    let choice = &body["choices"][0];
+    let usage = &body["usage"];
+    result["content"] = choice["message"]["content"].clone();
+    result["finish_reason"] = choice["finish_reason"].clone();
+    result["usage"] = usage.clone();
+    // batch requests cost half the list price
+    result["cost"] = json!(body["model"].as_str().and_then(|model| prices.cost(model, usage)).map(|cost| cost / 2.0));
+    if let Some(reasoning) = choice["message"]["reasoning_content"].as_str().filter(|reasoning| !reasoning.is_empty()) {
+        result["reasoning"] = reasoning.into();
+    }
+    result
+}
@@ -0,0 +1,233 @@
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use qllm::pricing::Prices;
+use qllm::{ChatRequest, HttpError, Message, QllmClient, RetryPolicy};
+
+use crate::cli::BenchArgs;
+use crate::commands::batch::{read_input, Defaults};
+use crate::{client, config, exchange_for, model, record_spend, DEFAULT_SYSTEM};
+
+const DEFAULT_PROMPT: &str = "Write a short story about a lighthouse keeper.";
+
+/// what came of one request
+struct Sample {
+    first_token: Option<Duration>,
+    latency: Duration,
+    tokens: u64,
+    error: Option<String>,
+}
+
+/// keep `--concurrency` streaming requests in flight for `--duration` seconds, then report
+/// the throughput, the latencies, and the errors
+pub async fn run(args: BenchArgs) -> Result<(), qllm::Error> {
+    if args.ramp >= args.duration {
+        return Err("--ramp must be shorter than --duration.".into());
+    }
+    let config = config(&args.common)?;
+    let mut client = client(&args.common, &config)?;
+    // a retried request would hide the errors the load causes
+    if args.common.retries.is_none() {
+        client = client.with_retry(RetryPolicy { retries: 0, ..RetryPolicy::default() });
+    }
+    let defaults = Defaults {
+        model: model(&args.common, &config),
+        system: args.system.clone()
+            .or_else(|| config.str("system").map(str::to_string))
+            .unwrap_or_else(|| DEFAULT_SYSTEM.to_string()),
+        params: args.sampler.to_params(config.sampler(args.sampler.preset.as_deref())?),
+        stream: true,
+    };
+    let requests = match &args.input {
+        Some(path) => defaults.requests(&read_input(path).await?)?.into_iter().map(|(_, _, request)| request).collect(),
+        None => {
+            let prompt = args.prompt.join(" ");
+            let prompt = if prompt.trim().is_empty() { DEFAULT_PROMPT } else { prompt.trim() };
+            let mut request = ChatRequest::new(&defaults.model, vec![Message::system(&defaults.system), Message::user(prompt)]);
+            request.params = defaults.params.clone();
+            vec![request]
+        }
+    };
+    if requests.is_empty() {
+        return Err("The input has no requests.".into());
+    }
+
+    let requests = Arc::new(requests);
+    let prices = Arc::new(Prices::new(config.get("prices")));
+    let concurrency = args.concurrency as usize;
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(args.duration);
+    let active = Arc::new(AtomicUsize::new(0));
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut workers = JoinSet::new();
+    for worker in 0..concurrency {
+        let start = started + Duration::from_secs(args.ramp) * worker as u32 / concurrency as u32;
+        let (client, requests, prices, active, sender) = (client.clone(), requests.clone(), prices.clone(), active.clone(), sender.clone());
+        workers.spawn(async move {
+            tokio::time::sleep_until(start.into()).await;
+            active.fetch_add(1, Ordering::Relaxed);
+            // the workers take turns through the requests, so that they all get sent
+            let mut next = worker;
+            while Instant::now() < deadline {
+                let sample = send(&client, &requests[next % requests.len()], &prices).await;
+                next += concurrency;
+                if sender.send(sample).is_err() {
+                    break;
+                }
+            }
+            active.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+    drop(sender);
+
+    let mut samples = Vec::new();
+    let mut ticks = (args.interval > 0).then(|| tokio::time::interval_at((started + Duration::from_secs(args.interval)).into(), Duration::from_secs(args.interval)));
+    let (mut since, mut shown) = (started, 0);
+    let interrupted = tokio::signal::ctrl_c();
+    tokio::pin!(interrupted);
+    loop {
+        tokio::select! {
+            sample = receiver.recv() => match sample {
+                Some(sample) => samples.push(sample),
+                None => break,
+            },
+            _ = async { ticks.as_mut().unwrap().tick().await }, if ticks.is_some() => {
+                print_progress(&samples[shown..], since.elapsed(), started.elapsed(), active.load(Ordering::Relaxed));
+                (since, shown) = (Instant::now(), samples.len());
+            }
+            _ = &mut interrupted => {
+                eprintln!("Stopped, leaving out the requests in flight.");
+                workers.abort_all();
+                break;
+            }
+        }
+    }
+    let elapsed = started.elapsed();
+
+    let summary = summarize(&samples, elapsed);
+    if args.json {
+        println!("{}", summary);
+    } else {
+        print_summary(&summary);
+    }
+    if !samples.is_empty() && samples.iter().all(|sample| sample.error.is_some()) {
+        return Err(format!("None of the {} requests succeeded.", samples.len()).into());
+    }
+    Ok(())
+}
+
+/// send a request and read its answer through, counting the tokens by the usage the server
+/// reports, or by the pieces of the stream without it, and record it in the history
+async fn send(client: &QllmClient, request: &ChatRequest, prices: &Prices) -> Sample {
+    let started = Instant::now();
+    let result = async {
+        let mut stream = client.chat(request).await?;
+        let (mut pieces, mut answer) = (0, String::new());
+        while let Some(delta) = stream.next_delta().await? {
+            pieces += 1;
+            answer.push_str(&delta);
+        }
+        Ok::<_, qllm::Error>((stream, answer, pieces))
+    };
+    match result.await {
+        Ok((stream, answer, pieces)) => {
+            let sample = Sample {
+                first_token: stream.first_token_at().map(|at| at.duration_since(started)),
+                latency: started.elapsed(),
+                tokens: stream.usage().and_then(|usage| qllm::token_counts(usage).1).unwrap_or(pieces),
+                error: None,
+            };
+            // after the timing, which the write would skew
+            record_spend(exchange_for(client, request, &answer, stream.usage(), prices), started);
+            sample
+        }
+        Err(e) => {
+            // the status alone, so that the same error of different requests is counted together
+            let error = match e.downcast_ref::<HttpError>() {
+                Some(e) => e.status.to_string(),
+                None => e.to_string(),
+            };
+            Sample { first_token: None, latency: started.elapsed(), tokens: 0, error: Some(error) }
+        }
+    }
+}
+
+/// a line on stderr about the requests finished since the last one
+fn print_progress(samples: &[Sample], span: Duration, elapsed: Duration, active: usize) {
+    let failed = samples.iter().filter(|sample| sample.error.is_some()).count();
+    let tokens = samples.iter().map(|sample| sample.tokens).sum::<u64>();
+    let workers = if active == 1 { "worker" } else { "workers" };
+    eprintln!(
+        "{:>4}s  {} {}  {} done  {} failed  {:.1} tokens/s",
+        elapsed.as_secs(), active, workers, samples.len(), failed, tokens as f64 / span.as_secs_f64()
+    );
+}
+
+/// the totals, rates, latency percentiles, and errors of a run, as JSON
+fn summarize(samples: &[Sample], elapsed: Duration) -> serde_json::Value {
+    let succeeded = samples.iter().filter(|sample| sample.error.is_none()).collect::<Vec<_>>();
+    let failed = samples.len() - succeeded.len();
+    let tokens = succeeded.iter().map(|sample| sample.tokens).sum::<u64>();
+    let seconds = elapsed.as_secs_f64();
+    let mut first_tokens = succeeded.iter().filter_map(|sample| sample.first_token).collect::<Vec<_>>();
+    let mut latencies = succeeded.iter().map(|sample| sample.latency).collect::<Vec<_>>();
+    // how fast one stream generates, once it has started
+    let mut speeds = succeeded.iter()
+        .filter_map(|sample| Some(sample.tokens as f64 / (sample.latency - sample.first_token?).as_secs_f64()))
+        .filter(|speed| speed.is_finite())
+        .collect::<Vec<_>>();
+    first_tokens.sort();
+    latencies.sort();
+    speeds.sort_by(f64::total_cmp);
+    let seconds_at = |sorted: &[Duration], p: f64| percentile(sorted, p).map(|d| (d.as_secs_f64() * 1000.0).round() / 1000.0);
+    let spread = |sorted: &[Duration]| json!({ "p50": seconds_at(sorted, 0.5), "p90": seconds_at(sorted, 0.9), "p99": seconds_at(sorted, 0.99) });
+    let mut errors = BTreeMap::new();
+    for error in samples.iter().filter_map(|sample| sample.error.as_ref()) {
+        *errors.entry(error.as_str()).or_insert(0) += 1;
+    }
+    json!({
+        "requests": samples.len(),
+        "failed": failed,
+        "error_rate": if samples.is_empty() { 0.0 } else { failed as f64 / samples.len() as f64 },
+        "seconds": (seconds * 10.0).round() / 10.0,
+        "requests_per_second": succeeded.len() as f64 / seconds,
+        "tokens": tokens,
+        "tokens_per_second": tokens as f64 / seconds,
+        "first_token": spread(&first_tokens),
+        "latency": spread(&latencies),
+        "stream_tokens_per_second": percentile(&speeds, 0.5),
+        "errors": errors,
+    })
+}
+
+/// the value below which the fraction `p` of the sorted values lie
+fn percentile<T: Copy>(sorted: &[T], p: f64) -> Option<T> {
+    let last = sorted.len().checked_sub(1)?;
+    Some(sorted[(last as f64 * p).round() as usize])
+}
+
+fn print_summary(summary: &serde_json::Value) {
+    let requests = summary["requests"].as_u64().unwrap_or(0);
+    println!("requests:     {} in {}s, {} failed ({:.1}%)", requests, summary["seconds"], summary["failed"], summary["error_rate"].as_f64().unwrap_or(0.0) * 100.0);
+    println!("throughput:   {:.2} requests/s, {:.1} tokens/s", summary["requests_per_second"].as_f64().unwrap_or(0.0), summary["tokens_per_second"].as_f64().unwrap_or(0.0));
+    let spread = |value: &serde_json::Value| match value["p50"].as_f64() {
+        Some(_) => ["p50", "p90", "p99"].iter().map(|p| format!("{} {:.2}s", p, value[p].as_f64().unwrap_or(0.0))).collect::<Vec<_>>().join(", "),
+        None => "-".to_string(),
+    };
+    println!("first token:  {}", spread(&summary["first_token"]));
+    println!("latency:      {}", spread(&summary["latency"]));
+    if let Some(speed) = summary["stream_tokens_per_second"].as_f64() {
+        println!("per stream:   {:.1} tokens/s", speed);
+    }
+    if let Some(errors) = summary["errors"].as_object().filter(|errors| !errors.is_empty()) {
+        let mut errors = errors.iter().collect::<Vec<_>>();
+        errors.sort_by_key(|(_, count)| std::cmp::Reverse(count.as_u64()));
+        for (i, (error, count)) in errors.iter().enumerate() {
+            println!("{}{} × {}", if i == 0 { "errors:       " } else { "              " }, count, error);
+        }
+    }
+}
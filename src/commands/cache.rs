@@ -0,0 +1,28 @@
+use qllm::cache::Cache;
+use qllm::Config;
+
+use crate::cache_ttl;
+use crate::cli::{CacheArgs, CacheCommand};
+
+/// report on or empty the answer cache
+pub fn run(args: CacheArgs) -> Result<(), qllm::Error> {
+    let config = Config::load(args.config.as_deref())?;
+    let ttl = cache_ttl(None, &config);
+    let cache = Cache::open_default();
+    match args.command {
+        CacheCommand::Stats => {
+            let stats = cache.stats(ttl)?;
+            println!("directory: {}", cache.dir().display());
+            println!("entries:   {} ({} expired)", stats.entries, stats.expired);
+            println!("size:      {:.1} KiB", stats.bytes as f64 / 1024.0);
+        }
+        CacheCommand::Clear { expired } => {
+            if expired && ttl.is_none() {
+                return Err("Cached answers never expire with cache_ttl = 0.".into());
+            }
+            let deleted = cache.clear(if expired { ttl } else { None })?;
+            eprintln!("Deleted {} cached answers.", deleted);
+        }
+    }
+    Ok(())
+}
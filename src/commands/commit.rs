@@ -0,0 +1,106 @@
+use qllm::pricing::Prices;
+use qllm::template::Template;
+use qllm::tokens::Tokenizer;
+use std::io::Write;
+use std::time::Instant;
+use qllm::{ChatRequest, Exchange, Message, QllmClient};
+
+use crate::chunked::{self, map_reduce};
+use crate::cli::CommitArgs;
+use crate::{client, config, confirm, exchange_for, git, model, read_answer, record_spend, routing, Budget, ChatOptions, Display};
+
+/// the template used unless there is a `commit` template of the user's own
+const TEMPLATE: &str = r#"
+description = "Write a commit message for the staged changes"
+system = "You write git commit messages in the Conventional Commits format."
+prompt = """Write a commit message for the staged change below. Start with a subject line of the form `type(scope): summary`, where type is one of feat, fix, docs, style, refactor, perf, test, build, ci or chore, the scope is optional, and the summary is in the imperative mood and under 72 characters in all. If the change needs explaining, follow it with a blank line and a body wrapped at 72 columns saying what changed and why. Reply with the message alone."""
+"#;
+
+/// write a commit message for `git diff --cached`, summarizing a diff too long for one
+/// request chunk by chunk, then commit with it once the user agrees
+pub async fn run(args: CommitArgs) -> Result<(), qllm::Error> {
+    let diff = git(&["diff", "--cached"]).await?;
+    if diff.trim().is_empty() {
+        return Err("Nothing is staged; stage the changes to commit with `git add` first.".into());
+    }
+    let template = Template::load_or("commit", TEMPLATE)?;
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let options = ChatOptions {
+        model: model(&args.common, &config),
+        system: template.system.as_deref().map(|system| template.render(system, &args.vars)).transpose()?.unwrap_or_default(),
+        template_prompt: None,
+        params: args.sampler.to_params(config.sampler(args.sampler.preset.as_deref())?),
+        stream: false,
+        tools: Vec::new(),
+        logprobs: None,
+        response_format: None,
+        display: Display::Raw,
+        show_reasoning: false,
+        wrap: None,
+        routing: routing(&[], &[], &config),
+        prices: Prices::new(config.get("prices")),
+        budget: Budget::new(args.max_cost, &config),
+    };
+    let mut prompt = template.prompt.as_deref().map(|prompt| template.render(prompt, &args.vars)).transpose()?.unwrap_or_default();
+    if !args.notes.is_empty() {
+        prompt = format!("{}\n\nNotes from the author of the change: {}", prompt, args.notes.join(" "));
+    }
+
+    let tokenizer = Tokenizer::for_model(&options.model, &config).unwrap_or(Tokenizer::Estimate);
+    let size = chunked::chunk_size(&client, &config, &options.model, args.chunk_tokens).await;
+    let started = Instant::now();
+    // what summarizing the parts of a long diff cost, to count with the message
+    let mut summarized = None;
+    let request = if tokenizer.count(&diff) <= size {
+        options.request(vec![Message::system(&options.system), Message::user(&format!("{}\n\n{}", prompt, diff))])
+    } else {
+        let chunks = tokenizer.chunks(&diff, size);
+        eprintln!("The diff is too long for one request, so it is summarized in {} chunks of up to {} tokens first.", chunks.len(), size);
+        let summarize = "Summarize what this part of a diff changes, for writing the commit message of the whole diff later.";
+        let (_, summaries, cost) = map_reduce(&client, &options, &tokenizer, &chunks, summarize, size, args.concurrency as usize).await?;
+        let summaries: Vec<String> = summaries.iter().enumerate()
+            .map(|(i, summary)| format!("Part {}:\n{}", i + 1, summary.trim()))
+            .collect();
+        summarized = cost;
+        let summaries = format!("The diff is too long to show whole, so here is what each of its consecutive parts changes.\n\n{}", summaries.join("\n\n"));
+        options.request(vec![Message::system(&options.system), Message::user(&format!("{}\n\n{}", prompt, summaries))])
+    };
+    let (message, cost) = answer(&client, &options, &request).await?;
+    let cost = match (summarized, cost) {
+        (Some(earlier), Some(last)) => Some(earlier + last),
+        (earlier, last) => earlier.or(last),
+    };
+    record_spend(Exchange { cost, ..exchange_for(&client, &request, &message, None, &options.prices) }, started);
+
+    println!("{}", message);
+    if args.print || !(args.yes || confirm("\nCommit with this message? [y/N] ")) {
+        return Ok(());
+    }
+    // removed when dropped, once git has read it
+    let mut file = tempfile::Builder::new().prefix("qllm-commit-").suffix(".txt").tempfile()?;
+    writeln!(file, "{}", message)?;
+    let mut command = tokio::process::Command::new("git");
+    command.arg("commit").arg("-F").arg(file.path());
+    if args.edit {
+        command.arg("--edit");
+    }
+    let status = command.status().await.map_err(|e| format!("Could not run git: {}", e))?;
+    if !status.success() {
+        return Err(format!("git commit failed ({}).", status).into());
+    }
+    Ok(())
+}
+
+/// the whole answer to the request, without fences around it, and what it cost
+async fn answer(client: &QllmClient, options: &ChatOptions, request: &ChatRequest) -> Result<(String, Option<f64>), qllm::Error> {
+    options.check_budget(request)?;
+    let (stream, answer) = read_answer(client, request).await?;
+    let cost = stream.usage().and_then(|usage| options.prices.cost(&request.model, usage));
+    let answer = answer.trim();
+    // models often fence the message although asked not to
+    let unfenced = answer.strip_prefix("```")
+        .and_then(|rest| rest.split_once('\n'))
+        .and_then(|(_, rest)| rest.trim_end().strip_suffix("```"));
+    Ok((unfenced.unwrap_or(answer).trim().to_string(), cost))
+}
@@ -0,0 +1,102 @@
+use std::time::Instant;
+use qllm::attach::Attachment;
+use qllm::pricing::Prices;
+use qllm::tokens::Tokenizer;
+use qllm::{diff, patch, Message};
+
+use crate::blocks;
+use crate::cli::EditArgs;
+use crate::{client, config, confirm, exchange_for, model, read_answer, record_spend, routing, theme, Budget, ChatOptions, Display};
+
+const EDIT_SYSTEM: &str = "You edit files as the user instructs. Reply with the whole new content of the file \
+in a single fenced code block, changing only what the instruction calls for and keeping the rest as it is. \
+For a small change to a long file, a unified diff of the file will do instead.";
+
+/// ask the model for the file rewritten as instructed, show the change as a diff, and
+/// write it once the user approves, keeping the old file as FILE.bak
+pub async fn run(args: EditArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let options = ChatOptions {
+        model: model(&args.common, &config),
+        system: EDIT_SYSTEM.to_string(),
+        template_prompt: None,
+        params: args.sampler.to_params(config.sampler(args.sampler.preset.as_deref())?),
+        stream: false,
+        tools: Vec::new(),
+        logprobs: None,
+        response_format: None,
+        display: Display::Raw,
+        show_reasoning: false,
+        wrap: None,
+        routing: routing(&[], &[], &config),
+        prices: Prices::new(config.get("prices")),
+        budget: Budget::new(args.max_cost, &config),
+    };
+    let path = &args.path;
+    let file = Attachment::read(path, &Tokenizer::Estimate, u64::MAX)?
+        .ok_or_else(|| format!("{} is not a text file.", path.display()))?;
+
+    let request = options.request(vec![
+        Message::system(&options.system),
+        Message::user(&format!("{}\n\n{}", file.to_markdown(), args.instruction.join(" "))),
+    ]);
+    options.check_budget(&request)?;
+    eprintln!("Asking {} to edit {}.", options.model, path.display());
+    let started = Instant::now();
+    let (stream, answer) = read_answer(&client, &request).await?;
+    record_spend(exchange_for(&client, &request, &answer, stream.usage(), &options.prices), started);
+    let name = path.display().to_string();
+    let edits = patch::parse(&answer);
+    let edited = if !edits.is_empty() {
+        let edits: Vec<&patch::Edit> = edits.iter().collect();
+        patch::apply_to(&file.text, &name, &edits).map_err(|failures| {
+            let reasons: Vec<String> = failures.iter().map(|f| format!("  {}: {}", f.edit, f.reason)).collect();
+            format!("The changes in the answer do not apply to {}:\n{}", name, reasons.join("\n"))
+        })?
+    } else {
+        // the longest block, should the model show a snippet before the file
+        let Some(block) = blocks::blocks(&answer).into_iter().max_by_key(|block| block.code.len()) else {
+            return Err(format!("The answer has no code block with the new file:\n{}", answer.trim()).into());
+        };
+        let mut edited = block.code;
+        if file.text.ends_with('\n') && !edited.ends_with('\n') {
+            edited.push('\n');
+        }
+        edited
+    };
+
+    let change = diff::unified(&file.text, &edited, &format!("a/{}", name), &format!("b/{}", name), 3);
+    if change.is_empty() {
+        eprintln!("The model left {} as it was.", name);
+        return Ok(());
+    }
+    print!("{}", colorize(&change));
+    if !args.yes && !confirm(&format!("Apply this change to {}? [y/N] ", name)) {
+        return Ok(());
+    }
+    if !args.no_backup {
+        let backup = format!("{}.bak", name);
+        std::fs::copy(path, &backup).map_err(|e| format!("Could not back up {} to {}: {}", name, backup, e))?;
+    }
+    std::fs::write(path, edited).map_err(|e| format!("Could not write {}: {}", name, e))?;
+    eprintln!("Wrote {}.", name);
+    Ok(())
+}
+
+/// a unified diff with removed lines, added ones, and hunk headers in the colours of the theme
+pub fn colorize(diff: &str) -> String {
+    let theme = theme::out();
+    diff.lines()
+        .map(|line| {
+            let colour = match line.as_bytes().first() {
+                _ if line.starts_with("---") || line.starts_with("+++") => &theme.diff_header,
+                Some(b'-') => &theme.removed,
+                Some(b'+') => &theme.added,
+                Some(b'@') => &theme.hunk,
+                _ => return format!("{}\n", line),
+            };
+            format!("{}{}{}\n", colour, line, theme.reset)
+        })
+        .collect()
+}
@@ -0,0 +1,232 @@
+use serde_json::{json, Value};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use qllm::eval::{self, Case, Check};
+use qllm::pricing::Prices;
+use qllm::{ChatRequest, Message, QllmClient, SamplerParams};
+
+use crate::cli::{CommonArgs, EvalArgs};
+use crate::{client, config, exchange_for, model, read_answer, record_spend, DEFAULT_SYSTEM};
+
+/// a model to run the cases against, or to grade them, set up as `-m` would set it up
+struct Target {
+    /// the name the suite or the command line gave it
+    label: String,
+    model: String,
+    client: QllmClient,
+    params: SamplerParams,
+    system: String,
+    prices: Prices,
+}
+
+/// the models that grade the `judge` checks, by name
+struct Judges {
+    /// the one for checks that name none
+    default: Option<String>,
+    targets: HashMap<String, Target>,
+}
+
+/// how a case went on a model
+struct Outcome {
+    case: String,
+    model: String,
+    answer: Option<String>,
+    failures: Vec<String>,
+    /// the grades of the `judge` checks
+    scores: Vec<Value>,
+    seconds: f64,
+}
+
+/// run every case of the suite on every model, print how each went, and fail on
+/// failures, or with `--baseline` on regressions
+pub async fn run(args: EvalArgs) -> Result<(), qllm::Error> {
+    let suite = eval::load(&args.suite)?;
+    let baseline = args.baseline.as_deref().map(read_baseline).transpose()?;
+
+    let names = match (&args.common.model, suite.models.is_empty()) {
+        (None, false) => suite.models.iter().cloned().map(Some).collect(),
+        _ => vec![args.common.model.clone()],
+    };
+    let mut targets = Vec::new();
+    for name in names {
+        targets.push(Arc::new(target(&args.common, name, args.sampler.preset.as_deref())?));
+    }
+
+    let default_judge = args.judge_model.clone()
+        .or(suite.judge_model.clone())
+        .or_else(|| config(&args.common).ok()?.str("judge_model").map(str::to_string));
+    let mut judges = Judges { default: default_judge, targets: HashMap::new() };
+    for check in suite.cases.iter().flat_map(|case| &case.checks) {
+        let Check::Judge(judge) = check else { continue };
+        let name = judge.model.clone().or(judges.default.clone())
+            .ok_or("The judge checks need a model to grade them: give --judge-model, or judge_model in the suite or the config.")?;
+        if let Entry::Vacant(entry) = judges.targets.entry(name) {
+            let mut judge = target(&args.common, Some(entry.key().clone()), None)?;
+            // grades should not change from one run to the next
+            judge.params.deterministic();
+            entry.insert(judge);
+        }
+    }
+    let judges = Arc::new(judges);
+
+    let cases = suite.cases.into_iter().map(Arc::new).collect::<Vec<_>>();
+    let permits = Arc::new(Semaphore::new(args.concurrency as usize));
+    let mut tasks = JoinSet::new();
+    for (i, case) in cases.iter().enumerate() {
+        for (j, target) in targets.iter().enumerate() {
+            let (case, target, judges, permits, sampler) = (case.clone(), target.clone(), judges.clone(), permits.clone(), args.sampler.clone());
+            tasks.spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let mut params = target.params.clone();
+                params.update(&case.params);
+                ((i, j), attempt(&case, &target, sampler.to_params(params), &judges).await)
+            });
+        }
+    }
+    let mut outcomes = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        outcomes.push(result?);
+    }
+    outcomes.sort_by_key(|(order, _)| *order);
+    let outcomes = outcomes.into_iter().map(|(_, outcome)| outcome).collect::<Vec<_>>();
+
+    let regressed = |outcome: &Outcome| {
+        !outcome.failures.is_empty()
+            && baseline.as_ref().is_some_and(|baseline| baseline.get(&(outcome.case.clone(), outcome.model.clone())) == Some(&true))
+    };
+    if args.json {
+        for outcome in &outcomes {
+            let mut line = json!({
+                "case": outcome.case, "model": outcome.model, "pass": outcome.failures.is_empty(),
+                "failures": outcome.failures, "answer": outcome.answer, "seconds": outcome.seconds,
+            });
+            if !outcome.scores.is_empty() {
+                line["scores"] = outcome.scores.clone().into();
+            }
+            println!("{}", line);
+        }
+    } else {
+        print_table(&outcomes, &regressed);
+    }
+
+    let failed = outcomes.iter().filter(|outcome| !outcome.failures.is_empty()).count();
+    let regressions = outcomes.iter().filter(|outcome| regressed(outcome)).count();
+    if !args.json {
+        println!("\n{} of {} passed.", outcomes.len() - failed, outcomes.len());
+    }
+    match baseline {
+        Some(_) if regressions > 0 => {
+            let cases = if regressions == 1 { "case" } else { "cases" };
+            Err(format!("{} {} passed in the baseline and fail now.", regressions, cases).into())
+        }
+        None if failed > 0 => Err(format!("{} of {} cases failed.", failed, outcomes.len()).into()),
+        _ => Ok(()),
+    }
+}
+
+/// set up the model `name` as `-m name` would, so that an alias may bring its profile
+fn target(common: &CommonArgs, name: Option<String>, preset: Option<&str>) -> Result<Target, qllm::Error> {
+    let common = CommonArgs { model: name.clone(), ..common.clone() };
+    let config = config(&common)?;
+    let model = model(&common, &config);
+    Ok(Target {
+        label: name.unwrap_or_else(|| model.clone()),
+        client: client(&common, &config)?,
+        params: config.sampler(preset)?,
+        system: config.str("system").unwrap_or(DEFAULT_SYSTEM).to_string(),
+        prices: Prices::new(config.get("prices")),
+        model,
+    })
+}
+
+/// ask the model the case's prompt, check the answer, and have it graded
+async fn attempt(case: &Case, target: &Target, params: SamplerParams, judges: &Judges) -> Outcome {
+    let system = case.system.as_deref().unwrap_or(&target.system);
+    let mut request = ChatRequest::new(&target.model, vec![Message::system(system), Message::user(&case.prompt)]);
+    request.params = params;
+    let started = Instant::now();
+    let answer = ask(target, &request, started).await;
+    let seconds = (started.elapsed().as_secs_f64() * 100.0).round() / 100.0;
+    let mut outcome = Outcome { case: case.name.clone(), model: target.label.clone(), answer: None, failures: Vec::new(), scores: Vec::new(), seconds };
+    let answer = match answer {
+        Ok(answer) => answer,
+        Err(e) => {
+            outcome.failures.push(format!("the request failed: {}", e));
+            return outcome;
+        }
+    };
+    outcome.failures = case.failures(&answer);
+
+    for check in &case.checks {
+        let Check::Judge(judge) = check else { continue };
+        // the judges were all set up before the run
+        let name = judge.model.as_ref().or(judges.default.as_ref()).expect("a judge model");
+        let grader = &judges.targets[name];
+        let mut request = judge.request(&grader.model, &case.prompt, &answer);
+        request.params = grader.params.clone();
+        let verdict = match ask(grader, &request, Instant::now()).await {
+            Ok(reply) => judge.verdict(&reply),
+            Err(e) => Err(format!("the judge {} failed: {}", grader.label, e)),
+        };
+        match verdict {
+            Ok((score, reason)) => {
+                outcome.failures.extend(judge.failure(score, &reason));
+                outcome.scores.push(json!({ "rubric": judge.rubric, "score": score, "reason": reason, "judge": grader.label }));
+            }
+            Err(e) => outcome.failures.push(e),
+        }
+    }
+    outcome.answer = Some(answer);
+    outcome
+}
+
+/// send a request to the target and read its answer, recording what it cost in the history
+async fn ask(target: &Target, request: &ChatRequest, started: Instant) -> Result<String, qllm::Error> {
+    let (stream, answer) = read_answer(&target.client, request).await?;
+    record_spend(exchange_for(&target.client, request, &answer, stream.usage(), &target.prices), started);
+    Ok(answer)
+}
+
+/// whether each case passed on each model, from the `--json` lines of an earlier run
+fn read_baseline(path: &std::path::Path) -> Result<HashMap<(String, String), bool>, qllm::Error> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let mut baseline = HashMap::new();
+    for (number, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let record: Value = serde_json::from_str(line).map_err(|e| format!("{} line {} is not valid JSON: {}", path.display(), number + 1, e))?;
+        let (Some(case), Some(model), Some(pass)) = (record["case"].as_str(), record["model"].as_str(), record["pass"].as_bool()) else {
+            return Err(format!("{} line {} is not a result of `qllm eval --json`.", path.display(), number + 1).into());
+        };
+        baseline.insert((case.to_string(), model.to_string()), pass);
+    }
+    Ok(baseline)
+}
+
+fn print_table(outcomes: &[Outcome], regressed: &dyn Fn(&Outcome) -> bool) {
+    let case = outcomes.iter().map(|outcome| outcome.case.len()).max().unwrap_or(0).max("CASE".len());
+    let model = outcomes.iter().map(|outcome| outcome.model.len()).max().unwrap_or(0).max("MODEL".len());
+    let judged = outcomes.iter().any(|outcome| !outcome.scores.is_empty());
+    println!("{:<case$}  {:<model$}  {:<9}  {:<8}{}", "CASE", "MODEL", "RESULT", "TIME", if judged { "  SCORES" } else { "" });
+    for outcome in outcomes {
+        let result = match (outcome.failures.is_empty(), regressed(outcome)) {
+            (true, _) => "pass",
+            (false, true) => "REGRESSED",
+            (false, false) => "FAIL",
+        };
+        let scores = outcome.scores.iter().map(|score| score["score"].as_f64().unwrap_or_default().to_string()).collect::<Vec<_>>().join(" ");
+        let row = format!("{:<case$}  {:<model$}  {:<9}  {:<8}  {}", outcome.case, outcome.model, result, format!("{:.2}s", outcome.seconds), scores);
+        println!("{}", row.trim_end());
+        for failure in &outcome.failures {
+            println!("    - {}", failure);
+        }
+        // enough of the answer to see what went wrong
+        if let Some(answer) = outcome.answer.as_ref().filter(|_| !outcome.failures.is_empty()) {
+            let line = answer.trim().lines().next().unwrap_or_default();
+            let more = if line.chars().count() > 100 || answer.trim().lines().nth(1).is_some() { "…" } else { "" };
+            println!("    answer: {}{}", line.chars().take(100).collect::<String>(), more);
+        }
+    }
+}
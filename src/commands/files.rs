@@ -0,0 +1,36 @@
+use serde_json::json;
+use qllm::files;
+use qllm::time::format_timestamp;
+
+use crate::cli::{FilesArgs, FilesCommand};
+use crate::{client, config};
+
+/// upload, list, or delete the files stored by the endpoint
+pub async fn run(args: FilesArgs) -> Result<(), qllm::Error> {
+    match args.command {
+        FilesCommand::Upload { common, file, purpose } => {
+            let client = client(&common, &config(&common)?)?;
+            let uploaded = files::upload(&client, &file, &purpose).await?;
+            eprintln!("Uploaded {} ({} bytes) for {}.", file.display(), uploaded.bytes, uploaded.purpose);
+            println!("{}", uploaded.id);
+        }
+        FilesCommand::List { common, purpose, json } => {
+            let client = client(&common, &config(&common)?)?;
+            for file in files::list(&client, purpose.as_deref()).await? {
+                if json {
+                    println!("{}", json!({ "id": file.id, "filename": file.filename, "bytes": file.bytes, "created_at": file.created_at, "purpose": file.purpose }));
+                } else {
+                    println!("{}  {}  {:>10}  {:<10}  {}", file.id, format_timestamp(file.created_at), file.bytes, file.purpose, file.filename);
+                }
+            }
+        }
+        FilesCommand::Rm { common, ids } => {
+            let client = client(&common, &config(&common)?)?;
+            for id in ids {
+                files::delete(&client, &id).await?;
+                eprintln!("Deleted {}.", id);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+use qllm::finetune::{self, Format, Job, JobRequest};
+use qllm::time::format_timestamp;
+use qllm::{files, QllmClient};
+
+use crate::cli::{FinetuneArgs, FinetuneCommand, FinetuneCreateArgs};
+use crate::{client, config, model};
+
+/// how often `events --follow` asks for new events
+const POLL: Duration = Duration::from_secs(10);
+
+/// start, list, inspect, or cancel fine-tuning jobs
+pub async fn run(args: FinetuneArgs) -> Result<(), qllm::Error> {
+    match args.command {
+        FinetuneCommand::Create(args) => create(*args).await?,
+        FinetuneCommand::List { common } => {
+            let client = client(&common, &config(&common)?)?;
+            for job in finetune::list(&client, 20).await? {
+                let trained = job.fine_tuned_model.as_deref().unwrap_or("-");
+                println!("{}  {}  {:<16}  {}  {}", job.id, format_timestamp(job.created_at), job.status, job.model, trained);
+            }
+        }
+        FinetuneCommand::Status { common, id } => {
+            let client = client(&common, &config(&common)?)?;
+            print_job(&finetune::retrieve(&client, &id).await?);
+        }
+        FinetuneCommand::Cancel { common, id } => {
+            let client = client(&common, &config(&common)?)?;
+            let job = finetune::cancel(&client, &id).await?;
+            eprintln!("The job {} is {}.", job.id, job.status);
+        }
+        FinetuneCommand::Events { common, id, count, follow } => {
+            let client = client(&common, &config(&common)?)?;
+            events(&client, &id, count, follow).await?;
+        }
+    }
+    Ok(())
+}
+
+/// check the files, upload them, and start the job
+async fn create(args: FinetuneCreateArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let model = model(&args.common, &config);
+    if model == "default" && !args.check {
+        return Err("Fine-tuning needs the model to train, such as -m gpt-4.1-mini-2025-04-14.".into());
+    }
+    let (training_file, format) = prepare(&client, &args.training_file, args.check).await?;
+    let validation_file = match &args.validation_file {
+        Some(file) => Some(prepare(&client, file, args.check).await?.0),
+        None => None,
+    };
+    if args.check {
+        return Ok(());
+    }
+    let request = JobRequest {
+        model,
+        training_file,
+        method: args.method.clone().or_else(|| format.map(|format| format.method().to_string())),
+        validation_file,
+        suffix: args.suffix.clone(),
+        epochs: args.epochs,
+        batch_size: args.batch_size,
+        learning_rate_multiplier: args.learning_rate_multiplier,
+        seed: args.seed,
+    };
+    let job = finetune::create(&client, &request).await?;
+    eprintln!("Started training {} on {}; the job is {}.", job.model, job.training_file, job.status);
+    println!("{}", job.id);
+    Ok(())
+}
+
+/// the id of a file for a job, checking and uploading it unless it is the id of one
+/// already uploaded; a file that is checked also gives the format of its examples
+async fn prepare(client: &QllmClient, file: &str, check_only: bool) -> Result<(String, Option<Format>), qllm::Error> {
+    let path = Path::new(file);
+    if !path.exists() && file.starts_with("file-") {
+        return Ok((file.to_string(), None));
+    }
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let (format, examples) = match finetune::validate(&text) {
+        Ok(checked) => checked,
+        Err(problems) => {
+            for problem in problems.iter().take(20) {
+                eprintln!("{}: {}", path.display(), problem);
+            }
+            if problems.len() > 20 {
+                eprintln!("{}: and {} more", path.display(), problems.len() - 20);
+            }
+            let count = if problems.len() == 1 { "problem" } else { "problems" };
+            return Err(format!("{} is not a valid training file, with {} {}.", path.display(), problems.len(), count).into());
+        }
+    };
+    eprintln!("{}: {} {} examples", path.display(), examples, format.method());
+    if check_only {
+        return Ok((String::new(), Some(format)));
+    }
+    let uploaded = files::upload(client, path, "fine-tune").await?;
+    eprintln!("Uploaded {} as {}.", path.display(), uploaded.id);
+    Ok((uploaded.id, Some(format)))
+}
+
+fn print_job(job: &Job) {
+    println!("id:         {}", job.id);
+    println!("status:     {}", job.status);
+    println!("model:      {}", job.model);
+    if let Some(model) = &job.fine_tuned_model {
+        println!("trained:    {}", model);
+    }
+    println!("created:    {}", format_timestamp(job.created_at));
+    if let Some(finished) = job.finished_at {
+        println!("finished:   {}", format_timestamp(finished));
+    }
+    println!("training:   {}", job.training_file);
+    if let Some(file) = &job.validation_file {
+        println!("validation: {}", file);
+    }
+    if let Some(tokens) = job.trained_tokens {
+        println!("tokens:     {}", tokens);
+    }
+    if let Some(error) = &job.error {
+        println!("error:      {}", error);
+    }
+}
+
+/// print the latest events of a job, and with `follow` the new ones until it has finished
+async fn events(client: &QllmClient, id: &str, count: u32, follow: bool) -> Result<(), qllm::Error> {
+    let mut seen = HashSet::new();
+    loop {
+        // asked before the events, so that the last ones are shown once it has finished
+        let done = follow && finetune::retrieve(client, id).await?.is_done();
+        for event in finetune::events(client, id, count).await? {
+            if seen.insert(event.id.clone()) {
+                println!("{}  {:<5}  {}", format_timestamp(event.created_at), event.level, event.message);
+            }
+        }
+        if !follow || done {
+            return Ok(());
+        }
+        tokio::time::sleep(POLL).await;
+    }
+}
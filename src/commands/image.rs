@@ -0,0 +1,65 @@
+use std::path::{Path, PathBuf};
+
+use qllm::images::{self, Backend, ImageRequest};
+
+use crate::cli::ImageArgs;
+use crate::{client, config, read_prompt};
+
+/// generate images from the prompt and save them, printing the file of each
+pub async fn run(args: ImageArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let prompt = read_prompt(&args.prompt, None, None).await?;
+    if prompt.trim().is_empty() {
+        return Err("Nothing to draw. Give the prompt as arguments or `-` to read it from stdin.".into());
+    }
+    let backend = match args.backend.as_deref().or(config.str("image_backend")) {
+        Some(name) => Backend::by_name(name)?,
+        None => Backend::detect(client.endpoint()),
+    };
+    // the chat model of the config would not draw, so only `image_model` stands in for -m
+    let model = args.common.model.clone()
+        .or_else(|| config.str("image_model").map(str::to_string))
+        .unwrap_or_else(|| "default".to_string());
+
+    let mut request = ImageRequest::new(&model, &prompt);
+    request.n = args.count;
+    request.size = args.size;
+    request.steps = args.steps;
+    request.negative_prompt = args.negative_prompt.clone();
+    request.seed = args.seed;
+    let generated = images::generate(&client, backend, &request).await?;
+    if generated.is_empty() {
+        return Err("The server returned no images.".into());
+    }
+
+    let stamp = qllm::time::now();
+    for (index, image) in generated.iter().enumerate() {
+        let path = output_path(args.output.as_deref(), stamp, index, generated.len(), image.extension());
+        std::fs::write(&path, &image.bytes).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+        if let Some(revised) = &image.revised_prompt {
+            eprintln!("{}: {}", path.display(), revised);
+        }
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// where the image at `index` of `count` is saved: `--output`, numbered when there are
+/// several, or else a file named for the time in the current directory
+fn output_path(output: Option<&Path>, stamp: u64, index: usize, count: usize, extension: &str) -> PathBuf {
+    let Some(output) = output else {
+        return match count {
+            1 => PathBuf::from(format!("image-{}.{}", stamp, extension)),
+            _ => PathBuf::from(format!("image-{}-{}.{}", stamp, index + 1, extension)),
+        };
+    };
+    let extension = output.extension().and_then(|extension| extension.to_str()).unwrap_or(extension);
+    match count {
+        1 => output.with_extension(extension),
+        _ => {
+            let stem = output.file_stem().and_then(|stem| stem.to_str()).unwrap_or("image");
+            output.with_file_name(format!("{}-{}.{}", stem, index + 1, extension))
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use qllm::attach::{self, Attachment};
+use qllm::index::{self, Chunk, Index};
+use qllm::tokens::Tokenizer;
+
+use crate::cli::IndexArgs;
+use crate::{client, config, model};
+
+/// the passages embedded in one request
+const BATCH: usize = 64;
+
+/// split the text files under a directory into passages, embed them, and save them as an
+/// index; without a directory, list the indexes
+pub async fn run(args: IndexArgs) -> Result<(), qllm::Error> {
+    let Some(dir) = &args.dir else {
+        for name in Index::names()? {
+            println!("{}", name);
+        }
+        return Ok(());
+    };
+    let name = args.name.clone().unwrap_or_else(|| index::default_name(dir));
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(format!("'{}' cannot name an index, which is kept as a file of that name.", name).into());
+    }
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let model = model(&args.common, &config);
+
+    let mut files = 0;
+    let mut passages = Vec::new();
+    for path in attach::walk(dir)? {
+        // the estimate is enough here, since files are split by lines and never cut
+        let Some(file) = Attachment::read(&path, &Tokenizer::Estimate, u64::MAX)? else {
+            continue;
+        };
+        files += 1;
+        for (lines, text) in index::split(&file.text, args.chunk_tokens) {
+            passages.push((file.path.clone(), lines, text));
+        }
+    }
+    if passages.is_empty() {
+        return Err(format!("There are no text files to index in {}.", dir.display()).into());
+    }
+
+    eprintln!("Embedding {} passages of {} files with {}.", passages.len(), files, model);
+    let mut chunks = Vec::with_capacity(passages.len());
+    for batch in passages.chunks(BATCH) {
+        let texts: Vec<String> = batch.iter().map(|(_, _, text)| text.clone()).collect();
+        let vectors = client.embed(&model, &texts).await?;
+        for ((path, lines, text), vector) in batch.iter().cloned().zip(vectors) {
+            chunks.push(Chunk { path, lines, text, vector });
+        }
+        eprint!("\rEmbedded {} of {} passages.", chunks.len(), passages.len());
+    }
+    eprintln!();
+
+    let root = dir.canonicalize().unwrap_or_else(|_| dir.clone()).display().to_string();
+    let index = Index::new(&name, &model, &root);
+    index.save(&chunks)?;
+    eprintln!("Saved the index {} to {}.", index.name, Index::path(&index.name).display());
+    Ok(())
+}
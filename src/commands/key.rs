@@ -0,0 +1,46 @@
+use std::io::{BufRead, IsTerminal, Read, Write};
+use qllm::keystore;
+
+use crate::cli::{KeyArgs, KeyCommand};
+
+/// store, print, or remove the api key of a profile in the system keyring
+pub fn run(args: KeyArgs) -> Result<(), qllm::Error> {
+    match args.command {
+        KeyCommand::Set { profile } => {
+            let key = read_key(&profile)?;
+            keystore::set(&profile, &key)?;
+            eprintln!("Stored the key of {} in the keyring.", profile);
+        }
+        KeyCommand::Get { profile } => {
+            let key = keystore::get(&profile)?.ok_or_else(|| format!("There is no key for {} in the keyring.", profile))?;
+            println!("{}", key);
+        }
+        KeyCommand::Rm { profile } => match keystore::delete(&profile)? {
+            true => eprintln!("Removed the key of {} from the keyring.", profile),
+            false => return Err(format!("There is no key for {} in the keyring.", profile).into()),
+        },
+    }
+    Ok(())
+}
+
+/// the key from stdin, typed at the terminal without being echoed or else piped in
+fn read_key(profile: &str) -> Result<String, qllm::Error> {
+    let mut key = String::new();
+    if std::io::stdin().is_terminal() {
+        eprint!("Key for {}: ", profile);
+        std::io::stderr().flush().ok();
+        let echo = |on: &str| std::process::Command::new("stty").arg(on).status();
+        echo("-echo")?;
+        let read = std::io::stdin().lock().read_line(&mut key);
+        echo("echo")?;
+        eprintln!();
+        read?;
+    } else {
+        std::io::stdin().read_to_string(&mut key)?;
+    }
+    let key = key.trim();
+    if key.is_empty() {
+        return Err("The key is empty.".into());
+    }
+    Ok(key.to_string())
+}
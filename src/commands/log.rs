@@ -0,0 +1,177 @@
+use serde_json::Value;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
+use qllm::audit::AuditLog;
+use qllm::time::{self, format_timestamp};
+use qllm::{token_counts, Config, History};
+
+use crate::audit_log;
+use crate::cli::{LogArgs, LogCommand};
+
+/// list recent exchanges, or show one in full
+pub fn run(args: LogArgs) -> Result<(), qllm::Error> {
+    match args.command {
+        Some(LogCommand::Tail { count, follow, json, config }) => return tail(&audit(config)?, count, follow, json),
+        Some(LogCommand::Export { days, csv, config }) => return export(&audit(config)?, days, csv),
+        None => {}
+    }
+    let history = History::open_default();
+
+    if let Some(id) = args.id {
+        let exchange = history.get(id)?.ok_or_else(|| format!("No exchange with id {} in {}.", id, history.path().display()))?;
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&exchange.to_json())?);
+            return Ok(());
+        }
+        println!("id:           {}", exchange.id);
+        println!("conversation: {}", exchange.conversation);
+        println!("time:         {} UTC ({} ms)", format_timestamp(exchange.timestamp), exchange.duration_ms);
+        println!("endpoint:     {}", exchange.endpoint);
+        println!("model:        {}", exchange.model);
+        println!("params:       {}", exchange.params);
+        if let Some(usage) = &exchange.usage {
+            println!("usage:        {}", usage);
+        }
+        println!("\n--- system\n{}", exchange.system);
+        println!("\n--- user\n{}", exchange.prompt);
+        println!("\n--- assistant\n{}", exchange.response);
+        return Ok(());
+    }
+
+    let recent = history.recent(args.count)?;
+    if args.json {
+        for exchange in &recent {
+            println!("{}", exchange.to_json());
+        }
+        return Ok(());
+    }
+    for exchange in &recent {
+        println!(
+            "{:>5}  {:>5}  {}  {:<24}  {}",
+            exchange.id,
+            exchange.conversation,
+            format_timestamp(exchange.timestamp),
+            truncate(&exchange.model, 24),
+            truncate(&exchange.prompt.replace('\n', " "), 60),
+        );
+    }
+    Ok(())
+}
+
+/// cut a string to at most `width` characters, marking the cut with an ellipsis
+pub fn truncate(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        s.to_string()
+    } else {
+        let mut cut: String = s.chars().take(width.saturating_sub(1)).collect();
+        cut.push('…');
+        cut
+    }
+}
+
+/// the audit log named in the config
+fn audit(config: Option<std::path::PathBuf>) -> Result<AuditLog, qllm::Error> {
+    let config = Config::load(config.as_deref())?;
+    audit_log(&config)?.ok_or_else(|| "There is no audit log; name one with audit_log in the config.".into())
+}
+
+/// print the last `count` requests of the audit log, then with `follow` each new one
+fn tail(log: &AuditLog, count: usize, follow: bool, json: bool) -> Result<(), qllm::Error> {
+    let show = |entry: &Value| match json {
+        true => println!("{}", entry),
+        false => println!("{}", summary(entry)),
+    };
+    let entries = log.entries()?;
+    for entry in &entries[entries.len().saturating_sub(count)..] {
+        show(entry);
+    }
+    if !follow {
+        return Ok(());
+    }
+    let mut offset = std::fs::metadata(log.path()).map(|m| m.len()).unwrap_or(0);
+    let mut partial = String::new();
+    loop {
+        std::thread::sleep(Duration::from_millis(500));
+        let Ok(mut file) = std::fs::File::open(log.path()) else { continue };
+        let len = file.metadata()?.len();
+        if len < offset {
+            // the log was rotated or truncated, start again from its beginning
+            offset = 0;
+            partial.clear();
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let mut bytes = Vec::new();
+        offset += file.read_to_end(&mut bytes)? as u64;
+        partial.push_str(&String::from_utf8_lossy(&bytes));
+        // an entry still being written is held back until its line is complete
+        while let Some(end) = partial.find('\n') {
+            if let Ok(entry) = serde_json::from_str::<Value>(&partial[..end]) {
+                show(&entry);
+            }
+            partial.drain(..=end);
+        }
+    }
+}
+
+/// one line about an audited request: when, which model, the tokens, the time taken,
+/// and how it ended
+fn summary(entry: &Value) -> String {
+    let (prompt, completion) = token_counts(&entry["usage"]);
+    let tokens = |count: Option<u64>| count.map(|n| n.to_string()).unwrap_or_else(|| "-".to_string());
+    let outcome = match entry["error"].as_str() {
+        Some(error) => format!("error: {}", truncate(error, 60)),
+        None => entry["finish_reason"].as_str().unwrap_or("done").to_string(),
+    };
+    format!(
+        "{}  {:<10}  {:<24}  {:>7} {:>7}  {:>7} ms  {}",
+        format_timestamp(entry["timestamp"].as_u64().unwrap_or_default()),
+        entry["kind"].as_str().unwrap_or_default(),
+        truncate(entry["model"].as_str().unwrap_or_default(), 24),
+        tokens(prompt),
+        tokens(completion),
+        entry["latency_ms"].as_u64().unwrap_or_default(),
+        outcome,
+    )
+}
+
+/// print the audit log's entries, of the last `days` days if given, as JSON lines or CSV
+fn export(log: &AuditLog, days: Option<u64>, csv: bool) -> Result<(), qllm::Error> {
+    let since = days.map(|days| (time::now() / 86400).saturating_sub(days.saturating_sub(1)) * 86400).unwrap_or(0);
+    let entries = log.entries()?;
+    let entries = entries.iter().filter(|entry| entry["timestamp"].as_u64().unwrap_or_default() >= since);
+    if !csv {
+        for entry in entries {
+            println!("{}", entry);
+        }
+        return Ok(());
+    }
+    println!("time,kind,endpoint,provider,model,prompt_tokens,completion_tokens,latency_ms,first_token_ms,finish_reason,error");
+    for entry in entries {
+        let (prompt, completion) = token_counts(&entry["usage"]);
+        let text = |key: &str| entry[key].as_str().map(csv_field).unwrap_or_default();
+        let number = |n: Option<u64>| n.map(|n| n.to_string()).unwrap_or_default();
+        println!(
+            "{},{},{},{},{},{},{},{},{},{},{}",
+            format_timestamp(entry["timestamp"].as_u64().unwrap_or_default()),
+            text("kind"),
+            text("endpoint"),
+            text("provider"),
+            text("model"),
+            number(prompt),
+            number(completion),
+            number(entry["latency_ms"].as_u64()),
+            number(entry["first_token_ms"].as_u64()),
+            text("finish_reason"),
+            text("error"),
+        );
+    }
+    Ok(())
+}
+
+/// a CSV field, quoted when it holds a comma, a quote, or a line break
+fn csv_field(text: &str) -> String {
+    match text.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.to_string(),
+    }
+}
@@ -0,0 +1,25 @@
+pub mod agent;
+pub mod ask;
+pub mod assistant;
+pub mod batch;
+pub mod batch_api;
+pub mod bench;
+pub mod cache;
+pub mod commit;
+pub mod edit;
+pub mod eval;
+pub mod files;
+pub mod finetune;
+pub mod image;
+pub mod index;
+pub mod key;
+pub mod log;
+pub mod moderate;
+pub mod ping;
+pub mod profiles;
+pub mod realtime;
+pub mod review;
+pub mod similar;
+pub mod template;
+pub mod tokens;
+pub mod usage;
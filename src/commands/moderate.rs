@@ -0,0 +1,65 @@
+use serde_json::json;
+use qllm::Moderation;
+
+use crate::cli::ModerateArgs;
+use crate::{client, config, read_prompt, read_stdin};
+
+/// score the input with the moderation endpoint; as a gate, pass clean input on to stdout
+/// and fail on flagged input
+pub async fn run(args: ModerateArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let input = read_prompt(&args.input, read_stdin(args.stdin).await?.as_deref(), None).await?;
+    if input.trim().is_empty() {
+        return Err("Nothing to moderate. Give the text as arguments or pass -c to read it from stdin.".into());
+    }
+    // a chat model can not moderate, so only `moderation_model` stands in for -m
+    let model = args.common.model.clone()
+        .or_else(|| config.str("moderation_model").map(str::to_string))
+        .unwrap_or_else(|| "default".to_string());
+    let threshold = args.threshold.or_else(|| config.get("moderation_threshold").and_then(|v| v.as_f64()));
+
+    let moderation = client.moderate(&model, std::slice::from_ref(&input)).await?.into_iter().next()
+        .ok_or("The moderation endpoint returned no result.")?;
+    let flagged = flagged(&moderation, threshold);
+    let report = if args.json {
+        json!({ "flagged": !flagged.is_empty(), "result": moderation.raw }).to_string()
+    } else {
+        format(&moderation, &flagged)
+    };
+
+    if !args.gate {
+        println!("{}", report);
+        return Ok(());
+    }
+    eprintln!("{}", report);
+    if !flagged.is_empty() {
+        return Err(format!("The input is flagged for {}.", flagged.join(", ")).into());
+    }
+    println!("{}", input.trim_end_matches('\n'));
+    Ok(())
+}
+
+/// the categories the input is flagged for: those scoring above the threshold, or
+/// without one, those the server flagged
+fn flagged(moderation: &Moderation, threshold: Option<f64>) -> Vec<String> {
+    match threshold {
+        Some(threshold) => moderation.scores.iter()
+            .filter(|(_, score)| *score > threshold)
+            .map(|(name, _)| name.clone())
+            .collect(),
+        // a server may flag the input without naming a category
+        None if moderation.flagged && moderation.categories.is_empty() => vec!["its content".to_string()],
+        None => moderation.categories.clone(),
+    }
+}
+
+/// the verdict, then each category with its score, highest first
+fn format(moderation: &Moderation, flagged: &[String]) -> String {
+    let mut lines = vec![if flagged.is_empty() { "not flagged".to_string() } else { "flagged".to_string() }];
+    for (name, score) in &moderation.scores {
+        let mark = if flagged.contains(name) { "  flagged" } else { "" };
+        lines.push(format!("{:.4}  {}{}", score, name, mark));
+    }
+    lines.join("\n")
+}
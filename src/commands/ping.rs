@@ -0,0 +1,175 @@
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use qllm::{ChatRequest, HttpError, Message, QllmClient, RetryPolicy};
+
+use crate::cli::{CommonArgs, PingArgs};
+use crate::{client, config, model};
+
+/// what was found out about one endpoint
+#[derive(Debug, Default)]
+struct Health {
+    profile: Option<String>,
+    endpoint: String,
+    model: String,
+    /// whether the server answered at all
+    reachable: Option<bool>,
+    /// whether it took the key
+    auth: Option<bool>,
+    /// whether it serves the model
+    served: Option<bool>,
+    /// the round trip of a one-token answer
+    latency: Option<Duration>,
+    error: Option<String>,
+}
+
+impl Health {
+    fn ok(&self) -> bool {
+        self.latency.is_some()
+    }
+}
+
+/// check each profile, or only the endpoint chosen on the command line, and print a row for each
+pub async fn run(args: PingArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let profiles = config.profiles().into_iter().map(|(name, _)| name.to_string()).collect::<Vec<_>>();
+    let chosen = args.common.profile.is_some() || !args.common.endpoint.is_empty() || profiles.is_empty();
+    let targets = match chosen {
+        true => vec![args.common.clone()],
+        false => profiles.into_iter().map(|name| CommonArgs { profile: Some(name), ..args.common.clone() }).collect(),
+    };
+
+    let timeout = Duration::from_secs(args.timeout);
+    let mut tasks = JoinSet::new();
+    for (index, common) in targets.into_iter().enumerate() {
+        tasks.spawn(async move { (index, check(&common, timeout).await) });
+    }
+    let mut results = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        results.push(result?);
+    }
+    results.sort_by_key(|(index, _)| *index);
+    let results = results.into_iter().map(|(_, health)| health).collect::<Vec<_>>();
+
+    if args.json {
+        for health in &results {
+            println!("{}", json!({
+                "profile": health.profile, "endpoint": health.endpoint, "model": health.model,
+                "ok": health.ok(), "reachable": health.reachable, "auth": health.auth, "model_served": health.served,
+                "latency_ms": health.latency.map(|latency| latency.as_millis() as u64), "error": health.error,
+            }));
+        }
+    } else {
+        print_table(&results);
+    }
+    let failed = results.iter().filter(|health| !health.ok()).count();
+    if failed > 0 {
+        let endpoints = if results.len() == 1 { "endpoint" } else { "endpoints" };
+        return Err(format!("{} of {} {} failed.", failed, results.len(), endpoints).into());
+    }
+    Ok(())
+}
+
+fn print_table(results: &[Health]) {
+    let width = |column: fn(&Health) -> &str, title: &str| results.iter().map(|health| column(health).len()).max().unwrap_or(0).max(title.len());
+    let name = width(|health| health.profile.as_deref().unwrap_or("-"), "PROFILE");
+    let endpoint = width(|health| &health.endpoint, "ENDPOINT");
+    let model = width(|health| &health.model, "MODEL");
+    println!("{:<name$}  {:<endpoint$}  {:<model$}  REACH  AUTH  MODEL  LATENCY", "PROFILE", "ENDPOINT", "MODEL");
+    let mark = |check: Option<bool>| match check {
+        Some(true) => "ok",
+        Some(false) => "FAIL",
+        None => "?",
+    };
+    for health in results {
+        // the server picks the model for "default", so there is nothing to look for
+        let served = if health.model == "default" { "-" } else { mark(health.served) };
+        let latency = health.latency.map(|latency| format!("{}ms", latency.as_millis())).unwrap_or_else(|| "-".to_string());
+        let mut row = format!(
+            "{:<name$}  {:<endpoint$}  {:<model$}  {:<5}  {:<4}  {:<5}  {}",
+            health.profile.as_deref().unwrap_or("-"), health.endpoint, health.model, mark(health.reachable), mark(health.auth), served, latency
+        );
+        if let Some(error) = &health.error {
+            row.push_str("  ");
+            row.push_str(error);
+        }
+        println!("{}", row.trim_end());
+    }
+}
+
+/// check one endpoint, giving up after `timeout`
+async fn check(common: &CommonArgs, timeout: Duration) -> Health {
+    let mut health = Health { profile: common.profile.clone(), ..Health::default() };
+    let setup = config(common).and_then(|config| Ok((client(common, &config)?, model(common, &config))));
+    let (client, model) = match setup {
+        Ok(setup) => setup,
+        Err(e) => {
+            health.error = Some(e.to_string());
+            return health;
+        }
+    };
+    health.endpoint = client.endpoint().to_string();
+    health.model = model.clone();
+    // a server that is down should say so at once, not after the retries
+    let client = client.with_retry(RetryPolicy { retries: 0, ..RetryPolicy::default() });
+    if tokio::time::timeout(timeout, probe(&client, &model, &mut health)).await.is_err() {
+        health.error = Some(format!("no answer within {}s", timeout.as_secs()));
+    }
+    health
+}
+
+/// list the models, which needs the key and tells whether the model is served, then
+/// time a one-token answer
+async fn probe(client: &QllmClient, model: &str, health: &mut Health) {
+    let status = |e: &qllm::Error| e.downcast_ref::<HttpError>().map(|e| e.status.as_u16());
+    match client.models().await {
+        Ok(models) => {
+            health.reachable = Some(true);
+            health.auth = Some(true);
+            if model != "default" {
+                health.served = Some(models.iter().any(|id| id == model));
+            }
+        }
+        Err(e) => match status(&e) {
+            Some(401 | 403) => {
+                health.reachable = Some(true);
+                health.auth = Some(false);
+                health.error = Some(format!("the key was refused: {}", e));
+                return;
+            }
+            // a server without the route still answered
+            Some(_) => health.reachable = Some(true),
+            None => {
+                health.reachable = Some(false);
+                health.error = Some(e.to_string());
+                return;
+            }
+        },
+    }
+
+    let mut request = ChatRequest::new(model, vec![Message::user("ping")]);
+    request.params.max_tokens = 1;
+    request.stream = false;
+    let started = Instant::now();
+    let answer = async {
+        let mut stream = client.chat(&request).await?;
+        while stream.next_delta().await?.is_some() {}
+        Ok::<_, qllm::Error>(())
+    };
+    match answer.await {
+        Ok(()) => {
+            health.latency = Some(started.elapsed());
+            health.auth = Some(true);
+            // the list may leave out aliases the server answers to
+            health.served = health.served.map(|_| true);
+        }
+        Err(e) => {
+            match status(&e) {
+                Some(401 | 403) => health.auth = Some(false),
+                Some(404) if model != "default" => health.served = Some(false),
+                _ => {}
+            }
+            health.error = Some(e.to_string());
+        }
+    }
+}
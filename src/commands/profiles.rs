@@ -0,0 +1,20 @@
+use qllm::Config;
+
+use crate::cli::ProfilesArgs;
+
+/// list the profiles in the config, marking the default one
+pub fn run(args: ProfilesArgs) -> Result<(), qllm::Error> {
+    let config = Config::load(args.config.as_deref())?;
+    let default = config.default_profile();
+    let profiles = config.profiles();
+    if profiles.is_empty() {
+        eprintln!("No profiles defined. Add [profiles.<name>] tables to {}.", Config::default_path().display());
+        return Ok(());
+    }
+    for (name, table) in profiles {
+        let marker = if default.as_deref() == Some(name) { "*" } else { " " };
+        let field = |key: &str| table.get(key).and_then(|v| v.as_str()).unwrap_or("-").to_string();
+        println!("{} {:<16} {:<40} {}", marker, name, field("endpoint"), field("model"));
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::io::{BufRead, IsTerminal, Write};
+use std::time::Instant;
+
+use crate::cli::RealtimeArgs;
+use crate::{client, config, model, theme, DEFAULT_SYSTEM};
+
+/// hold a conversation over one realtime session: the prompt, if there is one, then each
+/// line typed at the terminal or piped in, until the input ends
+pub async fn run(args: RealtimeArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let system = args.system.clone()
+        .or_else(|| config.str("system").map(str::to_string))
+        .unwrap_or_else(|| DEFAULT_SYSTEM.to_string());
+    let mut session = client.realtime(&model(&args.common, &config)).await?;
+    session.configure(Some(&system)).await?;
+
+    let interactive = std::io::stdin().is_terminal();
+    let mut lines = std::io::stdin().lock().lines();
+    let mut turn = args.prompt.join(" ");
+    loop {
+        if !turn.trim().is_empty() {
+            let started = Instant::now();
+            let mut first_token = None;
+            session.say(turn.trim()).await?;
+            let mut stdout = std::io::stdout();
+            while let Some(delta) = session.next_delta().await? {
+                first_token.get_or_insert_with(Instant::now);
+                write!(stdout, "{}", delta)?;
+                stdout.flush()?;
+            }
+            writeln!(stdout)?;
+            if args.stats {
+                print_stats(session.usage(), started, first_token);
+            }
+        }
+        if interactive {
+            eprint!("> ");
+            std::io::stderr().flush().ok();
+        }
+        turn = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+    }
+    if interactive {
+        eprintln!();
+    }
+    session.close().await;
+    Ok(())
+}
+
+/// the tokens and timing of one answer
+fn print_stats(usage: Option<&serde_json::Value>, started: Instant, first_token: Option<Instant>) {
+    let count = |name: &str| usage.and_then(|usage| usage[name].as_u64()).map(|n| n.to_string()).unwrap_or_else(|| "?".to_string());
+    let mut stats = format!("{} input + {} output tokens, {:.2}s", count("input_tokens"), count("output_tokens"), started.elapsed().as_secs_f64());
+    if let Some(first) = first_token {
+        stats.push_str(&format!(", {:.2}s to first token", first.duration_since(started).as_secs_f64()));
+    }
+    eprintln!("{}{}{}", theme::err().stats, stats, theme::err().reset);
+}
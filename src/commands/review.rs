@@ -0,0 +1,238 @@
+use serde_json::{json, Value};
+use std::time::Instant;
+use qllm::pricing::Prices;
+use qllm::template::Template;
+use qllm::tokens::Tokenizer;
+use qllm::{Message, ResponseFormat};
+
+use crate::blocks;
+use crate::chunked::chunk_size;
+use crate::cli::{ReviewArgs, Severity};
+use crate::{client, config, exchange_for, git, model, read_answer, record_spend, routing, Budget, ChatOptions, Display};
+
+/// the template used unless there is a `review` template of the user's own
+const TEMPLATE: &str = r#"
+description = "Review a git diff"
+system = "You are a careful senior engineer reviewing a change before it is merged."
+prompt = """Review the diff below. Report bugs, security problems, missing error handling, and code that is hard to maintain, each at the file and the line in the new version of the file where it is, as an error for what would break, a warning for what is likely wrong, or info for a suggestion. Leave out praise and what is fine, and report nothing rather than guess."""
+"#;
+
+/// the findings the model is asked for
+fn schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "findings": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "file": { "type": "string" },
+                        "line": { "type": "integer" },
+                        "severity": { "type": "string", "enum": ["error", "warning", "info"] },
+                        "message": { "type": "string" },
+                    },
+                    "required": ["file", "line", "severity", "message"],
+                    "additionalProperties": false,
+                },
+            },
+        },
+        "required": ["findings"],
+        "additionalProperties": false,
+    })
+}
+
+/// a finding of the review
+struct Finding {
+    file: String,
+    line: u64,
+    severity: Severity,
+    message: String,
+}
+
+/// a hunk of the diff, by the lines it covers in the new version of its file
+struct Hunk {
+    file: String,
+    header: String,
+    lines: (u64, u64),
+}
+
+/// review the diff of a range, the staged changes, or the uncommitted ones, a few files
+/// at a time when it is too long for one request
+pub async fn run(args: ReviewArgs) -> Result<(), qllm::Error> {
+    let diff = match (&args.range, args.staged) {
+        (Some(range), _) => git(&["diff", range]).await?,
+        (None, true) => git(&["diff", "--cached"]).await?,
+        (None, false) => git(&["diff", "HEAD"]).await?,
+    };
+    if diff.trim().is_empty() {
+        return Err("There are no changes to review.".into());
+    }
+    let template = Template::load_or("review", TEMPLATE)?;
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let options = ChatOptions {
+        model: model(&args.common, &config),
+        system: template.system.as_deref().map(|system| template.render(system, &args.vars)).transpose()?.unwrap_or_default(),
+        template_prompt: None,
+        params: args.sampler.to_params(config.sampler(args.sampler.preset.as_deref())?),
+        stream: false,
+        tools: Vec::new(),
+        logprobs: None,
+        response_format: Some(ResponseFormat::Schema { name: "review".to_string(), schema: schema() }),
+        display: Display::Raw,
+        show_reasoning: false,
+        wrap: None,
+        routing: routing(&[], &[], &config),
+        prices: Prices::new(config.get("prices")),
+        budget: Budget::new(args.max_cost, &config),
+    };
+    let prompt = template.prompt.as_deref().map(|prompt| template.render(prompt, &args.vars)).transpose()?.unwrap_or_default();
+
+    let tokenizer = Tokenizer::for_model(&options.model, &config).unwrap_or(Tokenizer::Estimate);
+    let size = chunk_size(&client, &config, &options.model, args.chunk_tokens).await;
+    let parts = split(&tokenizer, &diff, size);
+    let mut findings = Vec::new();
+    for (i, part) in parts.iter().enumerate() {
+        if parts.len() > 1 {
+            eprintln!("Reviewing part {} of {} of the diff.", i + 1, parts.len());
+        }
+        let request = options.request(vec![Message::system(&options.system), Message::user(&format!("{}\n\n{}", prompt, part))]);
+        options.check_budget(&request)?;
+        let started = Instant::now();
+        let (stream, answer) = read_answer(&client, &request).await?;
+        record_spend(exchange_for(&client, &request, &answer, stream.usage(), &options.prices), started);
+        findings.extend(parse(&answer)?);
+    }
+
+    let hunks = hunks(&diff);
+    let hunk_of = |finding: &Finding| hunks.iter().find(|h| h.file == finding.file && (h.lines.0..=h.lines.1).contains(&finding.line));
+    // in the order of the files in the diff, then by line
+    let position = |file: &str| hunks.iter().position(|h| h.file == file).unwrap_or(usize::MAX);
+    findings.sort_by(|a, b| (position(&a.file), &a.file, a.line).cmp(&(position(&b.file), &b.file, b.line)));
+    if args.json {
+        let findings: Vec<Value> = findings.iter()
+            .map(|f| json!({
+                "file": f.file,
+                "line": f.line,
+                "severity": severity_name(f.severity),
+                "message": f.message,
+                "hunk": hunk_of(f).map(|h| h.header.as_str()),
+            }))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&json!({ "model": options.model, "findings": findings }))?);
+    } else if findings.is_empty() {
+        println!("No findings.");
+    } else {
+        let mut file = None;
+        let mut hunk = None;
+        for finding in &findings {
+            if file != Some(&finding.file) {
+                println!("{}{}", if file.is_some() { "\n" } else { "" }, finding.file);
+                file = Some(&finding.file);
+                hunk = None;
+            }
+            let header = hunk_of(finding).map(|h| h.header.as_str());
+            if header.is_some() && header != hunk {
+                println!("  {}", header.unwrap_or_default());
+                hunk = header;
+            }
+            println!("    {:>5}  {:<7}  {}", finding.line, severity_name(finding.severity), finding.message);
+        }
+    }
+
+    if let Some(threshold) = args.fail_on {
+        let failing = findings.iter().filter(|f| f.severity >= threshold).count();
+        if failing > 0 {
+            let findings = if failing == 1 { "finding" } else { "findings" };
+            return Err(format!("{} {} of {} or worse.", failing, findings, severity_name(threshold)).into());
+        }
+    }
+    Ok(())
+}
+
+fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Info => "info",
+        Severity::Warning => "warning",
+        Severity::Error => "error",
+    }
+}
+
+/// the findings of an answer, tolerating a fence around the JSON
+fn parse(answer: &str) -> Result<Vec<Finding>, qllm::Error> {
+    let text = answer.trim();
+    let text = match blocks::blocks(text).as_slice() {
+        [block] if text.starts_with("```") => block.code.trim().to_string(),
+        _ => text.to_string(),
+    };
+    let value: Value = serde_json::from_str(&text).map_err(|e| format!("The review is not valid JSON: {}.", e))?;
+    let findings = value["findings"].as_array().ok_or("The review has no findings array.")?;
+    Ok(findings.iter()
+        .filter_map(|f| Some(Finding {
+            file: f["file"].as_str()?.to_string(),
+            line: f["line"].as_u64().unwrap_or_default(),
+            severity: match f["severity"].as_str() {
+                Some("error") => Severity::Error,
+                Some("warning") => Severity::Warning,
+                _ => Severity::Info,
+            },
+            message: f["message"].as_str()?.to_string(),
+        }))
+        .collect())
+}
+
+/// the diff in parts of whole files of at most about `size` tokens; a file longer than
+/// that is split at line breaks, with its header at the start of each piece
+fn split(tokenizer: &Tokenizer, diff: &str, size: u64) -> Vec<String> {
+    let mut files: Vec<String> = Vec::new();
+    for line in diff.split_inclusive('\n') {
+        match files.last_mut() {
+            Some(file) if !line.starts_with("diff --git ") => file.push_str(line),
+            _ => files.push(line.to_string()),
+        }
+    }
+    let mut parts: Vec<String> = Vec::new();
+    let mut tokens = 0;
+    for file in files {
+        let count = tokenizer.count(&file);
+        if count > size {
+            let header = file.lines().next().unwrap_or_default().to_string();
+            for (i, piece) in tokenizer.chunks(&file, size).into_iter().enumerate() {
+                parts.push(if i == 0 { piece } else { format!("{}\n{}", header, piece) });
+            }
+            tokens = size;
+            continue;
+        }
+        match parts.last_mut() {
+            Some(part) if tokens + count <= size => part.push_str(&file),
+            _ => {
+                parts.push(file);
+                tokens = 0;
+            }
+        }
+        tokens += count;
+    }
+    parts
+}
+
+/// the hunks of the diff, each with the file it changes
+fn hunks(diff: &str) -> Vec<Hunk> {
+    let mut hunks = Vec::new();
+    let mut file = String::new();
+    for line in diff.lines() {
+        if let Some(path) = line.strip_prefix("+++ ") {
+            file = path.strip_prefix("b/").unwrap_or(path).to_string();
+        } else if line.starts_with("@@ ") {
+            // @@ -old,count +new,count @@ context
+            let new = line.split(' ').find_map(|word| word.strip_prefix('+')).unwrap_or("1");
+            let (start, count) = match new.split_once(',') {
+                Some((start, count)) => (start.parse().unwrap_or(1), count.parse().unwrap_or(1)),
+                None => (new.parse().unwrap_or(1), 1),
+            };
+            let end: u64 = start + u64::max(count, 1) - 1;
+            hunks.push(Hunk { file: file.clone(), header: line.to_string(), lines: (start, end) });
+        }
+    }
+    hunks
+}
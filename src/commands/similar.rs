@@ -0,0 +1,56 @@
+use serde_json::json;
+use qllm::index::cosine;
+
+use crate::cli::SimilarArgs;
+use crate::{client, config, model, read_stdin};
+
+/// the candidates embedded in one request
+const BATCH: usize = 64;
+
+/// rank the candidate lines by their similarity to the query, most similar first
+pub async fn run(args: SimilarArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let model = model(&args.common, &config);
+    let query = args.query.join(" ");
+    let input = match &args.file {
+        Some(path) => std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?,
+        None => read_stdin(true).await?.unwrap_or_default(),
+    };
+    // the line numbers of the candidates, counted from 1, to tell apart lines that repeat
+    let (numbers, candidates): (Vec<usize>, Vec<String>) = input.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| (i + 1, line.to_string()))
+        .unzip();
+    if candidates.is_empty() {
+        return Err("There are no candidates to rank; give them one a line on stdin or with -f.".into());
+    }
+
+    let mut ranked = if args.rerank {
+        client.rerank(&model, &query, &candidates).await?
+    } else {
+        let target = client.embed(&model, std::slice::from_ref(&query)).await?.into_iter().next().unwrap_or_default();
+        let mut scores = Vec::with_capacity(candidates.len());
+        for (batch, texts) in candidates.chunks(BATCH).enumerate() {
+            let vectors = client.embed(&model, texts).await?;
+            scores.extend(vectors.iter().enumerate().map(|(i, vector)| (batch * BATCH + i, cosine(&target, vector))));
+        }
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scores
+    };
+    if let Some(top) = args.top {
+        ranked.truncate(top);
+    }
+
+    for (index, score) in ranked {
+        let Some(candidate) = candidates.get(index) else {
+            continue;
+        };
+        if args.json {
+            println!("{}", json!({ "line": numbers[index], "score": score, "text": candidate }));
+        } else {
+            println!("{:.4}  {}", score, candidate);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use qllm::template::{self, Template};
+
+use crate::cli::{TemplateArgs, TemplateCommand};
+use crate::run_editor;
+
+/// what `qllm template new` starts from
+const SKELETON: &str = r#"description = "what the template is for"
+system = "You are an expert in {{topic}}."
+prompt = ""
+
+# values for the {{placeholders}} that -D does not set
+[defaults]
+topic = "software"
+"#;
+
+/// manage the prompt templates in ~/.config/qllm/templates
+pub async fn run(args: TemplateArgs) -> Result<(), qllm::Error> {
+    match args.command {
+        TemplateCommand::List => list(),
+        TemplateCommand::Show { name } => {
+            check_name(&name)?;
+            Template::load(&name)?;
+            print!("{}", fs::read_to_string(template::path(&name))?);
+            Ok(())
+        }
+        TemplateCommand::New { name } => {
+            check_name(&name)?;
+            let path = template::path(&name);
+            if path.exists() {
+                return Err(format!("The template '{}' already exists, change it with `qllm template edit {}`.", name, name).into());
+            }
+            fs::create_dir_all(template::dir())?;
+            fs::write(&path, SKELETON)?;
+            edit(&name, &path).await
+        }
+        TemplateCommand::Edit { name } => {
+            check_name(&name)?;
+            let path = template::path(&name);
+            if !path.exists() {
+                return Err(format!("No template named '{}' in {}.", name, template::dir().display()).into());
+            }
+            edit(&name, &path).await
+        }
+        TemplateCommand::Import { source, name, force } => import(&source, name.as_deref(), force).await,
+    }
+}
+
+/// print one line per template with its variables and description
+fn list() -> Result<(), qllm::Error> {
+    let names = template::names()?;
+    if names.is_empty() {
+        eprintln!("No templates in {}. Write one with `qllm template new NAME`.", template::dir().display());
+    }
+    for name in names {
+        match Template::load(&name) {
+            Ok(template) => {
+                let variables = template.variables().join(",");
+                let variables = if variables.is_empty() { "-".to_string() } else { variables };
+                println!("{:<20} {:<30} {}", name, variables, template.description.unwrap_or_default());
+            }
+            Err(e) => println!("{:<20} {:<30} {}", name, "!", e),
+        }
+    }
+    Ok(())
+}
+
+/// a template name has to be a plain file name
+fn check_name(name: &str) -> Result<(), qllm::Error> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(format!("{:?} is not a template name.", name).into());
+    }
+    Ok(())
+}
+
+/// open a template in the editor, then check that it still parses
+async fn edit(name: &str, path: &Path) -> Result<(), qllm::Error> {
+    run_editor(path).await?;
+    let text = fs::read_to_string(path)?;
+    match Template::parse(name, &text) {
+        Ok(_) => eprintln!("Saved {}.", path.display()),
+        Err(e) => eprintln!("Saved {}, but it is not a usable template: {}", path.display(), e),
+    }
+    Ok(())
+}
+
+/// copy templates into the template directory from a .toml file, its url, a directory, or
+/// a git repository, checking each before it is written
+async fn import(source: &str, name: Option<&str>, force: bool) -> Result<(), qllm::Error> {
+    let remote = source.contains("://") || source.starts_with("git@");
+    let found = if remote && source.ends_with(".toml") {
+        let text = reqwest::get(source).await?.error_for_status()?.text().await?;
+        let stem = source.rsplit('/').next().unwrap_or_default().trim_end_matches(".toml");
+        vec![(stem.to_string(), text)]
+    } else if remote || source.ends_with(".git") {
+        clone(source).await?
+    } else if Path::new(source).is_dir() {
+        let mut found = Vec::new();
+        collect(Path::new(source), &mut found)?;
+        found
+    } else {
+        let path = Path::new(source);
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        vec![(path.file_stem().unwrap_or_default().to_string_lossy().into_owned(), text)]
+    };
+
+    let single = found.len() == 1;
+    if name.is_some() && !single {
+        return Err(format!("--name needs a single template, but {} has {}.", source, found.len()).into());
+    }
+    fs::create_dir_all(template::dir())?;
+    let mut imported = 0;
+    for (stem, text) in found {
+        let name = name.map(str::to_string).unwrap_or(stem);
+        check_name(&name)?;
+        // a directory or repository may hold other TOML files, which are skipped
+        if let Err(e) = Template::parse(&name, &text) {
+            if single {
+                return Err(format!("{} is not a template: {}", source, e).into());
+            }
+            continue;
+        }
+        let path = template::path(&name);
+        if path.exists() && !force {
+            eprintln!("Skipped {}, which already exists; --force replaces it.", name);
+            continue;
+        }
+        fs::write(&path, text)?;
+        eprintln!("Imported {}.", name);
+        imported += 1;
+    }
+    if imported == 0 {
+        eprintln!("Nothing imported from {}.", source);
+    }
+    Ok(())
+}
+
+/// the templates of a git repository, from a shallow clone into a temporary directory
+async fn clone(url: &str) -> Result<Vec<(String, String)>, qllm::Error> {
+    let dir = std::env::temp_dir().join(format!("qllm-templates-{}", std::process::id()));
+    let status = tokio::process::Command::new("git")
+        .args(["clone", "--quiet", "--depth", "1", url])
+        .arg(&dir)
+        .status()
+        .await
+        .map_err(|e| format!("Could not run git: {}", e));
+    let mut found = Vec::new();
+    let result = match status {
+        Ok(status) if status.success() => collect(&dir, &mut found),
+        Ok(status) => Err(format!("git clone {} exited with {}.", url, status).into()),
+        Err(e) => Err(e.into()),
+    };
+    let _ = fs::remove_dir_all(&dir);
+    result.map(|_| found)
+}
+
+/// the name and text of every .toml file under `dir`, leaving out hidden directories like .git
+fn collect(dir: &Path, found: &mut Vec<(String, String)>) -> Result<(), qllm::Error> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?.map(|entry| entry.map(|e| e.path())).collect::<Result<_, _>>()?;
+    paths.sort();
+    for path in paths {
+        let hidden = path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if path.is_dir() && !hidden {
+            collect(&path, found)?;
+        } else if path.extension().is_some_and(|ext| ext == "toml") {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            found.push((stem, fs::read_to_string(&path)?));
+        }
+    }
+    Ok(())
+}
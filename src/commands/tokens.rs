@@ -0,0 +1,59 @@
+use serde_json::json;
+use qllm::tokens::Tokenizer;
+use qllm::Message;
+
+use crate::cli::TokensArgs;
+use crate::{config, model, read_messages, read_prompt, read_stdin};
+
+/// count the tokens of the input with the model's tokenizer, as a single number for
+/// plain text, or per message with a total when there are several
+pub async fn run(args: TokensArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let model = model(&args.common, &config);
+    let tokenizer = match &args.tokenizer {
+        Some(name) => Tokenizer::named(name)?,
+        None => Tokenizer::for_model(&model, &config)?,
+    };
+
+    let mut messages = Vec::new();
+    if let Some(system) = &args.system {
+        messages.push(Message::system(system));
+    }
+    if let Some(path) = &args.messages {
+        messages.extend(read_messages(path)?);
+    }
+    let mut context = read_stdin(args.stdin).await?;
+    // a transcript from `qllm --recurse` is counted message by message
+    if let Some(previous) = context.as_deref().and_then(qllm::transcript::parse) {
+        messages.extend(previous);
+        context = None;
+    }
+    let text = read_prompt(&args.text, context.as_deref(), args.file.as_deref()).await?;
+    let plain = messages.is_empty();
+    if !text.is_empty() || plain {
+        messages.push(Message::user(&text));
+    }
+
+    let counts: Vec<u64> = messages.iter().map(|m| tokenizer.count(&m.content)).collect();
+    let total: u64 = counts.iter().sum();
+    if args.json {
+        println!("{}", json!({
+            "model": model,
+            "tokenizer": tokenizer.describe(),
+            "exact": tokenizer.exact(),
+            "messages": messages.iter().zip(&counts).map(|(m, n)| json!({ "role": m.role, "tokens": n })).collect::<Vec<_>>(),
+            "total": total,
+        }));
+    } else if plain {
+        println!("{}", total);
+    } else {
+        for (message, count) in messages.iter().zip(&counts) {
+            println!("{:<10} {:>8}", message.role, count);
+        }
+        println!("{:<10} {:>8}", "total", total);
+    }
+    if !tokenizer.exact() {
+        eprintln!("No tokenizer is known for {}, so this is {}; name a tokenizer.json for it under [tokenizers] in the config.", model, tokenizer.describe());
+    }
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use serde_json::json;
+use std::collections::BTreeMap;
+use qllm::pricing::Prices;
+use qllm::{time, token_counts, Config, History};
+
+use crate::cli::UsageArgs;
+use crate::commands::log::truncate;
+
+/// the totals of one day and model
+#[derive(Debug, Default)]
+struct Totals {
+    requests: u64,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    cost: f64,
+    /// requests whose cost is unknown, because the model has no price
+    unpriced: u64,
+}
+
+/// sum the history per day and model over the last `--days` days
+pub fn run(args: UsageArgs) -> Result<(), qllm::Error> {
+    let config = Config::load(args.config.as_deref())?;
+    let prices = Prices::new(config.get("prices"));
+    let history = History::open_default();
+    let since = (time::now() / 86400).saturating_sub(args.days.saturating_sub(1)) * 86400;
+
+    let mut totals: BTreeMap<(String, String), Totals> = BTreeMap::new();
+    for exchange in history.since(since)?.iter() {
+        let day = time::format_timestamp(exchange.timestamp)[..10].to_string();
+        let entry = totals.entry((day, exchange.model.clone())).or_default();
+        entry.requests += 1;
+        let usage = exchange.usage.clone().unwrap_or_default();
+        let (prompt, completion) = token_counts(&usage);
+        entry.prompt_tokens += prompt.unwrap_or(0);
+        entry.completion_tokens += completion.unwrap_or(0);
+        // exchanges recorded before costs were tracked are priced now
+        match exchange.cost.or_else(|| prices.cost(&exchange.model, &usage)) {
+            Some(cost) => entry.cost += cost,
+            None => entry.unpriced += 1,
+        }
+    }
+
+    if args.json {
+        for ((day, model), t) in &totals {
+            println!("{}", json!({
+                "day": day,
+                "model": model,
+                "requests": t.requests,
+                "prompt_tokens": t.prompt_tokens,
+                "completion_tokens": t.completion_tokens,
+                "cost": t.cost,
+                "unpriced_requests": t.unpriced,
+            }));
+        }
+        return Ok(());
+    }
+    if totals.is_empty() {
+        eprintln!("No exchanges in the last {} days.", args.days);
+        return Ok(());
+    }
+    println!("{:<10}  {:<28}  {:>8}  {:>10}  {:>10}  {:>10}", "day", "model", "requests", "prompt", "completion", "cost");
+    let mut sum = Totals::default();
+    for ((day, model), t) in &totals {
+        let marker = if t.unpriced > 0 { "*" } else { " " };
+        println!(
+            "{:<10}  {:<28}  {:>8}  {:>10}  {:>10}  {:>9.4}{}",
+            day, truncate(model, 28), t.requests, t.prompt_tokens, t.completion_tokens, t.cost, marker,
+        );
+        sum.requests += t.requests;
+        sum.prompt_tokens += t.prompt_tokens;
+        sum.completion_tokens += t.completion_tokens;
+        sum.cost += t.cost;
+        sum.unpriced += t.unpriced;
+    }
+    println!(
+        "{:<10}  {:<28}  {:>8}  {:>10}  {:>10}  {:>9.4}",
+        "total", "", sum.requests, sum.prompt_tokens, sum.completion_tokens, sum.cost,
+    );
+    if sum.unpriced > 0 {
+        eprintln!("* {} requests have no known price; add the model to [prices] in the config.", sum.unpriced);
+    }
+    Ok(())
+}
@@ -0,0 +1,472 @@
+//! The user configuration file, `~/.config/qllm/config.toml`.
+//!
+//! ```toml
+//! endpoint = "http://localhost:7000/v1"
+//! key_cmd = "pass show openai/api"
+//! model = "mistral-7b-instruct"
+//! system = "Help the user with their task."
+//!
+//! [sampler]
+//! temperature = 0.7
+//! top_p = 0.9
+//! ```
+//!
+//! Named profiles bundle an endpoint with its own key, model, and sampler
+//! settings, and are selected with `--profile` (or `QLLM_PROFILE`, or
+//! `default_profile` in the config):
+//!
+//! ```toml
+//! default_profile = "local"
+//!
+//! [profiles.local]
+//! endpoint = "http://localhost:8080/v1"
+//!
+//! [profiles.work]
+//! endpoint = "https://api.openai.com/v1"
+//! key_cmd = "pass show work/openai"
+//! model = "gpt-4o"
+//! sampler = { temperature = 0.2 }
+//! ```
+//!
+//! `--preset` (or `preset` in the config or a profile) applies a bundle of sampler
+//! settings on top of the `[sampler]` tables: one of the built-in `creative`,
+//! `balanced`, `precise`, and `code`, or a preset of the config's own:
+//!
+//! ```toml
+//! [presets.poetry]
+//! temperature = 1.2
+//! top_p = 0.98
+//! presence_penalty = 0.8
+//! ```
+//!
+//! Aliases name a model together with the profile that serves it, so that `-m fast`
+//! selects both:
+//!
+//! ```toml
+//! [aliases]
+//! fast = "llama-3.1-8b-instruct@local"
+//! smart = "claude-sonnet-4-5@anthropic"
+//! vertex = { model = "claude-sonnet-4-5@20250929", profile = "gcp" }
+//! ```
+//!
+//! Command line flags override the active profile, which overrides the rest of
+//! the config, which overrides the built-in defaults.
+
+use serde_json::{json, Map, Number, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use crate::{dirs, Result, SamplerParams};
+
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// the parsed config file, an empty table when there is none
+    pub table: Value,
+    pub path: Option<PathBuf>,
+    /// the name and table of the active profile
+    pub profile: Option<(String, Value)>,
+}
+
+impl Config {
+    /// the default location of the config file
+    pub fn default_path() -> PathBuf {
+        dirs::config_dir().join("config.toml")
+    }
+
+    /// load the config from `path`, or from QLLM_CONFIG or the default location;
+    /// a missing file at the default location is not an error
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match std::env::var_os("QLLM_CONFIG") {
+                Some(path) => (PathBuf::from(path), true),
+                None => (Config::default_path(), false),
+            },
+        };
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => {
+                return Ok(Config { table: Value::Object(Map::new()), path: None, profile: None });
+            }
+            Err(e) => return Err(format!("Could not read config file {}: {}", path.display(), e).into()),
+        };
+        let config: Config = content.parse().map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Config { path: Some(path), ..config })
+    }
+
+    /// the profiles defined in the config, by name
+    pub fn profiles(&self) -> Vec<(&str, &Value)> {
+        match self.table.get("profiles").and_then(Value::as_object) {
+            Some(profiles) => profiles.iter().map(|(name, table)| (name.as_str(), table)).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// the profile used when none is named on the command line
+    pub fn default_profile(&self) -> Option<String> {
+        std::env::var("QLLM_PROFILE").ok()
+            .filter(|name| !name.is_empty())
+            .or_else(|| self.table.get("default_profile").and_then(Value::as_str).map(str::to_string))
+    }
+
+    /// activate the named profile, or the default profile if there is one
+    pub fn select_profile(&mut self, name: Option<&str>) -> Result<()> {
+        let name = match name.map(str::to_string).or_else(|| self.default_profile()) {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let table = self.profiles().into_iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, table)| table.clone())
+            .ok_or_else(|| format!("No profile named '{}' in {}.", name, self.describe_path()))?;
+        self.profile = Some((name, table));
+        Ok(())
+    }
+
+    /// the model and profile that an alias in the `[aliases]` table stands for, written
+    /// either as `"model@profile"` or `{ model = "...", profile = "..." }`
+    pub fn alias(&self, name: &str) -> Result<Option<(String, Option<String>)>> {
+        let Some(alias) = self.table.get("aliases").and_then(|aliases| aliases.get(name)) else {
+            return Ok(None);
+        };
+        let (model, profile) = match alias {
+            Value::String(target) => match target.rsplit_once('@') {
+                Some((model, profile)) => (model.to_string(), Some(profile.to_string())),
+                None => (target.clone(), None),
+            },
+            Value::Object(table) => (
+                table.get("model").and_then(Value::as_str).unwrap_or_default().to_string(),
+                table.get("profile").and_then(Value::as_str).map(str::to_string),
+            ),
+            _ => return Err(format!("The alias '{}' in {} must be a string or a table.", name, self.describe_path()).into()),
+        };
+        if model.is_empty() {
+            return Err(format!("The alias '{}' in {} names no model.", name, self.describe_path()).into());
+        }
+        Ok(Some((model, profile)))
+    }
+
+    fn describe_path(&self) -> String {
+        match &self.path {
+            Some(path) => path.display().to_string(),
+            None => format!("{} (which does not exist)", Config::default_path().display()),
+        }
+    }
+
+    /// a setting from the active profile only
+    pub fn profile_value(&self, key: &str) -> Option<&Value> {
+        self.profile.as_ref().and_then(|(_, table)| table.get(key))
+    }
+
+    /// a setting from the active profile, falling back to the top level of the config
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.profile_value(key).or_else(|| self.table.get(key))
+    }
+
+    /// a string setting
+    pub fn str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(Value::as_str)
+    }
+
+    /// a string setting from the active profile only
+    pub fn profile_str(&self, key: &str) -> Option<&str> {
+        self.profile_value(key).and_then(Value::as_str)
+    }
+
+    /// whether the active profile has a key of its own, as `key`, `key_cmd`, or `keys`
+    pub fn profile_has_key(&self) -> bool {
+        ["key", "key_cmd", "keys"].iter().any(|name| self.profile_value(name).is_some())
+    }
+
+    /// resolve the api key: a literal `key`, otherwise the output of `key_cmd`;
+    /// a profile that sets any key never falls back to the top-level key
+    pub fn key(&self) -> Result<Option<String>> {
+        let in_profile = self.profile_has_key();
+        let lookup = |key: &str| if in_profile { self.profile_str(key) } else { self.table.get(key).and_then(Value::as_str) };
+        if let Some(key) = lookup("key") {
+            return Ok(Some(key.to_string()));
+        }
+        match lookup("key_cmd") {
+            Some(cmd) => run_key_cmd(cmd).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// the `keys` to use in turn, from the profile or else the top level like `key`
+    pub fn keys(&self) -> Result<Vec<String>> {
+        let keys = match self.profile_has_key() {
+            true => self.profile_value("keys"),
+            false => self.table.get("keys"),
+        };
+        let Some(keys) = keys else {
+            return Ok(Vec::new());
+        };
+        keys.as_array()
+            .and_then(|keys| keys.iter().map(|key| key.as_str().map(str::to_string)).collect::<Option<Vec<_>>>())
+            .filter(|keys| !keys.is_empty())
+            .ok_or_else(|| "keys must be a list of strings.".into())
+    }
+
+    /// the built-in sampler defaults updated with the config's `[sampler]` table,
+    /// then with the active profile's, then with the named preset or the `preset` setting
+    pub fn sampler(&self, preset: Option<&str>) -> Result<SamplerParams> {
+        let mut params = SamplerParams::default();
+        if let Some(table) = self.table.get("sampler") {
+            params.update(table);
+        }
+        if let Some(table) = self.profile_value("sampler") {
+            params.update(table);
+        }
+        if let Some(name) = preset.or_else(|| self.str("preset")) {
+            params.update(&self.preset(name)?);
+        }
+        Ok(params)
+    }
+
+    /// the sampler table of a `[presets.<name>]` in the config, or else of a built-in preset
+    pub fn preset(&self, name: &str) -> Result<Value> {
+        if let Some(table) = self.table.get("presets").and_then(|presets| presets.get(name)) {
+            return Ok(table.clone());
+        }
+        builtin_preset(name).ok_or_else(|| {
+            let mut names: Vec<String> = PRESETS.iter().map(|n| n.to_string()).collect();
+            if let Some(presets) = self.table.get("presets").and_then(Value::as_object) {
+                names.extend(presets.keys().filter(|n| !PRESETS.contains(&n.as_str())).cloned());
+            }
+            format!("No preset named '{}', choose one of {}.", name, names.join(", ")).into()
+        })
+    }
+}
+
+/// a config read from the text of a config file, without a path
+impl FromStr for Config {
+    type Err = crate::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        Ok(Config { table: parse_toml(text)?, path: None, profile: None })
+    }
+}
+
+/// the names of the built-in sampler presets
+pub const PRESETS: &[&str] = &["creative", "balanced", "precise", "code"];
+
+/// the settings of a built-in sampler preset
+fn builtin_preset(name: &str) -> Option<Value> {
+    let preset = match name {
+        "creative" => json!({
+            "temperature": 1.0, "top_p": 0.95, "top_k": 100, "min_p": 0.02,
+            "repetition_penalty": 1.05, "presence_penalty": 0.6, "frequency_penalty": 0.3,
+        }),
+        "balanced" => json!({
+            "temperature": 0.7, "top_p": 0.9, "top_k": 40, "min_p": 0.05,
+            "repetition_penalty": 1.1, "presence_penalty": 0.0, "frequency_penalty": 0.0,
+        }),
+        "precise" => json!({
+            "temperature": 0.2, "top_p": 0.8, "top_k": 20, "min_p": 0.1,
+            "repetition_penalty": 1.05, "presence_penalty": 0.0, "frequency_penalty": 0.0,
+        }),
+        // code suffers from penalties on the tokens it has to repeat
+        "code" => json!({
+            "temperature": 0.1, "top_p": 0.95, "top_k": 40, "min_p": 0.05,
+            "repetition_penalty": 1.0, "presence_penalty": 0.0, "frequency_penalty": 0.0,
+        }),
+        _ => return None,
+    };
+    Some(preset)
+}
+
+/// run a key command through the shell and use its trimmed stdout as the key
+pub fn run_key_cmd(cmd: &str) -> Result<String> {
+    let output = Command::new("sh").arg("-c").arg(cmd).output()
+        .map_err(|e| format!("Could not run key command `{}`: {}", cmd, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Key command `{}` failed with {}: {}",
+            cmd,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ).into());
+    }
+    let key = String::from_utf8(output.stdout)?.trim().to_string();
+    if key.is_empty() {
+        return Err(format!("Key command `{}` printed nothing.", cmd).into());
+    }
+    Ok(key)
+}
+
+/// parse a TOML document, such as the config file or a template, into a JSON object so it
+/// can be treated like an API payload; dates are kept as strings
+pub(crate) fn parse_toml(input: &str) -> Result<Value> {
+    let table = input.parse::<toml::Table>().map_err(|e| {
+        let line = e.span().map_or(1, |span| input[..span.start.min(input.len())].matches('\n').count() + 1);
+        format!("TOML error on line {}: {}", line, e.message().trim().replace('\n', ": "))
+    })?;
+    Ok(toml_to_json(toml::Value::Table(table)))
+}
+
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(text) => Value::String(text),
+        toml::Value::Integer(n) => n.into(),
+        // JSON has no infinity or NaN
+        toml::Value::Float(n) => Number::from_f64(n).map_or(Value::Null, Value::Number),
+        toml::Value::Boolean(b) => b.into(),
+        toml::Value::Datetime(date) => Value::String(date.to_string()),
+        toml::Value::Array(items) => Value::Array(items.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(table) => Value::Object(table.into_iter().map(|(key, value)| (key, toml_to_json(value))).collect::<Map<_, _>>()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_tables_into_json() {
+        let config = parse_toml(r#"
+            model = "gpt-4o"
+            max_cost = 0.5
+            retries = 3
+            stats = true
+            updated = 2024-05-01T10:00:00Z
+
+            [profiles.local]
+            endpoint = "http://localhost:11434/v1"
+            headers = { "X-Team" = "ml" }
+
+            [[models]]
+            name = "a"
+            [[models]]
+            name = "b"
+        "#).unwrap();
+        assert_eq!(config, json!({
+            "model": "gpt-4o",
+            "max_cost": 0.5,
+            "retries": 3,
+            "stats": true,
+            "updated": "2024-05-01T10:00:00Z",
+            "profiles": { "local": { "endpoint": "http://localhost:11434/v1", "headers": { "X-Team": "ml" } } },
+            "models": [{ "name": "a" }, { "name": "b" }],
+        }));
+    }
+
+    #[test]
+    fn rejects_a_table_defined_twice() {
+        let error = parse_toml("[a]\nx = 1\n[b]\n[a]\ny = 2\n").unwrap_err().to_string();
+        assert!(error.starts_with("TOML error on line 4:"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_a_key_defined_twice() {
+        assert!(parse_toml("a = 1\na = 2\n").is_err());
+        assert!(parse_toml("a.b = 1\n[a]\nb = 2\n").is_err());
+    }
+
+    #[test]
+    fn keeps_strings_as_written() {
+        let value = parse_toml("a = 'C:\\path'\nb = \"\"\"\nline\\\n  joined\"\"\"\n").unwrap();
+        assert_eq!(value["a"], "C:\\path");
+        assert_eq!(value["b"], "linejoined");
+    }
+
+    #[test]
+    fn prefers_a_literal_key_to_the_key_command() {
+        let config: Config = "key = \"sk-literal\"\nkey_cmd = \"echo sk-command\"\n".parse().unwrap();
+        assert_eq!(config.key().unwrap().as_deref(), Some("sk-literal"));
+        let config: Config = "key_cmd = \"echo '  sk-command  '\"\n".parse().unwrap();
+        assert_eq!(config.key().unwrap().as_deref(), Some("sk-command"));
+        assert_eq!("model = \"gpt-4o\"".parse::<Config>().unwrap().key().unwrap(), None);
+        assert!("key_cmd = \"true\"".parse::<Config>().unwrap().key().is_err());
+    }
+
+    /// a config with two profiles, with the default one or the named one active
+    fn profiles(name: Option<&str>) -> Result<Config> {
+        let mut config: Config = r#"
+            default_profile = "local"
+            key = "sk-top"
+            model = "gpt-4o-mini"
+
+            [profiles.local]
+            endpoint = "http://localhost:8080/v1"
+
+            [profiles.work]
+            endpoint = "https://api.openai.com/v1"
+            model = "gpt-4o"
+            keys = ["sk-a", "sk-b"]
+        "#.parse()?;
+        config.select_profile(name)?;
+        Ok(config)
+    }
+
+    #[test]
+    fn selects_the_named_or_default_profile() {
+        let local = profiles(None).unwrap();
+        assert_eq!(local.profile.as_ref().map(|(name, _)| name.as_str()), Some("local"));
+        assert_eq!(local.str("endpoint"), Some("http://localhost:8080/v1"));
+        assert_eq!(local.str("model"), Some("gpt-4o-mini"), "settings the profile leaves out come from the top level");
+
+        let work = profiles(Some("work")).unwrap();
+        assert_eq!(work.str("model"), Some("gpt-4o"));
+        let error = profiles(Some("home")).unwrap_err().to_string();
+        assert!(error.starts_with("No profile named 'home'"), "{}", error);
+    }
+
+    #[test]
+    fn keeps_a_profile_with_keys_of_its_own_to_them() {
+        let local = profiles(None).unwrap();
+        assert_eq!(local.key().unwrap().as_deref(), Some("sk-top"));
+        assert!(local.keys().unwrap().is_empty());
+        let work = profiles(Some("work")).unwrap();
+        assert_eq!(work.key().unwrap(), None, "the top-level key is not sent to another provider");
+        assert_eq!(work.keys().unwrap(), ["sk-a", "sk-b"]);
+    }
+
+    #[test]
+    fn applies_presets_over_the_sampler_tables() {
+        let mut config: Config = r#"
+            preset = "precise"
+
+            [sampler]
+            temperature = 0.5
+            max_tokens = 300
+
+            [profiles.poet]
+            sampler = { top_k = 60 }
+            preset = "poetry"
+
+            [presets.poetry]
+            temperature = 1.2
+            presence_penalty = 0.8
+        "#.parse().unwrap();
+        let params = config.sampler(None).unwrap();
+        assert_eq!((params.temperature, params.top_k, params.max_tokens), (0.2, 20, 300), "the preset wins over [sampler]");
+        assert_eq!(config.sampler(Some("creative")).unwrap().temperature, 1.0, "--preset wins over the config's preset");
+
+        config.select_profile(Some("poet")).unwrap();
+        let params = config.sampler(None).unwrap();
+        assert_eq!((params.temperature, params.presence_penalty), (1.2, 0.8));
+        assert_eq!((params.top_k, params.max_tokens), (60, 300), "what the preset leaves out comes from the sampler tables");
+        let error = config.sampler(Some("loud")).unwrap_err().to_string();
+        assert_eq!(error, "No preset named 'loud', choose one of creative, balanced, precise, code, poetry.");
+    }
+
+    #[test]
+    fn resolves_aliases_to_a_model_and_profile() {
+        let config: Config = r#"
+            [aliases]
+            fast = "llama-3.1-8b-instruct@local"
+            plain = "gpt-4o-mini"
+            vertex = { model = "claude-sonnet-4-5@20250929", profile = "gcp" }
+            broken = { profile = "gcp" }
+        "#.parse().unwrap();
+        let alias = |name| config.alias(name).unwrap();
+        assert_eq!(alias("fast"), Some(("llama-3.1-8b-instruct".to_string(), Some("local".to_string()))));
+        assert_eq!(alias("plain"), Some(("gpt-4o-mini".to_string(), None)));
+        // an @ in a table is part of the model name
+        assert_eq!(alias("vertex"), Some(("claude-sonnet-4-5@20250929".to_string(), Some("gcp".to_string()))));
+        assert_eq!(alias("gpt-4o"), None);
+        assert!(config.alias("broken").unwrap_err().to_string().starts_with("The alias 'broken'"));
+    }
+}
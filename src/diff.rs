@@ -0,0 +1,145 @@
+//! Line diffs of two versions of a text, and unified diffs made from them.
+//!
+//! The lines both versions start and end with are matched first, and what is left in
+//! between by the longest common subsequence, which is exact for the small changes a
+//! model makes to a file. A middle too long to compare that way is shown as replaced.
+
+/// a line of a diff
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// beyond this many cells of the longest common subsequence table, the changed middle
+/// is shown as removed and added whole
+const MAX_TABLE: usize = 4_000_000;
+
+/// the lines of `old` and `new`, each as kept, removed, or added, in order
+pub fn lines<'a>(old: &'a str, new: &'a str) -> Vec<Line<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut diff: Vec<Line> = old[..prefix].iter().map(|line| Line::Same(line)).collect();
+    if a.len() * b.len() > MAX_TABLE {
+        diff.extend(a.iter().map(|line| Line::Removed(line)));
+        diff.extend(b.iter().map(|line| Line::Added(line)));
+    } else {
+        // table[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut table = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                table[i * width + j] = if a[i] == b[j] {
+                    table[(i + 1) * width + j + 1] + 1
+                } else {
+                    table[(i + 1) * width + j].max(table[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                diff.push(Line::Same(a[i]));
+                i += 1;
+                j += 1;
+            } else if j == b.len() || (i < a.len() && table[(i + 1) * width + j] >= table[i * width + j + 1]) {
+                diff.push(Line::Removed(a[i]));
+                i += 1;
+            } else {
+                diff.push(Line::Added(b[j]));
+                j += 1;
+            }
+        }
+    }
+    diff.extend(old[old.len() - suffix..].iter().map(|line| Line::Same(line)));
+    diff
+}
+
+/// a unified diff from `old` to `new` with `context` lines around each change, under
+/// `---` and `+++` headers naming them; empty when they have the same lines
+pub fn unified(old: &str, new: &str, old_name: &str, new_name: &str, context: usize) -> String {
+    let diff = lines(old, new);
+    let changed: Vec<usize> = diff.iter().enumerate().filter(|(_, line)| !matches!(line, Line::Same(_))).map(|(i, _)| i).collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+    // the ranges of the diff each hunk covers, merging changes whose context overlaps
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(diff.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    // the line numbers, counted from 1, of each entry of the diff in the old and new text
+    let (mut old_line, mut new_line) = (1, 1);
+    let mut numbers = Vec::with_capacity(diff.len());
+    for line in &diff {
+        numbers.push((old_line, new_line));
+        match line {
+            Line::Same(_) => {
+                old_line += 1;
+                new_line += 1;
+            }
+            Line::Removed(_) => old_line += 1,
+            Line::Added(_) => new_line += 1,
+        }
+    }
+    for (start, end) in ranges {
+        let hunk = &diff[start..end];
+        let old_count = hunk.iter().filter(|line| !matches!(line, Line::Added(_))).count();
+        let new_count = hunk.iter().filter(|line| !matches!(line, Line::Removed(_))).count();
+        // an empty side is numbered by the line before it, as diff does
+        let (old_start, new_start) = numbers[start];
+        let old_start = if old_count == 0 { old_start - 1 } else { old_start };
+        let new_start = if new_count == 0 { new_start - 1 } else { new_start };
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n", old_start, old_count, new_start, new_count));
+        for line in hunk {
+            match line {
+                Line::Same(text) => out.push_str(&format!(" {}\n", text)),
+                Line::Removed(text) => out.push_str(&format!("-{}\n", text)),
+                Line::Added(text) => out.push_str(&format!("+{}\n", text)),
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch;
+
+    #[test]
+    fn finds_the_lines_changed() {
+        assert_eq!(lines("a\nb\nc\n", "a\nx\nc\nd\n"), [Line::Same("a"), Line::Removed("b"), Line::Added("x"), Line::Same("c"), Line::Added("d")]);
+        assert_eq!(unified("same\n", "same\n", "a/f", "b/f", 3), "");
+    }
+
+    #[test]
+    fn makes_hunks_with_context() {
+        let old: String = (1..=12).map(|n| format!("{}\n", n)).collect();
+        let new = old.replacen("2\n", "two\n", 1).replace("11\n", "eleven\n");
+        assert_eq!(
+            unified(&old, &new, "a/f", "b/f", 1),
+            "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n 1\n-2\n+two\n 3\n@@ -10,3 +10,3 @@\n 10\n-11\n+eleven\n 12\n",
+        );
+    }
+
+    #[test]
+    fn makes_diffs_that_apply() {
+        let old = "fn main() {\n    let x = 1;\n}\n";
+        let new = "fn main() {\n    let x = 2;\n    dbg!(x);\n}\n";
+        let edits = patch::parse(&unified(old, new, "a/src/main.rs", "b/src/main.rs", 3));
+        assert_eq!(patch::apply_to(old, "src/main.rs", &edits.iter().collect::<Vec<_>>()).unwrap(), new);
+    }
+}
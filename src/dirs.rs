@@ -0,0 +1,39 @@
+//! XDG base directories used for configuration, data, and caches.
+
+use std::env;
+use std::path::PathBuf;
+
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    match env::var_os(var) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home_dir().join(fallback),
+    }
+}
+
+/// the user's home directory, or the current directory if HOME is unset
+pub fn home_dir() -> PathBuf {
+    env::var_os("HOME").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// a path from the config, with `~/` at its start standing for the home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => home_dir().join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
+/// `$XDG_DATA_HOME/qllm`, usually `~/.local/share/qllm`
+pub fn data_dir() -> PathBuf {
+    xdg_dir("XDG_DATA_HOME", ".local/share").join("qllm")
+}
+
+/// `$XDG_CACHE_HOME/qllm`, usually `~/.cache/qllm`
+pub fn cache_dir() -> PathBuf {
+    xdg_dir("XDG_CACHE_HOME", ".cache").join("qllm")
+}
+
+/// `$XDG_CONFIG_HOME/qllm`, usually `~/.config/qllm`
+pub fn config_dir() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config").join("qllm")
+}
@@ -0,0 +1,365 @@
+//! Eval suites: prompts to run against models, and what their answers must look like.
+//!
+//! A suite is a YAML (or JSON) file such as
+//!
+//! ```yaml
+//! models: [gpt-4o-mini, local]
+//! system: Answer in as few words as you can.
+//! params: { temperature: 0 }
+//! cases:
+//!   - name: capital
+//!     prompt: What is the capital of France?
+//!     expect:
+//!       - contains: Paris
+//!       - regex: '^Paris\.?$'
+//!   - name: person
+//!     prompt: Describe a person as a JSON object.
+//!     expect:
+//!       schema: person.schema.json
+//! ```
+//!
+//! `expect` is a list of checks, or a mapping of them: `contains` (a substring),
+//! `regex`, `exact` (the whole answer, ignoring surrounding whitespace),
+//! `schema` (a JSON Schema, inline or in a file beside the suite), and `judge`.
+//! `contains`, `regex`, `exact`, and `judge` may take a list, each of which must
+//! hold. A case's own `system` and `params` replace or add to the suite's.
+//!
+//! A `judge` check has a second model grade the answer against a rubric, from 1
+//! to 10, and passes at a score of at least its threshold:
+//!
+//! ```yaml
+//! judge_model: gpt-4o
+//! cases:
+//!   - prompt: Explain recursion to a child.
+//!     expect:
+//!       - judge: Uses no jargon and gives an everyday example.
+//!       - judge: { rubric: Is at most three sentences., threshold: 9, model: local }
+//! ```
+
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::{schema, ChatRequest, Message, Result};
+
+/// the score a `judge` check needs to pass when it does not say
+pub const JUDGE_THRESHOLD: f64 = 7.0;
+
+const JUDGE_SYSTEM: &str = "You grade an answer to a question against a rubric. \
+Reply with only a JSON object: {\"score\": an integer from 1, the answer fails the rubric entirely, \
+to 10, it meets the rubric fully, \"reason\": one sentence on why}.";
+
+pub struct Suite {
+    /// the models to run every case against, none for the one from the command line or config
+    pub models: Vec<String>,
+    /// the model that grades the `judge` checks which do not name one
+    pub judge_model: Option<String>,
+    pub cases: Vec<Case>,
+}
+
+pub struct Case {
+    pub name: String,
+    pub prompt: String,
+    pub system: Option<String>,
+    /// sampler settings, as in a `[presets.NAME]` table
+    pub params: Value,
+    pub checks: Vec<Check>,
+}
+
+pub enum Check {
+    Contains(String),
+    Regex(Regex),
+    Exact(String),
+    Schema(Value),
+    Judge(Judge),
+}
+
+/// a rubric for a second model to grade the answer against
+pub struct Judge {
+    pub rubric: String,
+    /// the lowest passing score, from 1 to 10
+    pub threshold: f64,
+    /// the grading model, instead of the suite's
+    pub model: Option<String>,
+}
+
+/// read a suite from a YAML file, or a JSON one by its extension
+pub fn load(path: &Path) -> Result<Suite> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let value = match path.extension().is_some_and(|extension| extension == "json") {
+        true => serde_json::from_str(&text).map_err(|e| format!("{} is not valid JSON: {}", path.display(), e))?,
+        false => yaml(&text).map_err(|e| format!("{} is not valid YAML: {}", path.display(), e))?,
+    };
+    let dir = path.parent().unwrap_or(Path::new("."));
+    Suite::from_json(&value, dir).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// a YAML document as JSON, with anchors and `<<` merge keys resolved, so that cases
+/// may share parts of their settings
+fn yaml(text: &str) -> Result<Value> {
+    let mut value: serde_yaml::Value = serde_yaml::from_str(text)?;
+    value.apply_merge()?;
+    Ok(serde_json::to_value(value)?)
+}
+
+impl Suite {
+    /// a suite from its document, with schema files found relative to `dir`
+    pub fn from_json(value: &Value, dir: &Path) -> std::result::Result<Self, String> {
+        let table = value.as_object().ok_or("a suite must be a mapping with `cases`")?;
+        known_keys(table, &["models", "judge_model", "system", "params", "cases"])?;
+        let models = match table.get("models") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::String(model)) => vec![model.clone()],
+            Some(Value::Array(models)) => models.iter()
+                .map(|model| model.as_str().map(str::to_string).ok_or("`models` must be a list of model names"))
+                .collect::<std::result::Result<_, _>>()?,
+            Some(_) => return Err("`models` must be a list of model names".to_string()),
+        };
+        let judge_model = optional_str(table, "judge_model")?;
+        let system = optional_str(table, "system")?;
+        let params = optional_table(table, "params")?;
+        let cases = table.get("cases").and_then(Value::as_array).filter(|cases| !cases.is_empty()).ok_or("the suite has no `cases` list")?;
+
+        let mut names = HashSet::new();
+        let mut suite = Suite { models, judge_model, cases: Vec::new() };
+        for (i, case) in cases.iter().enumerate() {
+            let case = Case::from_json(case, i, dir).map_err(|e| format!("case {}: {}", i + 1, e))?;
+            if !names.insert(case.name.clone()) {
+                return Err(format!("two cases are named '{}'", case.name));
+            }
+            suite.cases.push(Case {
+                system: case.system.or_else(|| system.clone()),
+                params: merge(&params, &case.params),
+                ..case
+            });
+        }
+        Ok(suite)
+    }
+}
+
+impl Case {
+    fn from_json(value: &Value, index: usize, dir: &Path) -> std::result::Result<Self, String> {
+        let table = value.as_object().ok_or("a case must be a mapping with a `prompt`")?;
+        known_keys(table, &["name", "prompt", "system", "params", "expect"])?;
+        let prompt = table.get("prompt").and_then(Value::as_str).ok_or("a case needs a `prompt` string")?;
+        let checks = match table.get("expect") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Object(checks)) => Check::parse_all(checks, dir)?,
+            Some(Value::Array(items)) => {
+                let mut checks = Vec::new();
+                for item in items {
+                    let item = item.as_object().ok_or("each item of `expect` must be a mapping such as `contains: text`")?;
+                    checks.extend(Check::parse_all(item, dir)?);
+                }
+                checks
+            }
+            Some(_) => return Err("`expect` must be a list or a mapping of checks".to_string()),
+        };
+        Ok(Case {
+            name: optional_str(table, "name")?.unwrap_or_else(|| format!("case {}", index + 1)),
+            prompt: prompt.to_string(),
+            system: optional_str(table, "system")?,
+            params: optional_table(table, "params")?,
+            checks,
+        })
+    }
+
+    /// why the answer fails the checks of the case, nothing if it passes them; the `judge`
+    /// checks are left to the caller, who has the models
+    pub fn failures(&self, answer: &str) -> Vec<String> {
+        self.checks.iter().filter_map(|check| check.failure(answer)).collect()
+    }
+}
+
+impl Check {
+    /// the checks of a mapping such as `{ contains: [a, b], regex: x }`
+    fn parse_all(table: &Map<String, Value>, dir: &Path) -> std::result::Result<Vec<Self>, String> {
+        let mut checks = Vec::new();
+        for (kind, value) in table {
+            match (kind.as_str(), value) {
+                ("contains" | "regex" | "exact" | "judge", Value::Array(values)) => {
+                    for value in values {
+                        checks.push(Check::parse(kind, value, dir)?);
+                    }
+                }
+                _ => checks.push(Check::parse(kind, value, dir)?),
+            }
+        }
+        Ok(checks)
+    }
+
+    fn parse(kind: &str, value: &Value, dir: &Path) -> std::result::Result<Self, String> {
+        // YAML reads `exact: 42` as a number, which is meant as the text
+        let text = || match value {
+            Value::String(text) => Ok(text.clone()),
+            Value::Number(_) | Value::Bool(_) => Ok(value.to_string()),
+            _ => Err(format!("`{}` takes a string", kind)),
+        };
+        match kind {
+            "contains" => Ok(Check::Contains(text()?)),
+            "exact" => Ok(Check::Exact(text()?)),
+            "regex" => {
+                let pattern = text()?;
+                Regex::new(&pattern).map(Check::Regex).map_err(|e| {
+                    // the parser's message draws the pattern over several lines, the reason last
+                    let message = e.to_string();
+                    let reason = message.lines().last().unwrap_or_default().trim_start_matches("error: ").to_string();
+                    format!("invalid regex /{}/: {}", pattern, reason)
+                })
+            }
+            "schema" => match value {
+                Value::Object(_) => Ok(Check::Schema(value.clone())),
+                Value::String(file) => {
+                    let path = dir.join(file);
+                    let text = std::fs::read_to_string(&path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+                    let schema: Value = serde_json::from_str(&text).map_err(|e| format!("{} is not JSON: {}", path.display(), e))?;
+                    // the `json_schema` object of a response format holds the schema under `schema`
+                    match schema.get("schema").filter(|inner| schema.get("name").is_some() && inner.is_object()) {
+                        Some(inner) => Ok(Check::Schema(inner.clone())),
+                        None => Ok(Check::Schema(schema)),
+                    }
+                }
+                _ => Err("`schema` takes a JSON Schema mapping or the name of a file holding one".to_string()),
+            },
+            "judge" => Judge::parse(value).map(Check::Judge),
+            _ => Err(format!("unknown check '{}', expected contains, regex, exact, schema, or judge", kind)),
+        }
+    }
+
+    /// why the answer fails the check, or nothing if it passes
+    pub fn failure(&self, answer: &str) -> Option<String> {
+        match self {
+            Check::Contains(text) => (!answer.contains(text.as_str())).then(|| format!("does not contain {:?}", text)),
+            Check::Regex(regex) => (!regex.is_match(answer)).then(|| format!("does not match /{}/", regex.as_str())),
+            Check::Exact(text) => (answer.trim() != text.trim()).then(|| format!("is not exactly {:?}", text.trim())),
+            Check::Schema(schema) => {
+                let value = match serde_json::from_str::<Value>(unfence(answer)) {
+                    Ok(value) => value,
+                    Err(e) => return Some(format!("is not JSON: {}", e)),
+                };
+                let errors = schema::validate(&value, schema);
+                (!errors.is_empty()).then(|| format!("does not match the schema: {}", errors.join("; ")))
+            }
+            Check::Judge(_) => None,
+        }
+    }
+}
+
+impl Judge {
+    /// a rubric alone, or `{ rubric, threshold, model }`
+    fn parse(value: &Value) -> std::result::Result<Self, String> {
+        let table = match value {
+            Value::String(rubric) => return Ok(Judge { rubric: rubric.clone(), threshold: JUDGE_THRESHOLD, model: None }),
+            Value::Object(table) => table,
+            _ => return Err("`judge` takes a rubric, or a mapping with a `rubric`".to_string()),
+        };
+        known_keys(table, &["rubric", "threshold", "model"])?;
+        let rubric = table.get("rubric").and_then(Value::as_str).ok_or("`judge` needs a `rubric` string")?;
+        let threshold = match table.get("threshold") {
+            None | Some(Value::Null) => JUDGE_THRESHOLD,
+            Some(threshold) => threshold.as_f64().filter(|t| (1.0..=10.0).contains(t)).ok_or("the `threshold` of `judge` must be a score from 1 to 10")?,
+        };
+        Ok(Judge { rubric: rubric.to_string(), threshold, model: optional_str(table, "model")? })
+    }
+
+    /// the request asking `model` to grade the answer to `prompt`
+    pub fn request(&self, model: &str, prompt: &str, answer: &str) -> ChatRequest {
+        let question = format!("Question:\n{}\n\nAnswer:\n{}\n\nRubric:\n{}", prompt, answer, self.rubric);
+        ChatRequest::new(model, vec![Message::system(JUDGE_SYSTEM), Message::user(&question)])
+    }
+
+    /// the score and reason of the grading model's reply, or why there are none
+    pub fn verdict(&self, reply: &str) -> std::result::Result<(f64, String), String> {
+        // the object, even with a code fence or words around it
+        let object = match (reply.find('{'), reply.rfind('}')) {
+            (Some(start), Some(end)) if start < end => serde_json::from_str::<Value>(&reply[start..=end]).ok(),
+            _ => None,
+        };
+        let score = object.as_ref().and_then(|object| match &object["score"] {
+            Value::String(score) => score.trim().parse().ok(),
+            score => score.as_f64(),
+        });
+        match score {
+            Some(score) if (1.0..=10.0).contains(&score) => {
+                let reason = object.as_ref().and_then(|object| object["reason"].as_str()).unwrap_or_default();
+                Ok((score, reason.trim().to_string()))
+            }
+            _ => {
+                let reply = reply.split_whitespace().collect::<Vec<_>>().join(" ");
+                Err(format!("the judge gave no score from 1 to 10: {:?}", reply.chars().take(100).collect::<String>()))
+            }
+        }
+    }
+
+    /// why a score fails the check, or nothing if it passes
+    pub fn failure(&self, score: f64, reason: &str) -> Option<String> {
+        let reason = if reason.is_empty() { String::new() } else { format!(": {}", reason) };
+        (score < self.threshold).then(|| format!("scored {} of 10 on {:?}, below {}{}", score, self.rubric, self.threshold, reason))
+    }
+}
+
+/// the answer without the code fence a model may put around JSON
+fn unfence(answer: &str) -> &str {
+    let answer = answer.trim();
+    let Some(rest) = answer.strip_prefix("```") else {
+        return answer;
+    };
+    let body = rest.split_once('\n').map_or("", |(_, body)| body);
+    body.trim_end().strip_suffix("```").unwrap_or(body).trim()
+}
+
+fn known_keys(table: &Map<String, Value>, keys: &[&str]) -> std::result::Result<(), String> {
+    match table.keys().find(|key| !keys.contains(&key.as_str())) {
+        Some(key) => Err(format!("unknown key '{}'", key)),
+        None => Ok(()),
+    }
+}
+
+fn optional_str(table: &Map<String, Value>, key: &str) -> std::result::Result<Option<String>, String> {
+    match table.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(text)) => Ok(Some(text.clone())),
+        Some(_) => Err(format!("`{}` must be a string", key)),
+    }
+}
+
+fn optional_table(table: &Map<String, Value>, key: &str) -> std::result::Result<Value, String> {
+    match table.get(key) {
+        None | Some(Value::Null) => Ok(Value::Null),
+        Some(value @ Value::Object(_)) => Ok(value.clone()),
+        Some(_) => Err(format!("`{}` must be a mapping", key)),
+    }
+}
+
+/// the settings of `base` with those of `over` on top
+fn merge(base: &Value, over: &Value) -> Value {
+    match (base, over) {
+        (Value::Object(base), Value::Object(over)) => {
+            let mut merged = base.clone();
+            merged.extend(over.clone());
+            Value::Object(merged)
+        }
+        (_, Value::Null) => base.clone(),
+        _ => over.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_yaml_with_merge_keys_and_block_scalars() {
+        let text = "cases:\n  - &first\n    name: a\n    prompt: |\n      one\n      two\n    expect: { contains: x }\n  - <<: *first\n    name: b\n";
+        let value = yaml(text).unwrap();
+        assert_eq!(value["cases"][1], json!({ "name": "b", "prompt": "one\ntwo\n", "expect": { "contains": "x" } }));
+    }
+
+    #[test]
+    fn reports_where_yaml_is_wrong() {
+        let error = yaml("cases:\n  - prompt: x\n   bad: [\n").unwrap_err().to_string();
+        assert!(error.contains("line 3"), "{}", error);
+    }
+}
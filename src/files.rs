@@ -0,0 +1,118 @@
+//! The OpenAI Files API, which holds the inputs of batch jobs and fine-tuning, and the
+//! results they produce.
+
+use serde_json::Value;
+use std::path::Path;
+
+use crate::{api_url, time, QllmClient, Result};
+
+/// a file stored by the server
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileObject {
+    pub id: String,
+    pub filename: String,
+    pub bytes: u64,
+    /// unix seconds
+    pub created_at: u64,
+    /// what the file is for, such as `batch` or `fine-tune`
+    pub purpose: String,
+}
+
+impl FileObject {
+    pub fn from_json(value: &Value) -> Result<FileObject> {
+        Ok(FileObject {
+            id: value["id"].as_str().ok_or("the file object has no id")?.to_string(),
+            filename: value["filename"].as_str().unwrap_or_default().to_string(),
+            bytes: value["bytes"].as_u64().unwrap_or(0),
+            created_at: value["created_at"].as_u64().unwrap_or(0),
+            purpose: value["purpose"].as_str().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+/// upload a file for a purpose such as `batch`, `fine-tune`, `assistants`, or `user_data`
+pub async fn upload(client: &QllmClient, path: &Path, purpose: &str) -> Result<FileObject> {
+    let content = std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let filename = path.file_name().and_then(|name| name.to_str()).unwrap_or("file");
+    upload_content(client, filename, &content, purpose).await
+}
+
+/// upload content made in memory as a file of this name
+pub async fn upload_content(client: &QllmClient, filename: &str, content: &[u8], purpose: &str) -> Result<FileObject> {
+    let (content_type, body) = multipart(&[("purpose", None, purpose.as_bytes()), ("file", Some(filename), content)]);
+    let response: Value = client.post_bytes_once(&api_url(client.endpoint(), "files"), &content_type, body).await?.json().await?;
+    FileObject::from_json(&response)
+}
+
+/// the content of a stored file, such as the results of a batch job
+pub async fn content(client: &QllmClient, id: &str) -> Result<Vec<u8>> {
+    let url = api_url(client.endpoint(), &format!("files/{}/content", id));
+    Ok(client.get(&url).await?.bytes().await?.to_vec())
+}
+
+/// the files stored by the server, newest first, only those for `purpose` if given
+pub async fn list(client: &QllmClient, purpose: Option<&str>) -> Result<Vec<FileObject>> {
+    let mut files = Vec::new();
+    let mut after: Option<String> = None;
+    loop {
+        let mut url = reqwest::Url::parse(&api_url(client.endpoint(), "files"))
+            .map_err(|e| format!("{} is not a valid url: {}", client.endpoint(), e))?;
+        if let Some(purpose) = purpose {
+            url.query_pairs_mut().append_pair("purpose", purpose);
+        }
+        if let Some(after) = &after {
+            url.query_pairs_mut().append_pair("after", after);
+        }
+        let page: Value = client.get(url.as_str()).await?.json().await?;
+        let data = page["data"].as_array().ok_or("the file list has no data array")?;
+        for file in data {
+            files.push(FileObject::from_json(file)?);
+        }
+        // servers that do not page send everything at once, without has_more
+        match (page["has_more"].as_bool(), files.last()) {
+            (Some(true), Some(last)) if !data.is_empty() => after = Some(last.id.clone()),
+            _ => break,
+        }
+    }
+    files.sort_by_key(|file| std::cmp::Reverse(file.created_at));
+    Ok(files)
+}
+
+/// delete a file from the server
+pub async fn delete(client: &QllmClient, id: &str) -> Result<()> {
+    let response: Value = client.delete(&api_url(client.endpoint(), &format!("files/{}", id))).await?.json().await?;
+    match response["deleted"].as_bool() {
+        Some(false) => Err(format!("The server did not delete {}.", id).into()),
+        _ => Ok(()),
+    }
+}
+
+/// encode `multipart/form-data` fields, each a name, a file name if it is a file, and
+/// the content, returning the content type with its boundary and the body
+fn multipart(fields: &[(&str, Option<&str>, &[u8])]) -> (String, Vec<u8>) {
+    // the boundary must not occur in any of the content
+    let mut seed = time::now_ms();
+    let boundary = loop {
+        let boundary = format!("qllm-boundary-{:016x}", seed);
+        if !fields.iter().any(|(_, _, content)| content.windows(boundary.len()).any(|window| window == boundary.as_bytes())) {
+            break boundary;
+        }
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+    };
+    let mut body = Vec::new();
+    for (name, filename, content) in fields {
+        body.extend(format!("--{}\r\n", boundary).into_bytes());
+        match filename {
+            Some(filename) => body.extend(format!(
+                "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+                name,
+                filename.replace('"', "%22")
+            ).into_bytes()),
+            None => body.extend(format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name).into_bytes()),
+        }
+        body.extend_from_slice(content);
+        body.extend(b"\r\n");
+    }
+    body.extend(format!("--{}--\r\n", boundary).into_bytes());
+    (format!("multipart/form-data; boundary={}", boundary), body)
+}
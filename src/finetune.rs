@@ -0,0 +1,341 @@
+//! OpenAI fine-tuning: checking a training file before it is uploaded, and starting,
+//! following, and cancelling the jobs that train on it.
+
+use serde_json::{json, Value};
+
+use crate::{api_url, QllmClient, Result};
+
+/// the fewest examples the API trains on
+pub const MIN_EXAMPLES: usize = 10;
+
+/// a fine-tuning job, as the server last reported it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Job {
+    pub id: String,
+    /// the model being trained
+    pub model: String,
+    /// validating_files, queued, running, succeeded, failed, or cancelled
+    pub status: String,
+    /// the name of the trained model, once the job has succeeded
+    pub fine_tuned_model: Option<String>,
+    pub training_file: String,
+    pub validation_file: Option<String>,
+    /// unix seconds
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    pub trained_tokens: Option<u64>,
+    /// why the job failed
+    pub error: Option<String>,
+}
+
+impl Job {
+    pub fn from_json(value: &Value) -> Result<Job> {
+        let text = |name: &str| value[name].as_str().map(str::to_string);
+        let error = value["error"]["message"].as_str().filter(|message| !message.is_empty()).map(|message| {
+            match value["error"]["param"].as_str() {
+                Some(param) => format!("{} ({})", message, param),
+                None => message.to_string(),
+            }
+        });
+        Ok(Job {
+            id: value["id"].as_str().ok_or("the fine-tuning job has no id")?.to_string(),
+            model: text("model").unwrap_or_default(),
+            status: text("status").unwrap_or_default(),
+            fine_tuned_model: text("fine_tuned_model"),
+            training_file: text("training_file").unwrap_or_default(),
+            validation_file: text("validation_file"),
+            created_at: value["created_at"].as_u64().unwrap_or(0),
+            finished_at: value["finished_at"].as_u64(),
+            trained_tokens: value["trained_tokens"].as_u64(),
+            error,
+        })
+    }
+
+    /// whether the job has stopped
+    pub fn is_done(&self) -> bool {
+        matches!(self.status.as_str(), "succeeded" | "failed" | "cancelled")
+    }
+}
+
+/// a message of a job's log
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub id: String,
+    /// unix seconds
+    pub created_at: u64,
+    /// info, warn, or error
+    pub level: String,
+    pub message: String,
+}
+
+/// the kinds of training example, each trained on by its own method
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// conversations ending in what the assistant should say, for supervised training
+    Chat,
+    /// a conversation with a better and a worse answer, for direct preference optimization
+    Preference,
+}
+
+impl Format {
+    /// the `method` type the API trains this format with
+    pub fn method(self) -> &'static str {
+        match self {
+            Format::Chat => "supervised",
+            Format::Preference => "dpo",
+        }
+    }
+}
+
+/// what to train, and how
+#[derive(Clone, Debug, Default)]
+pub struct JobRequest {
+    pub model: String,
+    pub training_file: String,
+    /// `supervised` or `dpo`, left to the server if not given
+    pub method: Option<String>,
+    pub validation_file: Option<String>,
+    /// added to the name of the trained model
+    pub suffix: Option<String>,
+    pub epochs: Option<u32>,
+    pub batch_size: Option<u32>,
+    pub learning_rate_multiplier: Option<f64>,
+    pub seed: Option<i64>,
+}
+
+impl JobRequest {
+    pub fn to_json(&self) -> Value {
+        let mut body = json!({ "model": self.model, "training_file": self.training_file });
+        if let Some(file) = &self.validation_file {
+            body["validation_file"] = json!(file);
+        }
+        if let Some(suffix) = &self.suffix {
+            body["suffix"] = json!(suffix);
+        }
+        if let Some(seed) = self.seed {
+            body["seed"] = json!(seed);
+        }
+        let mut hyperparameters = serde_json::Map::new();
+        if let Some(epochs) = self.epochs {
+            hyperparameters.insert("n_epochs".into(), json!(epochs));
+        }
+        if let Some(size) = self.batch_size {
+            hyperparameters.insert("batch_size".into(), json!(size));
+        }
+        if let Some(multiplier) = self.learning_rate_multiplier {
+            hyperparameters.insert("learning_rate_multiplier".into(), json!(multiplier));
+        }
+        // the hyperparameters belong to the method, when it is given
+        match &self.method {
+            Some(method) => {
+                body["method"] = json!({ "type": method });
+                if !hyperparameters.is_empty() {
+                    body["method"][method] = json!({ "hyperparameters": hyperparameters });
+                }
+            }
+            None if !hyperparameters.is_empty() => body["hyperparameters"] = Value::Object(hyperparameters),
+            None => {}
+        }
+        body
+    }
+}
+
+fn jobs_url(client: &QllmClient, path: &str) -> String {
+    api_url(client.endpoint(), &format!("fine_tuning/jobs{}", path))
+}
+
+/// start a job training on files already uploaded
+pub async fn create(client: &QllmClient, request: &JobRequest) -> Result<Job> {
+    let response: Value = client.post_once(&jobs_url(client, ""), &request.to_json()).await?.json().await?;
+    Job::from_json(&response)
+}
+
+/// the most recent jobs, newest first
+pub async fn list(client: &QllmClient, limit: u32) -> Result<Vec<Job>> {
+    let response: Value = client.get(&jobs_url(client, &format!("?limit={}", limit))).await?.json().await?;
+    let data = response["data"].as_array().ok_or("the job list has no data array")?;
+    data.iter().map(Job::from_json).collect()
+}
+
+/// the job as it is now
+pub async fn retrieve(client: &QllmClient, id: &str) -> Result<Job> {
+    let response: Value = client.get(&jobs_url(client, &format!("/{}", id))).await?.json().await?;
+    Job::from_json(&response)
+}
+
+/// stop a job that has not finished
+pub async fn cancel(client: &QllmClient, id: &str) -> Result<Job> {
+    let response: Value = client.post_once(&jobs_url(client, &format!("/{}/cancel", id)), &json!({})).await?.json().await?;
+    Job::from_json(&response)
+}
+
+/// the latest `limit` events of a job, oldest first
+pub async fn events(client: &QllmClient, id: &str, limit: u32) -> Result<Vec<Event>> {
+    let response: Value = client.get(&jobs_url(client, &format!("/{}/events?limit={}", id, limit))).await?.json().await?;
+    let data = response["data"].as_array().ok_or("the event list has no data array")?;
+    // the server sends the newest first
+    let mut events = data.iter()
+        .map(|event| Event {
+            id: event["id"].as_str().unwrap_or_default().to_string(),
+            created_at: event["created_at"].as_u64().unwrap_or(0),
+            level: event["level"].as_str().unwrap_or("info").to_string(),
+            message: event["message"].as_str().unwrap_or_default().to_string(),
+        })
+        .collect::<Vec<_>>();
+    events.reverse();
+    Ok(events)
+}
+
+/// check a JSONL training file against the formats the API trains on: chat examples,
+/// each a `messages` array ending in what the assistant should say, or preference
+/// examples with an `input` and a `preferred_output` and `non_preferred_output`.
+/// Returns the format and number of examples, or the problems found, each with its line.
+pub fn validate(text: &str) -> std::result::Result<(Format, usize), Vec<String>> {
+    let mut problems = Vec::new();
+    let mut examples = 0;
+    let mut format = None;
+    for (number, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let mut problem = |message: String| problems.push(format!("line {}: {}", number + 1, message));
+        examples += 1;
+        let example: Value = match serde_json::from_str(line) {
+            Ok(example) => example,
+            Err(e) => {
+                problem(format!("not valid JSON: {}", e));
+                continue;
+            }
+        };
+        let this = match (example.get("messages"), example.get("input")) {
+            (Some(_), _) => Format::Chat,
+            (None, Some(_)) => Format::Preference,
+            _ => {
+                problem("expected an object with `messages`, or an `input` with preferred and non-preferred outputs".to_string());
+                continue;
+            }
+        };
+        match format {
+            None => format = Some(this),
+            Some(format) if format != this => problem(format!("a {} example in a file of {} examples", this.method(), format.method())),
+            Some(_) => {}
+        }
+        let result = match this {
+            Format::Chat => check_messages(&example["messages"], true),
+            Format::Preference => check_preference(&example),
+        };
+        if let Err(message) = result {
+            problem(message);
+        }
+    }
+    if examples < MIN_EXAMPLES && problems.is_empty() {
+        problems.push(format!("there are {} examples, and training needs at least {}", examples, MIN_EXAMPLES));
+    }
+    match (problems.is_empty(), format) {
+        (true, Some(format)) => Ok((format, examples)),
+        _ => Err(problems),
+    }
+}
+
+/// check a list of chat messages; an example to train on must include the assistant
+fn check_messages(messages: &Value, needs_assistant: bool) -> std::result::Result<(), String> {
+    let messages = messages.as_array().ok_or("`messages` is not an array")?;
+    for (index, message) in messages.iter().enumerate() {
+        let role = message["role"].as_str().ok_or_else(|| format!("message {} has no role", index + 1))?;
+        if !matches!(role, "system" | "developer" | "user" | "assistant" | "tool" | "function") {
+            return Err(format!("message {} has the unknown role {:?}", index + 1, role));
+        }
+        let calls = message.get("tool_calls").is_some() || message.get("function_call").is_some();
+        match &message["content"] {
+            Value::String(_) | Value::Array(_) => {}
+            Value::Null if role == "assistant" && calls => {}
+            Value::Null => return Err(format!("message {} has no content", index + 1)),
+            _ => return Err(format!("the content of message {} is not a string", index + 1)),
+        }
+        if let Some(weight) = message.get("weight") {
+            if role != "assistant" || !matches!(weight.as_u64(), Some(0 | 1)) {
+                return Err(format!("message {} has a weight, which only assistant messages may have, of 0 or 1", index + 1));
+            }
+        }
+    }
+    if needs_assistant && !messages.iter().any(|message| message["role"] == "assistant") {
+        return Err("there is no assistant message to learn from".to_string());
+    }
+    Ok(())
+}
+
+/// check a preference example: the conversation so far, then a better and a worse answer
+fn check_preference(example: &Value) -> std::result::Result<(), String> {
+    check_messages(&example["input"]["messages"], false).map_err(|e| format!("in `input`, {}", e))?;
+    for output in ["preferred_output", "non_preferred_output"] {
+        let messages = example.get(output).ok_or_else(|| format!("there is no `{}`", output))?;
+        check_messages(messages, true).map_err(|e| format!("in `{}`, {}", output, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `count` lines of the example
+    fn file(example: &Value, count: usize) -> String {
+        vec![example.to_string(); count].join("\n")
+    }
+
+    fn chat() -> Value {
+        json!({ "messages": [
+            { "role": "system", "content": "You answer in French." },
+            { "role": "user", "content": "Hello" },
+            { "role": "assistant", "content": "Bonjour", "weight": 1 },
+        ] })
+    }
+
+    fn preference() -> Value {
+        json!({
+            "input": { "messages": [{ "role": "user", "content": "Hello" }] },
+            "preferred_output": [{ "role": "assistant", "content": "Bonjour" }],
+            "non_preferred_output": [{ "role": "assistant", "content": "Hi" }],
+        })
+    }
+
+    #[test]
+    fn accepts_chat_and_preference_files() {
+        assert_eq!(validate(&format!("{}\n\n", file(&chat(), 12))), Ok((Format::Chat, 12)));
+        assert_eq!(validate(&file(&preference(), 10)), Ok((Format::Preference, 10)));
+    }
+
+    #[test]
+    fn needs_enough_examples() {
+        assert_eq!(validate(&file(&chat(), 9)), Err(vec!["there are 9 examples, and training needs at least 10".to_string()]));
+    }
+
+    #[test]
+    fn reports_each_problem_with_its_line() {
+        let mut lines = vec![chat().to_string(); 12];
+        lines[1] = "{\"messages\": [".to_string();
+        lines[3] = json!({ "messages": [{ "role": "user", "content": "Hello" }] }).to_string();
+        lines[5] = json!({ "messages": [{ "role": "robot", "content": "Beep" }] }).to_string();
+        lines[7] = json!({ "messages": [{ "role": "user", "content": "Hi", "weight": 1 }, { "role": "assistant", "content": "Salut" }] }).to_string();
+        lines[9] = preference().to_string();
+        lines[11] = json!({ "prompt": "Hello", "completion": "Bonjour" }).to_string();
+        let problems = validate(&lines.join("\n")).unwrap_err();
+        assert_eq!(problems.len(), 6, "{:#?}", problems);
+        assert!(problems[0].starts_with("line 2: not valid JSON"));
+        assert_eq!(problems[1..], [
+            "line 4: there is no assistant message to learn from",
+            "line 6: message 1 has the unknown role \"robot\"",
+            "line 8: message 1 has a weight, which only assistant messages may have, of 0 or 1",
+            "line 10: a dpo example in a file of supervised examples",
+            "line 12: expected an object with `messages`, or an `input` with preferred and non-preferred outputs",
+        ]);
+    }
+
+    #[test]
+    fn lets_assistant_messages_that_call_tools_leave_out_the_content() {
+        let example = json!({ "messages": [
+            { "role": "user", "content": "Weather in Oslo?" },
+            { "role": "assistant", "content": null, "tool_calls": [{ "id": "call_1", "type": "function", "function": { "name": "weather", "arguments": "{}" } }] },
+        ] });
+        assert_eq!(validate(&file(&example, 10)), Ok((Format::Chat, 10)));
+        let problems = validate(&file(&json!({ "messages": [{ "role": "user", "content": null }, { "role": "assistant", "content": "?" }] }), 10)).unwrap_err();
+        assert_eq!(problems[0], "line 1: message 1 has no content");
+    }
+}
@@ -0,0 +1,264 @@
+//! Splitting a streamed response body into the payloads of individual events.
+
+use crate::sse::SseParser;
+use crate::utf8::Utf8Decoder;
+
+/// how a provider frames the events of a streamed response
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// server-sent events, the payload being the `data:` field
+    Sse,
+    /// newline-delimited JSON, one event per line, as Ollama streams
+    Ndjson,
+    /// one JSON array whose elements arrive over time, as Gemini streams without `alt=sse`
+    JsonArray,
+    /// the binary `application/vnd.amazon.eventstream` framing used by AWS; each
+    /// payload becomes `{"event": <event or exception type>, "exception": bool, "body": <payload>}`
+    AwsEventStream,
+}
+
+/// turns body chunks into event payloads
+#[derive(Debug)]
+pub struct Framer {
+    framing: Framing,
+    /// raw bytes, for the framings that cut payloads out of binary or JSON structure
+    buffer: Vec<u8>,
+    /// decoded text, for the line based framings
+    text: String,
+    decoder: Utf8Decoder,
+    sse: SseParser,
+}
+
+impl Framer {
+    pub fn new(framing: Framing) -> Self {
+        Framer { framing, buffer: Vec::new(), text: String::new(), decoder: Utf8Decoder::new(), sse: SseParser::new() }
+    }
+
+    /// the payloads still buffered once the body has ended
+    pub fn finish(&mut self) -> Vec<String> {
+        match self.framing {
+            Framing::Sse => self.sse.finish().map(|event| event.data).into_iter().collect(),
+            Framing::Ndjson => {
+                let tail = self.decoder.finish();
+                self.text.push_str(&tail);
+                let rest = std::mem::take(&mut self.text).trim().to_string();
+                if rest.is_empty() { Vec::new() } else { vec![rest] }
+            }
+            Framing::JsonArray | Framing::AwsEventStream => Vec::new(),
+        }
+    }
+
+    /// feed the next chunk of the body, returning the payloads it completed
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<String> {
+        match self.framing {
+            Framing::Sse => self.sse.feed(bytes).into_iter().map(|event| event.data).collect(),
+            Framing::Ndjson => {
+                let text = self.decoder.decode(bytes);
+                self.text.push_str(&text);
+                let mut lines = Vec::new();
+                while let Some(end) = self.text.find('\n') {
+                    let line: String = self.text.drain(..=end).collect();
+                    let line = line.trim();
+                    if !line.is_empty() {
+                        lines.push(line.to_string());
+                    }
+                }
+                lines
+            }
+            Framing::JsonArray => {
+                self.buffer.extend_from_slice(bytes);
+                self.json_objects()
+            }
+            Framing::AwsEventStream => {
+                self.buffer.extend_from_slice(bytes);
+                self.aws_messages()
+            }
+        }
+    }
+
+    /// cut every complete event-stream message out of the buffer
+    ///
+    /// A message is a 12 byte prelude (total length, headers length, prelude
+    /// CRC), the headers, the payload, and a trailing 4 byte CRC.
+    fn aws_messages(&mut self) -> Vec<String> {
+        let mut messages = Vec::new();
+        while self.buffer.len() >= 12 {
+            let be32 = |b: &[u8]| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize;
+            let total = be32(&self.buffer[0..4]);
+            let headers_len = be32(&self.buffer[4..8]);
+            if total < 16 + headers_len {
+                // not an event stream after all, give up on the rest
+                self.buffer.clear();
+                break;
+            }
+            if self.buffer.len() < total {
+                break;
+            }
+            let message: Vec<u8> = self.buffer.drain(..total).collect();
+            let headers = aws_headers(&message[12..12 + headers_len]);
+            let payload = &message[12 + headers_len..total - 4];
+            let header = |name: &str| headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone());
+            let exception = header(":message-type").as_deref() == Some("exception");
+            let event = header(":event-type").or_else(|| header(":exception-type")).unwrap_or_default();
+            let body = serde_json::from_slice::<serde_json::Value>(payload)
+                .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(payload).into_owned()));
+            messages.push(serde_json::json!({ "event": event, "exception": exception, "body": body }).to_string());
+        }
+        messages
+    }
+
+    /// cut every complete top-level object out of the buffer, skipping the
+    /// array brackets and commas between them
+    fn json_objects(&mut self) -> Vec<String> {
+        let mut objects = Vec::new();
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut start = 0;
+        let mut consumed = 0;
+        for (i, &b) in self.buffer.iter().enumerate() {
+            if depth == 0 {
+                if b == b'{' {
+                    start = i;
+                    depth = 1;
+                } else {
+                    consumed = i + 1;
+                }
+                continue;
+            }
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if b == b'\\' {
+                    escaped = true;
+                } else if b == b'"' {
+                    in_string = false;
+                }
+                continue;
+            }
+            match b {
+                b'"' => in_string = true,
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        objects.push(String::from_utf8_lossy(&self.buffer[start..=i]).into_owned());
+                        consumed = i + 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.buffer.drain(..consumed);
+        objects
+    }
+}
+
+/// the string-valued headers of an event-stream message; other header types are skipped
+fn aws_headers(mut bytes: &[u8]) -> Vec<(String, String)> {
+    let mut headers = Vec::new();
+    while let Some((&name_len, rest)) = bytes.split_first() {
+        let name_len = name_len as usize;
+        if rest.len() < name_len + 1 {
+            break;
+        }
+        let name = String::from_utf8_lossy(&rest[..name_len]).into_owned();
+        let kind = rest[name_len];
+        let rest = &rest[name_len + 1..];
+        // the size of each value type, strings and byte arrays carry a 2 byte length
+        let (value_len, offset) = match kind {
+            0 | 1 => (0, 0),
+            2 => (1, 0),
+            3 => (2, 0),
+            4 => (4, 0),
+            5 | 8 => (8, 0),
+            9 => (16, 0),
+            6 | 7 if rest.len() >= 2 => (u16::from_be_bytes([rest[0], rest[1]]) as usize, 2),
+            _ => break,
+        };
+        if rest.len() < offset + value_len {
+            break;
+        }
+        if kind == 7 {
+            headers.push((name, String::from_utf8_lossy(&rest[offset..offset + value_len]).into_owned()));
+        }
+        bytes = &rest[offset + value_len..];
+    }
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    /// the payloads of a body fed to a framer in chunks of `size` bytes
+    fn payloads(framing: Framing, body: &[u8], size: usize) -> Vec<String> {
+        let mut framer = Framer::new(framing);
+        let mut payloads: Vec<String> = body.chunks(size).flat_map(|chunk| framer.feed(chunk)).collect();
+        payloads.extend(framer.finish());
+        payloads
+    }
+
+    /// an event-stream message with string headers; the CRCs are not checked, so they are zeros
+    fn aws_message(headers: &[(&str, &str)], payload: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for (name, value) in headers {
+            encoded.push(name.len() as u8);
+            encoded.extend_from_slice(name.as_bytes());
+            encoded.push(7);
+            encoded.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            encoded.extend_from_slice(value.as_bytes());
+        }
+        let total = 12 + encoded.len() + payload.len() + 4;
+        let mut message = Vec::new();
+        message.extend_from_slice(&(total as u32).to_be_bytes());
+        message.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+        message.extend_from_slice(&[0; 4]);
+        message.extend_from_slice(&encoded);
+        message.extend_from_slice(payload);
+        message.extend_from_slice(&[0; 4]);
+        message
+    }
+
+    #[test]
+    fn frames_ndjson_lines() {
+        let body = "{\"a\": \"ü\"}\n\n{\"b\": 2}\r\n{\"c\": 3}".as_bytes();
+        for size in [1, 3, body.len()] {
+            assert_eq!(payloads(Framing::Ndjson, body, size), ["{\"a\": \"ü\"}", "{\"b\": 2}", "{\"c\": 3}"], "chunks of {}", size);
+        }
+    }
+
+    #[test]
+    fn frames_sse_data() {
+        assert_eq!(payloads(Framing::Sse, b"data: 1\n\ndata: 2\n\n", 4), ["1", "2"]);
+    }
+
+    #[test]
+    fn frames_the_objects_of_a_json_array() {
+        let body = "[{\"text\": \"a } ] \\\" {\"},\n {\"nested\": {\"list\": [1, {\"x\": 2}]}}]".as_bytes();
+        for size in [1, 5, body.len()] {
+            let objects = payloads(Framing::JsonArray, body, size);
+            let objects: Vec<Value> = objects.iter().map(|object| serde_json::from_str(object).unwrap()).collect();
+            assert_eq!(objects, [json!({ "text": "a } ] \" {" }), json!({ "nested": { "list": [1, { "x": 2 }] } })]);
+        }
+    }
+
+    #[test]
+    fn frames_aws_event_stream_messages() {
+        let mut body = aws_message(&[(":message-type", "event"), (":event-type", "chunk")], b"{\"bytes\": \"eyJ9\"}");
+        body.extend(aws_message(&[(":message-type", "exception"), (":exception-type", "throttlingException")], b"slow down"));
+        for size in [1, 7, body.len()] {
+            let messages: Vec<Value> = payloads(Framing::AwsEventStream, &body, size).iter().map(|m| serde_json::from_str(m).unwrap()).collect();
+            assert_eq!(messages, [
+                json!({ "event": "chunk", "exception": false, "body": { "bytes": "eyJ9" } }),
+                json!({ "event": "throttlingException", "exception": true, "body": "slow down" }),
+            ]);
+        }
+    }
+
+    #[test]
+    fn gives_up_on_a_body_that_is_not_an_event_stream() {
+        assert!(payloads(Framing::AwsEventStream, b"<html>not an event stream</html>", 8).is_empty());
+    }
+}
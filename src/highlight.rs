@@ -0,0 +1,191 @@
+//! Syntax highlighting of fenced code blocks, with syntect's grammars.
+//!
+//! The grammars only tell which parts of a line are keywords, strings, comments, and
+//! numbers; those are coloured with the styles of the same names in the theme, so that
+//! `[theme]` and `--no-color` apply to code as they do to the rest of an answer.
+//! Without the `highlight` feature no language is known and code keeps one colour.
+
+#[cfg(feature = "highlight")]
+use std::sync::OnceLock;
+#[cfg(feature = "highlight")]
+use syntect::easy::ScopeRangeIterator;
+#[cfg(feature = "highlight")]
+use syntect::parsing::{ParseState, Scope, ScopeStack, SyntaxReference, SyntaxSet};
+
+#[cfg(feature = "highlight")]
+use crate::theme;
+
+/// the state of highlighting one code block, carried from line to line so that strings
+/// and comments may span lines
+#[cfg(feature = "highlight")]
+#[derive(Debug)]
+pub struct Highlighter {
+    state: ParseState,
+    stack: ScopeStack,
+}
+
+#[cfg(not(feature = "highlight"))]
+#[derive(Debug)]
+pub struct Highlighter;
+
+#[cfg(feature = "highlight")]
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// the grammar of a fence info string such as `rust` or `python title="x.py"`
+#[cfg(feature = "highlight")]
+fn syntax(info: &str) -> Option<&'static SyntaxReference> {
+    let name = info.trim().split(|c: char| c.is_whitespace() || c == ',' || c == '{').next()?.to_lowercase();
+    // names the bundled grammars do not go by, and languages close enough to one of them
+    let name = match name.as_str() {
+        "typescript" | "ts" | "tsx" | "jsx" | "mjs" => "js",
+        "golang" => "go",
+        "shell" | "console" | "shellsession" => "sh",
+        "csharp" => "cs",
+        "c++" => "cpp",
+        name => name,
+    };
+    syntaxes().find_syntax_by_token(name).filter(|syntax| syntax.name != "Plain Text")
+}
+
+/// a highlighter for a code block, if its fence names a language that is known
+#[cfg(feature = "highlight")]
+pub fn highlighter(info: &str) -> Option<Highlighter> {
+    Some(Highlighter { state: ParseState::new(syntax(info)?), stack: ScopeStack::new() })
+}
+
+#[cfg(not(feature = "highlight"))]
+pub fn highlighter(_info: &str) -> Option<Highlighter> {
+    None
+}
+
+/// the parts of the code the theme has a style for, by the scopes that name them
+#[cfg(feature = "highlight")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Part {
+    Keyword,
+    String,
+    Comment,
+    Number,
+}
+
+#[cfg(feature = "highlight")]
+fn parts() -> &'static [(Scope, Part)] {
+    static PARTS: OnceLock<Vec<(Scope, Part)>> = OnceLock::new();
+    PARTS.get_or_init(|| {
+        [
+            ("comment", Part::Comment),
+            ("string", Part::String),
+            ("constant.numeric", Part::Number),
+            ("constant.language", Part::Keyword),
+            ("keyword", Part::Keyword),
+            ("storage", Part::Keyword),
+        ]
+        .iter()
+        .map(|(scope, part)| (Scope::new(scope).expect("a valid scope"), *part))
+        .collect()
+    })
+}
+
+#[cfg(feature = "highlight")]
+impl Highlighter {
+    /// colour one line of the block, given without its newline
+    pub fn line(&mut self, line: &str) -> String {
+        let theme = theme::out();
+        let Some(spans) = self.spans(line) else {
+            return format!("{}{}{}", theme.code, line, theme.reset);
+        };
+        let mut out = String::new();
+        for (piece, part) in spans {
+            let style = match part {
+                Some(Part::Keyword) => &theme.keyword,
+                Some(Part::String) => &theme.string,
+                Some(Part::Comment) => &theme.comment,
+                Some(Part::Number) => &theme.number,
+                None => "",
+            };
+            if style.is_empty() {
+                out.push_str(piece);
+            } else {
+                out.push_str(&format!("{}{}{}", style, piece, theme.reset));
+            }
+        }
+        out
+    }
+
+    /// the pieces of a line with the part of the code each is, or none if the grammar
+    /// fails on it
+    fn spans<'a>(&mut self, line: &'a str) -> Option<Vec<(&'a str, Option<Part>)>> {
+        // the grammars expect each line to end with its newline
+        let text = format!("{}\n", line);
+        let ops = self.state.parse_line(&text, syntaxes()).ok()?;
+        let mut spans = Vec::new();
+        for (range, op) in ScopeRangeIterator::new(&ops, &text) {
+            self.stack.apply(op).ok()?;
+            let range = range.start.min(line.len())..range.end.min(line.len());
+            if range.is_empty() {
+                continue;
+            }
+            // the innermost scope with a style decides, so that an escape in a string is still a string
+            let part = self.stack.as_slice().iter().rev()
+                .find_map(|scope| parts().iter().find(|(prefix, _)| prefix.is_prefix_of(*scope)).map(|(_, part)| *part));
+            spans.push((&line[range], part));
+        }
+        Some(spans)
+    }
+}
+
+#[cfg(not(feature = "highlight"))]
+impl Highlighter {
+    pub fn line(&mut self, line: &str) -> String {
+        line.to_string()
+    }
+}
+
+#[cfg(all(test, feature = "highlight"))]
+mod tests {
+    use super::*;
+
+    /// the pieces of each line that are `part`
+    fn pieces(info: &str, lines: &[&str], part: Part) -> Vec<String> {
+        let mut highlighter = highlighter(info).unwrap();
+        lines.iter()
+            .flat_map(|line| highlighter.spans(line).unwrap())
+            .filter(|(_, p)| *p == Some(part))
+            .map(|(piece, _)| piece.to_string())
+            .filter(|piece| !piece.trim().is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn knows_languages_by_name_and_alias() {
+        for info in ["rust", "Python", "py title=\"x.py\"", "ts", "golang", "shell", "json", "yaml", "sql"] {
+            assert!(highlighter(info).is_some(), "{}", info);
+        }
+        assert!(highlighter("").is_none());
+        assert!(highlighter("nonsense").is_none());
+    }
+
+    #[test]
+    fn finds_keywords_strings_and_numbers() {
+        let line = ["fn main() { let x = \"hi\\n\"; let y = 42; }"];
+        assert!(pieces("rust", &line, Part::Keyword).contains(&"fn".to_string()));
+        assert_eq!(pieces("rust", &line, Part::String).concat(), "\"hi\\n\"");
+        assert_eq!(pieces("rust", &line, Part::Number), ["42"]);
+    }
+
+    #[test]
+    fn carries_a_comment_over_lines() {
+        let lines = ["x = 1 /* a", "still */ y"];
+        assert_eq!(pieces("c", &lines, Part::Comment).concat(), "/* astill */");
+    }
+
+    #[test]
+    fn keeps_the_text_as_it_is() {
+        let mut highlighter = highlighter("python").unwrap();
+        let line = "def f(a):  # ünïcode";
+        assert_eq!(highlighter.spans(line).unwrap().iter().map(|(piece, _)| *piece).collect::<String>(), line);
+    }
+}
@@ -0,0 +1,283 @@
+//! Persistent history of every prompt/response exchange.
+//!
+//! Exchanges are stored in the SQLite database `history.db` in the qllm data
+//! directory. Each exchange belongs to a conversation, so a conversation can later
+//! be rebuilt from its exchanges in order. The database hands out the ids of both,
+//! so that qllm runs at the same time never share one.
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::{dirs, Message, Result};
+
+/// one prompt/response pair along with the request that produced it
+#[derive(Clone, Debug, Default)]
+pub struct Exchange {
+    pub id: u64,
+    pub conversation: u64,
+    /// unix time in seconds at which the request was sent
+    pub timestamp: u64,
+    pub duration_ms: u64,
+    pub endpoint: String,
+    pub model: String,
+    pub params: Value,
+    pub system: String,
+    pub prompt: String,
+    pub response: String,
+    pub usage: Option<Value>,
+    /// the estimated cost in dollars, when the model has a known price
+    pub cost: Option<f64>,
+}
+
+impl Exchange {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "conversation": self.conversation,
+            "timestamp": self.timestamp,
+            "duration_ms": self.duration_ms,
+            "endpoint": self.endpoint,
+            "model": self.model,
+            "params": self.params,
+            "system": self.system,
+            "prompt": self.prompt,
+            "response": self.response,
+            "usage": self.usage,
+            "cost": self.cost,
+        })
+    }
+
+    /// the user and assistant messages of this exchange
+    pub fn messages(&self) -> Vec<Message> {
+        vec![Message::user(&self.prompt), Message::assistant(&self.response)]
+    }
+}
+
+/// the on-disk store of exchanges
+#[derive(Clone, Debug)]
+pub struct History {
+    path: PathBuf,
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE conversations (id INTEGER PRIMARY KEY AUTOINCREMENT);
+    CREATE TABLE exchanges (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        conversation INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL,
+        duration_ms INTEGER NOT NULL,
+        endpoint TEXT NOT NULL,
+        model TEXT NOT NULL,
+        params TEXT NOT NULL,
+        system TEXT NOT NULL,
+        prompt TEXT NOT NULL,
+        response TEXT NOT NULL,
+        usage TEXT,
+        cost REAL
+    );
+    CREATE INDEX exchanges_conversation ON exchanges (conversation);
+    CREATE INDEX exchanges_timestamp ON exchanges (timestamp);
+";
+
+const COLUMNS: &str = "id, conversation, timestamp, duration_ms, endpoint, model, params, system, prompt, response, usage, cost";
+
+impl History {
+    pub fn open(path: &Path) -> Self {
+        History { path: path.to_path_buf() }
+    }
+
+    /// the history database in the qllm data directory
+    pub fn open_default() -> Self {
+        History::open(&dirs::data_dir().join("history.db"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// a connection to the database, creating it on first use
+    fn connect(&self) -> Result<Connection> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut db = Connection::open(&self.path).map_err(|e| format!("Could not open {}: {}", self.path.display(), e))?;
+        // another qllm may be writing
+        db.busy_timeout(Duration::from_secs(10))?;
+        let version: i64 = db.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version == 0 {
+            let tx = db.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+            // someone else may have set it up while this one waited for the lock
+            if tx.query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))? == 0 {
+                tx.execute_batch(SCHEMA)?;
+                tx.execute_batch("PRAGMA user_version = 1")?;
+            }
+            tx.commit()?;
+        }
+        Ok(db)
+    }
+
+    fn select(&self, filter: &str, params: impl rusqlite::Params) -> Result<Vec<Exchange>> {
+        let db = self.connect()?;
+        let mut statement = db.prepare(&format!("SELECT {} FROM exchanges {} ORDER BY id", COLUMNS, filter))?;
+        let exchanges = statement.query_map(params, from_row)?.collect::<rusqlite::Result<_>>()?;
+        Ok(exchanges)
+    }
+
+    /// every stored exchange, oldest first
+    pub fn list(&self) -> Result<Vec<Exchange>> {
+        self.select("", [])
+    }
+
+    /// the latest `count` exchanges, oldest first
+    pub fn recent(&self, count: usize) -> Result<Vec<Exchange>> {
+        self.select("WHERE id IN (SELECT id FROM exchanges ORDER BY id DESC LIMIT ?1)", [count as i64])
+    }
+
+    /// the exchanges sent at or after the unix time `timestamp`, oldest first
+    pub fn since(&self, timestamp: u64) -> Result<Vec<Exchange>> {
+        self.select("WHERE timestamp >= ?1", [timestamp as i64])
+    }
+
+    pub fn get(&self, id: u64) -> Result<Option<Exchange>> {
+        Ok(self.select("WHERE id = ?1", [id as i64])?.pop())
+    }
+
+    /// the exchanges of one conversation, in order
+    pub fn conversation(&self, conversation: u64) -> Result<Vec<Exchange>> {
+        self.select("WHERE conversation = ?1", [conversation as i64])
+    }
+
+    /// the conversation of the most recent exchange
+    pub fn latest_conversation(&self) -> Result<Option<u64>> {
+        let db = self.connect()?;
+        let latest = db.query_row("SELECT conversation FROM exchanges ORDER BY id DESC LIMIT 1", [], |row| row.get::<_, i64>(0)).optional()?;
+        Ok(latest.map(|id| id as u64))
+    }
+
+    /// rebuild the full messages array of a conversation, starting with its system prompt
+    pub fn messages(&self, conversation: u64) -> Result<Vec<Message>> {
+        let exchanges = self.conversation(conversation)?;
+        let first = exchanges.first().ok_or_else(|| format!("No conversation with id {} in {}.", conversation, self.path.display()))?;
+        let mut messages = vec![Message::system(&first.system)];
+        for exchange in &exchanges {
+            messages.extend(exchange.messages());
+        }
+        Ok(messages)
+    }
+
+    /// an id for a conversation that has not been used yet, reserved so that no other
+    /// run gets it too
+    pub fn new_conversation(&self) -> Result<u64> {
+        let db = self.connect()?;
+        db.execute("INSERT INTO conversations DEFAULT VALUES", [])?;
+        Ok(db.last_insert_rowid() as u64)
+    }
+
+    /// store an exchange, assigning it the next free id
+    pub fn record(&self, exchange: &mut Exchange) -> Result<u64> {
+        let db = self.connect()?;
+        insert(&db, exchange, None)?;
+        exchange.id = db.last_insert_rowid() as u64;
+        Ok(exchange.id)
+    }
+}
+
+/// add an exchange to the table, under `id` or else the next free one
+fn insert(db: &Connection, exchange: &Exchange, id: Option<u64>) -> Result<()> {
+    db.execute(
+        &format!("INSERT INTO exchanges ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)", COLUMNS),
+        params![
+            id.map(|id| id as i64),
+            exchange.conversation as i64,
+            exchange.timestamp as i64,
+            exchange.duration_ms as i64,
+            exchange.endpoint,
+            exchange.model,
+            exchange.params.to_string(),
+            exchange.system,
+            exchange.prompt,
+            exchange.response,
+            exchange.usage.as_ref().map(Value::to_string),
+            exchange.cost,
+        ],
+    )?;
+    Ok(())
+}
+
+fn from_row(row: &Row) -> rusqlite::Result<Exchange> {
+    let json = |text: Option<String>| text.and_then(|text| serde_json::from_str(&text).ok());
+    Ok(Exchange {
+        id: row.get::<_, i64>(0)? as u64,
+        conversation: row.get::<_, i64>(1)? as u64,
+        timestamp: row.get::<_, i64>(2)? as u64,
+        duration_ms: row.get::<_, i64>(3)? as u64,
+        endpoint: row.get(4)?,
+        model: row.get(5)?,
+        params: json(row.get(6)?).unwrap_or_default(),
+        system: row.get(7)?,
+        prompt: row.get(8)?,
+        response: row.get(9)?,
+        usage: json(row.get(10)?),
+        cost: row.get(11)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(conversation: u64, prompt: &str) -> Exchange {
+        Exchange { conversation, timestamp: 1_700_000_000, model: "m".to_string(), prompt: prompt.to_string(), ..Exchange::default() }
+    }
+
+    #[test]
+    fn records_exchanges_by_conversation() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::open(&dir.path().join("history.db"));
+        let conversation = history.new_conversation().unwrap();
+        let mut first = Exchange { system: "be brief".to_string(), response: "hi".to_string(), ..exchange(conversation, "hello") };
+        assert_eq!(history.record(&mut first).unwrap(), 1);
+        history.record(&mut exchange(conversation, "again")).unwrap();
+        let exchanges = history.conversation(conversation).unwrap();
+        assert_eq!(exchanges.iter().map(|e| e.prompt.as_str()).collect::<Vec<_>>(), ["hello", "again"]);
+        assert_eq!(history.get(1).unwrap().unwrap().system, "be brief");
+        assert_eq!(history.recent(1).unwrap()[0].prompt, "again");
+        assert!(history.since(1_700_000_001).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rebuilds_a_conversation() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::open(&dir.path().join("history.db"));
+        assert_eq!(history.latest_conversation().unwrap(), None);
+        let conversation = history.new_conversation().unwrap();
+        history.record(&mut Exchange { system: "be brief".to_string(), response: "hi".to_string(), ..exchange(conversation, "hello") }).unwrap();
+        history.record(&mut exchange(conversation, "again")).unwrap();
+        assert_eq!(history.latest_conversation().unwrap(), Some(conversation));
+        let messages = history.messages(conversation).unwrap();
+        assert_eq!(messages.iter().map(|m| m.role.as_str()).collect::<Vec<_>>(), ["system", "user", "assistant", "user", "assistant"]);
+        assert_eq!(messages[0].content, "be brief");
+        assert!(history.messages(conversation + 1).is_err());
+    }
+
+    #[test]
+    fn runs_at_the_same_time_get_their_own_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.db");
+        let threads = (0..8).map(|_| {
+            let history = History::open(&path);
+            std::thread::spawn(move || {
+                let conversation = history.new_conversation().unwrap();
+                let id = history.record(&mut exchange(conversation, "hi")).unwrap();
+                (conversation, id)
+            })
+        }).collect::<Vec<_>>();
+        let ids = threads.into_iter().map(|thread| thread.join().unwrap()).collect::<Vec<_>>();
+        let conversations = ids.iter().map(|(conversation, _)| conversation).collect::<std::collections::HashSet<_>>();
+        let exchanges = ids.iter().map(|(_, id)| id).collect::<std::collections::HashSet<_>>();
+        assert_eq!((conversations.len(), exchanges.len()), (8, 8));
+    }
+}
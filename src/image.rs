@@ -0,0 +1,114 @@
+//! Images attached to chat messages for vision models.
+
+use base64::Engine;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::Result;
+
+/// an image, either inline or by reference
+#[derive(Clone, Debug, PartialEq)]
+pub enum Image {
+    /// base64 encoded bytes with their mime type
+    Data { mime: String, data: String },
+    /// a url the server fetches itself
+    Url(String),
+}
+
+impl Image {
+    /// encode raw image bytes, working out the mime type from them or the file name
+    pub fn from_bytes(bytes: &[u8], name: &str) -> Result<Self> {
+        let mime = mime_type(bytes, name).ok_or_else(|| format!("{} is not a PNG, JPEG, GIF, or WebP image", name))?;
+        Ok(Image::Data { mime: mime.to_string(), data: base64::engine::general_purpose::STANDARD.encode(bytes) })
+    }
+
+    /// a url for the OpenAI `image_url` part, a `data:` url for inline images
+    pub fn url(&self) -> String {
+        match self {
+            Image::Data { mime, data } => format!("data:{};base64,{}", mime, data),
+            Image::Url(url) => url.clone(),
+        }
+    }
+}
+
+/// the mime type of an image from its magic bytes, falling back to the file extension
+pub fn mime_type(bytes: &[u8], name: &str) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some("image/png");
+    }
+    if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+        return Some("image/jpeg");
+    }
+    if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        return Some("image/gif");
+    }
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    let extension = Path::new(name.split(['?', '#']).next().unwrap_or(name))
+        .extension()?
+        .to_str()?
+        .to_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// shrink an image to fit in a `max_size` pixel square with ImageMagick, keeping its format;
+/// images that already fit are left alone
+pub fn downscale(bytes: &[u8], name: &str, max_size: u32) -> Result<Vec<u8>> {
+    let format = match mime_type(bytes, name) {
+        Some(mime) => mime.trim_start_matches("image/"),
+        None => return Err(format!("{} is not a PNG, JPEG, GIF, or WebP image", name).into()),
+    };
+    let output = format!("{}:-", format);
+    let resize = format!("{}x{}>", max_size, max_size);
+    // ImageMagick 7 is `magick`, older releases only have `convert`
+    for program in ["magick", "convert"] {
+        let mut child = match Command::new(program)
+            .args(["-", "-resize", &resize, &output])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(_) => continue,
+        };
+        let mut stdin = child.stdin.take().ok_or("no stdin for ImageMagick")?;
+        let input = bytes.to_vec();
+        // write from a thread so a large image can not deadlock against the output
+        let writer = std::thread::spawn(move || stdin.write_all(&input));
+        let result = child.wait_with_output()?;
+        writer.join().map_err(|_| "could not write the image to ImageMagick")??;
+        if !result.status.success() {
+            return Err(format!("could not downscale {}: {}", name, String::from_utf8_lossy(&result.stderr).trim()).into());
+        }
+        return Ok(result.stdout);
+    }
+    Err("downscaling images needs ImageMagick (`magick` or `convert`) on the PATH".into())
+}
+
+/// load an image from a file or a url; urls are passed through when the server can
+/// fetch them itself, otherwise downloaded and inlined like files
+pub async fn load(spec: &str, pass_urls: bool, max_size: Option<u32>) -> Result<Image> {
+    let is_url = spec.starts_with("http://") || spec.starts_with("https://");
+    if is_url && pass_urls && max_size.is_none() {
+        return Ok(Image::Url(spec.to_string()));
+    }
+    let bytes = if is_url {
+        reqwest::get(spec).await?.error_for_status()?.bytes().await?.to_vec()
+    } else {
+        std::fs::read(spec).map_err(|e| format!("could not read the image {}: {}", spec, e))?
+    };
+    let bytes = match max_size {
+        Some(max_size) => downscale(&bytes, spec, max_size)?,
+        None => bytes,
+    };
+    Image::from_bytes(&bytes, spec)
+}
@@ -0,0 +1,222 @@
+//! Generating images from a prompt, with the OpenAI images API or a Stable Diffusion
+//! server: the AUTOMATIC1111 web UI (and Forge, which shares its API) or ComfyUI.
+//!
+//! The OpenAI API and the web UI answer with the images; ComfyUI queues a workflow,
+//! here the basic text-to-image graph, whose images are fetched once it has run.
+
+use base64::Engine;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::{api_url, time, QllmClient, Result};
+
+/// the kinds of server images can be generated with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    /// `/v1/images/generations`, as served by OpenAI, LocalAI, and others
+    OpenAi,
+    /// `/sdapi/v1/txt2img` of the AUTOMATIC1111 Stable Diffusion web UI
+    SdWebui,
+    /// the `/prompt` queue of ComfyUI
+    ComfyUi,
+}
+
+impl Backend {
+    /// the backend an endpoint most likely is, from the ports and paths they use
+    pub fn detect(endpoint: &str) -> Backend {
+        if endpoint.contains("/sdapi") || endpoint.contains(":7860") {
+            Backend::SdWebui
+        } else if endpoint.contains(":8188") {
+            Backend::ComfyUi
+        } else {
+            Backend::OpenAi
+        }
+    }
+
+    pub fn by_name(name: &str) -> Result<Backend> {
+        match name {
+            "openai" => Ok(Backend::OpenAi),
+            "sdwebui" | "a1111" => Ok(Backend::SdWebui),
+            "comfyui" => Ok(Backend::ComfyUi),
+            _ => Err(format!("Unknown image backend '{}', expected openai, sdwebui, or comfyui.", name).into()),
+        }
+    }
+}
+
+/// what to draw, and how
+#[derive(Clone, Debug)]
+pub struct ImageRequest {
+    pub model: String,
+    pub prompt: String,
+    /// what the image should not show
+    pub negative_prompt: Option<String>,
+    pub n: u32,
+    /// width and height in pixels
+    pub size: Option<(u32, u32)>,
+    /// sampling steps
+    pub steps: Option<u32>,
+    pub seed: Option<i64>,
+}
+
+impl ImageRequest {
+    pub fn new(model: &str, prompt: &str) -> Self {
+        ImageRequest { model: model.to_string(), prompt: prompt.to_string(), negative_prompt: None, n: 1, size: None, steps: None, seed: None }
+    }
+}
+
+/// a generated image
+#[derive(Clone, Debug)]
+pub struct GeneratedImage {
+    pub bytes: Vec<u8>,
+    /// the prompt the image was actually made from, when the server rewrote it
+    pub revised_prompt: Option<String>,
+}
+
+impl GeneratedImage {
+    /// the file extension of the image's format, from its first bytes
+    pub fn extension(&self) -> &'static str {
+        match self.bytes.as_slice() {
+            [0xff, 0xd8, ..] => "jpg",
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "webp",
+            [b'G', b'I', b'F', ..] => "gif",
+            _ => "png",
+        }
+    }
+}
+
+/// generate the images of a request with the endpoint of `client`
+pub async fn generate(client: &QllmClient, backend: Backend, request: &ImageRequest) -> Result<Vec<GeneratedImage>> {
+    match backend {
+        Backend::OpenAi => openai(client, request).await,
+        Backend::SdWebui => sd_webui(client, request).await,
+        Backend::ComfyUi => comfyui(client, request).await,
+    }
+}
+
+/// the root of a Stable Diffusion server, without the API path of the endpoint or the
+/// `/v1` of an endpoint written for the OpenAI API
+fn root(endpoint: &str) -> &str {
+    let endpoint = endpoint.trim_end_matches('/');
+    match endpoint.find("/sdapi") {
+        Some(at) => &endpoint[..at],
+        None => endpoint.strip_suffix("/v1").unwrap_or(endpoint),
+    }
+}
+
+fn decode(data: &str) -> Result<Vec<u8>> {
+    // the web UI may send a data url
+    let data = data.split_once("base64,").map_or(data, |(_, data)| data);
+    base64::engine::general_purpose::STANDARD.decode(data.trim())
+        .map_err(|e| format!("the server sent an image that is not valid base64: {}", e).into())
+}
+
+async fn openai(client: &QllmClient, request: &ImageRequest) -> Result<Vec<GeneratedImage>> {
+    if request.steps.is_some() || request.negative_prompt.is_some() {
+        return Err("Steps and negative prompts need a Stable Diffusion backend, sdwebui or comfyui.".into());
+    }
+    let mut body = json!({ "prompt": request.prompt, "n": request.n });
+    if request.model != "default" {
+        body["model"] = json!(request.model);
+    }
+    if let Some((width, height)) = request.size {
+        body["size"] = json!(format!("{}x{}", width, height));
+    }
+    if let Some(seed) = request.seed {
+        body["seed"] = json!(seed);
+    }
+    let response: Value = client.post_once(&api_url(client.endpoint(), "images/generations"), &body).await?.json().await?;
+    let data = response["data"].as_array().ok_or("the image response has no data array")?;
+    let mut images = Vec::with_capacity(data.len());
+    for item in data {
+        // newer models always answer in base64, older ones with a url by default
+        let bytes = match (item["b64_json"].as_str(), item["url"].as_str()) {
+            (Some(data), _) => decode(data)?,
+            (None, Some(url)) => client.download(url).await?,
+            (None, None) => return Err("an image in the response has neither b64_json nor a url".into()),
+        };
+        images.push(GeneratedImage { bytes, revised_prompt: item["revised_prompt"].as_str().map(str::to_string) });
+    }
+    Ok(images)
+}
+
+async fn sd_webui(client: &QllmClient, request: &ImageRequest) -> Result<Vec<GeneratedImage>> {
+    let (width, height) = request.size.unwrap_or((512, 512));
+    let mut body = json!({
+        "prompt": request.prompt,
+        "negative_prompt": request.negative_prompt.as_deref().unwrap_or(""),
+        "batch_size": request.n,
+        "width": width,
+        "height": height,
+        "seed": request.seed.unwrap_or(-1),
+    });
+    if let Some(steps) = request.steps {
+        body["steps"] = json!(steps);
+    }
+    if request.model != "default" {
+        body["override_settings"] = json!({ "sd_model_checkpoint": request.model });
+    }
+    let url = format!("{}/sdapi/v1/txt2img", root(client.endpoint()));
+    let response: Value = client.post_once(&url, &body).await?.json().await?;
+    let data = response["images"].as_array().ok_or("the txt2img response has no images")?;
+    data.iter()
+        .map(|image| {
+            let data = image.as_str().ok_or("an image in the txt2img response is not a string")?;
+            Ok(GeneratedImage { bytes: decode(data)?, revised_prompt: None })
+        })
+        .collect()
+}
+
+/// the basic text-to-image workflow of ComfyUI, with a checkpoint, two prompts, and a sampler
+fn comfyui_workflow(request: &ImageRequest, seed: i64) -> Value {
+    let (width, height) = request.size.unwrap_or((512, 512));
+    json!({
+        "checkpoint": { "class_type": "CheckpointLoaderSimple", "inputs": { "ckpt_name": request.model } },
+        "latent": { "class_type": "EmptyLatentImage", "inputs": { "width": width, "height": height, "batch_size": request.n } },
+        "positive": { "class_type": "CLIPTextEncode", "inputs": { "text": request.prompt, "clip": ["checkpoint", 1] } },
+        "negative": { "class_type": "CLIPTextEncode", "inputs": { "text": request.negative_prompt.as_deref().unwrap_or(""), "clip": ["checkpoint", 1] } },
+        "sampler": { "class_type": "KSampler", "inputs": {
+            "seed": seed, "steps": request.steps.unwrap_or(20), "cfg": 7.0, "sampler_name": "euler", "scheduler": "normal", "denoise": 1.0,
+            "model": ["checkpoint", 0], "positive": ["positive", 0], "negative": ["negative", 0], "latent_image": ["latent", 0],
+        } },
+        "decode": { "class_type": "VAEDecode", "inputs": { "samples": ["sampler", 0], "vae": ["checkpoint", 2] } },
+        "save": { "class_type": "SaveImage", "inputs": { "filename_prefix": "qllm", "images": ["decode", 0] } },
+    })
+}
+
+async fn comfyui(client: &QllmClient, request: &ImageRequest) -> Result<Vec<GeneratedImage>> {
+    if request.model == "default" {
+        return Err("ComfyUI needs the checkpoint to load as the model, e.g. -m sd_xl_base_1.0.safetensors.".into());
+    }
+    let root = root(client.endpoint());
+    // ComfyUI wants a seed, and the same one would make the same image every time
+    let seed = request.seed.unwrap_or(time::now_ms() as i64 % 1_000_000_007);
+    let queued: Value = client.post_once(&format!("{}/prompt", root), &json!({ "prompt": comfyui_workflow(request, seed) })).await?.json().await?;
+    let id = queued["prompt_id"].as_str().ok_or("ComfyUI did not queue the workflow")?.to_string();
+    tracing::debug!(id, "queued the workflow");
+
+    let entry = loop {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let history: Value = client.get(&format!("{}/history/{}", root, id)).await?.json().await?;
+        let entry = &history[&id];
+        if entry["status"]["status_str"] == "error" {
+            let messages = entry["status"]["messages"].to_string();
+            return Err(format!("ComfyUI could not run the workflow: {}", messages).into());
+        }
+        if entry["status"]["completed"] == true || entry["outputs"].as_object().is_some_and(|outputs| !outputs.is_empty()) {
+            break entry.clone();
+        }
+    };
+    let mut images = Vec::new();
+    for output in entry["outputs"].as_object().into_iter().flat_map(|outputs| outputs.values()) {
+        for image in output["images"].as_array().into_iter().flatten() {
+            let mut url = reqwest::Url::parse(&format!("{}/view", root)).map_err(|e| format!("{} is not a valid url: {}", root, e))?;
+            url.query_pairs_mut()
+                .append_pair("filename", image["filename"].as_str().unwrap_or_default())
+                .append_pair("subfolder", image["subfolder"].as_str().unwrap_or_default())
+                .append_pair("type", image["type"].as_str().unwrap_or("output"));
+            let bytes = client.get(url.as_str()).await?.bytes().await?.to_vec();
+            images.push(GeneratedImage { bytes, revised_prompt: None });
+        }
+    }
+    Ok(images)
+}
@@ -0,0 +1,258 @@
+//! A local index of embedded chunks of files, searched for the chunks nearest a question.
+//!
+//! Each index is a SQLite database named after it in `indexes/` under the qllm data
+//! directory, holding the embedding model and a table of chunks with their file, lines,
+//! text, and vector. Searching reads only the vectors, compares the question with each,
+//! and fetches the text of the nearest, so memory stays small however many chunks there
+//! are.
+
+use rusqlite::{params, Connection};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{dirs, pricing, Result};
+
+/// a piece of a file with its embedding
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk {
+    pub path: String,
+    /// the first and last line of the chunk, counted from 1
+    pub lines: (usize, usize),
+    pub text: String,
+    pub vector: Vec<f64>,
+}
+
+impl Chunk {
+    /// where the chunk comes from, as `path:first-last`
+    pub fn source(&self) -> String {
+        format!("{}:{}-{}", self.path, self.lines.0, self.lines.1)
+    }
+}
+
+/// the chunks of a set of files, embedded with one model
+#[derive(Clone, Debug)]
+pub struct Index {
+    pub name: String,
+    /// the model the chunks were embedded with, which questions must be embedded with too
+    pub model: String,
+    /// the directory that was indexed
+    pub root: String,
+    /// unix time in seconds at which the index was built
+    pub created: u64,
+    /// the database the index is kept in
+    file: PathBuf,
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE info (model TEXT NOT NULL, root TEXT NOT NULL, created INTEGER NOT NULL);
+    CREATE TABLE chunks (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL,
+        first INTEGER NOT NULL,
+        last INTEGER NOT NULL,
+        text TEXT NOT NULL,
+        vector BLOB NOT NULL
+    );
+";
+
+impl Index {
+    /// a new index called `name` of the directory `root`, built now
+    pub fn new(name: &str, model: &str, root: &str) -> Self {
+        Index {
+            name: name.to_string(),
+            model: model.to_string(),
+            root: root.to_string(),
+            created: crate::time::now(),
+            file: Index::path(name),
+        }
+    }
+
+    /// the file of the index called `name`
+    pub fn path(name: &str) -> PathBuf {
+        dirs::data_dir().join("indexes").join(format!("{}.db", name))
+    }
+
+    /// the names of the indexes that have been built
+    pub fn names() -> Result<Vec<String>> {
+        let entries = match fs::read_dir(dirs::data_dir().join("indexes")) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "db") {
+                names.extend(path.file_stem().map(|stem| stem.to_string_lossy().to_string()));
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn load(name: &str) -> Result<Self> {
+        Index::load_from(name, &Index::path(name))
+    }
+
+    fn load_from(name: &str, file: &Path) -> Result<Self> {
+        if !file.exists() {
+            return Err(format!("There is no index called {}; build it with `qllm index DIR --name {}`.", name, name).into());
+        }
+        let db = Connection::open(file).map_err(|e| format!("Could not open {}: {}", file.display(), e))?;
+        let (model, root, created) = db
+            .query_row("SELECT model, root, created FROM info", [], |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)?)))
+            .map_err(|e| format!("{} is not an index of qllm: {}", file.display(), e))?;
+        Ok(Index { name: name.to_string(), model, root, created: created as u64, file: file.to_path_buf() })
+    }
+
+    /// write the index with its chunks, replacing any of the same name once it is complete
+    pub fn save(&self, chunks: &[Chunk]) -> Result<()> {
+        if let Some(dir) = self.file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = self.file.with_extension("tmp");
+        // left by a build that was stopped
+        if let Err(e) = fs::remove_file(&temp) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        let mut db = Connection::open(&temp)?;
+        let tx = db.transaction()?;
+        tx.execute_batch(SCHEMA)?;
+        tx.execute("INSERT INTO info (model, root, created) VALUES (?1, ?2, ?3)", params![self.model, self.root, self.created as i64])?;
+        {
+            let mut insert = tx.prepare("INSERT INTO chunks (path, first, last, text, vector) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+            for chunk in chunks {
+                let vector: Vec<u8> = chunk.vector.iter().flat_map(|x| x.to_le_bytes()).collect();
+                insert.execute(params![chunk.path, chunk.lines.0 as i64, chunk.lines.1 as i64, chunk.text, vector])?;
+            }
+        }
+        tx.commit()?;
+        db.close().map_err(|(_, e)| e)?;
+        fs::rename(&temp, &self.file)?;
+        Ok(())
+    }
+
+    /// the `k` chunks most similar to `vector`, most similar first, with their cosine similarity
+    pub fn search(&self, vector: &[f64], k: usize) -> Result<Vec<(f64, Chunk)>> {
+        let db = Connection::open(&self.file).map_err(|e| format!("Could not open {}: {}", self.file.display(), e))?;
+        let mut scores = Vec::new();
+        let mut statement = db.prepare("SELECT id, vector FROM chunks")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: i64 = row.get(0)?;
+            let chunk = decode(row.get_ref(1)?.as_blob()?)
+                .ok_or_else(|| format!("The vector of chunk {} of the index {} is malformed; build the index again.", id, self.name))?;
+            if chunk.len() != vector.len() {
+                return Err(format!(
+                    "The index {} holds vectors of {} numbers, but the question's has {}; embed it with {} as the index was.",
+                    self.name, chunk.len(), vector.len(), self.model,
+                ).into());
+            }
+            scores.push((cosine(vector, &chunk), id));
+        }
+        scores.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scores.truncate(k);
+        let mut statement = db.prepare("SELECT path, first, last, text, vector FROM chunks WHERE id = ?1")?;
+        let mut found = Vec::new();
+        for (score, id) in scores {
+            let chunk = statement.query_row([id], |row| {
+                Ok(Chunk {
+                    path: row.get(0)?,
+                    lines: (row.get::<_, i64>(1)? as usize, row.get::<_, i64>(2)? as usize),
+                    text: row.get(3)?,
+                    vector: decode(row.get_ref(4)?.as_blob()?).unwrap_or_default(),
+                })
+            })?;
+            found.push((score, chunk));
+        }
+        Ok(found)
+    }
+}
+
+/// a vector from its bytes, little-endian numbers of eight bytes each
+fn decode(bytes: &[u8]) -> Option<Vec<f64>> {
+    if !bytes.len().is_multiple_of(8) {
+        return None;
+    }
+    Some(bytes.chunks_exact(8).map(|x| f64::from_le_bytes(x.try_into().expect("eight bytes"))).collect())
+}
+
+/// the cosine similarity of two vectors, 0 when either is all zeros
+pub fn cosine(a: &[f64], b: &[f64]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+/// `text` split at line breaks into pieces of about `max_tokens` tokens, each with its
+/// first and last line
+pub fn split(text: &str, max_tokens: u64) -> Vec<((usize, usize), String)> {
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    let mut first = 1;
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        if !piece.is_empty() && pricing::estimate_tokens(&piece) + pricing::estimate_tokens(line) > max_tokens {
+            pieces.push(((first, i), std::mem::take(&mut piece)));
+            first = i + 1;
+        }
+        piece.push_str(line);
+    }
+    if !piece.trim().is_empty() {
+        pieces.push(((first, first + piece.lines().count().max(1) - 1), piece));
+    }
+    pieces.retain(|(_, piece)| !piece.trim().is_empty());
+    pieces
+}
+
+/// the name an index of `dir` gets unless one is given: the name of the directory
+pub fn default_name(dir: &Path) -> String {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    dir.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| "root".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(path: &str, vector: &[f64]) -> Chunk {
+        Chunk { path: path.to_string(), lines: (1, 2), text: format!("text of {}", path), vector: vector.to_vec() }
+    }
+
+    #[test]
+    fn saves_and_searches_an_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = Index { file: dir.path().join("notes.db"), ..Index::new("notes", "embed", "/notes") };
+        let chunks = [chunk("a", &[1.0, 0.0]), chunk("b", &[0.0, 1.0]), chunk("c", &[1.0, 1.0])];
+        index.save(&chunks).unwrap();
+
+        let index = Index::load_from("notes", &dir.path().join("notes.db")).unwrap();
+        assert_eq!((index.model.as_str(), index.root.as_str()), ("embed", "/notes"));
+        let found = index.search(&[1.0, 0.1], 2).unwrap();
+        assert_eq!(found.iter().map(|(_, chunk)| chunk.clone()).collect::<Vec<_>>(), [chunks[0].clone(), chunks[2].clone()]);
+        assert!(found[0].0 > found[1].0);
+        assert!(index.search(&[1.0, 0.0, 0.0], 2).is_err());
+    }
+
+    #[test]
+    fn splits_text_at_line_breaks() {
+        // ten lines of eight characters, two tokens each
+        let text = "line...\n".repeat(10);
+        let pieces = split(&text, 5);
+        assert_eq!(pieces.iter().map(|(lines, _)| *lines).collect::<Vec<_>>(), [(1, 2), (3, 4), (5, 6), (7, 8), (9, 10)]);
+        assert!(pieces.iter().all(|(_, piece)| piece == "line...\nline...\n"));
+        // a line longer than the size is a piece of its own, never cut
+        let pieces = split("short\na line well over the size of a piece\nend", 3);
+        assert_eq!(pieces, [((1, 1), "short\n".to_string()), ((2, 2), "a line well over the size of a piece\n".to_string()), ((3, 3), "end".to_string())]);
+        assert!(split("\n\n  \n", 5).is_empty());
+    }
+
+    #[test]
+    fn decodes_vectors() {
+        let bytes: Vec<u8> = [0.5f64, -2.0].iter().flat_map(|x| x.to_le_bytes()).collect();
+        assert_eq!(decode(&bytes), Some(vec![0.5, -2.0]));
+        assert_eq!(decode(&bytes[..9]), None);
+    }
+}
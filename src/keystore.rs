@@ -0,0 +1,59 @@
+//! API keys kept in the operating system's keyring, for `qllm key`: the macOS
+//! keychain, the Windows credential manager, or the Secret Service of GNOME Keyring
+//! and KWallet.
+//!
+//! A key is stored under the service `qllm` with the name of the profile it belongs
+//! to, or `default` for the key used without a profile, so that it never has to be
+//! typed on a command line, exported, or written into the config.
+
+use crate::Result;
+
+/// the keyring service the keys are stored under
+#[cfg(feature = "keyring")]
+const SERVICE: &str = "qllm";
+
+#[cfg(feature = "keyring")]
+fn entry(profile: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(SERVICE, profile).map_err(|e| format!("Could not open the keyring: {}", e).into())
+}
+
+/// the key stored for a profile, if there is one
+#[cfg(feature = "keyring")]
+pub fn get(profile: &str) -> Result<Option<String>> {
+    match entry(profile)?.get_password() {
+        Ok(key) => Ok(Some(key)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(format!("Could not read the keyring: {}", e).into()),
+    }
+}
+
+/// store the key of a profile, replacing any it had
+#[cfg(feature = "keyring")]
+pub fn set(profile: &str, key: &str) -> Result<()> {
+    entry(profile)?.set_password(key).map_err(|e| format!("Could not write to the keyring: {}", e).into())
+}
+
+/// remove the key of a profile, returning whether there was one
+#[cfg(feature = "keyring")]
+pub fn delete(profile: &str) -> Result<bool> {
+    match entry(profile)?.delete_credential() {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(format!("Could not write to the keyring: {}", e).into()),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn get(_profile: &str) -> Result<Option<String>> {
+    Err("This qllm was built without the keyring feature.".into())
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn set(_profile: &str, _key: &str) -> Result<()> {
+    Err("This qllm was built without the keyring feature.".into())
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn delete(_profile: &str) -> Result<bool> {
+    Err("This qllm was built without the keyring feature.".into())
+}
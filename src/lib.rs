@@ -0,0 +1,56 @@
+//! qllm: a small client library for streaming chat completions from local and hosted LLMs.
+//!
+//! The `qllm` binary is a thin command-line wrapper around this crate.
+
+pub mod assistants;
+pub mod attach;
+pub mod audit;
+pub mod batches;
+pub mod cache;
+pub mod client;
+pub mod clipboard;
+pub mod config;
+pub mod diff;
+pub mod dirs;
+pub mod eval;
+pub mod files;
+pub mod finetune;
+pub mod framing;
+pub mod history;
+pub mod image;
+pub mod images;
+pub mod index;
+pub mod keystore;
+pub mod logprobs;
+pub mod mcp;
+pub mod notify;
+pub mod oauth;
+pub mod patch;
+pub mod pricing;
+pub mod provider;
+pub mod realtime;
+pub mod repomap;
+pub mod schema;
+pub mod sha256;
+pub mod sse;
+pub mod template;
+pub mod think;
+pub mod time;
+pub mod tls;
+pub mod tokens;
+pub mod tools;
+pub mod transcript;
+pub mod unix;
+pub mod utf8;
+
+pub use client::{api_url, token_counts, ChatRequest, ChatStream, CompletionRequest, Delta, HttpError, KeyPool, Message, Moderation, QllmClient, ResponseFormat, RetryPolicy, Rotation, SamplerParams, Timeouts};
+pub use config::Config;
+pub use history::{Exchange, History};
+pub use provider::{Provider, StreamEvent};
+pub use tls::Tls;
+pub use tools::{ToolCall, ToolCalls};
+
+/// the error type used throughout the library
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,48 @@
+//! Token log probabilities, for calibration and uncertainty analysis.
+//!
+//! OpenAI-compatible servers send them beside the content when a request sets
+//! `logprobs`, one entry per generated token with the most likely alternatives.
+
+use serde_json::{json, Value};
+
+/// a generated token with its log probability and the most likely alternatives
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    /// the `top_logprobs` alternatives, most likely first
+    pub top: Vec<(String, f64)>,
+}
+
+impl TokenLogprob {
+    /// the entries of an OpenAI `logprobs.content` array
+    pub fn parse_all(content: &Value) -> Vec<TokenLogprob> {
+        content.as_array().into_iter().flatten().filter_map(TokenLogprob::from_json).collect()
+    }
+
+    pub fn from_json(value: &Value) -> Option<Self> {
+        Some(TokenLogprob {
+            token: value["token"].as_str()?.to_string(),
+            logprob: value["logprob"].as_f64()?,
+            top: value["top_logprobs"].as_array().into_iter().flatten()
+                .filter_map(|top| Some((top["token"].as_str()?.to_string(), top["logprob"].as_f64()?)))
+                .collect(),
+        })
+    }
+
+    /// the entry in the OpenAI shape
+    pub fn to_json(&self) -> Value {
+        json!({
+            "token": self.token,
+            "logprob": self.logprob,
+            "top_logprobs": self.top.iter()
+                .map(|(token, logprob)| json!({ "token": token, "logprob": logprob }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// the probability of the token, from 0 to 1
+    pub fn probability(&self) -> f64 {
+        self.logprob.exp()
+    }
+}
@@ -1,199 +1,1829 @@
-use clap::Parser;
+mod blocks;
+mod chunked;
+mod cli;
+mod commands;
+mod highlight;
+mod render;
+mod pager;
+mod repl;
+mod spinner;
+mod theme;
+mod wrap;
+
+use clap::{Parser, ValueEnum};
 use std::env;
-use serde_json::{json, Value};
-use std::io::Write;
-use tokio::io::{self as async_io, AsyncReadExt};
-use tokio_stream::{self, StreamExt};
+use std::io::{BufRead, IsTerminal, Write};
+use tokio::io::{self as async_io, AsyncBufReadExt, AsyncReadExt};
+use std::time::{Duration, Instant};
+use qllm::attach::Attachment;
+use qllm::audit::AuditLog;
+use qllm::cache::{self, Cache};
+use qllm::oauth::OAuth;
+use qllm::pricing::{self, Prices};
+use qllm::template::Template;
+use qllm::tokens::{Cut, Tokenizer};
+use qllm::{ChatRequest, ChatStream, CompletionRequest, Delta, Config, Exchange, History, KeyPool, Message, QllmClient, ResponseFormat, RetryPolicy, Rotation, SamplerParams, Timeouts, Tls};
 
-/*
-    repeat_last_n = 64, repeat_penalty = 1.100, frequency_penalty = 0.000, presence_penalty = 0.000
-    top_k = 40, tfs_z = 1.000, top_p = 0.950, min_p = 0.050, typical_p = 1.000, temp = 0.800
-    mirostat = 0, mirostat_lr = 0.100, mirostat_ent = 5.000
-*/
+use cli::{ChatArgs, Cli, Command, CommonArgs, CompleteArgs, CopyPart, EmbedArgs, Fit, ModelsArgs, OutputFormat, Paging, Vote};
 
-#[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
-struct Args {
-    /// the model name
-    #[clap(short, long, default_value = "default")]
-    model: String,
+#[tokio::main]
+async fn main() -> Result<(), qllm::Error> {
+    let cli = Cli::parse();
+    init_logging(cli.verbose, cli.quiet);
 
-    /// the endpoint, taken from the environment variable QLLM_ENDPOINT if not specified
-    #[clap(short, long, required = false, default_value = "")]
-    endpoint: String,
+    let result = match cli.command {
+        Some(Command::Chat(args)) => chat(*args).await,
+        Some(Command::Complete(args)) => complete(args).await,
+        Some(Command::Realtime(args)) => commands::realtime::run(args).await,
+        Some(Command::Assistant(args)) => commands::assistant::run(args).await,
+        Some(Command::Embed(args)) => embed(args).await,
+        Some(Command::Image(args)) => commands::image::run(args).await,
+        Some(Command::Moderate(args)) => commands::moderate::run(args).await,
+        Some(Command::Similar(args)) => commands::similar::run(args).await,
+        Some(Command::Index(args)) => commands::index::run(args).await,
+        Some(Command::Ask(args)) => commands::ask::run(*args).await,
+        Some(Command::Models(args)) => models(args).await,
+        Some(Command::Ping(args)) => commands::ping::run(args).await,
+        Some(Command::Bench(args)) => commands::bench::run(args).await,
+        Some(Command::Eval(args)) => commands::eval::run(args).await,
+        Some(Command::Tokens(args)) => commands::tokens::run(args).await,
+        Some(Command::Edit(args)) => commands::edit::run(args).await,
+        Some(Command::Commit(args)) => commands::commit::run(args).await,
+        Some(Command::Review(args)) => commands::review::run(args).await,
+        Some(Command::Agent(args)) => commands::agent::run(args).await,
+        Some(Command::Batch(args)) => commands::batch::run(args).await,
+        Some(Command::BatchApi(args)) => commands::batch_api::run(args).await,
+        Some(Command::Files(args)) => commands::files::run(args).await,
+        Some(Command::Finetune(args)) => commands::finetune::run(args).await,
+        Some(Command::Log(args)) => commands::log::run(args),
+        Some(Command::Usage(args)) => commands::usage::run(args),
+        Some(Command::Profiles(args)) => commands::profiles::run(args),
+        Some(Command::Cache(args)) => commands::cache::run(args),
+        Some(Command::Key(args)) => commands::key::run(args),
+        Some(Command::Template(args)) => commands::template::run(args).await,
+        None => chat(cli.chat).await,
+    };
+    // a request that failed before its first token leaves its spinner behind
+    spinner::stop();
+    result
+}
 
-    /// the api key, which is taken from the environment variable QLLM_KEY if not specified
-    #[clap(short, long, required = false, default_value = "")]
-    key: String,
+/// log to stderr at the level of `--verbose` and `-q`: warnings such as retries by
+/// default, then requests and stream stats, then more detail; QLLM_LOG takes a filter
+/// such as `qllm=debug,hyper=trace` instead
+fn init_logging(verbose: u8, quiet: bool) {
+    let filter = match std::env::var("QLLM_LOG") {
+        Ok(filter) => filter,
+        Err(_) if quiet => "error".to_string(),
+        // the libraries underneath are only heard from with the most verbose level
+        Err(_) => match verbose {
+            0 => "warn",
+            1 => "warn,qllm=info",
+            2 => "warn,qllm=debug",
+            _ => "trace",
+        }.to_string(),
+    };
+    let filter = tracing_subscriber::EnvFilter::try_new(&filter).unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn"));
+    let stderr = || {
+        spinner::clear();
+        std::io::stderr()
+    };
+    let colour = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+    let logger = tracing_subscriber::fmt().with_writer(stderr).with_env_filter(filter).with_ansi(colour);
+    if verbose == 0 {
+        // a plain warning reads better without a timestamp and a module
+        logger.without_time().with_target(false).init();
+    } else {
+        logger.init();
+    }
+}
 
-    /// the system prompt
-    #[clap(short, long, required = false, default_value = "Help the user with their task.")]
-    system: String,
+const DEFAULT_SYSTEM: &str = "Help the user with their task.";
 
-    /// flag to say if we should read from stdin, use -c as the single character version
-    #[clap(short = 'c', long)]
-    stdin: bool,
+/// added to the system prompt with `--apply`, asking for edits in a form that can be applied
+const APPLY_SYSTEM: &str = "Give each change to a file as a unified diff with `---` and `+++` lines naming the file, \
+or as a search and replace block: the path of the file on a line of its own, then `<<<<<<< SEARCH`, \
+the lines to replace exactly as they are, `=======`, the new lines, and `>>>>>>> REPLACE`.";
 
-    /// no instruction prompt, just continuation of input
-    #[clap(short, long)]
-    no_instruct: bool,
+/// load the config and activate the selected profile, or else the profile of the `-m` alias
+fn config(common: &CommonArgs) -> Result<Config, qllm::Error> {
+    let mut config = Config::load(common.config.as_deref())?;
+    let alias = match &common.model {
+        Some(name) => config.alias(name)?,
+        None => None,
+    };
+    let profile = common.profile.clone().or_else(|| alias.and_then(|(_, profile)| profile));
+    config.select_profile(profile.as_deref())?;
+    tracing::debug!(profile = profile.as_deref(), "loaded the config");
+    theme::init(common.no_color, &config)?;
+    Ok(config)
+}
 
-    /// the positional argument is the user prompt
-    #[clap(name = "PROMPT", required = true)]
-    prompt: Vec<String>,
+/// build a client from the command line and the active profile, falling back to
+/// QLLM_ENDPOINT and QLLM_KEY, then the rest of the config
+fn client(common: &CommonArgs, config: &Config) -> Result<QllmClient, qllm::Error> {
+    let endpoint = if !common.endpoint.is_empty() {
+        common.endpoint.clone()
+    } else if let Some(endpoint) = config.profile_str("endpoint") {
+        endpoint.to_string()
+    } else if env::var_os("QLLM_ENDPOINT").is_some() {
+        std::env::var("QLLM_ENDPOINT")?
+    } else if let Some(endpoint) = config.str("endpoint") {
+        endpoint.to_string()
+    } else {
+        return Err("No endpoint specified. One must be given on the command line via -e, via the environmental variable QLLM_ENDPOINT, or as `endpoint` in the config file.".into());
+    };
+
+    // -k first, then a key the profile sets for itself, then the keyring, QLLM_KEY, and the
+    // rest of the config; `keys` in the config are used in turn in place of a key from the
+    // same place
+    let mut keys = Vec::new();
+    let key = if !common.key.is_empty() {
+        Some(common.key.clone())
+    } else if config.profile_has_key() {
+        keys = config.keys()?;
+        config.key()?
+    } else if let Some(key) = wants_key(&endpoint, config).then(|| keyring_key(config)).flatten() {
+        Some(key)
+    } else if env::var_os("QLLM_KEY").is_some() {
+        Some(std::env::var("QLLM_KEY")?)
+    } else {
+        keys = config.keys()?;
+        config.key()?
+    };
+
+    let mut retry = RetryPolicy::default();
+    if let Some(retries) = common.retries.or_else(|| config.get("retries").and_then(|v| v.as_u64()).map(|n| n as u32)) {
+        retry.retries = retries;
+    }
+    if let Some(delay) = common.retry_delay.or_else(|| config.get("retry_delay").and_then(|v| v.as_f64())) {
+        retry.delay = Duration::from_secs_f64(delay.max(0.0));
+    }
 
-    /// copy full prompt to the output, to make the output suitable for recursive use
-    #[clap(short, long)]
-    recurse: bool,
+    let mut timeouts = Timeouts::default();
+    let timeout = |flag: Option<f64>, name: &str, default: Option<Duration>| {
+        match flag.or_else(|| config.get(name).and_then(|v| v.as_f64())) {
+            Some(secs) if secs > 0.0 => Some(Duration::from_secs_f64(secs)),
+            Some(_) => None,
+            None => default,
+        }
+    };
+    timeouts.connect = timeout(common.connect_timeout, "connect_timeout", timeouts.connect);
+    timeouts.request = timeout(common.request_timeout, "request_timeout", timeouts.request);
+    timeouts.stall = timeout(common.stall_timeout, "stall_timeout", timeouts.stall);
 
-    /// context length
-    #[clap(short = 'l', long, default_value = "-1")]
-    max_tokens: i64,
+    let mut client = QllmClient::new(&endpoint, key).with_retry(retry).with_timeouts(timeouts)?.with_dump(common.dump);
+    if !keys.is_empty() {
+        let rotation = match config.str("key_rotation") {
+            None | Some("round-robin") => Rotation::RoundRobin,
+            Some("on-429") => Rotation::OnRateLimit,
+            Some(other) => return Err(format!("key_rotation must be \"round-robin\" or \"on-429\", not {:?}.", other).into()),
+        };
+        client = client.with_keys(KeyPool::new(keys, rotation));
+    }
+    if let Some(proxy) = common.proxy.as_deref().or(config.str("proxy")) {
+        client = client.with_proxy(proxy)?;
+    }
+    let path = |flag: &Option<String>, name: &str| flag.as_deref().or(config.str(name)).map(qllm::dirs::expand_home);
+    let tls = Tls {
+        client_cert: path(&common.client_cert, "client_cert"),
+        client_key: path(&common.client_key, "client_key"),
+        password: config.str("client_cert_password").map(str::to_string),
+        ca_cert: path(&common.ca_cert, "ca_cert"),
+        insecure: common.insecure || config.get("insecure").and_then(|v| v.as_bool()).unwrap_or(false),
+    };
+    if tls != Tls::default() {
+        client = client.with_tls(tls)?;
+    }
+    // headers from the config first, so the command line can replace them
+    if let Some(headers) = config.get("headers").and_then(|v| v.as_object()) {
+        for (name, value) in headers {
+            let value = value.as_str().ok_or_else(|| format!("the {} header in the config must be a string", name))?;
+            client = client.with_header(name, value)?;
+        }
+    }
+    for header in &common.headers {
+        let (name, value) = header.split_once(':')
+            .ok_or_else(|| format!("{:?} is not a header, expected \"Name: value\"", header))?;
+        client = client.with_header(name, value)?;
+    }
+    if let Some(name) = common.provider.as_deref().or(config.str("provider")) {
+        client = client.with_provider(qllm::provider::by_name(name)?);
+    }
+    if let Some(audit) = audit_log(config)? {
+        client = client.with_audit(audit);
+    }
+    if let Some(oauth) = oauth(config)? {
+        client = client.with_oauth(oauth);
+    }
+    tracing::info!(endpoint = client.endpoint(), provider = client.provider().name(), "using endpoint");
+    Ok(client)
+}
+
+/// whether the keyring is worth asking for a key to `endpoint`: not for a server on this
+/// machine or behind a Unix socket, which seldom wants one, nor with OAuth tokens
+fn wants_key(endpoint: &str, config: &Config) -> bool {
+    if config.get("oauth").is_some() || endpoint.starts_with("unix://") {
+        return false;
+    }
+    let host = reqwest::Url::parse(endpoint).ok().and_then(|url| url.host_str().map(str::to_string));
+    !matches!(host.as_deref(), Some("localhost" | "127.0.0.1" | "[::1]"))
+}
+
+/// the key stored with `qllm key set` for the active profile, or for `default` without
+/// one; a keyring that can not be reached is passed over
+fn keyring_key(config: &Config) -> Option<String> {
+    let profile = config.profile.as_ref().map_or("default", |(name, _)| name.as_str());
+    match qllm::keystore::get(profile) {
+        Ok(key) => key,
+        Err(e) => {
+            tracing::debug!("no key from the keyring: {}", e);
+            None
+        }
+    }
+}
+
+/// the client credentials of the `[oauth]` table in the profile or the config, with
+/// the secret given as `client_secret` or printed by `client_secret_cmd`
+fn oauth(config: &Config) -> Result<Option<OAuth>, qllm::Error> {
+    let Some(table) = config.get("oauth") else {
+        return Ok(None);
+    };
+    let field = |name: &str| table.get(name).and_then(|v| v.as_str());
+    let required = |name: &str| field(name).ok_or_else(|| format!("The [oauth] table needs a {}.", name));
+    let secret = match (field("client_secret"), field("client_secret_cmd")) {
+        (Some(secret), _) => secret.to_string(),
+        (None, Some(cmd)) => qllm::config::run_key_cmd(cmd)?,
+        (None, None) => return Err("The [oauth] table needs a client_secret or client_secret_cmd.".into()),
+    };
+    let mut oauth = OAuth::new(required("token_url")?, required("client_id")?, &secret);
+    if let Some(scope) = field("scope") {
+        oauth = oauth.with_scope(scope);
+    }
+    if let Some(audience) = field("audience") {
+        oauth = oauth.with_audience(audience);
+    }
+    Ok(Some(oauth))
+}
 
-    /// the temperature parameter for the model
-    #[clap(short, long, default_value = "0.8")]
-    temperature: f64,
+/// the audit log named by `audit_log` in the config, keeping hashes of the text unless
+/// `audit_text = "full"`
+fn audit_log(config: &Config) -> Result<Option<AuditLog>, qllm::Error> {
+    let Some(path) = config.str("audit_log") else {
+        return Ok(None);
+    };
+    let full_text = match config.str("audit_text") {
+        None | Some("hash") => false,
+        Some("full") => true,
+        Some(other) => return Err(format!("audit_text must be \"hash\" or \"full\", not {:?}.", other).into()),
+    };
+    Ok(Some(AuditLog::open(&qllm::dirs::expand_home(path), full_text)))
+}
 
-    /// the top_p parameter for the model
-    #[clap(long, default_value = "0.95")]
-    top_p: f64,
+/// the model from the command line, with aliases resolved, or the config
+fn model(common: &CommonArgs, config: &Config) -> String {
+    common.model.clone()
+        .map(|name| match config.alias(&name) {
+            Ok(Some((model, _))) => model,
+            _ => name,
+        })
+        .or_else(|| config.str("model").map(str::to_string))
+        .unwrap_or_else(|| "default".to_string())
+}
 
-    /// the min_p parameter for the model
-    #[clap(long, default_value = "0.05")]
-    min_p: f64,
+/// whether to stream, from `--no-stream` or `stream = false` in the config
+fn stream(no_stream: bool, config: &Config) -> bool {
+    !no_stream && config.get("stream").and_then(|v| v.as_bool()).unwrap_or(true)
+}
 
-    /// the top_k parameter for the model
-    #[clap(long, default_value = "40")]
-    top_k: usize,
+/// how to fit a prompt that is too long, from `--fit` or `fit` in the config
+fn fit(flag: Option<Fit>, config: &Config) -> Result<Fit, qllm::Error> {
+    if let Some(fit) = flag {
+        return Ok(fit);
+    }
+    match config.str("fit") {
+        Some(name) => Fit::from_str(name, true).map_err(|_| format!("`fit` in the config must be one of error, truncate-head, truncate-tail, or middle-out, not {:?}.", name).into()),
+        None => Ok(Fit::Error),
+    }
+}
 
-    /// the repetition penalty for the model
-    #[clap(long, default_value = "1.1")]
-    repetition_penalty: f64,
+/// how long cached answers stay valid, from `--cache-ttl` or the config; 0 means for ever
+fn cache_ttl(flag: Option<u64>, config: &Config) -> Option<Duration> {
+    match flag.or_else(|| config.get("cache_ttl").and_then(|v| v.as_u64())).unwrap_or(86400) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
 
-    /// the token set to consider for repetition penalty
-    #[clap(long, default_value = "64")]
-    repetition_penalty_last: usize,
+/// the chat settings after merging the command line over the config
+pub struct ChatOptions {
+    pub model: String,
+    pub system: String,
+    /// the filled-in user prompt of `--template`, put before the rest of the prompt
+    pub template_prompt: Option<String>,
+    pub params: SamplerParams,
+    pub stream: bool,
+    pub tools: Vec<serde_json::Value>,
+    /// ask for token log probabilities with this many alternatives
+    pub logprobs: Option<usize>,
+    /// the JSON the answers must be, from `--json` or `--schema`
+    pub response_format: Option<ResponseFormat>,
+    /// how answers are printed
+    pub display: Display,
+    /// print the reasoning of thinking models rather than hide it
+    pub show_reasoning: bool,
+    /// the width prose is wrapped at
+    pub wrap: Option<usize>,
+    /// OpenRouter's fallback models and provider preferences
+    pub routing: Option<serde_json::Value>,
+    pub prices: Prices,
+    pub budget: Budget,
+}
 
-    /// the presence penalty for the model
-    #[clap(long, default_value = "0.0")]
-    presence_penalty: f64,
+/// how an answer is printed as it streams in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Display {
+    /// the text as it arrives
+    Raw,
+    /// markdown formatted for the terminal
+    Render,
+    /// only the code of the fenced blocks, or of the nth block
+    Code(Option<usize>),
+    /// nothing while streaming, for `--output json`
+    Quiet,
+    /// one JSON object per delta, for `--output ndjson`
+    Events,
+    /// the text coloured by the probability of each token, for `--logprobs`
+    Confidence,
+}
 
-    /// the frequency penalty for the model
-    #[clap(long, default_value = "0.0")]
-    frequency_penalty: f64,
+impl ChatOptions {
+    fn new(args: &ChatArgs, config: &Config) -> Result<Self, qllm::Error> {
+        let tools = match &args.tools {
+            Some(path) => qllm::tools::load(path)?,
+            None => Vec::new(),
+        };
+        let template = args.template.as_deref().map(Template::load).transpose()?;
+        let fill = |text: Option<&String>| -> Result<Option<String>, qllm::Error> {
+            match (&template, text) {
+                (Some(template), Some(text)) => Ok(Some(template.render(text, &args.vars)?)),
+                _ => Ok(None),
+            }
+        };
+        let template_system = fill(template.as_ref().and_then(|t| t.system.as_ref()))?;
+        let mut system = args.system.clone()
+            .or_else(|| args.system_file.clone())
+            .or(template_system)
+            .or_else(|| config.str("system").map(str::to_string))
+            .unwrap_or_else(|| DEFAULT_SYSTEM.to_string());
+        if args.apply {
+            system = format!("{}\n\n{}", system, APPLY_SYSTEM);
+        }
+        Ok(ChatOptions {
+            model: model(&args.common, config),
+            system,
+            template_prompt: fill(template.as_ref().and_then(|t| t.prompt.as_ref()))?.filter(|prompt| !prompt.is_empty()),
+            params: args.sampler.to_params(config.sampler(args.sampler.preset.as_deref())?),
+            stream: stream(args.no_stream, config),
+            tools,
+            logprobs: args.logprobs.map(usize::from),
+            show_reasoning: args.show_reasoning || config.get("show_reasoning").and_then(|v| v.as_bool()).unwrap_or(false),
+            wrap: wrap_width(args.wrap, config),
+            routing: routing(&args.route, &args.prefer, config),
+            response_format: match &args.schema {
+                Some(path) => Some(load_schema(path)?),
+                None => args.json.then_some(ResponseFormat::Json),
+            },
+            prices: Prices::new(config.get("prices")),
+            budget: Budget::new(args.max_cost, config),
+            display: match args.output {
+                OutputFormat::Json => Display::Quiet,
+                OutputFormat::Ndjson => Display::Events,
+                OutputFormat::Text => display(
+                    args.code_only,
+                    args.render || config.get("render").and_then(|v| v.as_bool()).unwrap_or(false),
+                    args.logprobs.is_some(),
+                ),
+            },
+        })
+    }
 
-    /// the typical p parameter for the model
-    #[clap(long, default_value = "1.0")]
-    typical_p: f64,
+    fn request(&self, messages: Vec<Message>) -> ChatRequest {
+        let mut request = ChatRequest::new(&self.model, messages);
+        request.params = self.params.clone();
+        request.stream = self.stream;
+        request.tools = self.tools.clone();
+        request.logprobs = self.logprobs;
+        request.response_format = self.response_format.clone();
+        request.routing = self.routing.clone();
+        request
+    }
 
-    /// the mirostat mode for the model
-    #[clap(long, default_value = "0")]
-    mirostat_mode: u8,
+    /// check a chat request against the budget before it is sent
+    fn check_budget(&self, request: &ChatRequest) -> Result<(), qllm::Error> {
+        self.budget.check(&self.prices, &request.model, prompt_tokens(request), request.params.max_tokens)
+    }
+}
 
-    /// the mirostat tau parameter for the model
-    #[clap(long, default_value = "5.0")]
-    mirostat_tau: f64,
+/// the text of a chat request that counts towards its prompt tokens, tool definitions included
+fn prompt_text(request: &ChatRequest) -> String {
+    let mut text: String = request.messages.iter().map(|m| m.content.as_str()).collect();
+    if !request.tools.is_empty() {
+        text.push_str(&serde_json::Value::from(request.tools.clone()).to_string());
+    }
+    text
+}
 
-    /// the mirostat eta parameter for the model
-    #[clap(long, default_value = "0.1")]
-    mirostat_eta: f64
+/// a rough count of the tokens a chat request sends
+fn prompt_tokens(request: &ChatRequest) -> u64 {
+    pricing::estimate_tokens(&prompt_text(request))
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+/// requests estimated to need fewer tokens than this fit any model, so the context
+/// window is not looked up for them
+const SMALL_CONTEXT: u64 = 2048;
 
-    let endpoint = if !args.endpoint.is_empty() {
-        args.endpoint.clone()
-    } else if env::var_os("QLLM_ENDPOINT").is_some() {
-        std::env::var("QLLM_ENDPOINT")?
-    } else {
-        return Err("No endpoint specified. One must be given on the command line via -e or via the environmental variable QLLM_ENDPOINT.".into());
+/// the room a prompt must leave for the answer when `max_tokens` is not set
+const MIN_ANSWER: u64 = 256;
+
+/// the model's context window, from `context_length` in the config or else the server
+async fn context_length(client: &QllmClient, config: &Config, model: &str) -> Option<u64> {
+    match config.get("context_length").and_then(|v| v.as_u64()) {
+        Some(length) => Some(length),
+        // a server that can not say is no reason to fail the request
+        None => client.context_length(model).await.unwrap_or(None),
+    }
+}
+
+/// look up the model's context window when a request could come near it; a prompt that leaves too little room for an
+/// answer is refused or has its input cut according to `fit`, and a `max_tokens` beyond the
+/// room left is lowered; `context` is the part of the last user message that may be cut
+async fn fit_context(client: &QllmClient, config: &Config, fit: Fit, request: &mut ChatRequest, context: Option<&str>) -> Result<(), qllm::Error> {
+    let max_tokens = request.params.max_tokens.max(0) as u64;
+    if prompt_tokens(request) + max_tokens <= SMALL_CONTEXT {
+        return Ok(());
+    }
+    let Some(length) = context_length(client, config, &request.model).await else {
+        return Ok(());
     };
+    let tokenizer = Tokenizer::for_model(&request.model, config).unwrap_or(Tokenizer::Estimate);
+    let mut prompt = tokenizer.count(&prompt_text(request));
+    // room for the answer asked for, but never more than half the context
+    let answer = if max_tokens > 0 { max_tokens } else { MIN_ANSWER };
+    let budget = length - answer.min(length / 2);
+    if prompt > budget {
+        let over = prompt - budget;
+        let cut = match fit {
+            Fit::Error => return Err(format!(
+                "The prompt is about {} tokens, more than the {} the {} token context of {} leaves for it. \
+                 Pass --fit to cut the input down to size.",
+                prompt, budget, length, request.model,
+            ).into()),
+            Fit::TruncateHead => Cut::Head,
+            Fit::TruncateTail => Cut::Tail,
+            Fit::MiddleOut => Cut::Middle,
+        };
+        let message = request.messages.iter_mut().rev().find(|m| m.role == "user")
+            .ok_or("The prompt is too long for the context, and has no user message to cut.")?;
+        // cut the input from stdin rather than the instructions around it, where it can be found
+        let (start, end) = match context.filter(|c| !c.is_empty()).and_then(|c| message.content.find(c).map(|i| (i, i + c.len()))) {
+            Some(span) => span,
+            None => (0, message.content.len()),
+        };
+        let span = &message.content[start..end];
+        let tokens = tokenizer.count(span);
+        if tokens <= over {
+            return Err(format!(
+                "The prompt is about {} tokens, more than the {} the {} token context of {} leaves for it, even without the input.",
+                prompt - tokens, budget, length, request.model,
+            ).into());
+        }
+        let kept = tokenizer.truncate(span, tokens - over, cut);
+        let lines = span.lines().count() - kept.lines().count().min(span.lines().count());
+        let from = match cut {
+            Cut::Head => "start",
+            Cut::Tail => "end",
+            Cut::Middle => "middle",
+        };
+        eprintln!(
+            "Cut about {} tokens ({} lines) from the {} of the input to fit the {} token context of {}.",
+            tokens - tokenizer.count(&kept), lines, from, length, request.model,
+        );
+        message.content.replace_range(start..end, &kept);
+        prompt = tokenizer.count(&prompt_text(request));
+    }
+    if max_tokens > length.saturating_sub(prompt) {
+        let room = length.saturating_sub(prompt);
+        eprintln!("Lowering max_tokens from {} to {} to fit the {} token context of {}.", max_tokens, room, length, request.model);
+        request.params.max_tokens = room as i64;
+    }
+    Ok(())
+}
 
-    // set a key if we have one in the environment under QLLM_KEY
-    let key = if !args.key.is_empty() {
-        Some(args.key.clone())
-    } else if env::var_os("QLLM_KEY").is_some() {
-        Some(std::env::var("QLLM_KEY")?)
-    } else {
-        None
+/// the spending limits from `--max-cost` and the `max_cost` and `monthly_budget` config keys
+pub struct Budget {
+    per_request: Option<f64>,
+    monthly: Option<f64>,
+}
+
+impl Budget {
+    fn new(max_cost: Option<f64>, config: &Config) -> Self {
+        Budget {
+            per_request: max_cost.or_else(|| config.get("max_cost").and_then(|v| v.as_f64())),
+            monthly: config.get("monthly_budget").and_then(|v| v.as_f64()),
+        }
+    }
+
+    /// refuse a request whose estimated cost breaks a limit, unless the user confirms it on
+    /// the terminal; `max_tokens` counts as output when it is set, models without a price pass
+    fn check(&self, prices: &Prices, model: &str, prompt_tokens: u64, max_tokens: i64) -> Result<(), qllm::Error> {
+        if self.per_request.is_none() && self.monthly.is_none() {
+            return Ok(());
+        }
+        let Some(price) = prices.get(model) else {
+            return Ok(());
+        };
+        let estimate = price.cost(prompt_tokens, max_tokens.max(0) as u64);
+        let mut problems = Vec::new();
+        if let Some(limit) = self.per_request.filter(|&limit| estimate > limit) {
+            problems.push(format!("the estimated ${:.4} for about {} prompt tokens is over the limit of ${:.4}", estimate, prompt_tokens, limit));
+        }
+        problems.extend(self.check_month(prices, estimate)?);
+        Budget::settle(problems)
+    }
+
+    /// refuse a batch of requests if any of them breaks the limit of a request, or all of
+    /// them together the monthly budget, asking once for the whole batch
+    fn check_all(&self, prices: &Prices, requests: &[ChatRequest]) -> Result<(), qllm::Error> {
+        if self.per_request.is_none() && self.monthly.is_none() {
+            return Ok(());
+        }
+        let estimates: Vec<f64> = requests.iter()
+            .filter_map(|request| Some(prices.get(&request.model)?.cost(prompt_tokens(request), request.params.max_tokens.max(0) as u64)))
+            .collect();
+        let mut problems = Vec::new();
+        if let Some(limit) = self.per_request {
+            let over: Vec<f64> = estimates.iter().copied().filter(|&estimate| estimate > limit).collect();
+            if let Some(highest) = over.iter().copied().reduce(f64::max) {
+                problems.push(format!("{} of the {} requests are estimated over the limit of ${:.4}, at up to ${:.4}", over.len(), requests.len(), limit, highest));
+            }
+        }
+        problems.extend(self.check_month(prices, estimates.iter().sum())?);
+        Budget::settle(problems)
+    }
+
+    /// what is wrong with spending `estimate` more this month
+    fn check_month(&self, prices: &Prices, estimate: f64) -> Result<Option<String>, qllm::Error> {
+        let Some(limit) = self.monthly else {
+            return Ok(None);
+        };
+        let spent = month_spend(prices)?;
+        Ok((spent + estimate > limit).then(|| {
+            format!("${:.4} spent this month plus the estimated ${:.4} is over the monthly budget of ${:.2}", spent, estimate, limit)
+        }))
+    }
+
+    /// pass if nothing is wrong, or else if the user says to go ahead anyway
+    fn settle(problems: Vec<String>) -> Result<(), qllm::Error> {
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let problems = problems.join(", and ");
+        if std::io::stderr().is_terminal() && confirm(&format!("{}; send it anyway? [y/N] ", problems)) {
+            return Ok(());
+        }
+        Err(format!("Not sent: {}.", problems).into())
+    }
+}
+
+/// the cost of the exchanges in the history since the start of this calendar month
+fn month_spend(prices: &Prices) -> Result<f64, qllm::Error> {
+    Ok(History::open_default().since(qllm::time::start_of_month(qllm::time::now()))?.iter()
+        .filter_map(|e| e.cost.or_else(|| prices.cost(&e.model, e.usage.as_ref()?)))
+        // not sum(), which gives -0 for no exchanges
+        .fold(0.0, |spent, cost| spent + cost))
+}
+
+/// ask a yes or no question on the terminal, so that stdin can still carry the prompt
+fn confirm(question: &str) -> bool {
+    eprint!("{}", question);
+    std::io::stderr().flush().ok();
+    let mut line = String::new();
+    let read = match std::fs::File::open("/dev/tty") {
+        Ok(tty) => std::io::BufReader::new(tty).read_line(&mut line),
+        Err(_) => std::io::stdin().lock().read_line(&mut line),
     };
+    read.is_ok() && matches!(line.trim().to_lowercase().as_str(), "y" | "yes")
+}
 
-    // Check for stdin data using select
-    let mut stdin = async_io::stdin();
+/// read a `--schema` file, either a bare JSON Schema named after the file or an
+/// OpenAI `json_schema` object with its own `name` and `schema`
+fn load_schema(path: &std::path::Path) -> Result<ResponseFormat, qllm::Error> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let value: serde_json::Value = serde_json::from_str(&text).map_err(|e| format!("{} is not JSON: {}", path.display(), e))?;
+    if let (Some(name), Some(schema)) = (value["name"].as_str(), value.get("schema").filter(|s| s.is_object())) {
+        return Ok(ResponseFormat::Schema { name: name.to_string(), schema: schema.clone() });
+    }
+    if !value.is_object() {
+        return Err(format!("{} does not hold a JSON Schema object", path.display()).into());
+    }
+    // the name may only have letters, digits, underscores, and dashes
+    let name: String = path.file_stem().unwrap_or_default().to_string_lossy().chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    Ok(ResponseFormat::Schema { name, schema: value })
+}
+
+/// check that an answer is the JSON the request asked for
+fn check_json(answer: &str, format: &ResponseFormat) -> Result<(), qllm::Error> {
+    let text = answer.trim();
+    // tolerate a model that wraps the JSON in a code block anyway
+    let text = match blocks::blocks(text).as_slice() {
+        [block] if text.starts_with("```") => block.code.trim().to_string(),
+        _ => text.to_string(),
+    };
+    let value: serde_json::Value = serde_json::from_str(&text).map_err(|e| format!("The answer is not valid JSON: {}.", e))?;
+    if let ResponseFormat::Schema { schema, .. } = format {
+        let errors = qllm::schema::validate(&value, schema);
+        if !errors.is_empty() {
+            return Err(format!("The answer does not match the schema: {}.", errors.join("; ")).into());
+        }
+    }
+    Ok(())
+}
+
+/// read all of stdin for `-c`
+async fn read_stdin(stdin: bool) -> Result<Option<String>, qllm::Error> {
+    if !stdin {
+        return Ok(None);
+    }
     let mut input = String::new();
-    // if we read from stdin
-    if args.stdin {
-        // read from stdin
-        stdin.read_to_string(&mut input).await?;
+    async_io::stdin().read_to_string(&mut input).await?;
+    Ok(Some(input))
+}
+
+/// join the positional words after the text of `--file`, reading a `-` word from stdin,
+/// and prepend the `-c` context read from stdin
+async fn read_prompt(words: &[String], context: Option<&str>, file: Option<&std::path::Path>) -> Result<String, qllm::Error> {
+    let dash = words.iter().any(|word| word == "-");
+    if dash && context.is_some() {
+        return Err("Both `-` and -c read stdin, use one of them.".into());
+    }
+    let mut input = String::new();
+    if dash {
+        async_io::stdin().read_to_string(&mut input).await?;
     }
 
-    let mut user_prompt = args.prompt.join(" ");
-    if !input.is_empty() {
-        user_prompt = format!("{}\n{}", input, user_prompt);
-    } else {
-        user_prompt = user_prompt.to_string();
-    }
-
-    let client = reqwest::Client::new();
-    let models = json!({
-        "messages": [
-            { "role": "system", "content": args.system },
-            { "role": "user", "content": user_prompt },
-        ],
-        "max_tokens": args.max_tokens,
-        "temperature": args.temperature,
-        "top_p": args.top_p,
-        "top_k": args.top_k,
-        "min_p": args.min_p,
-        "repetition_penalty": args.repetition_penalty,
-        "repetition_penalty_last": args.repetition_penalty_last,
-        "presence_penalty": args.presence_penalty,
-        "frequency_penalty": args.frequency_penalty,
-        "typical_p": args.typical_p,
-        "mirostat_mode": args.mirostat_mode,
-        "mirostat_tau": args.mirostat_tau,
-        "mirostat_eta": args.mirostat_eta,
-        "stream": true
-    });
+    let mut parts = Vec::new();
+    if let Some(path) = file {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        parts.push(text.trim_end().to_string());
+    }
+    let words = words.iter()
+        .map(|word| if word == "-" { input.trim_end() } else { word.as_str() })
+        .collect::<Vec<_>>()
+        .join(" ");
+    if !words.is_empty() {
+        parts.push(words);
+    }
+    let prompt = parts.join("\n");
+    match context {
+        Some(context) if !context.is_empty() => Ok(format!("{}\n{}", context, prompt)),
+        _ => Ok(prompt),
+    }
+}
+
+/// split the positional words into the prompt and the files to attach after it, which are
+/// the words of the form `@path` that name a file; any other word, `@` or not, is prompt
+fn file_words(words: &[String]) -> (Vec<String>, Vec<String>) {
+    let (files, words): (Vec<&String>, Vec<&String>) = words.iter()
+        .partition(|word| word.strip_prefix('@').is_some_and(|path| std::path::Path::new(path).is_file()));
+    let files = files.iter().map(|word| word[1..].to_string()).collect();
+    (words.into_iter().cloned().collect(), files)
+}
+
+/// files attached to a prompt are cut to this many tokens unless `--max-file-tokens` says otherwise
+const MAX_FILE_TOKENS: u64 = 16384;
 
-    let response = client.post(&endpoint)
-        .header("Content-Type", "application/json")
-        .bearer_auth(key.unwrap_or_default())
-        .json(&models)
-        .send()
-        .await?;
-
-    let mut stream = response.bytes_stream();
-    let mut first = true;
-
-    while let Some(item) = stream.next().await {
-        match item {
-            Ok(bytes) => {
-                let line = String::from_utf8_lossy(&bytes).trim().to_string();
-                if line == "data: [DONE]" {
-                    break;
+/// the files as fenced blocks under their names, cut to `max_tokens` tokens each;
+/// binary files are left out with a note on stderr, and with `summary` what was
+/// included is listed there with its tokens
+fn attach_files(paths: &[String], model: &str, config: &Config, max_tokens: u64, summary: bool) -> Result<String, qllm::Error> {
+    if paths.is_empty() {
+        return Ok(String::new());
+    }
+    let tokenizer = Tokenizer::for_model(model, config).unwrap_or(Tokenizer::Estimate);
+    let mut blocks = Vec::new();
+    let mut total = 0;
+    let mut binary = 0;
+    for path in paths {
+        match Attachment::read(std::path::Path::new(path), &tokenizer, max_tokens)? {
+            Some(attachment) => {
+                if let Some(tokens) = attachment.cut_from {
+                    eprintln!("Attaching only the first {} of the {} tokens of {}.", attachment.tokens, tokens, path);
+                }
+                if summary {
+                    eprintln!("{:>8}  {}", attachment.tokens, path);
                 }
-                if let Some(json_str) = line.strip_prefix("data: ") {
-                    if let Ok(parsed) = serde_json::from_str::<Value>(json_str) {
-                        if let Some(text) = parsed["choices"][0]["delta"]["content"].as_str() {
-                            let mut text = text;
-                            if first {
-                                // trim the leading space from the first response
-                                text = text.trim_start();
-                                first = false;
-                            }
-                            print!("{}", text);
-                            // flush stdout to make sure the text is visible immediately
-                            std::io::stdout().flush().unwrap();
-                        }
-                    }
+                total += attachment.tokens;
+                blocks.push(attachment.to_markdown());
+            }
+            // a walked directory is full of these, so they are only counted
+            None if summary => binary += 1,
+            None => eprintln!("Leaving out {}, which is not a text file.", path),
+        }
+    }
+    if summary {
+        let files = |n: usize| if n == 1 { "1 file".to_string() } else { format!("{} files", n) };
+        let skipped = if binary > 0 { format!(", leaving out {} that are binary", files(binary)) } else { String::new() };
+        eprintln!("{:>8}  tokens in {}{} ({})", total, files(blocks.len()), skipped, tokenizer.describe());
+    }
+    Ok(blocks.join("\n\n"))
+}
+
+/// a map of the files with their signatures, or of the current directory when there
+/// are none, cut to `max_tokens` tokens
+fn repomap(paths: &[String], model: &str, config: &Config, max_tokens: u64) -> Result<String, qllm::Error> {
+    let paths = match paths.is_empty() {
+        true => qllm::attach::walk(std::path::Path::new("."))?.iter().map(|path| path.display().to_string()).collect(),
+        false => paths.to_vec(),
+    };
+    let tokenizer = Tokenizer::for_model(model, config).unwrap_or(Tokenizer::Estimate);
+    let mut map = qllm::repomap::map(&paths);
+    let tokens = tokenizer.count(&map);
+    if tokens > max_tokens {
+        eprintln!("Attaching only the first {} of the {} tokens of the map.", max_tokens, tokens);
+        map = tokenizer.truncate(&map, max_tokens, Cut::Tail);
+    }
+    eprintln!("Mapped {} files in {} tokens ({}).", paths.len(), tokenizer.count(&map), tokenizer.describe());
+    Ok(format!("Map of the repository, each file with the signatures it defines:\n```\n{}\n```", map))
+}
+
+/// the files named with `@`, then those matching `--files` and under `--dir`,
+/// each once
+fn attached_paths(files: &[String], args: &ChatArgs) -> Result<Vec<String>, qllm::Error> {
+    let mut found: Vec<std::path::PathBuf> = files.iter().map(std::path::PathBuf::from).collect();
+    for pattern in &args.globs {
+        let matched = qllm::attach::glob(pattern)?;
+        if matched.is_empty() {
+            eprintln!("No files match {}.", pattern);
+        }
+        found.extend(matched);
+    }
+    for dir in &args.dirs {
+        found.extend(qllm::attach::walk(dir)?);
+    }
+    let mut seen = std::collections::HashSet::new();
+    Ok(found.into_iter()
+        .filter(|path| seen.insert(path.clone()))
+        .map(|path| path.display().to_string())
+        .collect())
+}
+
+/// let the user write the prompt in `$VISUAL` or `$EDITOR`, as `git commit` does
+async fn edit_prompt(initial: &str) -> Result<String, qllm::Error> {
+    // a file of its own that no one else can read or put in place first, removed when dropped
+    let file = tempfile::Builder::new().prefix("qllm-prompt-").suffix(".md").tempfile()?;
+    file.as_file().write_all(initial.as_bytes())?;
+    run_editor(file.path()).await?;
+    let text = std::fs::read_to_string(file.path())?.trim().to_string();
+    if text.is_empty() {
+        return Err("Aborting because the prompt is empty.".into());
+    }
+    Ok(text)
+}
+
+/// open `path` in `$VISUAL` or `$EDITOR`, or `vi`, and wait for the editor to exit
+async fn run_editor(path: &std::path::Path) -> Result<(), qllm::Error> {
+    let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+    // through the shell, so that an editor with arguments like `code --wait` works
+    let status = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status()
+        .await
+        .map_err(|e| format!("Could not run the editor {}: {}", editor, e))?;
+    if !status.success() {
+        return Err(format!("The editor {} exited with {}.", editor, status).into());
+    }
+    Ok(())
+}
+
+/// the stdout of a git command
+async fn git(args: &[&str]) -> Result<String, qllm::Error> {
+    let output = tokio::process::Command::new("git").args(args).output().await
+        .map_err(|e| format!("Could not run git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// the width of `--wrap`, or of `wrap` in the config as a number of columns or `true`;
+/// 0 and `true` mean the width of the terminal, and no wrapping when there is none
+fn wrap_width(flag: Option<usize>, config: &Config) -> Option<usize> {
+    let width = flag.or_else(|| match config.get("wrap") {
+        Some(serde_json::Value::Bool(true)) => Some(0),
+        Some(value) => value.as_u64().map(|width| width as usize),
+        None => None,
+    })?;
+    match width {
+        0 => wrap::terminal_width(),
+        width => Some(width),
+    }
+}
+
+/// the fields of the config's `[openrouter]` table, such as `provider` preferences, with
+/// the fallback models of `--route` and the provider order of `--prefer` over them
+fn routing(route: &[String], prefer: &[String], config: &Config) -> Option<serde_json::Value> {
+    let mut routing = config.get("openrouter").and_then(|table| table.as_object()).cloned().unwrap_or_default();
+    if !route.is_empty() {
+        routing.insert("models".into(), serde_json::json!(route));
+    }
+    if !prefer.is_empty() {
+        let provider = routing.entry("provider").or_insert_with(|| serde_json::json!({}));
+        provider["order"] = serde_json::json!(prefer);
+    }
+    (!routing.is_empty()).then_some(serde_json::Value::Object(routing))
+}
+
+/// the display chosen by `--code-only`, `--render`, and `--logprobs`, in that order of precedence
+fn display(code_only: Option<Option<usize>>, render: bool, logprobs: bool) -> Display {
+    match code_only {
+        Some(block) => Display::Code(block),
+        None if render => Display::Render,
+        None if logprobs => Display::Confidence,
+        None => Display::Raw,
+    }
+}
+
+/// the tokens behind a delta coloured by their probability: green when likely, yellow when
+/// in doubt, and red when unlikely; the plain text if the tokens do not add up to it
+fn confidence(text: &str, tokens: &[qllm::logprobs::TokenLogprob]) -> String {
+    let joined: String = tokens.iter().map(|t| t.token.as_str()).collect();
+    // the first delta of a stream has its leading whitespace trimmed
+    if tokens.is_empty() || (joined != text && joined.trim_start() != text) {
+        return text.to_string();
+    }
+    let theme = theme::out();
+    let mut out = String::new();
+    let mut trim = joined != text;
+    for token in tokens {
+        let colour = match token.probability() {
+            p if p >= 0.9 => &theme.likely,
+            p if p >= 0.5 => &theme.unsure,
+            _ => &theme.unlikely,
+        };
+        let shown = if trim { token.token.trim_start() } else { token.token.as_str() };
+        trim &= shown.is_empty();
+        out.push_str(&format!("{}{}{}", colour, shown, theme.reset));
+    }
+    out
+}
+
+/// print the stream as it arrives, returning the full text; rendered markdown and
+/// code blocks are printed a line at a time, and `tee` gets the plain text too;
+/// reasoning goes dimmed to stderr when `reasoning` is set, and is left out otherwise
+async fn print_stream(stream: &mut ChatStream, display: Display, wrap: Option<usize>, reasoning: bool, mut tee: Option<&mut std::fs::File>) -> Result<String, qllm::Error> {
+    let mut answer = String::new();
+    let mut renderer = (display == Display::Render).then(render::Renderer::new);
+    let mut wrapper = wrap.filter(|_| matches!(display, Display::Raw | Display::Render | Display::Confidence)).map(wrap::Wrapper::new);
+    let mut wrapped = |text: String| match wrapper.as_mut() {
+        Some(wrapper) => wrapper.push(&text),
+        None => text,
+    };
+    let mut filter = match display {
+        Display::Code(block) => Some(blocks::BlockFilter::new(block)),
+        _ => None,
+    };
+    let mut index = 0;
+    // the log probabilities already printed
+    let mut seen = 0;
+    // whether reasoning has been printed without a line break after it
+    let mut thinking = false;
+    while let Some(piece) = stream.next_piece().await? {
+        spinner::stop();
+        let text = match piece {
+            Delta::Answer(text) => text,
+            Delta::Reasoning(text) => {
+                if !reasoning || display == Display::Quiet {
+                    continue;
+                }
+                if display == Display::Events {
+                    println!("{}", serde_json::json!({ "type": "reasoning", "text": text, "timestamp_ms": qllm::time::now_ms() }));
+                    continue;
+                }
+                let text = if thinking { text.as_str() } else { text.trim_start() };
+                if !text.is_empty() {
+                    eprint!("{}{}{}", theme::err().reasoning, text, theme::err().reset);
+                    thinking = true;
+                }
+                continue;
+            }
+        };
+        if std::mem::take(&mut thinking) {
+            eprintln!("\n");
+        }
+        let tokens = &stream.logprobs()[seen..];
+        seen = stream.logprobs().len();
+        if display == Display::Quiet {
+            // printed as a whole by the caller
+        } else if display == Display::Events {
+            let mut event = serde_json::json!({ "type": "delta", "index": index, "text": text, "timestamp_ms": qllm::time::now_ms() });
+            if !tokens.is_empty() {
+                event["logprobs"] = tokens.iter().map(qllm::logprobs::TokenLogprob::to_json).collect();
+            }
+            println!("{}", event);
+            index += 1;
+        } else if display == Display::Confidence {
+            print!("{}", wrapped(confidence(&text, tokens)));
+        } else if let Some(renderer) = renderer.as_mut() {
+            print!("{}", wrapped(renderer.push(&text)));
+        } else if let Some(filter) = filter.as_mut() {
+            print!("{}", filter.push(&text));
+        } else {
+            print!("{}", wrapped(text.clone()));
+        }
+        // flush stdout to make sure the text is visible immediately
+        std::io::stdout().flush().unwrap();
+        if let Some(file) = tee.as_mut() {
+            file.write_all(text.as_bytes())?;
+        }
+        answer.push_str(&text);
+    }
+    spinner::stop();
+    if thinking {
+        eprintln!();
+    }
+    if let Some(mut renderer) = renderer {
+        print!("{}", wrapped(renderer.finish()));
+    }
+    if let Some(mut wrapper) = wrapper {
+        print!("{}", wrapper.finish());
+    }
+    if let Some(mut filter) = filter {
+        print!("{}", filter.finish());
+    }
+    Ok(answer)
+}
+
+/// a whole answer as print_stream shows it, for the pager; the colours of
+/// `--logprobs` are left out, as the log probabilities are not kept with the text
+fn formatted(answer: &str, display: Display, wrap: Option<usize>) -> String {
+    let text = match display {
+        Display::Render => {
+            let mut renderer = render::Renderer::new();
+            renderer.push(answer) + &renderer.finish()
+        }
+        Display::Code(block) => {
+            let mut filter = blocks::BlockFilter::new(block);
+            return filter.push(answer) + &filter.finish();
+        }
+        _ => answer.to_string(),
+    };
+    match wrap {
+        Some(width) => {
+            let mut wrapper = wrap::Wrapper::new(width);
+            wrapper.push(&text) + &wrapper.finish()
+        }
+        None => text,
+    }
+}
+
+/// the answer and what is known about it, for `--output json`
+fn output_json(stream: &ChatStream, answer: &str, model: &str, params: &SamplerParams, timestamp: u64, started: Instant) -> serde_json::Value {
+    let mut output = serde_json::json!({
+        "content": answer,
+        "finish_reason": stream.finish_reason(),
+        "model": model,
+        "usage": stream.usage(),
+        "timing": {
+            "started": timestamp,
+            "duration_ms": started.elapsed().as_millis() as u64,
+            "first_token_ms": stream.first_token_at().map(|at| at.duration_since(started).as_millis() as u64),
+        },
+        "params": params.to_json(),
+    });
+    let calls = stream.tool_calls();
+    if !calls.is_empty() {
+        output["tool_calls"] = calls.iter().map(qllm::ToolCall::to_json).collect();
+    }
+    if !stream.reasoning().is_empty() {
+        output["reasoning"] = stream.reasoning().into();
+    }
+    if !stream.logprobs().is_empty() {
+        output["logprobs"] = stream.logprobs().iter().map(qllm::logprobs::TokenLogprob::to_json).collect();
+    }
+    output
+}
+
+/// open the `--out` file, writing the `--out-header` comment if asked
+fn open_out(args: &ChatArgs, options: &ChatOptions) -> Result<Option<std::fs::File>, qllm::Error> {
+    let Some(path) = &args.out else {
+        return Ok(None);
+    };
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(args.append)
+        .truncate(!args.append)
+        .open(path)
+        .map_err(|e| format!("could not open {}: {}", path.display(), e))?;
+    if args.out_header {
+        writeln!(
+            file,
+            "<!-- qllm {} model={} params={} -->",
+            qllm::time::format_timestamp(qllm::time::now()),
+            options.model,
+            options.params.to_json(),
+        )?;
+    }
+    Ok(Some(file))
+}
+
+/// print token counts, timing, and throughput to stderr for `--stats`
+fn print_stats(stream: &ChatStream, answer: &str, started: Instant, cost: Option<f64>) {
+    let total = started.elapsed();
+    let (prompt, completion) = stream.usage().map(qllm::token_counts).unwrap_or_default();
+    let count = |n: Option<u64>| n.map(|n| n.to_string()).unwrap_or_else(|| "?".to_string());
+    // the parts of the counts reported by OpenAI, OpenRouter, and others in the same shape
+    let usage = stream.usage().cloned().unwrap_or_default();
+    let part = |n: Option<u64>, what: &str| n.filter(|n| *n > 0).map(|n| format!(" ({} {})", n, what)).unwrap_or_default();
+    let cached = part(usage["prompt_tokens_details"]["cached_tokens"].as_u64(), "cached");
+    let reasoning = part(usage["completion_tokens_details"]["reasoning_tokens"].as_u64(), "reasoning");
+    let mut stats = format!("{} prompt{} + {} completion{} tokens, {:.2}s", count(prompt), cached, count(completion), reasoning, total.as_secs_f64());
+    if let Some(first) = stream.first_token_at() {
+        let to_first = first.duration_since(started);
+        stats.push_str(&format!(", {:.2}s to first token", to_first.as_secs_f64()));
+        let generating = (total - to_first).as_secs_f64();
+        if let (Some(completion), true) = (completion, generating > 0.0) {
+            stats.push_str(&format!(", {:.1} tokens/s", completion as f64 / generating));
+        }
+    }
+    if let Some(cost) = cost {
+        // OpenRouter reports what was billed, otherwise the cost is estimated from prices
+        let billed = if usage["cost"].is_number() { " billed" } else { "" };
+        stats.push_str(&format!(", ${:.4}{}", cost, billed));
+    }
+    // keep the stats off the last line of the answer
+    let separator = if answer.is_empty() || answer.ends_with('\n') { "" } else { "\n" };
+    eprintln!("{}{}{}{}", separator, theme::err().stats, stats, theme::err().reset);
+}
+
+/// print the summary of `--output json`, or the closing event of `--output ndjson`
+fn print_output(format: OutputFormat, mut output: serde_json::Value) {
+    match format {
+        OutputFormat::Text => {}
+        OutputFormat::Json => println!("{}", output),
+        OutputFormat::Ndjson => {
+            output["type"] = "done".into();
+            println!("{}", output);
+        }
+    }
+}
+
+/// write the code blocks of an answer to `--save-blocks DIR`
+fn save_blocks(answer: &str, dir: Option<&std::path::Path>) -> Result<(), qllm::Error> {
+    if let Some(dir) = dir {
+        for path in blocks::save(&blocks::blocks(answer), dir)? {
+            eprintln!("wrote {}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// show the first line of a finished answer in a desktop notification for `--notify`,
+/// warning rather than failing when there is no desktop to show it on
+fn notify_done(model: &str, answer: &str) {
+    let first = answer.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or("(an empty answer)");
+    let first: String = match first.char_indices().nth(200) {
+        Some((end, _)) => format!("{}…", &first[..end]),
+        None => first.to_string(),
+    };
+    if let Err(e) = qllm::notify::send(&format!("{} is done", model), &first) {
+        tracing::warn!("{}", e);
+    }
+}
+
+/// put the answer, or the code of its last block, on the clipboard for `--copy`
+fn copy_answer(answer: &str, part: CopyPart) -> Result<(), qllm::Error> {
+    let text = match part {
+        CopyPart::Answer => answer.trim().to_string(),
+        CopyPart::Code => blocks::blocks(answer).pop().map(|block| block.code).ok_or("The answer has no code block to copy.")?,
+    };
+    qllm::clipboard::copy(&text)?;
+    eprintln!("Copied {} to the clipboard.", if part == CopyPart::Code { "the last code block" } else { "the answer" });
+    Ok(())
+}
+
+/// apply the edits of an answer for `--apply`, reporting on stderr what was written and
+/// which edits did not apply, and why
+fn apply_edits(answer: &str) -> Result<(), qllm::Error> {
+    let edits = qllm::patch::parse(answer);
+    if edits.is_empty() {
+        return Err("The answer has no diffs or search and replace blocks to apply.".into());
+    }
+    let report = qllm::patch::apply(&edits, std::path::Path::new("."));
+    for (path, count) in &report.applied {
+        eprintln!("Applied {} {} to {}.", count, if *count == 1 { "edit" } else { "edits" }, path);
+    }
+    for failure in &report.failed {
+        match failure.edit.as_str() {
+            "" => eprintln!("Left {} as it was: {}.", failure.path, failure.reason),
+            edit => eprintln!("Could not apply `{}` to {}: {}.", edit, failure.path, failure.reason),
+        }
+    }
+    match report.failed.len() {
+        0 => Ok(()),
+        failed => Err(format!("{} of the {} edits did not apply, and their files were left as they were.", failed, edits.len()).into()),
+    }
+}
+
+/// print the tool calls of a finished stream as one JSON object per line
+fn print_tool_calls(stream: &ChatStream, answer: &str) {
+    for (i, call) in stream.tool_calls().iter().enumerate() {
+        if i == 0 && !answer.is_empty() {
+            println!();
+        }
+        println!("{}", call.to_json());
+    }
+}
+
+async fn chat(args: ChatArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    if (!args.route.is_empty() || !args.prefer.is_empty()) && client.provider().name() != "openrouter" {
+        return Err("--route and --prefer only work with OpenRouter, as --provider openrouter or an openrouter.ai endpoint.".into());
+    }
+    let options = ChatOptions::new(&args, &config)?;
+    let history = History::open_default();
+    let (conversation, mut messages) = conversation(&history, &args, &options)?;
+    let examples = examples(&args)?;
+    // a system message among the examples replaces the usual one
+    if examples.iter().any(|m| m.role == "system") {
+        messages.retain(|m| m.role != "system");
+    }
+    messages.extend(examples);
+    let mut images = Vec::new();
+    for spec in &args.images {
+        images.push(qllm::image::load(spec, client.provider().image_urls(), args.image_max_size).await?);
+    }
+    if let Some(template) = &args.map {
+        return map_lines(&client, &options, template, !args.no_history).await;
+    }
+    if args.chunked {
+        return chunked::run(&client, &args, &options, &history, &config).await;
+    }
+    let prompted = !args.prompt.is_empty() || args.stdin || args.file.is_some() || args.messages.is_some() || args.edit || options.template_prompt.is_some()
+        || !args.globs.is_empty() || !args.dirs.is_empty() || args.repomap || args.paste;
+    if args.interactive || !prompted {
+        return repl::run(&client, &args, &options, &history, conversation, messages, images).await;
+    }
+    let mut context = read_stdin(args.stdin).await?;
+    // a transcript from `qllm --recurse` continues that conversation instead of being context
+    let previous = context.as_deref().and_then(qllm::transcript::parse);
+    if let Some(previous) = &previous {
+        if previous.iter().any(|m| m.role == "system") {
+            messages.retain(|m| m.role != "system");
+        }
+        messages.extend(previous.iter().cloned());
+        context = None;
+    }
+    if args.paste {
+        let pasted = qllm::clipboard::paste()?;
+        context = Some(match context {
+            Some(stdin) if !stdin.is_empty() => format!("{}\n{}", pasted.trim_end(), stdin),
+            _ => pasted.trim_end().to_string(),
+        });
+    }
+    let (words, files) = file_words(&args.prompt);
+    let mut user_prompt = read_prompt(&words, context.as_deref(), args.file.as_deref()).await?;
+    if let Some(lead) = &options.template_prompt {
+        user_prompt = if user_prompt.is_empty() { lead.clone() } else { format!("{}\n{}", lead, user_prompt) };
+    }
+    if args.edit {
+        // start from the prompt given so far, or else the one being continued
+        let previous = messages.iter().rev().find(|m| m.role == "user").map(|m| m.content.as_str());
+        let initial = if user_prompt.is_empty() { previous.unwrap_or_default() } else { &user_prompt };
+        user_prompt = edit_prompt(initial).await?;
+    }
+    let max_file_tokens = args.max_file_tokens.or_else(|| config.get("max_file_tokens").and_then(|v| v.as_u64())).unwrap_or(MAX_FILE_TOKENS);
+    let bundle = !args.globs.is_empty() || !args.dirs.is_empty();
+    let paths = attached_paths(&files, &args)?;
+    let attached = if args.repomap {
+        repomap(&paths, &options.model, &config, max_file_tokens)?
+    } else {
+        attach_files(&paths, &options.model, &config, max_file_tokens, bundle)?
+    };
+    if !attached.is_empty() {
+        user_prompt = if user_prompt.is_empty() { attached } else { format!("{}\n\n{}", user_prompt, attached) };
+    }
+
+    // a --messages file or a transcript may already end with the prompt
+    if !user_prompt.is_empty() || !images.is_empty() || (args.messages.is_none() && previous.is_none()) {
+        messages.push(Message { images, ..Message::user(&user_prompt) });
+    }
+    // trailing whitespace in a prefill is rejected by Anthropic and confuses most models
+    let prefill = args.prefill.as_deref().map(str::trim_end).filter(|text| !text.is_empty());
+    if let Some(prefill) = prefill {
+        messages.push(Message::assistant(prefill));
+    }
+    // with --recurse the prefill is printed as the start of the answer
+    let shown_prefill = prefill.filter(|_| args.recurse);
+    // the part of a printed answer that the model wrote
+    let generated = |answer: &str| shown_prefill.and_then(|prefill| answer.strip_prefix(prefill)).unwrap_or(answer).to_string();
+    let mut request = options.request(messages);
+    request.n = args.n as usize;
+    if !args.compare.is_empty() {
+        return compare(&client, &args, &options, &history, &request).await;
+    }
+    if let Some(count) = args.samples {
+        return samples(&client, &args, &options, &history, &request, count as usize).await;
+    }
+    // the command is printed, not sent, so there is nothing to fit or pay for
+    if args.as_curl {
+        println!("{}", client.chat_curl(&request)?);
+        return Ok(());
+    }
+    // answers with tool calls, several choices, or logprobs are not cached, since all but the text would be lost
+    let caching = !args.raw && !args.no_cache && request.tools.is_empty() && request.n == 1 && request.logprobs.is_none()
+        && (args.cache || config.get("cache").and_then(|v| v.as_bool()).unwrap_or(false));
+    let cache = caching.then(|| (Cache::open_default(), Cache::key(&client.chat_url(), &request)));
+    let cached = cache.as_ref().and_then(|(cache, key)| cache.get(key, cache_ttl(args.cache_ttl, &config)));
+    if cached.is_some() {
+        tracing::info!("answering from the cache");
+    }
+    if cached.is_none() {
+        fit_context(&client, &config, fit(args.fit, &config)?, &mut request, context.as_deref()).await?;
+        options.check_budget(&request)?;
+    }
+
+    if args.raw {
+        let mut response = client.chat_response(&request).await?;
+        let mut stdout = std::io::stdout();
+        while let Some(chunk) = response.chunk().await? {
+            match stdout.write_all(&chunk).and_then(|_| stdout.flush()) {
+                // the reader went away, as with `| head`
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => break,
+                result => result?,
+            }
+        }
+        return Ok(());
+    }
+
+    let started = Instant::now();
+    let timestamp = qllm::time::now();
+    let mut out = open_out(&args, &options)?;
+    let mut stream = match &cached {
+        Some(entry) => client.replay(replay_events(entry)),
+        None => {
+            spinner::start(&format!("waiting for {}", request.model));
+            client.chat(&request).await?
+        }
+    };
+    if let Some(prefill) = shown_prefill {
+        stream.prefill(prefill);
+    }
+    let sections = request.n > 1 && matches!(options.display, Display::Raw | Display::Render);
+    // --recurse prints the conversation so far, for another qllm -c to continue
+    if args.recurse && matches!(options.display, Display::Raw | Display::Confidence) {
+        let earlier = if prefill.is_some() { &request.messages[..request.messages.len() - 1] } else { &request.messages[..] };
+        println!("{}{}", qllm::transcript::format(earlier), qllm::transcript::marker("assistant"));
+    }
+    if sections {
+        println!("--- choice 1");
+    }
+    // an answer that may still be repaired is only printed once it has been checked
+    let repairs = if request.response_format.is_some() { args.repair_attempts } else { 0 };
+    let buffered = repairs > 0 && !matches!(options.display, Display::Quiet | Display::Events);
+    let live = if buffered { Display::Quiet } else { options.display };
+    let mut answer = print_stream(&mut stream, live, options.wrap, options.show_reasoning, if buffered { None } else { out.as_mut() }).await?;
+    // the cost of the answers that had to be repaired
+    let mut repaired_cost: Option<f64> = None;
+    if let Some(format) = &request.response_format {
+        let mut retry = request.clone();
+        if prefill.is_some() {
+            retry.messages.pop();
+        }
+        for attempt in 1..=repairs {
+            let Err(e) = check_json(&generated(&answer), format) else { break };
+            eprintln!("{} Asking for a repair ({}/{}).", e, attempt, repairs);
+            if let Some(cost) = stream.usage().and_then(|usage| options.prices.cost(&request.model, usage)) {
+                repaired_cost = Some(repaired_cost.unwrap_or(0.0) + cost);
+            }
+            retry.messages.push(Message::assistant(&answer));
+            retry.messages.push(Message::user(&format!("{} Reply with only the corrected JSON.", e)));
+            options.check_budget(&retry)?;
+            spinner::start(&format!("waiting for {}", request.model));
+            stream = client.chat(&retry).await?;
+            if let Some(prefill) = shown_prefill {
+                stream.prefill(prefill);
+            }
+            answer = print_stream(&mut stream, live, options.wrap, options.show_reasoning, None).await?;
+        }
+    }
+    if buffered {
+        let mut replay = client.replay(vec![qllm::StreamEvent::Content(answer.clone())]);
+        print_stream(&mut replay, options.display, options.wrap, false, out.as_mut()).await?;
+    }
+    print_choices(&client, &stream, options.display, options.wrap, sections).await?;
+    if let Some(file) = out.as_mut() {
+        writeln!(file)?;
+    }
+    let valid = match &request.response_format {
+        Some(format) => check_json(&generated(&answer), format),
+        None => Ok(()),
+    };
+    // only answers that passed the check are worth replaying
+    if let (Some((cache, key)), None, true) = (&cache, &cached, valid.is_ok()) {
+        cache.put(key, &cache::Entry {
+            created: timestamp,
+            // the prefill is added again when the answer is replayed
+            content: generated(&answer),
+            usage: stream.usage().cloned(),
+            finish_reason: stream.finish_reason().map(str::to_string),
+        })?;
+    }
+    // a cached answer is not billed again
+    let cost = match (repaired_cost, stream.usage().filter(|_| cached.is_none()).and_then(|usage| options.prices.cost(&request.model, usage))) {
+        (Some(earlier), Some(last)) => Some(earlier + last),
+        (earlier, last) => earlier.or(last),
+    };
+    let mut output = output_json(&stream, &answer, &request.model, &request.params, timestamp, started);
+    output["cost"] = cost.into();
+    output["cached"] = cached.is_some().into();
+    if request.n > 1 {
+        let choices = stream.choices().values().map(|text| text.trim_start());
+        output["choices"] = std::iter::once(answer.as_str()).chain(choices).collect::<Vec<_>>().into();
+    }
+    if args.output == OutputFormat::Text {
+        print_tool_calls(&stream, &answer);
+    }
+    save_blocks(&answer, args.save_blocks.as_deref())?;
+    let applied = if args.apply { apply_edits(&answer) } else { Ok(()) };
+    let copied = args.copy.map_or(Ok(()), |part| copy_answer(&answer, part));
+
+    if !args.no_history {
+        let mut exchange = Exchange {
+            conversation,
+            timestamp,
+            duration_ms: started.elapsed().as_millis() as u64,
+            cost,
+            ..exchange_for(&client, &request, &answer, stream.usage(), &options.prices)
+        };
+        if cached.is_some() {
+            // nothing was sent, so nothing counts towards the usage report or budget
+            exchange.usage = None;
+            exchange.cost = Some(0.0);
+        }
+        history.record(&mut exchange)?;
+        output["id"] = exchange.id.into();
+        output["conversation"] = conversation.into();
+    }
+    print_output(args.output, output);
+    if args.stats || config.get("stats").and_then(|v| v.as_bool()).unwrap_or(false) {
+        print_stats(&stream, &answer, started, cost);
+    }
+    let paging = args.pager.or_else(|| config.str("pager").and_then(|when| Paging::from_str(when, true).ok())).unwrap_or(Paging::Never);
+    if args.output == OutputFormat::Text && paging != Paging::Never {
+        let shown = formatted(&answer, options.display, options.wrap);
+        if pager::wanted(paging, &shown) {
+            if !answer.ends_with('\n') {
+                println!();
+            }
+            pager::page(&shown).await?;
+        }
+    }
+    if args.notify {
+        notify_done(&request.model, &answer);
+    }
+    valid.and(applied).and(copied)
+}
+
+/// send the `--map` template once for every line of stdin, with the line in place of `{}`
+/// (or after the template if it has none), printing each answer on a line of its own in order
+async fn map_lines(client: &QllmClient, options: &ChatOptions, template: &str, record: bool) -> Result<(), qllm::Error> {
+    let mut lines = async_io::BufReader::new(async_io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        // an empty line stays empty, so the output lines up with the input
+        if line.trim().is_empty() {
+            println!();
+            continue;
+        }
+        let prompt = if template.contains("{}") { template.replace("{}", &line) } else { format!("{}\n{}", template, line) };
+        let request = options.request(vec![Message::system(&options.system), Message::user(&prompt)]);
+        options.check_budget(&request)?;
+        let started = Instant::now();
+        let (stream, answer) = read_answer(client, &request).await?;
+        println!("{}", answer.trim().lines().map(str::trim).collect::<Vec<_>>().join(" "));
+        if record {
+            record_spend(exchange_for(client, &request, &answer, stream.usage(), &options.prices), started);
+        }
+    }
+    Ok(())
+}
+
+/// a request with its whole answer, and how long it took
+type Answer = (ChatRequest, ChatStream, String, Duration);
+
+/// send a request in the background and collect its whole answer
+fn spawn_answer(client: &QllmClient, request: ChatRequest, started: Instant) -> tokio::task::JoinHandle<Result<Answer, qllm::Error>> {
+    let client = client.clone();
+    tokio::spawn(async move {
+        let (stream, answer) = read_answer(&client, &request).await?;
+        Ok((request, stream, answer, started.elapsed()))
+    })
+}
+
+/// send a chat request and read its whole answer, keeping the stream for its usage
+pub(crate) async fn read_answer(client: &QllmClient, request: &ChatRequest) -> Result<(ChatStream, String), qllm::Error> {
+    let mut stream = client.chat(request).await?;
+    let mut answer = String::new();
+    while let Some(delta) = stream.next_delta().await? {
+        answer.push_str(&delta);
+    }
+    Ok((stream, answer))
+}
+
+/// send the request to every `--compare` model at once, then print their answers in
+/// sections of their own, in the order the models were given
+async fn compare(client: &QllmClient, args: &ChatArgs, options: &ChatOptions, history: &History, request: &ChatRequest) -> Result<(), qllm::Error> {
+    let timestamp = qllm::time::now();
+    let started = Instant::now();
+    let requests: Vec<ChatRequest> = args.compare.iter()
+        .map(|model| ChatRequest { model: model.clone(), ..request.clone() })
+        .collect();
+    options.budget.check_all(&options.prices, &requests)?;
+    let handles: Vec<_> = requests.into_iter().map(|request| spawn_answer(client, request, started)).collect();
+
+    let sections = !matches!(options.display, Display::Quiet | Display::Events);
+    let mut outputs = Vec::new();
+    let mut failed = 0;
+    for (i, (model, handle)) in args.compare.iter().zip(handles).enumerate() {
+        if sections {
+            println!("{}--- {}", if i > 0 { "\n\n" } else { "" }, model);
+        }
+        let (request, stream, answer, elapsed) = match handle.await? {
+            Ok(result) => result,
+            Err(e) => {
+                failed += 1;
+                eprintln!("Error: {}", e);
+                outputs.push(serde_json::json!({ "model": model, "error": e.to_string() }));
+                continue;
+            }
+        };
+        if sections {
+            let mut replay = client.replay(vec![qllm::StreamEvent::Content(answer.clone())]);
+            print_stream(&mut replay, options.display, options.wrap, false, None).await?;
+        }
+        let cost = stream.usage().and_then(|usage| options.prices.cost(model, usage));
+        let mut output = output_json(&stream, &answer, model, &request.params, timestamp, started);
+        output["timing"]["duration_ms"] = (elapsed.as_millis() as u64).into();
+        output["cost"] = cost.into();
+        if !args.no_history {
+            let mut exchange = Exchange {
+                conversation: history.new_conversation()?,
+                timestamp,
+                duration_ms: elapsed.as_millis() as u64,
+                ..exchange_for(client, &request, &answer, stream.usage(), &options.prices)
+            };
+            history.record(&mut exchange)?;
+            output["id"] = exchange.id.into();
+            output["conversation"] = exchange.conversation.into();
+        }
+        outputs.push(output);
+    }
+    print_output(args.output, serde_json::json!({ "compare": outputs }));
+    if failed > 0 {
+        return Err(format!("{} of {} models failed.", failed, args.compare.len()).into());
+    }
+    Ok(())
+}
+
+/// draw `--samples` answers at once, then print each in a section of its own or reduce
+/// them to one with `--vote`
+async fn samples(client: &QllmClient, args: &ChatArgs, options: &ChatOptions, history: &History, request: &ChatRequest, count: usize) -> Result<(), qllm::Error> {
+    if request.params.temperature <= 0.0 {
+        return Err("--samples needs a temperature above 0, or the samples would all be the same.".into());
+    }
+    let timestamp = qllm::time::now();
+    let started = Instant::now();
+    options.budget.check_all(&options.prices, &vec![request.clone(); count])?;
+    let handles: Vec<_> = (0..count).map(|_| spawn_answer(client, request.clone(), started)).collect();
+    let mut answers = Vec::new();
+    let mut cost: Option<f64> = None;
+    // the samples that did come back were paid for, so a failed one only leaves them fewer
+    let mut errors = Vec::new();
+    for handle in handles {
+        let (_, stream, answer, _) = match handle.await? {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                errors.push(e.to_string());
+                continue;
+            }
+        };
+        if let Some(sample) = stream.usage().and_then(|usage| options.prices.cost(&request.model, usage)) {
+            cost = Some(cost.unwrap_or(0.0) + sample);
+        }
+        answers.push(answer);
+    }
+    if answers.is_empty() {
+        return Err(format!("All {} samples failed.", count).into());
+    }
+
+    let sections = !matches!(options.display, Display::Quiet | Display::Events);
+    let mut output = serde_json::json!({ "model": request.model, "params": request.params.to_json(), "samples": answers });
+    if !errors.is_empty() {
+        output["errors"] = errors.clone().into();
+    }
+    let answer = match args.vote {
+        None => {
+            for (i, answer) in answers.iter().enumerate() {
+                if sections {
+                    println!("{}--- sample {}", if i > 0 { "\n\n" } else { "" }, i + 1);
+                    let mut replay = client.replay(vec![qllm::StreamEvent::Content(answer.clone())]);
+                    print_stream(&mut replay, options.display, options.wrap, false, None).await?;
                 }
             }
-            Err(e) => return Err(e.into()),
+            answers[0].clone()
+        }
+        Some(Vote::Majority) => {
+            let (answer, votes) = majority(&answers);
+            eprintln!("{} of {} samples agree.", votes, answers.len());
+            output["votes"] = votes.into();
+            let mut replay = client.replay(vec![qllm::StreamEvent::Content(answer.to_string())]);
+            print_stream(&mut replay, options.display, options.wrap, false, None).await?
+        }
+        Some(Vote::Aggregate) => {
+            let candidates: Vec<String> = answers.iter().enumerate()
+                .map(|(i, answer)| format!("Answer {}:\n{}", i + 1, answer))
+                .collect();
+            let mut aggregate = request.clone();
+            aggregate.messages.push(Message::user(&format!(
+                "Here are {} independent answers to the request above.\n\n{}\n\nWeigh where they agree and disagree, \
+                 and reply with the single best final answer, without mentioning the candidates.",
+                answers.len(),
+                candidates.join("\n\n"),
+            )));
+            options.check_budget(&aggregate)?;
+            spinner::start(&format!("waiting for {}", aggregate.model));
+            let mut stream = client.chat(&aggregate).await?;
+            let answer = print_stream(&mut stream, options.display, options.wrap, options.show_reasoning, None).await?;
+            if let Some(last) = stream.usage().and_then(|usage| options.prices.cost(&request.model, usage)) {
+                cost = Some(cost.unwrap_or(0.0) + last);
+            }
+            answer
+        }
+    };
+    output["content"] = answer.as_str().into();
+    output["cost"] = cost.into();
+
+    if !args.no_history {
+        let mut exchange = Exchange {
+            conversation: history.new_conversation()?,
+            timestamp,
+            duration_ms: started.elapsed().as_millis() as u64,
+            cost,
+            ..exchange_for(client, request, &answer, None, &options.prices)
+        };
+        history.record(&mut exchange)?;
+        output["id"] = exchange.id.into();
+        output["conversation"] = exchange.conversation.into();
+    }
+    print_output(args.output, output);
+    if !errors.is_empty() {
+        return Err(format!("{} of {} samples failed.", errors.len(), count).into());
+    }
+    Ok(())
+}
+
+/// the answer given most often, compared without case, surrounding whitespace, or a final
+/// period, with the number of answers that agree on it; ties go to the earliest
+fn majority(answers: &[String]) -> (&str, usize) {
+    let normal = |answer: &str| answer.trim().trim_end_matches('.').split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    let keys: Vec<String> = answers.iter().map(|answer| normal(answer)).collect();
+    let mut best = (0, 0);
+    for (i, key) in keys.iter().enumerate() {
+        let votes = keys.iter().filter(|other| *other == key).count();
+        if votes > best.1 {
+            best = (i, votes);
         }
     }
+    (answers[best.0].as_str(), best.1)
+}
 
+/// print the choices after the first once the stream has ended, each in a section of
+/// its own when `sections` is set
+async fn print_choices(client: &QllmClient, stream: &ChatStream, display: Display, wrap: Option<usize>, sections: bool) -> Result<(), qllm::Error> {
+    if matches!(display, Display::Quiet | Display::Events) {
+        // they are in the summary printed at the end
+        return Ok(());
+    }
+    for (index, text) in stream.choices() {
+        if sections {
+            println!("\n\n--- choice {}", index + 1);
+        }
+        let mut replay = client.replay(vec![qllm::StreamEvent::Content(text.clone())]);
+        print_stream(&mut replay, display, wrap, false, None).await?;
+    }
     Ok(())
 }
+
+/// the events that play a cached answer back as if it were streamed
+fn replay_events(entry: &cache::Entry) -> Vec<qllm::StreamEvent> {
+    let mut events = vec![qllm::StreamEvent::Content(entry.content.clone())];
+    if let Some(reason) = &entry.finish_reason {
+        events.push(qllm::StreamEvent::Finish(reason.clone()));
+    }
+    if let Some(usage) = &entry.usage {
+        events.push(qllm::StreamEvent::Usage(usage.clone()));
+    }
+    events.push(qllm::StreamEvent::Done);
+    events
+}
+
+/// the messages of `--messages` followed by the few-shot exchanges of `--user` and `--assistant`
+fn examples(args: &ChatArgs) -> Result<Vec<Message>, qllm::Error> {
+    if args.users.len() != args.assistants.len() {
+        return Err(format!("Every --user needs an --assistant answer, got {} and {}.", args.users.len(), args.assistants.len()).into());
+    }
+    let mut messages = match &args.messages {
+        Some(path) => read_messages(path)?,
+        None => Vec::new(),
+    };
+    for (user, assistant) in args.users.iter().zip(&args.assistants) {
+        messages.push(Message::user(user));
+        messages.push(Message::assistant(assistant));
+    }
+    Ok(messages)
+}
+
+/// read a `--messages` file, holding an array of messages or the body of an OpenAI request
+fn read_messages(path: &std::path::Path) -> Result<Vec<Message>, qllm::Error> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let value: serde_json::Value = serde_json::from_str(&text).map_err(|e| format!("{} is not valid JSON: {}", path.display(), e))?;
+    let list = value.get("messages").unwrap_or(&value).as_array()
+        .ok_or_else(|| format!("{} must hold an array of messages.", path.display()))?;
+    list.iter()
+        .map(|message| Message::from_json(message).map_err(|e| format!("{}: {}", path.display(), e).into()))
+        .collect()
+}
+
+/// pick the conversation to add to, either resumed from the history or a fresh one; with
+/// --no-history a fresh one is never stored, so it gets no id and the history is not opened
+fn conversation(history: &History, args: &ChatArgs, options: &ChatOptions) -> Result<(u64, Vec<Message>), qllm::Error> {
+    let resumed = if let Some(cid) = args.cid {
+        Some(cid)
+    } else if args.continue_last {
+        Some(history.latest_conversation()?.ok_or("There is no previous conversation to continue.")?)
+    } else {
+        None
+    };
+    match resumed {
+        Some(cid) => Ok((cid, history.messages(cid)?)),
+        None if args.no_history => Ok((0, vec![Message::system(&options.system)])),
+        None => Ok((history.new_conversation()?, vec![Message::system(&options.system)])),
+    }
+}
+
+/// store a billed request that is not part of a conversation in the history, in one of its
+/// own, so that `qllm usage` and the monthly budget count what every command spends; the
+/// answer was paid for either way, so a history that can not be written is only warned about
+pub(crate) fn record_spend(exchange: Exchange, started: Instant) -> Option<u64> {
+    let history = History::open_default();
+    let recorded = history.new_conversation().and_then(|conversation| {
+        let mut exchange = Exchange {
+            conversation,
+            timestamp: qllm::time::now().saturating_sub(started.elapsed().as_secs()),
+            duration_ms: started.elapsed().as_millis() as u64,
+            ..exchange
+        };
+        history.record(&mut exchange)
+    });
+    recorded.map_err(|e| tracing::warn!("Could not record a request in the history: {}", e)).ok()
+}
+
+/// describe a finished chat request for the history, the caller fills in the conversation and timing
+fn exchange_for(client: &QllmClient, request: &ChatRequest, answer: &str, usage: Option<&serde_json::Value>, prices: &Prices) -> Exchange {
+    let system = request.messages.iter()
+        .find(|m| m.role == "system")
+        .map(|m| m.content.clone())
+        .unwrap_or_default();
+    // the last user message, as a prefill may follow it
+    let prompt = request.messages.iter().rev()
+        .find(|m| m.role == "user")
+        .map(|m| m.content.clone())
+        .unwrap_or_default();
+    Exchange {
+        endpoint: client.chat_url(),
+        model: request.model.clone(),
+        params: request.params.to_json(),
+        system,
+        prompt,
+        response: answer.to_string(),
+        usage: usage.cloned(),
+        cost: usage.and_then(|usage| prices.cost(&request.model, usage)),
+        ..Exchange::default()
+    }
+}
+
+async fn complete(args: CompleteArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let prompt = read_prompt(&args.prompt, read_stdin(args.stdin).await?.as_deref(), args.file.as_deref()).await?;
+
+    let mut request = CompletionRequest::new(&model(&args.common, &config), &prompt);
+    request.params = args.sampler.to_params(config.sampler(args.sampler.preset.as_deref())?);
+    request.stream = stream(args.no_stream, &config);
+    let prices = Prices::new(config.get("prices"));
+    Budget::new(args.max_cost, &config).check(&prices, &request.model, pricing::estimate_tokens(&prompt), request.params.max_tokens)?;
+
+    let started = Instant::now();
+    let timestamp = qllm::time::now();
+    let display = match args.output {
+        OutputFormat::Text => Display::Raw,
+        OutputFormat::Json => Display::Quiet,
+        OutputFormat::Ndjson => Display::Events,
+    };
+    let mut stream = client.complete(&request).await?;
+    let answer = print_stream(&mut stream, display, None, false, None).await?;
+    let cost = stream.usage().and_then(|usage| prices.cost(&request.model, usage));
+    let mut output = output_json(&stream, &answer, &request.model, &request.params, timestamp, started);
+    output["cost"] = cost.into();
+    output["id"] = record_spend(Exchange {
+        endpoint: client.endpoint().to_string(),
+        model: request.model.clone(),
+        params: request.params.to_json(),
+        prompt,
+        response: answer.clone(),
+        usage: stream.usage().cloned(),
+        cost,
+        ..Exchange::default()
+    }, started).into();
+    print_output(args.output, output);
+    if args.stats || config.get("stats").and_then(|v| v.as_bool()).unwrap_or(false) {
+        print_stats(&stream, &answer, started, cost);
+    }
+    Ok(())
+}
+
+async fn embed(args: EmbedArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let input = read_prompt(&args.input, read_stdin(args.stdin).await?.as_deref(), None).await?;
+    if input.trim().is_empty() {
+        return Err("Nothing to embed. Give the text as arguments or pass -c to read it from stdin.".into());
+    }
+
+    let vectors = client.embed(&model(&args.common, &config), &[input]).await?;
+    for vector in vectors {
+        println!("{}", serde_json::to_string(&vector)?);
+    }
+    Ok(())
+}
+
+async fn models(args: ModelsArgs) -> Result<(), qllm::Error> {
+    let config = config(&args.common)?;
+    let client = client(&args.common, &config)?;
+    let filter = args.filter.as_deref().map(str::to_lowercase);
+    for (id, entry) in client.model_entries().await? {
+        if filter.as_ref().is_some_and(|filter| !id.to_lowercase().contains(filter)) {
+            continue;
+        }
+        if args.json {
+            println!("{}", entry);
+        } else {
+            println!("{}", id);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn asks_the_keyring_only_for_endpoints_that_want_a_key() {
+        let config = Config { table: json!({}), ..Config::default() };
+        assert!(wants_key("https://api.openai.com/v1/chat/completions", &config));
+        assert!(wants_key("http://10.0.0.5:8080/v1", &config));
+        for endpoint in ["http://localhost:8080/v1", "http://127.0.0.1:11434", "http://[::1]:8000/v1", "unix:///run/llama.sock/v1"] {
+            assert!(!wants_key(endpoint, &config), "{}", endpoint);
+        }
+        let oauth = Config { table: json!({ "oauth": { "token_url": "https://idp/token" } }), ..Config::default() };
+        assert!(!wants_key("https://gateway/v1", &oauth));
+    }
+
+    #[test]
+    fn attaches_only_the_files_named_with_an_at() {
+        let words: Vec<String> = ["explain", "@Cargo.toml", "and", "Cargo.toml", "to", "@team", "@src/main.rs"].map(String::from).to_vec();
+        let (prompt, files) = file_words(&words);
+        assert_eq!(prompt, ["explain", "and", "Cargo.toml", "to", "@team"]);
+        assert_eq!(files, ["Cargo.toml", "src/main.rs"]);
+    }
+}
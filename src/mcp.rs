@@ -0,0 +1,303 @@
+//! A minimal Model Context Protocol client.
+//!
+//! Servers are declared in the config as `[mcp_servers.<name>]` tables, either a
+//! `command` (with optional `args` and `env`) spoken to over stdio, or a `url`
+//! using the HTTP+SSE transport. Only tools are supported: they are listed at
+//! startup and offered to the model as functions named `<server>__<tool>`.
+
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+
+use crate::sse::{SseEvent, SseParser};
+use crate::Result;
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// separates the server name from the tool name in the function names the model sees
+const SEPARATOR: &str = "__";
+
+/// a tool offered by an MCP server
+#[derive(Clone, Debug, PartialEq)]
+pub struct McpTool {
+    pub name: String,
+    pub description: String,
+    pub input_schema: Value,
+}
+
+enum Transport {
+    Stdio {
+        // kept so the server is killed along with the client
+        _child: Child,
+        stdin: ChildStdin,
+        stdout: Lines<BufReader<ChildStdout>>,
+    },
+    Sse {
+        http: reqwest::Client,
+        /// where messages are posted, announced by the server's `endpoint` event
+        post_url: String,
+        events: SseStream,
+    },
+}
+
+/// the server-to-client half of the SSE transport
+struct SseStream {
+    response: reqwest::Response,
+    parser: SseParser,
+    pending: VecDeque<SseEvent>,
+}
+
+impl SseStream {
+    async fn next(&mut self) -> Result<SseEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(event);
+            }
+            match self.response.chunk().await? {
+                Some(bytes) => self.pending.extend(self.parser.feed(&bytes)),
+                None => return Err("the MCP server closed its event stream".into()),
+            }
+        }
+    }
+}
+
+/// a connection to one MCP server
+pub struct McpServer {
+    pub name: String,
+    pub tools: Vec<McpTool>,
+    transport: Transport,
+    next_id: u64,
+}
+
+impl std::fmt::Debug for McpServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("McpServer").field("name", &self.name).field("tools", &self.tools).finish()
+    }
+}
+
+impl McpServer {
+    /// start or connect to the server described by a config table, and list its tools
+    pub async fn connect(name: &str, table: &Value) -> Result<Self> {
+        let transport = if let Some(command) = table["command"].as_str() {
+            let mut process = tokio::process::Command::new(command);
+            for arg in table["args"].as_array().into_iter().flatten() {
+                process.arg(arg.as_str().ok_or("MCP server args must be strings")?);
+            }
+            for (key, value) in table["env"].as_object().into_iter().flatten() {
+                process.env(key, value.as_str().ok_or("MCP server env values must be strings")?);
+            }
+            let mut child = process
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| format!("could not start the MCP server {} ({}): {}", name, command, e))?;
+            let stdin = child.stdin.take().ok_or("no stdin for the MCP server")?;
+            let stdout = child.stdout.take().ok_or("no stdout for the MCP server")?;
+            Transport::Stdio { _child: child, stdin, stdout: BufReader::new(stdout).lines() }
+        } else if let Some(url) = table["url"].as_str() {
+            let http = reqwest::Client::new();
+            let response = http.get(url).header("accept", "text/event-stream").send().await?.error_for_status()?;
+            let mut events = SseStream { response, parser: SseParser::new(), pending: VecDeque::new() };
+            // the first event tells us where to post our messages
+            let endpoint = loop {
+                let event = events.next().await?;
+                if event.event.as_deref() == Some("endpoint") {
+                    break event.data;
+                }
+            };
+            let post_url = reqwest::Url::parse(url)?.join(endpoint.trim())?.to_string();
+            Transport::Sse { http, post_url, events }
+        } else {
+            return Err(format!("the MCP server {} needs a `command` or a `url`", name).into());
+        };
+
+        let mut server = McpServer { name: name.to_string(), tools: Vec::new(), transport, next_id: 1 };
+        server.request("initialize", json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": { "name": "qllm", "version": env!("CARGO_PKG_VERSION") },
+        })).await?;
+        server.send(&json!({ "jsonrpc": "2.0", "method": "notifications/initialized" })).await?;
+        server.tools = server.list_tools().await?;
+        Ok(server)
+    }
+
+    async fn send(&mut self, message: &Value) -> Result<()> {
+        match &mut self.transport {
+            Transport::Stdio { stdin, .. } => {
+                stdin.write_all(format!("{}\n", message).as_bytes()).await?;
+                stdin.flush().await?;
+            }
+            Transport::Sse { http, post_url, .. } => {
+                http.post(post_url.as_str()).json(message).send().await?.error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the next JSON-RPC message from the server
+    async fn receive(&mut self) -> Result<Value> {
+        loop {
+            let data = match &mut self.transport {
+                Transport::Stdio { stdout, .. } => stdout.next_line().await?
+                    .ok_or_else(|| format!("the MCP server {} exited", self.name))?,
+                Transport::Sse { events, .. } => {
+                    let event = events.next().await?;
+                    if event.event.as_deref().unwrap_or("message") != "message" {
+                        continue;
+                    }
+                    event.data
+                }
+            };
+            // servers may log to stdout by mistake, skip anything that is not JSON
+            if let Ok(message) = serde_json::from_str(&data) {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// send a request and wait for its response, answering pings in the meantime
+    async fn request(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })).await?;
+        loop {
+            let message = self.receive().await?;
+            if let Some(method) = message["method"].as_str() {
+                // a request from the server, notifications have no id and need no answer
+                if !message["id"].is_null() {
+                    let reply = if method == "ping" {
+                        json!({ "jsonrpc": "2.0", "id": message["id"], "result": {} })
+                    } else {
+                        json!({ "jsonrpc": "2.0", "id": message["id"], "error": { "code": -32601, "message": "method not found" } })
+                    };
+                    self.send(&reply).await?;
+                }
+                continue;
+            }
+            if message["id"].as_u64() != Some(id) {
+                continue;
+            }
+            if let Some(error) = message.get("error") {
+                let text = error["message"].as_str().map(str::to_string).unwrap_or_else(|| error.to_string());
+                return Err(format!("the MCP server {} failed {}: {}", self.name, method, text).into());
+            }
+            return Ok(message["result"].clone());
+        }
+    }
+
+    async fn list_tools(&mut self) -> Result<Vec<McpTool>> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.request("tools/list", params).await?;
+            for tool in result["tools"].as_array().into_iter().flatten() {
+                tools.push(McpTool {
+                    name: tool["name"].as_str().unwrap_or_default().to_string(),
+                    description: tool["description"].as_str().unwrap_or_default().to_string(),
+                    input_schema: tool.get("inputSchema").cloned().unwrap_or_else(|| json!({ "type": "object" })),
+                });
+            }
+            cursor = result["nextCursor"].as_str().map(str::to_string);
+            if cursor.is_none() {
+                return Ok(tools);
+            }
+        }
+    }
+
+    /// call a tool, returning its text content; tool errors come back as text for the model
+    pub async fn call_tool(&mut self, name: &str, arguments: Value) -> Result<String> {
+        let result = self.request("tools/call", json!({ "name": name, "arguments": arguments })).await?;
+        let mut text = String::new();
+        for item in result["content"].as_array().into_iter().flatten() {
+            match item["type"].as_str() {
+                Some("text") => text.push_str(item["text"].as_str().unwrap_or_default()),
+                Some("resource") => text.push_str(item["resource"]["text"].as_str().unwrap_or_default()),
+                Some(other) => text.push_str(&format!("[{} content]", other)),
+                None => {}
+            }
+        }
+        if result["isError"].as_bool() == Some(true) {
+            text = format!("error: {}", text);
+        }
+        Ok(text)
+    }
+
+    /// the tools as OpenAI-style function definitions, named `<server>__<tool>`
+    pub fn functions(&self) -> Vec<Value> {
+        self.tools.iter()
+            .map(|tool| json!({
+                "type": "function",
+                "function": {
+                    "name": format!("{}{}{}", self.name, SEPARATOR, tool.name),
+                    "description": tool.description,
+                    "parameters": tool.input_schema,
+                },
+            }))
+            .collect()
+    }
+}
+
+/// connect to every server in a `mcp_servers` config table
+pub async fn connect_all(servers: &Value) -> Result<Vec<McpServer>> {
+    let mut connected = Vec::new();
+    for (name, table) in servers.as_object().into_iter().flatten() {
+        connected.push(McpServer::connect(name, table).await?);
+    }
+    Ok(connected)
+}
+
+/// find the server and tool behind a function name made by [`McpServer::functions`]
+pub fn find<'a>(servers: &'a mut [McpServer], function: &str) -> Option<(&'a mut McpServer, String)> {
+    let (server, tool) = function.split_once(SEPARATOR)?;
+    let server = servers.iter_mut().find(|s| s.name == server)?;
+    Some((server, tool.to_string()))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    /// a server speaking over stdio that answers the requests qllm makes, in order, with
+    /// a log line, a notification, and a ping of its own in between
+    const SERVER: &str = r#"
+        read line; echo '{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2024-11-05","capabilities":{"tools":{}},"serverInfo":{"name":"calc","version":"1"}}}'
+        read line
+        read line; echo 'calc server ready'; echo '{"jsonrpc":"2.0","id":2,"result":{"tools":[{"name":"add","description":"Add two numbers","inputSchema":{"type":"object","properties":{"a":{"type":"number"},"b":{"type":"number"}}}}],"nextCursor":"2"}}'
+        read line; echo '{"jsonrpc":"2.0","method":"notifications/message","params":{"level":"info","data":"listing"}}'; echo '{"jsonrpc":"2.0","id":3,"result":{"tools":[{"name":"divide"}]}}'
+        read line; echo '{"jsonrpc":"2.0","id":"s1","method":"ping"}'
+        read pong
+        case "$pong" in
+            *'"id":"s1"'*'"result"'*) echo '{"jsonrpc":"2.0","id":4,"result":{"content":[{"type":"text","text":"4"}]}}' ;;
+            *) echo '{"jsonrpc":"2.0","id":4,"error":{"code":1,"message":"no pong"}}' ;;
+        esac
+        read line; echo '{"jsonrpc":"2.0","id":5,"result":{"content":[{"type":"text","text":"division by zero"}],"isError":true}}'
+        read line; echo '{"jsonrpc":"2.0","id":6,"error":{"code":-32602,"message":"unknown tool"}}'
+    "#;
+
+    #[tokio::test]
+    async fn lists_and_calls_the_tools_of_a_stdio_server() {
+        let mut servers = vec![McpServer::connect("calc", &json!({ "command": "sh", "args": ["-c", SERVER] })).await.unwrap()];
+        let names: Vec<&str> = servers[0].tools.iter().map(|tool| tool.name.as_str()).collect();
+        assert_eq!(names, ["add", "divide"]);
+        assert_eq!(servers[0].tools[1].input_schema, json!({ "type": "object" }));
+        let functions = servers[0].functions();
+        assert_eq!(functions[0]["function"]["name"], "calc__add");
+        assert_eq!(functions[0]["function"]["parameters"]["properties"]["a"], json!({ "type": "number" }));
+
+        let (server, tool) = find(&mut servers, "calc__add").unwrap();
+        assert_eq!(tool, "add");
+        assert_eq!(server.call_tool(&tool, json!({ "a": 2, "b": 2 })).await.unwrap(), "4");
+        assert_eq!(server.call_tool("divide", json!({ "a": 1, "b": 0 })).await.unwrap(), "error: division by zero");
+        let error = server.call_tool("sqrt", json!({})).await.unwrap_err().to_string();
+        assert_eq!(error, "the MCP server calc failed tools/call: unknown tool");
+        assert!(find(&mut servers, "other__add").is_none());
+    }
+}
@@ -0,0 +1,21 @@
+//! Desktop notifications, for `--notify`, through notify-rust: D-Bus on Linux and the
+//! BSDs, the notification center on macOS, and toasts on Windows.
+
+use crate::Result;
+
+/// show a notification from qllm with a summary and a body
+#[cfg(feature = "notify")]
+pub fn send(summary: &str, body: &str) -> Result<()> {
+    notify_rust::Notification::new()
+        .appname("qllm")
+        .summary(summary)
+        .body(body)
+        .show()
+        .map_err(|e| format!("Could not show a notification: {}", e))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "notify"))]
+pub fn send(_summary: &str, _body: &str) -> Result<()> {
+    Err("This qllm was built without the notify feature.".into())
+}
@@ -0,0 +1,220 @@
+//! OAuth2 bearer tokens from the client credentials flow, for gateways that take an
+//! expiring token instead of a fixed key.
+//!
+//! A token is fetched from the token url with the client id and secret, then kept in
+//! memory and in the qllm cache directory until a minute before it expires, so that
+//! each run does not fetch its own. One the server rejects with 401 before then is
+//! forgotten and fetched again.
+
+use serde_json::{json, Value};
+use std::fs;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+use crate::{dirs, sha256, time, Result};
+
+/// how long before it expires a token is no longer used, in seconds
+const MARGIN: u64 = 60;
+
+/// how long a token lasts when the server does not say, in seconds
+const DEFAULT_LIFETIME: u64 = 3600;
+
+/// the client credentials of a token endpoint and the token last fetched from it
+#[derive(Debug)]
+pub struct OAuth {
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    scope: Option<String>,
+    audience: Option<String>,
+    /// the file the token is cached in, named by the url, client, and scope
+    cache: PathBuf,
+    token: tokio::sync::Mutex<Option<Token>>,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    access_token: String,
+    /// seconds since the epoch
+    expires_at: u64,
+}
+
+impl Token {
+    fn fresh(&self) -> bool {
+        self.expires_at > time::now() + MARGIN
+    }
+
+    fn to_json(&self) -> Value {
+        json!({ "access_token": self.access_token, "expires_at": self.expires_at })
+    }
+
+    fn from_json(value: &Value) -> Option<Token> {
+        Some(Token {
+            access_token: value["access_token"].as_str()?.to_string(),
+            expires_at: value["expires_at"].as_u64()?,
+        })
+    }
+}
+
+impl OAuth {
+    pub fn new(token_url: &str, client_id: &str, client_secret: &str) -> Self {
+        let mut oauth = OAuth {
+            token_url: token_url.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            scope: None,
+            audience: None,
+            cache: PathBuf::new(),
+            token: tokio::sync::Mutex::new(None),
+        };
+        oauth.cache = oauth.cache_path();
+        oauth
+    }
+
+    /// ask for tokens with a scope, or several separated by spaces
+    pub fn with_scope(mut self, scope: &str) -> Self {
+        self.scope = Some(scope.to_string());
+        self.cache = self.cache_path();
+        self
+    }
+
+    /// ask for tokens for an audience, which some servers want instead of a scope
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.audience = Some(audience.to_string());
+        self.cache = self.cache_path();
+        self
+    }
+
+    fn cache_path(&self) -> PathBuf {
+        let name = [Some(self.token_url.as_str()), Some(self.client_id.as_str()), self.scope.as_deref(), self.audience.as_deref()]
+            .map(|part| part.unwrap_or(""))
+            .join("\n");
+        dirs::cache_dir().join("oauth").join(format!("{}.json", sha256::hex(&sha256::digest(name.as_bytes()))))
+    }
+
+    /// a token that is still good, from memory, the cache, or else the token url
+    pub async fn token(&self, http: &reqwest::Client) -> Result<String> {
+        let mut token = self.token.lock().await;
+        if let Some(token) = token.as_ref().filter(|token| token.fresh()) {
+            return Ok(token.access_token.clone());
+        }
+        let fetched = match self.load().filter(Token::fresh) {
+            Some(cached) => cached,
+            None => {
+                let fetched = self.fetch(http).await?;
+                if let Err(e) = self.save(&fetched) {
+                    tracing::warn!("could not cache the OAuth token in {}: {}", self.cache.display(), e);
+                }
+                fetched
+            }
+        };
+        let access_token = fetched.access_token.clone();
+        *token = Some(fetched);
+        Ok(access_token)
+    }
+
+    /// forget a token the server rejected, unless another request already replaced it
+    pub async fn invalidate(&self, rejected: &str) {
+        let mut token = self.token.lock().await;
+        if token.as_ref().is_some_and(|token| token.access_token == rejected) {
+            *token = None;
+        }
+        if self.load().is_some_and(|token| token.access_token == rejected) {
+            fs::remove_file(&self.cache).ok();
+        }
+    }
+
+    async fn fetch(&self, http: &reqwest::Client) -> Result<Token> {
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+        ];
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope));
+        }
+        if let Some(audience) = &self.audience {
+            form.push(("audience", audience));
+        }
+        tracing::debug!(url = %self.token_url, client = %self.client_id, "fetching an OAuth token");
+        let response = http.post(&self.token_url).form(&form).send().await
+            .map_err(|e| format!("Could not get an OAuth token from {}: {}", self.token_url, e))?;
+        let status = response.status();
+        let text = response.text().await?;
+        let body: Value = serde_json::from_str(&text).unwrap_or(Value::Null);
+        if !status.is_success() {
+            let reason = body["error_description"].as_str().or(body["error"].as_str()).unwrap_or(text.trim());
+            return Err(format!("Could not get an OAuth token from {}: {} {}", self.token_url, status, reason).into());
+        }
+        let access_token = body["access_token"].as_str()
+            .ok_or_else(|| format!("The token url {} did not return an access_token.", self.token_url))?;
+        // some servers send the lifetime as a string
+        let lifetime = body["expires_in"].as_u64()
+            .or_else(|| body["expires_in"].as_str().and_then(|secs| secs.parse().ok()))
+            .unwrap_or(DEFAULT_LIFETIME);
+        Ok(Token { access_token: access_token.to_string(), expires_at: time::now() + lifetime })
+    }
+
+    fn load(&self) -> Option<Token> {
+        let content = fs::read_to_string(&self.cache).ok()?;
+        Token::from_json(&serde_json::from_str(&content).ok()?)
+    }
+
+    /// write the token where only the user can read it, through a temporary file
+    fn save(&self, token: &Token) -> Result<()> {
+        if let Some(dir) = self.cache.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = self.cache.with_extension("tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options.open(&temp)?.write_all(token.to_json().to_string().as_bytes())?;
+        fs::rename(&temp, &self.cache)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(expires_in: i64) -> Token {
+        Token { access_token: "eyJ.token".to_string(), expires_at: time::now().saturating_add_signed(expires_in) }
+    }
+
+    #[test]
+    fn stops_using_a_token_a_minute_before_it_expires() {
+        assert!(token(3600).fresh());
+        assert!(token(MARGIN as i64 + 5).fresh());
+        assert!(!token(MARGIN as i64 - 5).fresh());
+        assert!(!token(-10).fresh());
+    }
+
+    #[test]
+    fn reads_back_a_cached_token() {
+        let cached = Token::from_json(&token(600).to_json()).unwrap();
+        assert_eq!(cached.access_token, "eyJ.token");
+        assert!(cached.fresh());
+        assert!(Token::from_json(&json!({ "access_token": "eyJ.token" })).is_none());
+    }
+
+    #[test]
+    fn caches_tokens_per_client_and_scope() {
+        let oauth = || OAuth::new("https://idp.example.com/token", "qllm", "secret");
+        let paths = [
+            oauth().cache,
+            oauth().with_scope("llm.read").cache,
+            oauth().with_audience("https://gateway").cache,
+            OAuth::new("https://idp.example.com/token", "other", "secret").cache,
+        ];
+        for (i, path) in paths.iter().enumerate() {
+            assert!(path.starts_with(dirs::cache_dir().join("oauth")));
+            assert!(!paths[i + 1..].contains(path), "{}", path.display());
+        }
+        assert_eq!(oauth().cache, OAuth::new("https://idp.example.com/token", "qllm", "another secret").cache);
+    }
+}
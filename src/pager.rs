@@ -0,0 +1,54 @@
+//! Show a finished answer again in `$PAGER` for `--pager`, to scroll back through an
+//! answer longer than the screen from its start.
+//!
+//! The answer still streams in live as usual; the pager opens once it is complete, and
+//! only when stdout is a terminal.
+
+use std::io::IsTerminal;
+use tokio::io::AsyncWriteExt;
+
+use crate::cli::Paging;
+use crate::wrap;
+
+/// whether `text` should be paged: always with `--pager`, and with `--pager=auto` when it
+/// takes more rows than the terminal has
+pub fn wanted(paging: Paging, text: &str) -> bool {
+    if !std::io::stdout().is_terminal() {
+        return false;
+    }
+    match paging {
+        Paging::Always => true,
+        Paging::Never => false,
+        Paging::Auto => match terminal_size::terminal_size() {
+            Some((terminal_size::Width(width), terminal_size::Height(height))) => rows(text, width as usize) > height as usize,
+            None => false,
+        },
+    }
+}
+
+/// the terminal rows `text` takes at `width` columns, counting the long lines the
+/// terminal wraps
+fn rows(text: &str, width: usize) -> usize {
+    text.lines().map(|line| wrap::width(line).div_ceil(width.max(1)).max(1)).sum()
+}
+
+/// send `text` through `$PAGER`, or `less -R`, and wait for it to be closed
+pub async fn page(text: &str) -> Result<(), qllm::Error> {
+    let pager = std::env::var("PAGER").ok().filter(|pager| !pager.trim().is_empty()).unwrap_or_else(|| "less -R".to_string());
+    // through the shell, so that a pager with arguments like `less -R` works
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(&pager)
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Could not run the pager {}: {}", pager, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // a pager quit before reading everything closes the pipe, which is no error
+        match stdin.write_all(text.as_bytes()).await {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+            result => result?,
+        }
+    }
+    child.wait().await.map_err(|e| format!("Could not run the pager {}: {}", pager, e))?;
+    Ok(())
+}
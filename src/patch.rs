@@ -0,0 +1,400 @@
+//! Edits to files found in a model's answer, and applying them to the files.
+//!
+//! Two forms are understood: unified diffs, as `git diff` prints them, and search and
+//! replace blocks, with the path of the file on the line before them:
+//!
+//! ```text
+//! src/main.rs
+//! <<<<<<< SEARCH
+//! fn main() {}
+//! =======
+//! fn main() { println!("hi"); }
+//! >>>>>>> REPLACE
+//! ```
+//!
+//! Models get line numbers and whitespace wrong, so hunks are looked for nearest where
+//! they say they are, then with whitespace at the ends of lines ignored, then with up to
+//! two lines of their context left off.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// the marks of a search and replace block
+const SEARCH: &str = "<<<<<<< SEARCH";
+const DIVIDER: &str = "=======";
+const REPLACE: &str = ">>>>>>> REPLACE";
+
+/// a line of a hunk
+#[derive(Clone, Debug, PartialEq)]
+pub enum HunkLine {
+    Context(String),
+    Removed(String),
+    Added(String),
+}
+
+/// a hunk of a unified diff
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hunk {
+    pub header: String,
+    /// the line it starts at in the old file, counted from 1
+    pub old_start: usize,
+    pub lines: Vec<HunkLine>,
+}
+
+/// an edit to one file
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    /// the hunks of a unified diff, which `created` or `deleted` the file when the old or
+    /// the new one is /dev/null
+    Diff { path: String, hunks: Vec<Hunk>, created: bool, deleted: bool },
+    /// a search and replace block; an empty search creates the file, or adds to its end
+    Replace { path: String, search: String, replace: String },
+}
+
+impl Edit {
+    pub fn path(&self) -> &str {
+        match self {
+            Edit::Diff { path, .. } | Edit::Replace { path, .. } => path,
+        }
+    }
+}
+
+/// an edit that could not be applied
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub path: String,
+    /// the hunk header, or the first line searched for
+    pub edit: String,
+    pub reason: String,
+}
+
+/// what applying edits did
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    /// the files written, each with the number of edits made to it
+    pub applied: Vec<(String, usize)>,
+    pub failed: Vec<Failure>,
+}
+
+/// the edits in `text`, in order
+pub fn parse(text: &str) -> Vec<Edit> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut edits = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if line.trim_end() == SEARCH {
+            if let Some((edit, next)) = replace_block(&lines, i) {
+                edits.push(edit);
+                i = next;
+                continue;
+            }
+        } else if line.starts_with("--- ") && lines.get(i + 1).is_some_and(|next| next.starts_with("+++ ")) {
+            let (edit, next) = diff(&lines, i);
+            edits.extend(edit);
+            i = next;
+            continue;
+        }
+        i += 1;
+    }
+    edits
+}
+
+/// the search and replace block whose SEARCH mark is at `start`, and the line after it
+fn replace_block(lines: &[&str], start: usize) -> Option<(Edit, usize)> {
+    // the path is the last line before the mark that is not a fence
+    let path = lines[..start].iter().rev()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with("```"))?;
+    let path = path.trim_matches(|c: char| c == '`' || c == '*' || c == ':').trim().to_string();
+    let divider = start + 1 + lines[start + 1..].iter().position(|line| line.trim_end() == DIVIDER)?;
+    let end = divider + 1 + lines[divider + 1..].iter().position(|line| line.trim_end() == REPLACE)?;
+    let join = |lines: &[&str]| if lines.is_empty() { String::new() } else { format!("{}\n", lines.join("\n")) };
+    Some((Edit::Replace { path, search: join(&lines[start + 1..divider]), replace: join(&lines[divider + 1..end]) }, end + 1))
+}
+
+/// the unified diff of a file whose `---` line is at `start`, and the line after it
+fn diff(lines: &[&str], start: usize) -> (Option<Edit>, usize) {
+    let name = |line: &str| {
+        // a tab separates the name from a timestamp
+        let name = line[4..].split('\t').next().unwrap_or_default().trim();
+        name.strip_prefix("a/").or_else(|| name.strip_prefix("b/")).unwrap_or(name).to_string()
+    };
+    let old = name(lines[start]);
+    let new = name(lines[start + 1]);
+    let mut hunks = Vec::new();
+    let mut i = start + 2;
+    while i < lines.len() && lines[i].starts_with("@@") {
+        let header = lines[i].to_string();
+        let old_start = header.split(' ').nth(1)
+            .and_then(|range| range.strip_prefix('-'))
+            .and_then(|range| range.split(',').next())
+            .and_then(|line| line.parse().ok())
+            .unwrap_or(1);
+        let mut hunk = Hunk { header, old_start, lines: Vec::new() };
+        i += 1;
+        while i < lines.len() {
+            let line = lines[i];
+            let parsed = match line.chars().next() {
+                Some(' ') => HunkLine::Context(line[1..].to_string()),
+                Some('-') if !line.starts_with("--- ") => HunkLine::Removed(line[1..].to_string()),
+                Some('+') if !line.starts_with("+++ ") => HunkLine::Added(line[1..].to_string()),
+                // some models drop the space before an empty line of context
+                None => HunkLine::Context(String::new()),
+                Some('\\') => {
+                    i += 1;
+                    continue;
+                }
+                _ => break,
+            };
+            hunk.lines.push(parsed);
+            i += 1;
+        }
+        // an empty line may as well end the diff as be context, so leave it off the end
+        while hunk.lines.last() == Some(&HunkLine::Context(String::new())) && lines.get(i - 1).is_some_and(|line| line.is_empty()) {
+            hunk.lines.pop();
+            i -= 1;
+        }
+        hunks.push(hunk);
+    }
+    if hunks.is_empty() {
+        return (None, start + 2);
+    }
+    let (created, deleted) = (old == "/dev/null", new == "/dev/null");
+    let path = if deleted { old } else { new };
+    (Some(Edit::Diff { path, hunks, created, deleted }), i)
+}
+
+/// apply the edits to the files under `root`, writing each file only if all of its edits apply
+pub fn apply(edits: &[Edit], root: &Path) -> Report {
+    let mut by_file: BTreeMap<&str, Vec<&Edit>> = BTreeMap::new();
+    for edit in edits {
+        by_file.entry(edit.path()).or_default().push(edit);
+    }
+    let mut report = Report::default();
+    for (path, edits) in by_file {
+        let fail = |reason: String| Failure { path: path.to_string(), edit: String::new(), reason };
+        if path.is_empty() || Path::new(path).is_absolute() || path.split('/').any(|part| part == "..") {
+            report.failed.push(fail("the path is not inside the working tree".to_string()));
+            continue;
+        }
+        let file = root.join(path);
+        if edits.iter().any(|edit| matches!(edit, Edit::Diff { deleted: true, .. })) {
+            report.failed.push(fail("deleting files is left to you".to_string()));
+            continue;
+        }
+        if file.exists() && edits.iter().any(|edit| matches!(edit, Edit::Diff { created: true, .. })) {
+            report.failed.push(fail("the diff creates it, but it exists".to_string()));
+            continue;
+        }
+        let text = match fs::read_to_string(&file) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let creates = |edit: &&Edit| match edit {
+                    Edit::Diff { created, .. } => *created,
+                    Edit::Replace { search, .. } => search.is_empty(),
+                };
+                if !edits.iter().any(creates) {
+                    report.failed.push(fail("there is no such file".to_string()));
+                    continue;
+                }
+                String::new()
+            }
+            Err(e) => {
+                report.failed.push(fail(format!("could not read it: {}", e)));
+                continue;
+            }
+        };
+        match apply_to(&text, path, &edits) {
+            Ok(edited) => {
+                let written = file.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(&file, edited));
+                match written {
+                    Ok(()) => report.applied.push((path.to_string(), edits.len())),
+                    Err(e) => report.failed.push(fail(format!("could not write it: {}", e))),
+                }
+            }
+            Err(failures) => report.failed.extend(failures),
+        }
+    }
+    report
+}
+
+/// the text of a file with the edits to it made, or why some of them could not be
+pub fn apply_to(text: &str, path: &str, edits: &[&Edit]) -> std::result::Result<String, Vec<Failure>> {
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let mut failures = Vec::new();
+    // how far earlier hunks moved the lines after them
+    let mut offset: isize = 0;
+    for edit in edits {
+        match edit {
+            Edit::Diff { hunks, .. } => {
+                for hunk in hunks {
+                    let expected = (hunk.old_start as isize - 1 + offset).max(0) as usize;
+                    match apply_hunk(&mut lines, hunk, expected) {
+                        Ok(moved) => offset += moved,
+                        Err(reason) => failures.push(Failure { path: path.to_string(), edit: hunk.header.clone(), reason }),
+                    }
+                }
+            }
+            Edit::Replace { search, replace, .. } => {
+                let search: Vec<String> = search.lines().map(str::to_string).collect();
+                let replace: Vec<String> = replace.lines().map(str::to_string).collect();
+                if search.is_empty() {
+                    lines.extend(replace);
+                    continue;
+                }
+                match find(&lines, &search, 0) {
+                    Some(at) => {
+                        lines.splice(at..at + search.len(), replace);
+                    }
+                    None => failures.push(Failure {
+                        path: path.to_string(),
+                        edit: search[0].clone(),
+                        reason: mismatch(&lines, &search),
+                    }),
+                }
+            }
+        }
+    }
+    if !failures.is_empty() {
+        return Err(failures);
+    }
+    let mut edited = lines.join("\n");
+    if !lines.is_empty() && (text.ends_with('\n') || text.is_empty()) {
+        edited.push('\n');
+    }
+    Ok(edited)
+}
+
+/// apply a hunk as near `expected` as it matches, returning how many lines it added
+fn apply_hunk(lines: &mut Vec<String>, hunk: &Hunk, expected: usize) -> std::result::Result<isize, String> {
+    // leave off up to two lines of context at each end when the whole hunk does not match
+    for fuzz in 0..=2 {
+        let leading = hunk.lines.iter().take(fuzz).take_while(|line| matches!(line, HunkLine::Context(_))).count();
+        let trailing = hunk.lines.iter().rev().take(fuzz).take_while(|line| matches!(line, HunkLine::Context(_))).count();
+        if fuzz > 0 && leading + trailing == 0 || leading + trailing >= hunk.lines.len() {
+            continue;
+        }
+        let part = &hunk.lines[leading..hunk.lines.len() - trailing];
+        let old: Vec<String> = part.iter().filter_map(|line| match line {
+            HunkLine::Context(text) | HunkLine::Removed(text) => Some(text.clone()),
+            HunkLine::Added(_) => None,
+        }).collect();
+        let new: Vec<String> = part.iter().filter_map(|line| match line {
+            HunkLine::Context(text) | HunkLine::Added(text) => Some(text.clone()),
+            HunkLine::Removed(_) => None,
+        }).collect();
+        let at = match old.is_empty() {
+            true => Some((expected + leading).min(lines.len())),
+            false => find(lines, &old, expected + leading),
+        };
+        if let Some(at) = at {
+            let moved = new.len() as isize - old.len() as isize;
+            lines.splice(at..at + old.len(), new);
+            return Ok(moved);
+        }
+    }
+    let old: Vec<String> = hunk.lines.iter().filter_map(|line| match line {
+        HunkLine::Context(text) | HunkLine::Removed(text) => Some(text.clone()),
+        HunkLine::Added(_) => None,
+    }).collect();
+    Err(mismatch(lines, &old))
+}
+
+/// where `pattern` is in `lines`, nearest to `expected`: matching exactly if it can,
+/// else ignoring whitespace at the ends of lines, and then at their starts too
+fn find(lines: &[String], pattern: &[String], expected: usize) -> Option<usize> {
+    if pattern.len() > lines.len() {
+        return None;
+    }
+    let levels: [fn(&str) -> &str; 3] = [|line| line, str::trim_end, str::trim];
+    for normalize in levels {
+        let matches = |at: usize| lines[at..at + pattern.len()].iter().zip(pattern).all(|(a, b)| normalize(a) == normalize(b));
+        let found = (0..=lines.len() - pattern.len()).filter(|&at| matches(at)).min_by_key(|&at| at.abs_diff(expected));
+        if found.is_some() {
+            return found;
+        }
+    }
+    None
+}
+
+/// why `pattern` is not in `lines`: the first of its lines that is nowhere in them, or
+/// else that its lines are not together in that order
+fn mismatch(lines: &[String], pattern: &[String]) -> String {
+    match pattern.iter().find(|line| !lines.iter().any(|l| l.trim() == line.trim())) {
+        Some(line) => format!("no line of the file is `{}`", line.trim()),
+        None => format!("the {} lines it changes are not together in the file", pattern.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = "fn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}\n";
+
+    fn edited(answer: &str) -> std::result::Result<String, Vec<Failure>> {
+        let edits = parse(answer);
+        apply_to(FILE, "src/main.rs", &edits.iter().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn parses_diffs_and_replace_blocks() {
+        let answer = "Here:\n\n```diff\n--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,2 +1,2 @@\n fn main() {\n-    let x = 1;\n+    let x = 2;\n```\n\n`src/lib.rs`:\n```\n<<<<<<< SEARCH\n=======\npub fn f() {}\n>>>>>>> REPLACE\n```\n";
+        let edits = parse(answer);
+        assert_eq!(edits.len(), 2);
+        let Edit::Diff { path, hunks, created, deleted } = &edits[0] else { panic!("{:?}", edits[0]) };
+        assert_eq!((path.as_str(), *created, *deleted, hunks[0].old_start), ("src/main.rs", false, false, 1));
+        assert_eq!(hunks[0].lines, [
+            HunkLine::Context("fn main() {".to_string()),
+            HunkLine::Removed("    let x = 1;".to_string()),
+            HunkLine::Added("    let x = 2;".to_string()),
+        ]);
+        assert_eq!(edits[1], Edit::Replace { path: "src/lib.rs".to_string(), search: String::new(), replace: "pub fn f() {}\n".to_string() });
+    }
+
+    #[test]
+    fn applies_a_hunk_with_the_wrong_line_number() {
+        let answer = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -40,2 +40,2 @@\n     let x = 1;\n-    println!(\"{}\", x);\n+    println!(\"x = {}\", x);\n";
+        assert_eq!(edited(answer).unwrap(), FILE.replace("\"{}\"", "\"x = {}\""));
+    }
+
+    #[test]
+    fn applies_a_hunk_with_wrong_context_and_whitespace() {
+        // the line of context is not in the file and the line removed has lost its indent
+        let answer = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,3 +1,3 @@\n fn start() {\n-let x = 1;\n+    let x = 2;\n";
+        assert_eq!(edited(answer).unwrap(), FILE.replace('1', "2"));
+    }
+
+    #[test]
+    fn replaces_a_block() {
+        let answer = "src/main.rs\n<<<<<<< SEARCH\n    let x = 1;\n=======\n    let x = 1;\n    let y = 2;\n>>>>>>> REPLACE\n";
+        assert_eq!(edited(answer).unwrap(), FILE.replace("1;\n", "1;\n    let y = 2;\n"));
+    }
+
+    #[test]
+    fn says_why_an_edit_does_not_apply() {
+        let missing = edited("src/main.rs\n<<<<<<< SEARCH\n    let z = 3;\n=======\n>>>>>>> REPLACE\n").unwrap_err();
+        assert_eq!(missing[0].reason, "no line of the file is `let z = 3;`");
+        let apart = edited("src/main.rs\n<<<<<<< SEARCH\nfn main() {\n}\n=======\n>>>>>>> REPLACE\n").unwrap_err();
+        assert_eq!(apart[0].reason, "the 2 lines it changes are not together in the file");
+    }
+
+    #[test]
+    fn applies_edits_to_files_under_a_root() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), FILE).unwrap();
+        let answer = "src/main.rs\n<<<<<<< SEARCH\n    let x = 1;\n=======\n    let x = 2;\n>>>>>>> REPLACE\n\
+                      --- /dev/null\n+++ b/src/new.rs\n@@ -0,0 +1 @@\n+pub fn new() {}\n\
+                      ../outside.rs\n<<<<<<< SEARCH\n=======\nx\n>>>>>>> REPLACE\n\
+                      --- a/src/gone.rs\n+++ b/src/gone.rs\n@@ -1 +1 @@\n-a\n+b\n";
+        let report = apply(&parse(answer), dir.path());
+        assert_eq!(report.applied, [("src/main.rs".to_string(), 1), ("src/new.rs".to_string(), 1)]);
+        let reasons: Vec<(&str, &str)> = report.failed.iter().map(|failure| (failure.path.as_str(), failure.reason.as_str())).collect();
+        assert_eq!(reasons, [("../outside.rs", "the path is not inside the working tree"), ("src/gone.rs", "there is no such file")]);
+        assert_eq!(fs::read_to_string(dir.path().join("src/main.rs")).unwrap(), FILE.replace('1', "2"));
+        assert_eq!(fs::read_to_string(dir.path().join("src/new.rs")).unwrap(), "pub fn new() {}\n");
+    }
+}
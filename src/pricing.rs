@@ -0,0 +1,169 @@
+//! Per-model token prices for estimating what a request cost.
+//!
+//! Prices are in US dollars per million tokens. The built-in table covers the
+//! common hosted models; a `[prices]` table in the config adds or overrides
+//! entries, e.g. `"gpt-4o" = { input = 2.5, output = 10.0 }`.
+
+use serde_json::Value;
+
+/// the price of a model, in dollars per million tokens
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+impl Price {
+    /// the cost of a request in dollars
+    pub fn cost(&self, prompt_tokens: u64, completion_tokens: u64) -> f64 {
+        (prompt_tokens as f64 * self.input + completion_tokens as f64 * self.output) / 1_000_000.0
+    }
+}
+
+/// list prices at the time of writing, matched against model names by substring
+const BUILTIN: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-4", 30.00, 60.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("o1-mini", 1.10, 4.40),
+    ("o1", 15.00, 60.00),
+    ("o3-mini", 1.10, 4.40),
+    ("o3", 2.00, 8.00),
+    ("o4-mini", 1.10, 4.40),
+    ("text-embedding-3-small", 0.02, 0.0),
+    ("text-embedding-3-large", 0.13, 0.0),
+    ("claude-opus-4", 15.00, 75.00),
+    ("claude-sonnet-4", 3.00, 15.00),
+    ("claude-3-7-sonnet", 3.00, 15.00),
+    ("claude-3-5-sonnet", 3.00, 15.00),
+    ("claude-3-5-haiku", 0.80, 4.00),
+    ("claude-3-opus", 15.00, 75.00),
+    ("claude-3-sonnet", 3.00, 15.00),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("gemini-2.5-pro", 1.25, 10.00),
+    ("gemini-2.5-flash", 0.30, 2.50),
+    ("gemini-2.0-flash", 0.10, 0.40),
+    ("gemini-1.5-pro", 1.25, 5.00),
+    ("gemini-1.5-flash", 0.075, 0.30),
+    ("mistral-large", 2.00, 6.00),
+    ("mistral-small", 0.20, 0.60),
+];
+
+/// a rough token count for text that has not been sent yet, at four characters a token
+pub fn estimate_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+/// the built-in prices with any overrides from the config
+#[derive(Clone, Debug, Default)]
+pub struct Prices {
+    overrides: Vec<(String, Price)>,
+}
+
+impl Prices {
+    /// prices from a config `prices` table, over the built-in ones
+    pub fn new(table: Option<&Value>) -> Self {
+        let overrides = table.and_then(Value::as_object).into_iter().flatten()
+            .filter_map(|(model, price)| {
+                let input = price["input"].as_f64()?;
+                let output = price["output"].as_f64().unwrap_or(0.0);
+                Some((model.clone(), Price { input, output }))
+            })
+            .collect();
+        Prices { overrides }
+    }
+
+    /// the price of a model: an exact override first, then the longest override or
+    /// built-in name contained in the model name, so that `gpt-4o-mini-2024-07-18`
+    /// and `anthropic.claude-3-5-sonnet-20240620-v1:0` are found
+    pub fn get(&self, model: &str) -> Option<Price> {
+        if let Some((_, price)) = self.overrides.iter().find(|(name, _)| name == model) {
+            return Some(*price);
+        }
+        let model = model.to_lowercase();
+        let overrides = self.overrides.iter().map(|(name, price)| (name.to_lowercase(), *price));
+        let builtin = BUILTIN.iter().map(|&(name, input, output)| (name.to_string(), Price { input, output }));
+        // overrides come first, so they win ties with built-in names of the same length
+        overrides.chain(builtin)
+            .filter(|(name, _)| model.contains(name.as_str()))
+            .fold(None, |best: Option<(String, Price)>, (name, price)| match best {
+                Some(best) if best.0.len() >= name.len() => Some(best),
+                _ => Some((name, price)),
+            })
+            .map(|(_, price)| price)
+    }
+
+    /// the cost of a request: what it was billed, when the usage says as OpenRouter's
+    /// does, or else its usage at the model's price
+    pub fn cost(&self, model: &str, usage: &Value) -> Option<f64> {
+        if let Some(cost) = usage["cost"].as_f64() {
+            return Some(cost);
+        }
+        let price = self.get(model)?;
+        let (prompt, completion) = crate::client::token_counts(usage);
+        if prompt.is_none() && completion.is_none() {
+            return None;
+        }
+        Some(price.cost(prompt.unwrap_or(0), completion.unwrap_or(0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn prices_tokens_per_million() {
+        let price = Price { input: 2.5, output: 10.0 };
+        assert_eq!(price.cost(1_000_000, 0), 2.5);
+        assert_eq!(price.cost(1000, 500), 0.0075);
+        assert_eq!(price.cost(0, 0), 0.0);
+    }
+
+    #[test]
+    fn finds_the_longest_name_in_the_model() {
+        let prices = Prices::default();
+        assert_eq!(prices.get("gpt-4o-mini-2024-07-18"), Some(Price { input: 0.15, output: 0.60 }));
+        assert_eq!(prices.get("gpt-4o-2024-08-06"), Some(Price { input: 2.50, output: 10.00 }));
+        assert_eq!(prices.get("anthropic.claude-3-5-sonnet-20240620-v1:0"), Some(Price { input: 3.00, output: 15.00 }));
+        assert_eq!(prices.get("llama-3.1-8b-instruct"), None);
+    }
+
+    #[test]
+    fn lets_the_config_add_and_override_prices() {
+        let prices = Prices::new(Some(&json!({
+            "gpt-4o": { "input": 2.0, "output": 8.0 },
+            "llama": { "input": 0.1 },
+            "broken": { "output": 1.0 },
+        })));
+        assert_eq!(prices.get("gpt-4o"), Some(Price { input: 2.0, output: 8.0 }));
+        // a longer built-in name still wins over a shorter override
+        assert_eq!(prices.get("gpt-4o-mini"), Some(Price { input: 0.15, output: 0.60 }));
+        assert_eq!(prices.get("Llama-3.1-8B"), Some(Price { input: 0.1, output: 0.0 }));
+        assert_eq!(prices.get("broken"), None);
+    }
+
+    #[test]
+    fn costs_the_usage_of_any_provider_or_takes_the_billed_cost() {
+        let prices = Prices::default();
+        assert_eq!(prices.cost("gpt-4o", &json!({ "prompt_tokens": 1000, "completion_tokens": 500 })), Some(0.0075));
+        assert_eq!(prices.cost("claude-3-haiku", &json!({ "input_tokens": 4000, "output_tokens": 800 })), Some(0.002));
+        assert_eq!(prices.cost("gemini-2.0-flash", &json!({ "promptTokenCount": 10000 })), Some(0.001));
+        assert_eq!(prices.cost("unknown", &json!({ "prompt_tokens": 1000, "cost": 0.0042 })), Some(0.0042));
+        assert_eq!(prices.cost("unknown", &json!({ "prompt_tokens": 1000 })), None);
+        assert_eq!(prices.cost("gpt-4o", &json!({})), None);
+    }
+
+    #[test]
+    fn estimates_four_characters_a_token() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(estimate_tokens("ääää"), 1);
+    }
+}
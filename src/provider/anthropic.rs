@@ -0,0 +1,226 @@
+use reqwest::RequestBuilder;
+use serde_json::{json, Map, Value};
+
+use super::{Provider, StreamEvent};
+use crate::image::Image;
+use crate::{ChatRequest, Message, Result};
+
+const API_VERSION: &str = "2023-06-01";
+
+/// the max_tokens sent when none is given, since the Messages API requires one
+const DEFAULT_MAX_TOKENS: i64 = 4096;
+
+/// the Anthropic Messages API
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Anthropic;
+
+/// a chat message, with any images as content blocks ahead of the text
+fn message(message: &Message) -> Value {
+    if message.images.is_empty() {
+        return message.to_json();
+    }
+    let mut content: Vec<Value> = message.images.iter()
+        .map(|image| match image {
+            Image::Data { mime, data } => json!({ "type": "image", "source": { "type": "base64", "media_type": mime, "data": data } }),
+            Image::Url(url) => json!({ "type": "image", "source": { "type": "url", "url": url } }),
+        })
+        .collect();
+    content.push(json!({ "type": "text", "text": message.content }));
+    json!({ "role": message.role, "content": content })
+}
+
+impl Provider for Anthropic {
+    fn name(&self) -> &'static str {
+        "anthropic"
+    }
+
+    /// `https://api.anthropic.com`, `.../v1` and `.../v1/messages` all lead to the messages route
+    fn chat_url(&self, endpoint: &str, _request: &ChatRequest) -> String {
+        let endpoint = endpoint.trim_end_matches('/');
+        if endpoint.ends_with("/messages") {
+            endpoint.to_string()
+        } else if endpoint.ends_with("/v1") {
+            format!("{}/messages", endpoint)
+        } else {
+            format!("{}/v1/messages", endpoint)
+        }
+    }
+
+    fn chat_body(&self, request: &ChatRequest) -> Value {
+        let mut body = Map::new();
+        body.insert("model".into(), json!(request.model));
+
+        // the system prompt is a top-level field rather than a message
+        let system: Vec<&str> = request.messages.iter()
+            .filter(|m| m.role == "system")
+            .map(|m| m.content.as_str())
+            .collect();
+        if !system.is_empty() {
+            body.insert("system".into(), json!(system.join("\n\n")));
+        }
+        let messages: Vec<Value> = request.messages.iter()
+            .filter(|m| m.role != "system")
+            .map(message)
+            .collect();
+        body.insert("messages".into(), Value::Array(messages));
+
+        let params = &request.params;
+        let max_tokens = if params.max_tokens > 0 { params.max_tokens } else { DEFAULT_MAX_TOKENS };
+        body.insert("max_tokens".into(), json!(max_tokens));
+        body.insert("temperature".into(), json!(params.temperature.clamp(0.0, 1.0)));
+        body.insert("top_p".into(), json!(params.top_p));
+        if params.top_k > 0 {
+            body.insert("top_k".into(), json!(params.top_k));
+        }
+        body.insert("stream".into(), json!(request.stream));
+        Value::Object(body)
+    }
+
+    fn authorize(&self, builder: RequestBuilder, key: Option<&str>) -> RequestBuilder {
+        builder
+            .header("x-api-key", key.unwrap_or_default())
+            .header("anthropic-version", API_VERSION)
+    }
+
+    fn parse_event(&self, data: &str) -> Result<Vec<StreamEvent>> {
+        let parsed: Value = match serde_json::from_str(data) {
+            Ok(parsed) => parsed,
+            Err(_) => return Ok(Vec::new()),
+        };
+        let mut events = Vec::new();
+        match parsed["type"].as_str().unwrap_or_default() {
+            "message_start" if parsed["message"]["usage"].is_object() => {
+                events.push(StreamEvent::Usage(parsed["message"]["usage"].clone()));
+            }
+            "content_block_start" => {
+                if let Some(text) = parsed["content_block"]["text"].as_str().filter(|t| !t.is_empty()) {
+                    events.push(StreamEvent::Content(text.to_string()));
+                }
+            }
+            "content_block_delta" => {
+                if let Some(text) = parsed["delta"]["text"].as_str() {
+                    events.push(StreamEvent::Content(text.to_string()));
+                }
+                // extended thinking arrives as thinking_delta
+                if let Some(text) = parsed["delta"]["thinking"].as_str() {
+                    events.push(StreamEvent::Reasoning(text.to_string()));
+                }
+            }
+            "message_delta" => {
+                if let Some(reason) = parsed["delta"]["stop_reason"].as_str() {
+                    events.push(StreamEvent::Finish(reason.to_string()));
+                }
+                if parsed["usage"].is_object() {
+                    events.push(StreamEvent::Usage(parsed["usage"].clone()));
+                }
+            }
+            "message_stop" => events.push(StreamEvent::Done),
+            "error" => {
+                let message = parsed["error"]["message"].as_str().unwrap_or("unknown error");
+                return Err(format!("server error: {}", message).into());
+            }
+            // ping and content_block_stop carry nothing we need
+            _ => {}
+        }
+        Ok(events)
+    }
+
+    fn parse_response(&self, body: &Value) -> Result<Vec<StreamEvent>> {
+        if let Some(message) = body["error"]["message"].as_str() {
+            return Err(format!("server error: {}", message).into());
+        }
+        let mut events = Vec::new();
+        let thinking: String = body["content"].as_array()
+            .map(|blocks| blocks.iter().filter_map(|b| b["thinking"].as_str()).collect())
+            .unwrap_or_default();
+        if !thinking.is_empty() {
+            events.push(StreamEvent::Reasoning(thinking));
+        }
+        let text: String = body["content"].as_array()
+            .map(|blocks| blocks.iter().filter_map(|b| b["text"].as_str()).collect())
+            .unwrap_or_default();
+        events.push(StreamEvent::Content(text));
+        if let Some(reason) = body["stop_reason"].as_str() {
+            events.push(StreamEvent::Finish(reason.to_string()));
+        }
+        if body["usage"].is_object() {
+            events.push(StreamEvent::Usage(body["usage"].clone()));
+        }
+        events.push(StreamEvent::Done);
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the events of a stream, one payload at a time
+    fn parse(payloads: &[&str]) -> Vec<StreamEvent> {
+        payloads.iter().flat_map(|data| Anthropic.parse_event(data).unwrap()).collect()
+    }
+
+    #[test]
+    fn moves_the_system_prompt_out_of_the_messages() {
+        let mut request = ChatRequest::new("claude-3-5-haiku-latest", vec![Message::system("Be brief."), Message::user("Hi")]);
+        request.params.temperature = 1.5;
+        let body = Anthropic.chat_body(&request);
+        assert_eq!(body["system"], "Be brief.");
+        assert_eq!(body["messages"], json!([{ "role": "user", "content": "Hi" }]));
+        assert_eq!(body["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert_eq!(body["temperature"], 1.0);
+        assert_eq!(body["top_k"], 40);
+        assert_eq!(body["stream"], true);
+    }
+
+    #[test]
+    fn parses_a_streamed_message() {
+        let events = parse(&[
+            r#"{"type":"message_start","message":{"id":"msg_01","type":"message","role":"assistant","content":[],"model":"claude-3-5-haiku-20241022","stop_reason":null,"usage":{"input_tokens":12,"output_tokens":1}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"thinking","thinking":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"thinking_delta","thinking":"A greeting."}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type": "ping"}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"text_delta","text":"Hello"}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"text_delta","text":" there."}}"#,
+            r#"{"type":"content_block_stop","index":1}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"end_turn","stop_sequence":null},"usage":{"output_tokens":6}}"#,
+            r#"{"type":"message_stop"}"#,
+        ]);
+        assert_eq!(events, [
+            StreamEvent::Usage(json!({ "input_tokens": 12, "output_tokens": 1 })),
+            StreamEvent::Reasoning("A greeting.".to_string()),
+            StreamEvent::Content("Hello".to_string()),
+            StreamEvent::Content(" there.".to_string()),
+            StreamEvent::Finish("end_turn".to_string()),
+            StreamEvent::Usage(json!({ "output_tokens": 6 })),
+            StreamEvent::Done,
+        ]);
+    }
+
+    #[test]
+    fn fails_on_an_error_event() {
+        let error = Anthropic.parse_event(r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#).unwrap_err();
+        assert_eq!(error.to_string(), "server error: Overloaded");
+    }
+
+    #[test]
+    fn parses_a_whole_message() {
+        let body = json!({
+            "id": "msg_01",
+            "type": "message",
+            "role": "assistant",
+            "content": [{ "type": "thinking", "thinking": "Easy." }, { "type": "text", "text": "4" }],
+            "stop_reason": "end_turn",
+            "usage": { "input_tokens": 14, "output_tokens": 5 },
+        });
+        assert_eq!(Anthropic.parse_response(&body).unwrap(), [
+            StreamEvent::Reasoning("Easy.".to_string()),
+            StreamEvent::Content("4".to_string()),
+            StreamEvent::Finish("end_turn".to_string()),
+            StreamEvent::Usage(json!({ "input_tokens": 14, "output_tokens": 5 })),
+            StreamEvent::Done,
+        ]);
+    }
+}
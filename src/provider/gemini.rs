@@ -0,0 +1,224 @@
+use reqwest::RequestBuilder;
+use serde_json::{json, Map, Value};
+
+use super::{Provider, StreamEvent};
+use crate::framing::Framing;
+use crate::image::Image;
+use crate::client::ResponseFormat;
+use crate::{ChatRequest, Result};
+
+/// Google's Gemini `generateContent` API
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Gemini;
+
+impl Provider for Gemini {
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    /// an endpoint naming a `:generateContent` or `:streamGenerateContent` method
+    /// is used as is, otherwise it is taken as the API base, e.g.
+    /// `https://generativelanguage.googleapis.com/v1beta`
+    fn chat_url(&self, endpoint: &str, request: &ChatRequest) -> String {
+        let endpoint = endpoint.trim_end_matches('/');
+        let method = if request.stream { ":streamGenerateContent" } else { ":generateContent" };
+        if endpoint.contains(":generateContent") || endpoint.contains(":streamGenerateContent") {
+            return endpoint.replace(":streamGenerateContent", ":generateContent").replace(":generateContent", method);
+        }
+        let base = match endpoint.find("/models") {
+            Some(i) => &endpoint[..i],
+            None => endpoint,
+        };
+        let model = request.model.trim_start_matches("models/");
+        format!("{}/models/{}{}", base, model, method)
+    }
+
+    fn chat_body(&self, request: &ChatRequest) -> Value {
+        let mut body = Map::new();
+
+        let system: Vec<Value> = request.messages.iter()
+            .filter(|m| m.role == "system")
+            .map(|m| json!({ "text": m.content }))
+            .collect();
+        if !system.is_empty() {
+            body.insert("systemInstruction".into(), json!({ "parts": system }));
+        }
+        // gemini calls the assistant "model"
+        let contents: Vec<Value> = request.messages.iter()
+            .filter(|m| m.role != "system")
+            .map(|m| {
+                let role = if m.role == "assistant" { "model" } else { "user" };
+                let mut parts = vec![json!({ "text": m.content })];
+                for image in &m.images {
+                    if let Image::Data { mime, data } = image {
+                        parts.push(json!({ "inline_data": { "mime_type": mime, "data": data } }));
+                    }
+                }
+                json!({ "role": role, "parts": parts })
+            })
+            .collect();
+        body.insert("contents".into(), Value::Array(contents));
+
+        let params = &request.params;
+        let mut generation = Map::new();
+        generation.insert("temperature".into(), json!(params.temperature));
+        generation.insert("topP".into(), json!(params.top_p));
+        if params.top_k > 0 {
+            generation.insert("topK".into(), json!(params.top_k));
+        }
+        if params.max_tokens > 0 {
+            generation.insert("maxOutputTokens".into(), json!(params.max_tokens));
+        }
+        if params.presence_penalty != 0.0 {
+            generation.insert("presencePenalty".into(), json!(params.presence_penalty));
+        }
+        if params.frequency_penalty != 0.0 {
+            generation.insert("frequencyPenalty".into(), json!(params.frequency_penalty));
+        }
+        if let Some(seed) = params.seed {
+            generation.insert("seed".into(), json!(seed));
+        }
+        if let Some(format) = &request.response_format {
+            generation.insert("responseMimeType".into(), json!("application/json"));
+            if let ResponseFormat::Schema { schema, .. } = format {
+                generation.insert("responseJsonSchema".into(), schema.clone());
+            }
+        }
+        body.insert("generationConfig".into(), Value::Object(generation));
+        Value::Object(body)
+    }
+
+    fn supports_json(&self) -> bool {
+        true
+    }
+
+    fn models_url(&self, endpoint: &str) -> String {
+        let endpoint = endpoint.trim_end_matches('/');
+        match endpoint.find("/models") {
+            Some(i) => format!("{}/models", &endpoint[..i]),
+            None => format!("{}/models", endpoint),
+        }
+    }
+
+    fn parse_models(&self, body: &Value) -> Vec<(String, Value)> {
+        body["models"].as_array()
+            .map(|models| models.iter()
+                .filter_map(|m| Some((m["name"].as_str()?.trim_start_matches("models/").to_string(), m.clone())))
+                .collect())
+            .unwrap_or_default()
+    }
+
+    fn context_length(&self, info: &Value) -> Option<u64> {
+        info["inputTokenLimit"].as_u64()
+    }
+
+    /// the key goes in the query string rather than a header
+    fn authorize(&self, builder: RequestBuilder, key: Option<&str>) -> RequestBuilder {
+        match key {
+            Some(key) => builder.query(&[("key", key)]),
+            None => builder,
+        }
+    }
+
+    fn framing(&self) -> Framing {
+        Framing::JsonArray
+    }
+
+    fn parse_event(&self, data: &str) -> Result<Vec<StreamEvent>> {
+        // the framing only hands over whole objects, so one that does not decode is a broken response
+        let parsed: Value = serde_json::from_str(data).map_err(|e| format!("the event is not JSON ({}): {}", e, data))?;
+        if let Some(message) = parsed["error"]["message"].as_str() {
+            return Err(format!("server error: {}", message).into());
+        }
+        let mut events = Vec::new();
+        let candidate = &parsed["candidates"][0];
+        if let Some(parts) = candidate["content"]["parts"].as_array() {
+            // thought summaries are parts marked `thought`
+            let (thoughts, answer): (Vec<&Value>, Vec<&Value>) = parts.iter().partition(|p| p["thought"].as_bool() == Some(true));
+            let thought: String = thoughts.iter().filter_map(|p| p["text"].as_str()).collect();
+            if !thought.is_empty() {
+                events.push(StreamEvent::Reasoning(thought));
+            }
+            let text: String = answer.iter().filter_map(|p| p["text"].as_str()).collect();
+            if !text.is_empty() {
+                events.push(StreamEvent::Content(text));
+            }
+        }
+        if let Some(reason) = candidate["finishReason"].as_str() {
+            events.push(StreamEvent::Finish(reason.to_string()));
+        }
+        if parsed["usageMetadata"].is_object() {
+            events.push(StreamEvent::Usage(parsed["usageMetadata"].clone()));
+        }
+        if let Some(reason) = parsed["promptFeedback"]["blockReason"].as_str() {
+            return Err(format!("prompt blocked: {}", reason).into());
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Message;
+
+    #[test]
+    fn sends_the_system_prompt_apart_and_calls_the_assistant_model() {
+        let mut request = ChatRequest::new("gemini-2.0-flash", vec![Message::system("Be brief."), Message::user("Hi"), Message::assistant("Hello."), Message::user("Bye")]);
+        request.params.max_tokens = 100;
+        request.params.seed = Some(7);
+        request.response_format = Some(ResponseFormat::Json);
+        let body = Gemini.chat_body(&request);
+        assert_eq!(body["systemInstruction"], json!({ "parts": [{ "text": "Be brief." }] }));
+        assert_eq!(body["contents"], json!([
+            { "role": "user", "parts": [{ "text": "Hi" }] },
+            { "role": "model", "parts": [{ "text": "Hello." }] },
+            { "role": "user", "parts": [{ "text": "Bye" }] },
+        ]));
+        assert_eq!(body["generationConfig"], json!({
+            "temperature": 0.8,
+            "topP": 0.95,
+            "topK": 40,
+            "maxOutputTokens": 100,
+            "seed": 7,
+            "responseMimeType": "application/json",
+        }));
+    }
+
+    #[test]
+    fn parses_streamed_chunks() {
+        let thought = r#"{"candidates": [{"content": {"parts": [{"text": "The user greets.", "thought": true}, {"text": "Hel"}], "role": "model"}, "index": 0}], "modelVersion": "gemini-2.5-flash"}"#;
+        assert_eq!(Gemini.parse_event(thought).unwrap(), [
+            StreamEvent::Reasoning("The user greets.".to_string()),
+            StreamEvent::Content("Hel".to_string()),
+        ]);
+        let last = r#"{"candidates": [{"content": {"parts": [{"text": "lo."}], "role": "model"}, "finishReason": "STOP", "index": 0}], "usageMetadata": {"promptTokenCount": 4, "candidatesTokenCount": 3, "totalTokenCount": 7}}"#;
+        assert_eq!(Gemini.parse_event(last).unwrap(), [
+            StreamEvent::Content("lo.".to_string()),
+            StreamEvent::Finish("STOP".to_string()),
+            StreamEvent::Usage(json!({ "promptTokenCount": 4, "candidatesTokenCount": 3, "totalTokenCount": 7 })),
+        ]);
+    }
+
+    #[test]
+    fn parses_a_whole_response() {
+        let body = json!({
+            "candidates": [{ "content": { "parts": [{ "text": "4" }], "role": "model" }, "finishReason": "STOP" }],
+            "usageMetadata": { "promptTokenCount": 6, "candidatesTokenCount": 1 },
+        });
+        assert_eq!(Gemini.parse_response(&body).unwrap(), [
+            StreamEvent::Content("4".to_string()),
+            StreamEvent::Finish("STOP".to_string()),
+            StreamEvent::Usage(json!({ "promptTokenCount": 6, "candidatesTokenCount": 1 })),
+        ]);
+    }
+
+    #[test]
+    fn fails_on_errors_blocked_prompts_and_broken_events() {
+        let error = Gemini.parse_event(r#"{"error": {"code": 400, "message": "API key not valid.", "status": "INVALID_ARGUMENT"}}"#).unwrap_err();
+        assert_eq!(error.to_string(), "server error: API key not valid.");
+        let blocked = Gemini.parse_event(r#"{"promptFeedback": {"blockReason": "SAFETY"}}"#).unwrap_err();
+        assert_eq!(blocked.to_string(), "prompt blocked: SAFETY");
+        assert!(Gemini.parse_event(r#"{"candidates": [{"content""#).is_err());
+    }
+}
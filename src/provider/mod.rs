@@ -0,0 +1,170 @@
+//! Providers translate chat requests into a particular vendor's wire format
+//! and parse that vendor's streamed events back into [`StreamEvent`]s.
+
+use reqwest::RequestBuilder;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::framing::Framing;
+use crate::client::api_url;
+use crate::logprobs::TokenLogprob;
+use crate::tools::ToolCallDelta;
+use crate::{ChatRequest, CompletionRequest, Result};
+
+mod anthropic;
+mod bedrock;
+mod gemini;
+mod ollama;
+mod openai;
+mod openrouter;
+
+pub use anthropic::Anthropic;
+pub use bedrock::{sign_v4, Bedrock, Credentials};
+pub use gemini::Gemini;
+pub use ollama::Ollama;
+pub use openai::OpenAi;
+pub use openrouter::OpenRouter;
+
+/// one parsed piece of a streamed response
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamEvent {
+    /// a piece of the answer text
+    Content(String),
+    /// a piece of the model's reasoning, which is not part of the answer
+    Reasoning(String),
+    /// token usage as reported by the server, in the provider's own shape
+    Usage(Value),
+    /// a fragment of a tool call, see [`crate::tools::ToolCalls`]
+    ToolCall(ToolCallDelta),
+    /// the log probabilities of the tokens of the next `Content` event
+    Logprobs(Vec<TokenLogprob>),
+    /// why generation stopped
+    Finish(String),
+    /// the content, tool call, and finish events up to the next `Choice` belong to the
+    /// choice with this index, when several answers were asked for
+    Choice(usize),
+    /// the server signalled the end of the stream
+    Done,
+}
+
+pub trait Provider: Send + Sync + std::fmt::Debug {
+    /// the name used for `--provider` and in the config
+    fn name(&self) -> &'static str;
+
+    /// the url chat requests are sent to
+    fn chat_url(&self, endpoint: &str, request: &ChatRequest) -> String;
+
+    /// the JSON body of a chat request
+    fn chat_body(&self, request: &ChatRequest) -> Value;
+
+    /// whether `tools` in a chat request are sent to the model
+    fn supports_tools(&self) -> bool {
+        false
+    }
+
+    /// whether a chat request can ask for several answers with `n`
+    fn supports_choices(&self) -> bool {
+        false
+    }
+
+    /// whether a chat request can constrain the answer with a `response_format`
+    fn supports_json(&self) -> bool {
+        false
+    }
+
+    /// whether a chat request can ask for token log probabilities
+    fn supports_logprobs(&self) -> bool {
+        false
+    }
+
+    /// whether image urls can be sent as they are, rather than downloaded and inlined
+    fn image_urls(&self) -> bool {
+        false
+    }
+
+    /// the url and body of a plain text completion, if the provider has such an API
+    fn completion(&self, _endpoint: &str, _request: &CompletionRequest) -> Option<(String, Value)> {
+        None
+    }
+
+    /// the url listing the available models
+    fn models_url(&self, endpoint: &str) -> String {
+        api_url(endpoint, "models")
+    }
+
+    /// the model ids in a models listing, each with the server's whole entry for it
+    fn parse_models(&self, body: &Value) -> Vec<(String, Value)> {
+        body["data"].as_array()
+            .map(|models| models.iter().filter_map(|m| Some((m["id"].as_str()?.to_string(), m.clone()))).collect())
+            .unwrap_or_default()
+    }
+
+    /// the url and body of a request that describes `model` beyond its listing entry,
+    /// if the provider has one
+    fn model_info(&self, _endpoint: &str, _model: &str) -> Option<(String, Value)> {
+        None
+    }
+
+    /// the context window in tokens, from a model's listing entry or its description
+    fn context_length(&self, info: &Value) -> Option<u64> {
+        // OpenRouter, vLLM, and Groq name it differently, llama.cpp only lists what it was trained with
+        ["context_length", "max_model_len", "context_window"].iter()
+            .find_map(|key| info[*key].as_u64())
+            .or_else(|| info["meta"]["n_ctx_train"].as_u64())
+    }
+
+    /// attach the credentials and any provider specific headers
+    fn authorize(&self, builder: RequestBuilder, key: Option<&str>) -> RequestBuilder;
+
+    /// sign the finished request, for providers whose signature covers the body
+    fn sign(&self, _request: &mut reqwest::Request, _key: Option<&str>) -> Result<()> {
+        Ok(())
+    }
+
+    /// how the events of a streamed response are framed
+    fn framing(&self) -> Framing {
+        Framing::Sse
+    }
+
+    /// parse the data of one streamed event
+    fn parse_event(&self, data: &str) -> Result<Vec<StreamEvent>>;
+
+    /// parse a complete, non-streamed response into the events a stream would have produced
+    fn parse_response(&self, body: &Value) -> Result<Vec<StreamEvent>> {
+        self.parse_event(&body.to_string())
+    }
+}
+
+/// the names accepted by [`by_name`]
+pub const PROVIDERS: &[&str] = &["openai", "openrouter", "anthropic", "gemini", "ollama", "bedrock"];
+
+/// look up a provider by name
+pub fn by_name(name: &str) -> Result<Arc<dyn Provider>> {
+    match name {
+        "openai" => Ok(Arc::new(OpenAi)),
+        "openrouter" => Ok(Arc::new(OpenRouter)),
+        "anthropic" => Ok(Arc::new(Anthropic)),
+        "gemini" => Ok(Arc::new(Gemini)),
+        "ollama" => Ok(Arc::new(Ollama)),
+        "bedrock" => Ok(Arc::new(Bedrock)),
+        _ => Err(format!("Unknown provider '{}', expected one of: {}.", name, PROVIDERS.join(", ")).into()),
+    }
+}
+
+/// guess the provider from the endpoint url, defaulting to the OpenAI-compatible API
+pub fn detect(endpoint: &str) -> Arc<dyn Provider> {
+    let url = endpoint.trim_end_matches('/');
+    if url.contains("api.anthropic.com") || url.ends_with("/messages") {
+        Arc::new(Anthropic)
+    } else if url.contains("generativelanguage.googleapis.com") || url.contains(":streamGenerateContent") || url.contains(":generateContent") {
+        Arc::new(Gemini)
+    } else if url.contains("bedrock-runtime") {
+        Arc::new(Bedrock)
+    } else if url.contains("openrouter.ai") {
+        Arc::new(OpenRouter)
+    } else if url.contains(":11434") || url.contains("/api/chat") || url.contains("/api/generate") {
+        Arc::new(Ollama)
+    } else {
+        Arc::new(OpenAi)
+    }
+}
@@ -0,0 +1,264 @@
+use reqwest::RequestBuilder;
+use serde_json::{json, Map, Value};
+
+use super::{Provider, StreamEvent};
+use crate::framing::Framing;
+use crate::image::Image;
+use crate::tools::ToolCallDelta;
+use crate::client::ResponseFormat;
+use crate::{ChatRequest, CompletionRequest, Message, Result, SamplerParams};
+
+/// Ollama's native `/api/chat` and `/api/generate` API
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ollama;
+
+/// the server root, without any `/api/...` or `/v1/...` route
+fn base(endpoint: &str) -> &str {
+    let endpoint = endpoint.trim_end_matches('/');
+    for marker in ["/api/", "/v1"] {
+        if let Some(i) = endpoint.find(marker) {
+            return &endpoint[..i];
+        }
+    }
+    endpoint.strip_suffix("/api").unwrap_or(endpoint)
+}
+
+/// the sampler settings go in an `options` block with Ollama's own names
+fn options(params: &SamplerParams) -> Value {
+    let mut options = Map::new();
+    if params.max_tokens > 0 {
+        options.insert("num_predict".into(), json!(params.max_tokens));
+    }
+    options.insert("temperature".into(), json!(params.temperature));
+    options.insert("top_p".into(), json!(params.top_p));
+    options.insert("top_k".into(), json!(params.top_k));
+    options.insert("min_p".into(), json!(params.min_p));
+    options.insert("typical_p".into(), json!(params.typical_p));
+    options.insert("repeat_penalty".into(), json!(params.repetition_penalty));
+    options.insert("repeat_last_n".into(), json!(params.repetition_penalty_last));
+    options.insert("presence_penalty".into(), json!(params.presence_penalty));
+    options.insert("frequency_penalty".into(), json!(params.frequency_penalty));
+    options.insert("mirostat".into(), json!(params.mirostat_mode));
+    options.insert("mirostat_tau".into(), json!(params.mirostat_tau));
+    options.insert("mirostat_eta".into(), json!(params.mirostat_eta));
+    if let Some(seed) = params.seed {
+        options.insert("seed".into(), json!(seed));
+    }
+    Value::Object(options)
+}
+
+/// a chat message, with tool call arguments as objects rather than JSON strings
+fn message(message: &Message) -> Value {
+    let mut json = message.to_json();
+    // images are a list of base64 strings beside the text
+    if !message.images.is_empty() {
+        json["content"] = json!(message.content);
+        let images: Vec<&str> = message.images.iter()
+            .filter_map(|image| match image {
+                Image::Data { data, .. } => Some(data.as_str()),
+                Image::Url(_) => None,
+            })
+            .collect();
+        json["images"] = json!(images);
+    }
+    for (i, call) in message.tool_calls.iter().enumerate() {
+        if let Ok(arguments) = call.parsed_arguments() {
+            json["tool_calls"][i]["function"]["arguments"] = arguments;
+        }
+    }
+    json
+}
+
+impl Provider for Ollama {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn chat_url(&self, endpoint: &str, _request: &ChatRequest) -> String {
+        format!("{}/api/chat", base(endpoint))
+    }
+
+    fn chat_body(&self, request: &ChatRequest) -> Value {
+        let mut body = json!({
+            "model": request.model,
+            "messages": request.messages.iter().map(message).collect::<Vec<_>>(),
+            "options": options(&request.params),
+            "stream": request.stream,
+        });
+        if !request.tools.is_empty() {
+            body["tools"] = json!(request.tools);
+        }
+        // `format` takes "json" or the schema itself
+        match &request.response_format {
+            Some(ResponseFormat::Json) => body["format"] = json!("json"),
+            Some(ResponseFormat::Schema { schema, .. }) => body["format"] = schema.clone(),
+            None => {}
+        }
+        body
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_json(&self) -> bool {
+        true
+    }
+
+    fn completion(&self, endpoint: &str, request: &CompletionRequest) -> Option<(String, Value)> {
+        let body = json!({
+            "model": request.model,
+            "prompt": request.prompt,
+            // no chat template, just continue the text
+            "raw": true,
+            "options": options(&request.params),
+            "stream": request.stream,
+        });
+        Some((format!("{}/api/generate", base(endpoint)), body))
+    }
+
+    fn models_url(&self, endpoint: &str) -> String {
+        format!("{}/api/tags", base(endpoint))
+    }
+
+    fn parse_models(&self, body: &Value) -> Vec<(String, Value)> {
+        body["models"].as_array()
+            .map(|models| models.iter().filter_map(|m| Some((m["name"].as_str()?.to_string(), m.clone()))).collect())
+            .unwrap_or_default()
+    }
+
+    fn model_info(&self, endpoint: &str, model: &str) -> Option<(String, Value)> {
+        Some((format!("{}/api/show", base(endpoint)), json!({ "model": model })))
+    }
+
+    /// the `num_ctx` the model is run with, or else the context of its architecture
+    fn context_length(&self, info: &Value) -> Option<u64> {
+        let parameters = info["parameters"].as_str().unwrap_or_default();
+        let num_ctx = parameters.lines()
+            .find_map(|line| line.trim().strip_prefix("num_ctx"))
+            .and_then(|value| value.trim().parse().ok());
+        num_ctx.or_else(|| info["model_info"].as_object()?.iter()
+            .find(|(key, _)| key.ends_with(".context_length"))
+            .and_then(|(_, value)| value.as_u64()))
+    }
+
+    /// a stock install needs no key, but proxies in front of one may
+    fn authorize(&self, builder: RequestBuilder, key: Option<&str>) -> RequestBuilder {
+        match key {
+            Some(key) if !key.is_empty() => builder.bearer_auth(key),
+            _ => builder,
+        }
+    }
+
+    fn framing(&self) -> Framing {
+        Framing::Ndjson
+    }
+
+    fn parse_event(&self, data: &str) -> Result<Vec<StreamEvent>> {
+        // the framing only hands over whole objects, so one that does not decode is a broken response
+        let parsed: Value = serde_json::from_str(data).map_err(|e| format!("the event is not JSON ({}): {}", e, data))?;
+        if let Some(message) = parsed["error"].as_str() {
+            return Err(format!("server error: {}", message).into());
+        }
+        let mut events = Vec::new();
+        // thinking models stream their reasoning as message.thinking
+        if let Some(text) = parsed["message"]["thinking"].as_str().or(parsed["thinking"].as_str()).filter(|t| !t.is_empty()) {
+            events.push(StreamEvent::Reasoning(text.to_string()));
+        }
+        // /api/chat streams message.content, /api/generate streams response
+        if let Some(text) = parsed["message"]["content"].as_str().or(parsed["response"].as_str()) {
+            if !text.is_empty() {
+                events.push(StreamEvent::Content(text.to_string()));
+            }
+        }
+        // tool calls arrive whole, with the arguments as an object rather than a string
+        let calls = parsed["message"]["tool_calls"].as_array().into_iter().flatten();
+        for (index, call) in calls.enumerate() {
+            let arguments = &call["function"]["arguments"];
+            events.push(StreamEvent::ToolCall(ToolCallDelta {
+                index,
+                id: call["id"].as_str().map(str::to_string),
+                name: call["function"]["name"].as_str().map(str::to_string),
+                arguments: arguments.as_str().map(str::to_string).unwrap_or_else(|| arguments.to_string()),
+            }));
+        }
+        if parsed["done"].as_bool() == Some(true) {
+            let reason = parsed["done_reason"].as_str().unwrap_or("stop");
+            events.push(StreamEvent::Finish(reason.to_string()));
+            events.push(StreamEvent::Usage(json!({
+                "prompt_tokens": parsed["prompt_eval_count"],
+                "completion_tokens": parsed["eval_count"],
+                "total_duration_ns": parsed["total_duration"],
+            })));
+            events.push(StreamEvent::Done);
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::ToolCall;
+
+    /// the events of a stream, one line at a time
+    fn parse(lines: &[&str]) -> Vec<StreamEvent> {
+        lines.iter().flat_map(|data| Ollama.parse_event(data).unwrap()).collect()
+    }
+
+    #[test]
+    fn sends_tool_call_arguments_as_objects_and_the_schema_as_format() {
+        let mut called = Message::assistant("");
+        called.tool_calls.push(ToolCall { id: "call_1".to_string(), name: "add".to_string(), arguments: r#"{"a": 1, "b": 2}"#.to_string() });
+        let mut request = ChatRequest::new("llama3.2", vec![Message::user("1 + 2?"), called]);
+        request.params.max_tokens = 64;
+        request.response_format = Some(ResponseFormat::Schema { name: "sum".to_string(), schema: json!({ "type": "integer" }) });
+        let body = Ollama.chat_body(&request);
+        assert_eq!(body["messages"][1]["tool_calls"][0]["function"]["arguments"], json!({ "a": 1, "b": 2 }));
+        assert_eq!(body["options"]["num_predict"], 64);
+        assert_eq!(body["options"]["repeat_penalty"], 1.1);
+        assert_eq!(body["format"], json!({ "type": "integer" }));
+        assert_eq!(body["stream"], true);
+    }
+
+    #[test]
+    fn parses_a_streamed_chat() {
+        let events = parse(&[
+            r#"{"model":"qwen3","created_at":"2025-05-01T10:00:00Z","message":{"role":"assistant","content":"","thinking":"Greeting."},"done":false}"#,
+            r#"{"model":"qwen3","created_at":"2025-05-01T10:00:01Z","message":{"role":"assistant","content":"Hi"},"done":false}"#,
+            r#"{"model":"qwen3","created_at":"2025-05-01T10:00:02Z","message":{"role":"assistant","content":""},"done":true,"done_reason":"stop","total_duration":5000000,"prompt_eval_count":11,"eval_count":3}"#,
+        ]);
+        assert_eq!(events, [
+            StreamEvent::Reasoning("Greeting.".to_string()),
+            StreamEvent::Content("Hi".to_string()),
+            StreamEvent::Finish("stop".to_string()),
+            StreamEvent::Usage(json!({ "prompt_tokens": 11, "completion_tokens": 3, "total_duration_ns": 5000000 })),
+            StreamEvent::Done,
+        ]);
+    }
+
+    #[test]
+    fn parses_whole_tool_calls_and_generate_responses() {
+        let called = r#"{"model":"llama3.2","message":{"role":"assistant","content":"","tool_calls":[{"function":{"name":"add","arguments":{"a":1,"b":2}}}]},"done":false}"#;
+        assert_eq!(parse(&[called]), [StreamEvent::ToolCall(ToolCallDelta {
+            index: 0,
+            id: None,
+            name: Some("add".to_string()),
+            arguments: r#"{"a":1,"b":2}"#.to_string(),
+        })]);
+        let body = json!({ "model": "llama3.2", "response": "3", "done": true, "done_reason": "length", "prompt_eval_count": 5, "eval_count": 1 });
+        assert_eq!(Ollama.parse_response(&body).unwrap(), [
+            StreamEvent::Content("3".to_string()),
+            StreamEvent::Finish("length".to_string()),
+            StreamEvent::Usage(json!({ "prompt_tokens": 5, "completion_tokens": 1, "total_duration_ns": null })),
+            StreamEvent::Done,
+        ]);
+    }
+
+    #[test]
+    fn fails_on_errors_and_broken_lines() {
+        let error = Ollama.parse_event(r#"{"error":"model 'llama9' not found"}"#).unwrap_err();
+        assert_eq!(error.to_string(), "server error: model 'llama9' not found");
+        assert!(Ollama.parse_event(r#"{"model":"llama3.2","message":"#).is_err());
+    }
+}
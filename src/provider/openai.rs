@@ -0,0 +1,233 @@
+use reqwest::RequestBuilder;
+use serde_json::Value;
+
+use super::{Provider, StreamEvent};
+use crate::client::api_url;
+use crate::logprobs::TokenLogprob;
+use crate::tools::ToolCallDelta;
+use crate::{ChatRequest, CompletionRequest, Result};
+
+/// the fragments of `tool_calls`, from a streamed delta or a whole message
+fn tool_calls(calls: &Value) -> impl Iterator<Item = StreamEvent> + '_ {
+    calls.as_array().into_iter().flatten().enumerate().map(|(i, call)| {
+        StreamEvent::ToolCall(ToolCallDelta {
+            index: call["index"].as_u64().map(|n| n as usize).unwrap_or(i),
+            id: call["id"].as_str().map(str::to_string),
+            name: call["function"]["name"].as_str().map(str::to_string),
+            arguments: call["function"]["arguments"].as_str().unwrap_or_default().to_string(),
+        })
+    })
+}
+
+/// the OpenAI chat completions API, also spoken by vllm, llama.cpp and most local servers
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OpenAi;
+
+impl Provider for OpenAi {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    /// the endpoint itself when it names a completion route, otherwise its
+    /// `/chat/completions` route
+    fn chat_url(&self, endpoint: &str, _request: &ChatRequest) -> String {
+        let endpoint = endpoint.trim_end_matches('/');
+        if endpoint.ends_with("/completions") {
+            endpoint.to_string()
+        } else {
+            api_url(endpoint, "chat/completions")
+        }
+    }
+
+    fn chat_body(&self, request: &ChatRequest) -> Value {
+        request.to_json()
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_choices(&self) -> bool {
+        true
+    }
+
+    fn supports_json(&self) -> bool {
+        true
+    }
+
+    fn supports_logprobs(&self) -> bool {
+        true
+    }
+
+    fn image_urls(&self) -> bool {
+        true
+    }
+
+    fn completion(&self, endpoint: &str, request: &CompletionRequest) -> Option<(String, Value)> {
+        Some((api_url(endpoint, "completions"), request.to_json()))
+    }
+
+    fn authorize(&self, builder: RequestBuilder, key: Option<&str>) -> RequestBuilder {
+        builder.bearer_auth(key.unwrap_or_default())
+    }
+
+    fn parse_event(&self, data: &str) -> Result<Vec<StreamEvent>> {
+        if data == "[DONE]" {
+            return Ok(vec![StreamEvent::Done]);
+        }
+        let mut events = Vec::new();
+        let parsed: Value = match serde_json::from_str(data) {
+            Ok(parsed) => parsed,
+            // tolerate servers that interleave non-JSON keepalives
+            Err(_) => return Ok(events),
+        };
+        if let Some(message) = parsed["error"]["message"].as_str().or(parsed["error"].as_str()) {
+            return Err(format!("server error: {}", message).into());
+        }
+        for (i, choice) in parsed["choices"].as_array().into_iter().flatten().enumerate() {
+            events.push(StreamEvent::Choice(choice["index"].as_u64().map(|n| n as usize).unwrap_or(i)));
+            // the probabilities go first, so they are known by the time the text is read
+            let logprobs = TokenLogprob::parse_all(&choice["logprobs"]["content"]);
+            if !logprobs.is_empty() {
+                events.push(StreamEvent::Logprobs(logprobs));
+            }
+            // DeepSeek and vllm call it reasoning_content, OpenRouter and others reasoning
+            if let Some(text) = choice["delta"]["reasoning_content"].as_str().or(choice["delta"]["reasoning"].as_str()) {
+                events.push(StreamEvent::Reasoning(text.to_string()));
+            }
+            // chat streams carry delta.content, text completions carry text
+            if let Some(text) = choice["delta"]["content"].as_str().or(choice["text"].as_str()) {
+                events.push(StreamEvent::Content(text.to_string()));
+            }
+            events.extend(tool_calls(&choice["delta"]["tool_calls"]));
+            if let Some(reason) = choice["finish_reason"].as_str() {
+                events.push(StreamEvent::Finish(reason.to_string()));
+            }
+        }
+        if parsed["usage"].is_object() {
+            events.push(StreamEvent::Usage(parsed["usage"].clone()));
+        }
+        Ok(events)
+    }
+
+    fn parse_response(&self, body: &Value) -> Result<Vec<StreamEvent>> {
+        if let Some(message) = body["error"]["message"].as_str().or(body["error"].as_str()) {
+            return Err(format!("server error: {}", message).into());
+        }
+        let mut events = Vec::new();
+        for (i, choice) in body["choices"].as_array().into_iter().flatten().enumerate() {
+            events.push(StreamEvent::Choice(choice["index"].as_u64().map(|n| n as usize).unwrap_or(i)));
+            let logprobs = TokenLogprob::parse_all(&choice["logprobs"]["content"]);
+            if !logprobs.is_empty() {
+                events.push(StreamEvent::Logprobs(logprobs));
+            }
+            if let Some(text) = choice["message"]["reasoning_content"].as_str().or(choice["message"]["reasoning"].as_str()) {
+                events.push(StreamEvent::Reasoning(text.to_string()));
+            }
+            if let Some(text) = choice["message"]["content"].as_str().or(choice["text"].as_str()) {
+                events.push(StreamEvent::Content(text.to_string()));
+            }
+            events.extend(tool_calls(&choice["message"]["tool_calls"]));
+            if let Some(reason) = choice["finish_reason"].as_str() {
+                events.push(StreamEvent::Finish(reason.to_string()));
+            }
+        }
+        if body["usage"].is_object() {
+            events.push(StreamEvent::Usage(body["usage"].clone()));
+        }
+        events.push(StreamEvent::Done);
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Message;
+    use serde_json::json;
+
+    /// the events of a stream, one payload at a time
+    fn parse(payloads: &[&str]) -> Vec<StreamEvent> {
+        payloads.iter().flat_map(|data| OpenAi.parse_event(data).unwrap()).collect()
+    }
+
+    #[test]
+    fn sends_the_request_as_it_is() {
+        let request = ChatRequest::new("gpt-4o-mini", vec![Message::user("Hi")]);
+        let body = OpenAi.chat_body(&request);
+        assert_eq!(body, request.to_json());
+        assert_eq!(body["messages"], json!([{ "role": "user", "content": "Hi" }]));
+        assert_eq!(body["stream_options"], json!({ "include_usage": true }));
+        assert!(OpenAi.chat_body(&ChatRequest::new("default", Vec::new())).get("model").is_none());
+    }
+
+    #[test]
+    fn parses_a_streamed_completion() {
+        let events = parse(&[
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","choices":[{"index":0,"delta":{"role":"assistant","content":""},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","choices":[{"index":0,"delta":{"reasoning_content":"Think."},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+            r#"{"id":"chatcmpl-1","object":"chat.completion.chunk","choices":[],"usage":{"prompt_tokens":9,"completion_tokens":2,"total_tokens":11}}"#,
+            "[DONE]",
+        ]);
+        assert_eq!(events, [
+            StreamEvent::Choice(0),
+            StreamEvent::Content(String::new()),
+            StreamEvent::Choice(0),
+            StreamEvent::Reasoning("Think.".to_string()),
+            StreamEvent::Choice(0),
+            StreamEvent::Content("Hello".to_string()),
+            StreamEvent::Choice(0),
+            StreamEvent::Finish("stop".to_string()),
+            StreamEvent::Usage(json!({ "prompt_tokens": 9, "completion_tokens": 2, "total_tokens": 11 })),
+            StreamEvent::Done,
+        ]);
+    }
+
+    #[test]
+    fn parses_streamed_tool_calls() {
+        let events = parse(&[
+            r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"get_weather","arguments":""}}]}}]}"#,
+            r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"city\":"}}]}}]}"#,
+        ]);
+        assert_eq!(events, [
+            StreamEvent::Choice(0),
+            StreamEvent::ToolCall(ToolCallDelta { index: 0, id: Some("call_1".to_string()), name: Some("get_weather".to_string()), arguments: String::new() }),
+            StreamEvent::Choice(0),
+            StreamEvent::ToolCall(ToolCallDelta { index: 0, id: None, name: None, arguments: "{\"city\":".to_string() }),
+        ]);
+    }
+
+    #[test]
+    fn skips_keepalives_and_fails_on_errors() {
+        assert_eq!(OpenAi.parse_event(": keepalive").unwrap(), []);
+        let error = OpenAi.parse_event(r#"{"error":{"message":"Rate limit reached","type":"requests"}}"#).unwrap_err();
+        assert_eq!(error.to_string(), "server error: Rate limit reached");
+    }
+
+    #[test]
+    fn parses_a_whole_completion_with_several_choices() {
+        let body = json!({
+            "id": "chatcmpl-2",
+            "object": "chat.completion",
+            "choices": [
+                { "index": 0, "message": { "role": "assistant", "content": "Yes." }, "finish_reason": "stop" },
+                { "index": 1, "message": { "role": "assistant", "content": null, "tool_calls": [
+                    { "id": "call_2", "type": "function", "function": { "name": "search", "arguments": "{\"q\":\"rust\"}" } },
+                ] }, "finish_reason": "tool_calls" },
+            ],
+            "usage": { "prompt_tokens": 20, "completion_tokens": 8, "total_tokens": 28 },
+        });
+        assert_eq!(OpenAi.parse_response(&body).unwrap(), [
+            StreamEvent::Choice(0),
+            StreamEvent::Content("Yes.".to_string()),
+            StreamEvent::Finish("stop".to_string()),
+            StreamEvent::Choice(1),
+            StreamEvent::ToolCall(ToolCallDelta { index: 0, id: Some("call_2".to_string()), name: Some("search".to_string()), arguments: "{\"q\":\"rust\"}".to_string() }),
+            StreamEvent::Finish("tool_calls".to_string()),
+            StreamEvent::Usage(json!({ "prompt_tokens": 20, "completion_tokens": 8, "total_tokens": 28 })),
+            StreamEvent::Done,
+        ]);
+    }
+}
@@ -0,0 +1,114 @@
+use reqwest::RequestBuilder;
+use serde_json::{json, Value};
+
+use super::{OpenAi, Provider, StreamEvent};
+use crate::{ChatRequest, CompletionRequest, Result};
+
+/// OpenRouter, which speaks the OpenAI API with attribution headers, routing between
+/// models and providers, and the billed cost in its usage
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OpenRouter;
+
+impl Provider for OpenRouter {
+    fn name(&self) -> &'static str {
+        "openrouter"
+    }
+
+    fn chat_url(&self, endpoint: &str, request: &ChatRequest) -> String {
+        OpenAi.chat_url(endpoint, request)
+    }
+
+    /// the OpenAI body with the request's routing, and asking for the cost in the usage
+    fn chat_body(&self, request: &ChatRequest) -> Value {
+        let mut body = OpenAi.chat_body(request);
+        if let (Some(body), Some(routing)) = (body.as_object_mut(), request.routing.as_ref().and_then(Value::as_object)) {
+            for (name, value) in routing {
+                body.insert(name.clone(), value.clone());
+            }
+        }
+        body["usage"] = json!({ "include": true });
+        body
+    }
+
+    fn supports_tools(&self) -> bool {
+        true
+    }
+
+    fn supports_choices(&self) -> bool {
+        true
+    }
+
+    fn supports_json(&self) -> bool {
+        true
+    }
+
+    fn supports_logprobs(&self) -> bool {
+        true
+    }
+
+    fn image_urls(&self) -> bool {
+        true
+    }
+
+    fn completion(&self, endpoint: &str, request: &CompletionRequest) -> Option<(String, Value)> {
+        OpenAi.completion(endpoint, request)
+    }
+
+    /// the key, and the attribution OpenRouter shows for requests from qllm, which
+    /// `[headers]` or `-H` can replace
+    fn authorize(&self, builder: RequestBuilder, key: Option<&str>) -> RequestBuilder {
+        OpenAi.authorize(builder, key)
+            .header("HTTP-Referer", "https://github.com/ekg/qllm")
+            .header("X-Title", "qllm")
+    }
+
+    fn parse_event(&self, data: &str) -> Result<Vec<StreamEvent>> {
+        // the stream is kept alive with comments, which the framing already drops
+        OpenAi.parse_event(data)
+    }
+
+    fn parse_response(&self, body: &Value) -> Result<Vec<StreamEvent>> {
+        OpenAi.parse_response(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Message;
+
+    #[test]
+    fn adds_the_routing_and_asks_for_the_cost() {
+        let mut request = ChatRequest::new("anthropic/claude-3.5-haiku", vec![Message::user("Hi")]);
+        request.routing = Some(json!({ "models": ["openai/gpt-4o-mini"], "provider": { "sort": "price" } }));
+        let body = OpenRouter.chat_body(&request);
+        assert_eq!(body["model"], "anthropic/claude-3.5-haiku");
+        assert_eq!(body["models"], json!(["openai/gpt-4o-mini"]));
+        assert_eq!(body["provider"], json!({ "sort": "price" }));
+        assert_eq!(body["usage"], json!({ "include": true }));
+        assert_eq!(body["messages"], json!([{ "role": "user", "content": "Hi" }]));
+    }
+
+    #[test]
+    fn parses_the_billed_usage() {
+        let chunk = r#"{"id":"gen-1","provider":"Anthropic","model":"anthropic/claude-3.5-haiku","object":"chat.completion.chunk","choices":[{"index":0,"delta":{"role":"assistant","content":""},"finish_reason":"stop","native_finish_reason":"end_turn"}],"usage":{"prompt_tokens":8,"completion_tokens":4,"total_tokens":12,"cost":0.0000264}}"#;
+        assert_eq!(OpenRouter.parse_event(chunk).unwrap(), [
+            StreamEvent::Choice(0),
+            StreamEvent::Content(String::new()),
+            StreamEvent::Finish("stop".to_string()),
+            StreamEvent::Usage(json!({ "prompt_tokens": 8, "completion_tokens": 4, "total_tokens": 12, "cost": 0.0000264 })),
+        ]);
+        let body = json!({
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": "Hello.", "reasoning": "Greet back." }, "finish_reason": "stop" }],
+            "usage": { "prompt_tokens": 8, "completion_tokens": 6, "cost": 0.00003 },
+        });
+        assert_eq!(OpenRouter.parse_response(&body).unwrap(), [
+            StreamEvent::Choice(0),
+            StreamEvent::Reasoning("Greet back.".to_string()),
+            StreamEvent::Content("Hello.".to_string()),
+            StreamEvent::Finish("stop".to_string()),
+            StreamEvent::Usage(json!({ "prompt_tokens": 8, "completion_tokens": 6, "cost": 0.00003 })),
+            StreamEvent::Done,
+        ]);
+    }
+}
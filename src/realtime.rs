@@ -0,0 +1,156 @@
+//! The OpenAI Realtime API, in text for now: one WebSocket held open for a whole
+//! conversation, so that each turn is a message on it rather than a new HTTP request.
+//!
+//! The server keeps the conversation, so a turn sends only the new user message and
+//! asks for a response, whose text then arrives as a stream of delta events.
+
+use reqwest::header::HeaderMap;
+use serde_json::{json, Value};
+
+use crate::{api_url, Result};
+
+/// the `wss://` url of the realtime API of an endpoint such as `https://api.openai.com/v1`
+pub fn url(endpoint: &str, model: &str) -> Result<String> {
+    let mut url = reqwest::Url::parse(&api_url(endpoint, "realtime"))
+        .map_err(|e| format!("{} is not a valid url: {}", endpoint, e))?;
+    let scheme = if url.scheme() == "http" { "ws" } else { "wss" };
+    url.set_scheme(scheme).map_err(|_| format!("{} can not be reached over a WebSocket", endpoint))?;
+    url.query_pairs_mut().append_pair("model", model);
+    Ok(url.into())
+}
+
+#[cfg(feature = "realtime")]
+type Socket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// an open realtime session
+pub struct Session {
+    #[cfg(feature = "realtime")]
+    socket: Socket,
+    /// the usage of the last complete response
+    usage: Option<Value>,
+}
+
+impl Session {
+    /// open a session, authorized with the key and sending the extra headers
+    #[cfg(feature = "realtime")]
+    pub async fn connect(url: &str, key: Option<&str>, headers: &HeaderMap) -> Result<Session> {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+        let mut request = url.into_client_request()?;
+        if let Some(key) = key.filter(|key| !key.is_empty()) {
+            request.headers_mut().insert("authorization", format!("Bearer {}", key).parse()?);
+        }
+        for (name, value) in headers {
+            request.headers_mut().insert(name, value.clone());
+        }
+        tracing::debug!(url, "opening a realtime session");
+        let (socket, _) = tokio_tungstenite::connect_async(request).await
+            .map_err(|e| format!("Could not open a realtime session at {}: {}", url, e))?;
+        Ok(Session { socket, usage: None })
+    }
+
+    #[cfg(not(feature = "realtime"))]
+    pub async fn connect(_url: &str, _key: Option<&str>, _headers: &HeaderMap) -> Result<Session> {
+        Err("This qllm was built without the realtime feature.".into())
+    }
+
+    /// ask for answers in text, following the instructions if there are any
+    pub async fn configure(&mut self, instructions: Option<&str>) -> Result<()> {
+        let mut session = json!({ "type": "realtime", "output_modalities": ["text"] });
+        if let Some(instructions) = instructions.filter(|instructions| !instructions.is_empty()) {
+            session["instructions"] = json!(instructions);
+        }
+        self.send(json!({ "type": "session.update", "session": session })).await
+    }
+
+    /// add a user message to the conversation and ask for a response to it
+    pub async fn say(&mut self, text: &str) -> Result<()> {
+        self.send(json!({
+            "type": "conversation.item.create",
+            "item": { "type": "message", "role": "user", "content": [{ "type": "input_text", "text": text }] },
+        })).await?;
+        self.usage = None;
+        self.send(json!({ "type": "response.create" })).await
+    }
+
+    /// the next piece of the response, or None once it is complete
+    pub async fn next_delta(&mut self) -> Result<Option<String>> {
+        loop {
+            let event = self.receive().await?;
+            match event["type"].as_str().unwrap_or_default() {
+                // the beta API names the text deltas differently
+                "response.output_text.delta" | "response.text.delta" => {
+                    return Ok(Some(event["delta"].as_str().unwrap_or_default().to_string()));
+                }
+                "response.done" => {
+                    let response = &event["response"];
+                    if response["status"] == "failed" {
+                        let error = &response["status_details"]["error"];
+                        let message = error["message"].as_str().or(error["code"].as_str()).unwrap_or("the response failed");
+                        return Err(format!("The realtime response failed: {}", message).into());
+                    }
+                    self.usage = Some(response["usage"].clone()).filter(|usage| !usage.is_null());
+                    return Ok(None);
+                }
+                "error" => {
+                    let message = event["error"]["message"].as_str().unwrap_or("unknown error");
+                    return Err(format!("The realtime session failed: {}", message).into());
+                }
+                other => tracing::trace!(event = other, "realtime event"),
+            }
+        }
+    }
+
+    /// the token usage of the last complete response
+    pub fn usage(&self) -> Option<&Value> {
+        self.usage.as_ref()
+    }
+
+    /// close the session politely
+    #[cfg(feature = "realtime")]
+    pub async fn close(mut self) {
+        if let Err(e) = self.socket.close(None).await {
+            tracing::debug!("could not close the realtime session: {}", e);
+        }
+    }
+
+    #[cfg(not(feature = "realtime"))]
+    pub async fn close(self) {}
+
+    #[cfg(feature = "realtime")]
+    async fn send(&mut self, event: Value) -> Result<()> {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::Message;
+        self.socket.send(Message::Text(event.to_string())).await
+            .map_err(|e| format!("Could not send to the realtime session: {}", e).into())
+    }
+
+    #[cfg(not(feature = "realtime"))]
+    async fn send(&mut self, _event: Value) -> Result<()> {
+        Err("This qllm was built without the realtime feature.".into())
+    }
+
+    /// the next event from the server, answering its pings on the way
+    #[cfg(feature = "realtime")]
+    async fn receive(&mut self) -> Result<Value> {
+        use futures_util::StreamExt;
+        use tokio_tungstenite::tungstenite::Message;
+        loop {
+            let message = self.socket.next().await
+                .ok_or("The server closed the realtime session.")?
+                .map_err(|e| format!("The realtime session failed: {}", e))?;
+            match message {
+                Message::Text(text) => return Ok(serde_json::from_str(&text)?),
+                Message::Close(frame) => {
+                    let reason = frame.map(|frame| frame.reason.to_string()).filter(|reason| !reason.is_empty());
+                    return Err(format!("The server closed the realtime session{}.", reason.map(|reason| format!(": {}", reason)).unwrap_or_default()).into());
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    #[cfg(not(feature = "realtime"))]
+    async fn receive(&mut self) -> Result<Value> {
+        Err("This qllm was built without the realtime feature.".into())
+    }
+}
@@ -0,0 +1,189 @@
+//! Render streamed markdown for the terminal with ANSI escapes.
+//!
+//! Text is rendered a line at a time as soon as each line is complete, so long
+//! answers still appear progressively. Code blocks in a known language are
+//! highlighted, see [`crate::highlight`].
+
+use crate::blocks;
+use crate::highlight::{self, Highlighter};
+use crate::theme;
+
+#[derive(Debug, Default)]
+pub struct Renderer {
+    /// the incomplete line waiting for its newline
+    line: String,
+    /// the fence that opened the current code block, if inside one
+    fence: Option<String>,
+    /// the highlighter of the current code block, if the fence names a known language
+    highlighter: Option<Highlighter>,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Renderer::default()
+    }
+
+    /// add streamed text, returning the rendering of every line it completed
+    pub fn push(&mut self, text: &str) -> String {
+        self.line.push_str(text);
+        let mut out = String::new();
+        while let Some(end) = self.line.find('\n') {
+            let line: String = self.line.drain(..=end).collect();
+            out.push_str(&self.render_line(line.trim_end_matches(['\n', '\r'])));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// render whatever is left once the stream has ended
+    pub fn finish(&mut self) -> String {
+        if self.line.is_empty() {
+            return String::new();
+        }
+        let line = std::mem::take(&mut self.line);
+        self.render_line(&line)
+    }
+
+    fn render_line(&mut self, line: &str) -> String {
+        let theme = theme::out();
+        let trimmed = line.trim_start();
+        if let Some(fence) = &self.fence {
+            if blocks::closes(line, fence) {
+                self.fence = None;
+                return format!("{}{}{}", theme.dim, line, theme.reset);
+            }
+            return match &mut self.highlighter {
+                Some(highlighter) => highlighter.line(line),
+                None => format!("{}{}{}", theme.code, line, theme.reset),
+            };
+        }
+        if let Some((fence, info)) = blocks::fence(line) {
+            self.highlighter = highlight::highlighter(info);
+            self.fence = Some(fence);
+            return format!("{}{}{}", theme.dim, line, theme.reset);
+        }
+
+        let hashes = trimmed.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+            let title = inline(trimmed[hashes..].trim());
+            return if hashes == 1 {
+                format!("{}{}{}", theme.title, title, theme.reset)
+            } else {
+                format!("{}{}{}", theme.heading, title, theme.reset)
+            };
+        }
+        if trimmed.len() >= 3 && trimmed.chars().all(|c| c == '-' || c == '*' || c == '_' || c == ' ')
+            && trimmed.chars().filter(|c| !c.is_whitespace()).collect::<std::collections::HashSet<_>>().len() == 1
+        {
+            return format!("{}{}{}", theme.dim, "─".repeat(40), theme.reset);
+        }
+        if let Some(quote) = trimmed.strip_prefix('>') {
+            return format!("{}│{} {}", theme.dim, theme.reset, inline(quote.trim_start()));
+        }
+
+        let indent = &line[..line.len() - trimmed.len()];
+        for bullet in ["- ", "* ", "+ "] {
+            if let Some(item) = trimmed.strip_prefix(bullet) {
+                return format!("{}• {}", indent, inline(item));
+            }
+        }
+        inline(line)
+    }
+}
+
+/// render the inline markup of a line: code spans, bold, italics, and links
+fn inline(text: &str) -> String {
+    let theme = theme::out();
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    let mut bold = false;
+    let mut italic = false;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c == '\\' && next.is_some_and(|n| n.is_ascii_punctuation()) {
+            out.push(chars[i + 1]);
+            i += 2;
+        } else if c == '`' {
+            match chars[i + 1..].iter().position(|&c| c == '`') {
+                Some(len) => {
+                    let code: String = chars[i + 1..i + 1 + len].iter().collect();
+                    out.push_str(&format!("{}{}{}{}", theme.code, code, theme.reset, restore(bold, italic)));
+                    i += len + 2;
+                }
+                None => {
+                    out.push(c);
+                    i += 1;
+                }
+            }
+        } else if (c == '*' || c == '_') && next == Some(c) {
+            bold = !bold;
+            out.push_str(&theme.reset);
+            out.push_str(&restore(bold, italic));
+            i += 2;
+        } else if (c == '*' || c == '_') && emphasis(&chars, i, italic) {
+            italic = !italic;
+            out.push_str(&theme.reset);
+            out.push_str(&restore(bold, italic));
+            i += 1;
+        } else if c == '[' {
+            match link(&chars[i..]) {
+                Some((label, url, len)) => {
+                    out.push_str(&format!("{}{}{}{} {}({}){}{}", theme.link, label, theme.reset, restore(bold, italic), theme.dim, url, theme.reset, restore(bold, italic)));
+                    i += len;
+                }
+                None => {
+                    out.push(c);
+                    i += 1;
+                }
+            }
+        } else {
+            out.push(c);
+            i += 1;
+        }
+    }
+    if bold || italic {
+        out.push_str(&theme.reset);
+    }
+    out
+}
+
+/// the escapes that bring back the bold and italic state after a reset
+fn restore(bold: bool, italic: bool) -> String {
+    let theme = theme::out();
+    let mut out = String::new();
+    if bold {
+        out.push_str(&theme.bold);
+    }
+    if italic {
+        out.push_str(&theme.italic);
+    }
+    out
+}
+
+/// whether a single `*` or `_` opens or closes emphasis, rather than being a literal
+/// character as in `2 * 3` or `snake_case`
+fn emphasis(chars: &[char], i: usize, open: bool) -> bool {
+    let before = if i > 0 { Some(chars[i - 1]) } else { None };
+    let after = chars.get(i + 1).copied();
+    if open {
+        before.is_some_and(|c| !c.is_whitespace()) && !after.is_some_and(char::is_alphanumeric)
+    } else {
+        after.is_some_and(|c| !c.is_whitespace())
+            && !before.is_some_and(char::is_alphanumeric)
+            && chars[i + 1..].contains(&chars[i])
+    }
+}
+
+/// a `[label](url)` link at the start of `chars`, with its length in chars
+fn link(chars: &[char]) -> Option<(String, String, usize)> {
+    let close = chars.iter().position(|&c| c == ']')?;
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = close + 1 + chars[close + 1..].iter().position(|&c| c == ')')?;
+    let label: String = chars[1..close].iter().collect();
+    let url: String = chars[close + 2..end].iter().collect();
+    Some((label, url, end + 1))
+}
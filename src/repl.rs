@@ -0,0 +1,151 @@
+use std::io::Write;
+use std::time::Instant;
+use rustyline::error::ReadlineError;
+use rustyline::history::FileHistory;
+use rustyline::config::{Behavior, Config};
+use rustyline::Editor;
+use qllm::image::Image;
+use qllm::{History, Message, QllmClient};
+
+use crate::cli::ChatArgs;
+use crate::{check_json, exchange_for, open_out, print_stream, print_tool_calls, save_blocks, spinner, ChatOptions};
+
+const HELP: &str = "commands: /exit or /quit to leave, /clear to forget the conversation, /help for this message
+end a line with \\ to continue typing on the next line; the arrow keys recall earlier turns";
+
+/// the line editor, with the turns of earlier sessions in its history
+type LineEditor = Editor<(), FileHistory>;
+
+/// run an interactive session, keeping the conversation in memory between turns
+pub async fn run(
+    client: &QllmClient,
+    args: &ChatArgs,
+    options: &ChatOptions,
+    history: &History,
+    mut conversation: u64,
+    mut messages: Vec<Message>,
+    mut images: Vec<Image>,
+) -> Result<(), qllm::Error> {
+    // an initial prompt given on the command line opens the conversation
+    let mut pending = Some(args.prompt.join(" ")).filter(|p| !p.is_empty());
+    let history_path = qllm::dirs::data_dir().join("repl_history");
+    // the prompt goes to the terminal, keeping stdout for the answers
+    let mut editor = LineEditor::with_config(Config::builder().behavior(Behavior::PreferTerm).build())?;
+    // there is no history yet on the first run
+    let _ = editor.load_history(&history_path);
+    let mut out = open_out(args, options)?;
+
+    loop {
+        let input = match pending.take() {
+            Some(input) => input,
+            None => match tokio::task::block_in_place(|| read_input(&mut editor))? {
+                Some(input) => input,
+                // end of input
+                None => break,
+            },
+        };
+        match input.trim() {
+            "" => continue,
+            "/exit" | "/quit" => break,
+            "/help" => {
+                eprintln!("{}", HELP);
+                continue;
+            }
+            "/clear" => {
+                messages = vec![Message::system(&options.system)];
+                if !args.no_history {
+                    conversation = history.new_conversation()?;
+                }
+                eprintln!("(conversation cleared)");
+                continue;
+            }
+            _ => {}
+        }
+
+        // images from the command line go with the first turn
+        messages.push(Message { images: std::mem::take(&mut images), ..Message::user(&input) });
+        let request = options.request(messages.clone());
+        if let Err(e) = options.check_budget(&request) {
+            eprintln!("error: {}", e);
+            messages.pop();
+            continue;
+        }
+
+        let started = Instant::now();
+        let timestamp = qllm::time::now();
+        spinner::start(&format!("waiting for {}", request.model));
+        match client.chat(&request).await {
+            Ok(mut stream) => match print_stream(&mut stream, options.display, options.wrap, options.show_reasoning, out.as_mut()).await {
+                Ok(answer) => {
+                    if let Some(file) = out.as_mut() {
+                        writeln!(file, "\n")?;
+                    }
+                    print_tool_calls(&stream, &answer);
+                    save_blocks(&answer, args.save_blocks.as_deref())?;
+                    println!();
+                    if let Some(Err(e)) = options.response_format.as_ref().map(|format| check_json(&answer, format)) {
+                        tracing::warn!("{}", e);
+                    }
+                    messages.push(Message::assistant(&answer));
+                    if !args.no_history {
+                        let mut exchange = exchange_for(client, &request, &answer, stream.usage(), &options.prices);
+                        exchange.conversation = conversation;
+                        exchange.timestamp = timestamp;
+                        exchange.duration_ms = started.elapsed().as_millis() as u64;
+                        history.record(&mut exchange)?;
+                    }
+                }
+                Err(e) => {
+                    // keep the session alive, but forget the turn that failed
+                    spinner::stop();
+                    eprintln!("\nerror: {}", e);
+                    messages.pop();
+                }
+            },
+            Err(e) => {
+                spinner::stop();
+                eprintln!("error: {}", e);
+                messages.pop();
+            }
+        }
+    }
+
+    if let Err(e) = std::fs::create_dir_all(qllm::dirs::data_dir()).map_err(ReadlineError::from).and_then(|_| editor.save_history(&history_path)) {
+        tracing::warn!("Could not save the history of the session to {}: {}", history_path.display(), e);
+    }
+    Ok(())
+}
+
+/// read one user turn, joining lines that end with a backslash; Ctrl-C drops what has
+/// been typed of the turn so far
+fn read_input(editor: &mut LineEditor) -> Result<Option<String>, qllm::Error> {
+    let mut input = String::new();
+    let mut prompt = "> ";
+    loop {
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                prompt = "> ";
+                continue;
+            }
+            Err(ReadlineError::Eof) if input.is_empty() => return Ok(None),
+            Err(ReadlineError::Eof) => return Ok(Some(input)),
+            Err(e) => return Err(e.into()),
+        };
+        if !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str())?;
+        }
+        match line.strip_suffix('\\') {
+            Some(continued) => {
+                input.push_str(continued);
+                input.push('\n');
+                prompt = ". ";
+            }
+            None => {
+                input.push_str(&line);
+                return Ok(Some(input));
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! A map of a repository for questions about a whole codebase: each file by name, with
+//! the signatures of what it defines under it in place of its contents.
+//!
+//! Signatures are found with tree-sitter for Rust, Python, JavaScript, TypeScript and
+//! Go; other files, and all files without the `repomap` feature, are listed by name.
+
+use std::path::Path;
+
+/// the map of `paths`, with the signatures of each under its name, indented as they nest
+pub fn map(paths: &[impl AsRef<Path>]) -> String {
+    let mut lines = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        lines.push(path.display().to_string());
+        // files that are not text are listed by name only
+        if let Ok(text) = std::fs::read_to_string(path) {
+            lines.extend(signatures(path, &text).into_iter().map(|line| format!("  {}", line)));
+        }
+    }
+    lines.join("\n")
+}
+
+/// the signatures defined in a file, one a line and indented by their nesting, or none
+/// for a language that is not known
+#[cfg(feature = "repomap")]
+pub fn signatures(path: &Path, text: &str) -> Vec<String> {
+    let Some(language) = Language::of(path) else {
+        return Vec::new();
+    };
+    let mut parser = tree_sitter::Parser::new();
+    if parser.set_language(&language.grammar()).is_err() {
+        return Vec::new();
+    }
+    let Some(tree) = parser.parse(text, None) else {
+        return Vec::new();
+    };
+    let mut lines = Vec::new();
+    visit(language, tree.root_node(), text, 0, &mut lines);
+    lines
+}
+
+#[cfg(not(feature = "repomap"))]
+pub fn signatures(_path: &Path, _text: &str) -> Vec<String> {
+    Vec::new()
+}
+
+#[cfg(feature = "repomap")]
+#[derive(Clone, Copy)]
+enum Language {
+    Rust,
+    Python,
+    JavaScript,
+    TypeScript,
+    Tsx,
+    Go,
+}
+
+#[cfg(feature = "repomap")]
+impl Language {
+    fn of(path: &Path) -> Option<Self> {
+        Some(match path.extension()?.to_str()? {
+            "rs" => Language::Rust,
+            "py" => Language::Python,
+            "js" | "mjs" | "cjs" | "jsx" => Language::JavaScript,
+            "ts" | "mts" | "cts" => Language::TypeScript,
+            "tsx" => Language::Tsx,
+            "go" => Language::Go,
+            _ => return None,
+        })
+    }
+
+    fn grammar(self) -> tree_sitter::Language {
+        match self {
+            Language::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Language::Python => tree_sitter_python::LANGUAGE.into(),
+            Language::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
+            Language::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Language::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            Language::Go => tree_sitter_go::LANGUAGE.into(),
+        }
+    }
+
+    /// the kinds of node whose signature goes in the map
+    fn definitions(self) -> &'static [&'static str] {
+        match self {
+            Language::Rust => &[
+                "function_item", "function_signature_item", "struct_item", "enum_item", "union_item", "trait_item",
+                "impl_item", "mod_item", "type_item", "const_item", "static_item", "macro_definition",
+            ],
+            Language::Python => &["function_definition", "class_definition"],
+            Language::JavaScript => &["function_declaration", "generator_function_declaration", "class_declaration", "method_definition"],
+            Language::TypeScript | Language::Tsx => &[
+                "function_declaration", "generator_function_declaration", "function_signature", "class_declaration",
+                "abstract_class_declaration", "method_definition", "method_signature", "abstract_method_signature",
+                "interface_declaration", "type_alias_declaration", "enum_declaration", "module", "internal_module",
+            ],
+            Language::Go => &["function_declaration", "method_declaration", "type_declaration"],
+        }
+    }
+
+    /// the kinds of definition whose body holds definitions of its own, such as methods
+    fn containers(self) -> &'static [&'static str] {
+        match self {
+            Language::Rust => &["impl_item", "trait_item", "mod_item"],
+            Language::Python => &["class_definition"],
+            Language::JavaScript => &["class_declaration"],
+            Language::TypeScript | Language::Tsx => &[
+                "class_declaration", "abstract_class_declaration", "interface_declaration", "module", "internal_module",
+            ],
+            Language::Go => &[],
+        }
+    }
+}
+
+/// add the signatures among the children of `node`, looking through the statements that
+/// are not definitions but not into the bodies of functions
+#[cfg(feature = "repomap")]
+fn visit(language: Language, node: tree_sitter::Node, text: &str, depth: usize, lines: &mut Vec<String>) {
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        if !language.definitions().contains(&child.kind()) {
+            visit(language, child, text, depth, lines);
+            continue;
+        }
+        lines.push(format!("{}{}", "  ".repeat(depth), signature(child, text)));
+        if language.containers().contains(&child.kind()) {
+            if let Some(body) = child.child_by_field_name("body") {
+                visit(language, body, text, depth + 1, lines);
+            }
+        }
+    }
+}
+
+/// the text of a definition up to its body or value, or its first line when it has
+/// neither, on one line
+#[cfg(feature = "repomap")]
+fn signature(node: tree_sitter::Node, text: &str) -> String {
+    let start = node.start_byte();
+    // the value of a Rust constant can be as long as a body
+    let value = || matches!(node.kind(), "const_item" | "static_item").then(|| node.child_by_field_name("value")).flatten();
+    let end = match node.child_by_field_name("body").or_else(value) {
+        Some(body) => body.start_byte(),
+        None => text[start..node.end_byte()].find('\n').map(|i| start + i).unwrap_or(node.end_byte()),
+    };
+    let signature = text[start..end].split_whitespace().collect::<Vec<_>>().join(" ");
+    signature.trim_end_matches(['{', ';', '=', ' ']).to_string()
+}
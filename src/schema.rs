@@ -0,0 +1,222 @@
+//! A JSON Schema validator covering the keywords structured output schemas use.
+//!
+//! It understands `type`, `enum`, `const`, `properties`, `required`,
+//! `additionalProperties`, `items`, `prefixItems`, the length, size, and range
+//! bounds, `uniqueItems`, `anyOf`, `oneOf`, `allOf`, and `$ref` to definitions
+//! in the same document. Unknown keywords are
+//! ignored, so a schema using them validates more loosely rather than failing.
+
+use serde_json::Value;
+
+/// the ways `value` breaks `schema`, each prefixed with the JSON pointer of the offending part
+pub fn validate(value: &Value, schema: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    check(value, schema, schema, "", &mut errors);
+    errors
+}
+
+fn check(value: &Value, schema: &Value, root: &Value, path: &str, errors: &mut Vec<String>) {
+    let at = if path.is_empty() { "/" } else { path };
+    let schema = match schema {
+        // `true` accepts anything, `false` nothing
+        Value::Bool(true) => return,
+        Value::Bool(false) => return errors.push(format!("{}: no value is allowed here", at)),
+        Value::Object(schema) => schema,
+        _ => return,
+    };
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        match resolve(root, reference) {
+            Some(target) => check(value, target, root, path, errors),
+            None => errors.push(format!("{}: can not resolve {}", at, reference)),
+        }
+    }
+
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|name| has_type(value, name)) {
+            errors.push(format!("{}: expected {}, found {}", at, types.join(" or "), type_name(value)));
+            return;
+        }
+    }
+    if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+        if !options.contains(value) {
+            errors.push(format!("{}: {} is not one of {}", at, value, Value::Array(options.clone())));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if value != expected {
+            errors.push(format!("{}: expected {}, found {}", at, expected, value));
+        }
+    }
+
+    match value {
+        Value::Object(object) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for name in schema.get("required").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+                if !object.contains_key(name) {
+                    errors.push(format!("{}: missing the required property {:?}", at, name));
+                }
+            }
+            for (name, item) in object {
+                let item_path = format!("{}/{}", path, name.replace('~', "~0").replace('/', "~1"));
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(property) => check(item, property, root, &item_path, errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => errors.push(format!("{}: the property {:?} is not allowed", at, name)),
+                        Some(additional) => check(item, additional, root, &item_path, errors),
+                        None => {}
+                    },
+                }
+            }
+            bounds(object.len() as f64, schema, "minProperties", "maxProperties", "properties", at, errors);
+        }
+        Value::Array(items) => {
+            let prefix = schema.get("prefixItems").and_then(Value::as_array);
+            for (i, item) in items.iter().enumerate() {
+                let item_path = format!("{}/{}", path, i);
+                match prefix.and_then(|prefix| prefix.get(i)) {
+                    Some(item_schema) => check(item, item_schema, root, &item_path, errors),
+                    None => {
+                        if let Some(item_schema) = schema.get("items") {
+                            check(item, item_schema, root, &item_path, errors);
+                        }
+                    }
+                }
+            }
+            bounds(items.len() as f64, schema, "minItems", "maxItems", "items", at, errors);
+            if schema.get("uniqueItems") == Some(&Value::Bool(true))
+                && items.iter().enumerate().any(|(i, item)| items[..i].contains(item))
+            {
+                errors.push(format!("{}: the items are not unique", at));
+            }
+        }
+        Value::String(text) => {
+            bounds(text.chars().count() as f64, schema, "minLength", "maxLength", "characters", at, errors);
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            let limit = |key: &str| schema.get(key).and_then(Value::as_f64);
+            if limit("minimum").is_some_and(|min| number < min) || limit("exclusiveMinimum").is_some_and(|min| number <= min) {
+                errors.push(format!("{}: {} is below the minimum", at, number));
+            }
+            if limit("maximum").is_some_and(|max| number > max) || limit("exclusiveMaximum").is_some_and(|max| number >= max) {
+                errors.push(format!("{}: {} is above the maximum", at, number));
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+        for sub in all {
+            check(value, sub, root, path, errors);
+        }
+    }
+    if let Some(any) = schema.get("anyOf").and_then(Value::as_array) {
+        if !any.iter().any(|sub| validate_at(value, sub, root, path).is_empty()) {
+            errors.push(format!("{}: matches none of the anyOf schemas", at));
+        }
+    }
+    if let Some(one) = schema.get("oneOf").and_then(Value::as_array) {
+        let matches = one.iter().filter(|sub| validate_at(value, sub, root, path).is_empty()).count();
+        if matches != 1 {
+            errors.push(format!("{}: matches {} of the oneOf schemas instead of one", at, matches));
+        }
+    }
+}
+
+fn validate_at(value: &Value, schema: &Value, root: &Value, path: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    check(value, schema, root, path, &mut errors);
+    errors
+}
+
+/// check a count against a pair of bounds such as `minItems` and `maxItems`
+fn bounds(count: f64, schema: &serde_json::Map<String, Value>, min: &str, max: &str, what: &str, at: &str, errors: &mut Vec<String>) {
+    if let Some(min) = schema.get(min).and_then(Value::as_f64).filter(|&min| count < min) {
+        errors.push(format!("{}: {} {}, fewer than {}", at, count, what, min));
+    }
+    if let Some(max) = schema.get(max).and_then(Value::as_f64).filter(|&max| count > max) {
+        errors.push(format!("{}: {} {}, more than {}", at, count, what, max));
+    }
+}
+
+/// a `#/...` reference within the schema document
+fn resolve<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    let pointer = reference.strip_prefix('#')?;
+    root.pointer(pointer)
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "number" => value.is_number(),
+        other => type_name(value) == other,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn person() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string", "minLength": 1 },
+                "age": { "type": "integer", "minimum": 0, "exclusiveMaximum": 150 },
+                "tags": { "type": "array", "items": { "$ref": "#/$defs/tag" }, "uniqueItems": true, "maxItems": 3 },
+            },
+            "required": ["name", "age"],
+            "additionalProperties": false,
+            "$defs": { "tag": { "enum": ["a", "b", "c"] } },
+        })
+    }
+
+    #[test]
+    fn accepts_a_valid_value() {
+        assert_eq!(validate(&json!({ "name": "Ada", "age": 36, "tags": ["a", "b"] }), &person()), Vec::<String>::new());
+        assert!(validate(&json!(36.0), &json!({ "type": "integer" })).is_empty());
+        assert!(validate(&json!("anything"), &json!({ "x-unknown": 1 })).is_empty());
+    }
+
+    #[test]
+    fn reports_each_error_with_its_pointer() {
+        let errors = validate(&json!({ "name": "", "age": 150, "tags": ["a", "a", "z", "b"], "extra/x": 1 }), &person());
+        assert_eq!(errors, [
+            "/age: 150 is above the maximum",
+            "/: the property \"extra/x\" is not allowed",
+            "/name: 0 characters, fewer than 1",
+            "/tags/2: \"z\" is not one of [\"a\",\"b\",\"c\"]",
+            "/tags: 4 items, more than 3",
+            "/tags: the items are not unique",
+        ]);
+        assert_eq!(validate(&json!({}), &person()), ["/: missing the required property \"name\"", "/: missing the required property \"age\""]);
+        assert_eq!(validate(&json!("36"), &json!({ "type": ["integer", "null"] })), ["/: expected integer or null, found string"]);
+    }
+
+    #[test]
+    fn combines_schemas() {
+        let schema = json!({ "oneOf": [{ "type": "string" }, { "type": "number" }, { "const": 1 }] });
+        assert!(validate(&json!("x"), &schema).is_empty());
+        assert_eq!(validate(&json!(1), &schema), ["/: matches 2 of the oneOf schemas instead of one"]);
+        assert_eq!(validate(&json!(true), &json!({ "anyOf": [{ "type": "string" }, false] })), ["/: matches none of the anyOf schemas"]);
+        assert_eq!(validate(&json!([1, "x"]), &json!({ "prefixItems": [{ "type": "number" }], "items": false })), ["/1: no value is allowed here"]);
+        assert_eq!(validate(&json!(1), &json!({ "$ref": "#/nowhere" })), ["/: can not resolve #/nowhere"]);
+    }
+}
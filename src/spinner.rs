@@ -0,0 +1,64 @@
+//! A spinner on stderr with the time waited so far, shown while a request waits for its
+//! first token, so that a model slowly reading a long prompt does not look like a hang.
+//!
+//! There is only ever one request being waited on, so the spinner is started and
+//! stopped through free functions rather than passed down to where the first token is
+//! printed. Nothing is drawn when stderr is not a terminal.
+
+use std::io::{IsTerminal, Write};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// a first token sooner than this is not worth a spinner flickering past
+const DELAY: Duration = Duration::from_millis(250);
+
+const INTERVAL: Duration = Duration::from_millis(100);
+
+static SPINNER: Mutex<Option<(Sender<()>, JoinHandle<()>)>> = Mutex::new(None);
+
+/// spin with the time since now until `stop`, saying what is waited for
+pub fn start(waiting_for: &str) {
+    if !std::io::stderr().is_terminal() {
+        return;
+    }
+    stop();
+    let (tx, rx) = mpsc::channel();
+    let label = waiting_for.to_string();
+    let started = Instant::now();
+    let thread = std::thread::spawn(move || {
+        let mut drawn = false;
+        let mut frame = 0;
+        let mut wait = DELAY;
+        while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(wait) {
+            wait = INTERVAL;
+            eprint!("\r\x1b[K{} {} {:.1}s", FRAMES[frame % FRAMES.len()], label, started.elapsed().as_secs_f64());
+            let _ = std::io::stderr().flush();
+            drawn = true;
+            frame += 1;
+        }
+        if drawn {
+            eprint!("\r\x1b[K");
+            let _ = std::io::stderr().flush();
+        }
+    });
+    *SPINNER.lock().unwrap() = Some((tx, thread));
+}
+
+/// stop the spinner, if there is one, and clear its line before anything else is printed
+pub fn stop() {
+    if let Some((tx, thread)) = SPINNER.lock().unwrap().take() {
+        let _ = tx.send(());
+        let _ = thread.join();
+    }
+}
+
+/// clear the spinner's line for a message, leaving it to be drawn again under it
+pub fn clear() {
+    if SPINNER.lock().unwrap().is_some() {
+        eprint!("\r\x1b[K");
+    }
+}
@@ -0,0 +1,164 @@
+//! A buffered parser for server-sent events.
+//!
+//! Network reads do not line up with events: one chunk may hold several events,
+//! and one event may be split across chunks. The parser decodes chunks with a
+//! [`Utf8Decoder`], buffers the text, and only processes complete lines,
+//! following the field rules of the
+//! [SSE specification](https://html.spec.whatwg.org/multipage/server-sent-events.html).
+
+use crate::utf8::Utf8Decoder;
+
+/// one dispatched event
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// the `event:` field, if any
+    pub event: Option<String>,
+    /// the `data:` fields joined with newlines
+    pub data: String,
+    /// the `id:` field, if any
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct SseParser {
+    decoder: Utf8Decoder,
+    buffer: String,
+    /// the event being assembled from the lines seen so far
+    event: Option<String>,
+    data: Vec<String>,
+    id: Option<String>,
+    has_data: bool,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        SseParser::default()
+    }
+
+    /// feed the next chunk, returning the events it completed
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<SseEvent> {
+        let text = self.decoder.decode(bytes);
+        self.buffer.push_str(&text);
+        let mut events = Vec::new();
+        while let Some(line) = self.next_line() {
+            if let Some(event) = self.process_line(&line) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// dispatch whatever is left when the stream ends without a final blank line
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        let rest = self.decoder.finish();
+        self.buffer.push_str(&rest);
+        if !self.buffer.is_empty() {
+            let line = std::mem::take(&mut self.buffer);
+            self.process_line(line.trim_end_matches('\r'));
+        }
+        self.dispatch()
+    }
+
+    /// take one complete line off the buffer; lines end in \n, \r\n, or \r
+    fn next_line(&mut self) -> Option<String> {
+        let end = self.buffer.find(['\n', '\r'])?;
+        let bytes = self.buffer.as_bytes();
+        let terminator = if bytes[end] == b'\r' {
+            match bytes.get(end + 1) {
+                Some(b'\n') => 2,
+                Some(_) => 1,
+                // a trailing \r might be the first half of \r\n, wait for more
+                None => return None,
+            }
+        } else {
+            1
+        };
+        let line = self.buffer[..end].to_string();
+        self.buffer.drain(..end + terminator);
+        Some(line)
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        // lines starting with a colon are comments, often used as keepalives
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => {
+                self.data.push(value.to_string());
+                self.has_data = true;
+            }
+            "id" => self.id = Some(value.to_string()),
+            // retry and unknown fields are ignored
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        let id = self.id.take();
+        if !std::mem::take(&mut self.has_data) {
+            return None;
+        }
+        let data = std::mem::take(&mut self.data).join("\n");
+        Some(SseEvent { event, data, id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(events: &[SseEvent]) -> Vec<&str> {
+        events.iter().map(|event| event.data.as_str()).collect()
+    }
+
+    #[test]
+    fn splits_a_chunk_into_events() {
+        let mut parser = SseParser::new();
+        let events = parser.feed(b"data: one\n\n: keepalive\n\nevent: delta\nid: 7\ndata: two\ndata: lines\n\n");
+        assert_eq!(data(&events), ["one", "two\nlines"]);
+        assert_eq!((events[1].event.as_deref(), events[1].id.as_deref()), (Some("delta"), Some("7")));
+        assert_eq!(parser.finish(), None);
+    }
+
+    #[test]
+    fn joins_events_split_across_chunks() {
+        let body = "data: {\"text\": \"héllo\"}\r\n\r\ndata:[DONE]\r\n\r\n".as_bytes();
+        let mut parser = SseParser::new();
+        let events: Vec<SseEvent> = body.iter().flat_map(|b| parser.feed(&[*b])).collect();
+        assert_eq!(data(&events), ["{\"text\": \"héllo\"}", "[DONE]"]);
+    }
+
+    #[test]
+    fn waits_to_see_whether_a_carriage_return_ends_a_line() {
+        let mut parser = SseParser::new();
+        assert!(parser.feed(b"data: a\r").is_empty());
+        assert!(parser.feed(b"\n").is_empty());
+        assert_eq!(data(&parser.feed(b"\r\r")), ["a"]);
+    }
+
+    #[test]
+    fn dispatches_an_unterminated_event_at_the_end() {
+        let mut parser = SseParser::new();
+        assert!(parser.feed(b"event: done\ndata: last").is_empty());
+        let event = parser.finish().unwrap();
+        assert_eq!((event.event.as_deref(), event.data.as_str()), (Some("done"), "last"));
+    }
+
+    #[test]
+    fn skips_events_without_data() {
+        let mut parser = SseParser::new();
+        assert!(parser.feed(b"event: ping\n\nretry: 100\n\n").is_empty());
+        assert_eq!(data(&parser.feed(b"data\n\n")), [""]);
+    }
+}
@@ -0,0 +1,206 @@
+//! Prompt templates, kept as `~/.config/qllm/templates/<name>.toml`.
+//!
+//! ```toml
+//! description = "Review a change"
+//! system = "You are a careful {{language}} reviewer."
+//! prompt = "Review this change, looking for {{focus}}:"
+//!
+//! [defaults]
+//! focus = "bugs"
+//! ```
+//!
+//! `qllm --template review -D language=Rust` fills in the `{{var}}` placeholders of the
+//! system and user prompts from `-v` values, then from the template's `[defaults]`.
+
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::parse_toml;
+use crate::{dirs, Result};
+
+#[derive(Clone, Debug, Default)]
+pub struct Template {
+    pub name: String,
+    pub description: Option<String>,
+    pub system: Option<String>,
+    pub prompt: Option<String>,
+    /// the values of variables that `-v` does not set
+    pub defaults: Map<String, Value>,
+}
+
+/// the directory templates are kept in
+pub fn dir() -> PathBuf {
+    dirs::config_dir().join("templates")
+}
+
+/// the file of the template called `name`
+pub fn path(name: &str) -> PathBuf {
+    dir().join(format!("{}.toml", name))
+}
+
+/// the names of the templates in the template directory, sorted
+pub fn names() -> Result<Vec<String>> {
+    let entries = match fs::read_dir(dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Could not read {}: {}", dir().display(), e).into()),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            if let Some(stem) = path.file_stem() {
+                names.push(stem.to_string_lossy().into_owned());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+impl Template {
+    /// read the template called `name` from the template directory
+    pub fn load(name: &str) -> Result<Self> {
+        let path = path(name);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(format!("No template named '{}' in {}.", name, dir().display()).into());
+            }
+            Err(e) => return Err(format!("Could not read {}: {}", path.display(), e).into()),
+        };
+        Template::parse(name, &text).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+
+    /// read the template called `name` if the user has one, else the one in `builtin`
+    pub fn load_or(name: &str, builtin: &str) -> Result<Self> {
+        match path(name).exists() {
+            true => Template::load(name),
+            false => Template::parse(name, builtin),
+        }
+    }
+
+    /// a template from the text of its file
+    pub fn parse(name: &str, text: &str) -> Result<Self> {
+        let table = parse_toml(text)?;
+        let string = |key: &str| match table.get(key) {
+            None => Ok(None),
+            Some(Value::String(text)) => Ok(Some(text.clone())),
+            Some(_) => Err(format!("`{}` must be a string", key)),
+        };
+        let template = Template {
+            name: name.to_string(),
+            description: string("description")?,
+            system: string("system")?,
+            prompt: string("prompt")?,
+            defaults: table.get("defaults").and_then(Value::as_object).cloned().unwrap_or_default(),
+        };
+        if template.system.is_none() && template.prompt.is_none() {
+            return Err("a template needs a `system` or a `prompt`".into());
+        }
+        Ok(template)
+    }
+
+    /// the names of the variables in the system and user prompts, in order of appearance
+    pub fn variables(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for text in self.system.iter().chain(&self.prompt) {
+            for (_, name, _) in placeholders(text) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    /// fill in the placeholders of `text` from `vars`, then from the defaults; a variable
+    /// with neither is an error
+    pub fn render(&self, text: &str, vars: &[(String, String)]) -> Result<String> {
+        let mut out = String::new();
+        let mut rest = 0;
+        for (start, name, end) in placeholders(text) {
+            let value = match vars.iter().rev().find(|(key, _)| *key == name) {
+                Some((_, value)) => value.clone(),
+                None => match self.defaults.get(&name) {
+                    Some(Value::String(value)) => value.clone(),
+                    Some(value) => value.to_string(),
+                    None => {
+                        return Err(format!("The template '{}' needs a value for {{{{{}}}}}, give one with -D {}=...", self.name, name, name).into());
+                    }
+                },
+            };
+            out.push_str(&text[rest..start]);
+            out.push_str(&value);
+            rest = end;
+        }
+        out.push_str(&text[rest..]);
+        Ok(out)
+    }
+}
+
+/// the `{{name}}` placeholders in `text`, as their start, name, and end; spaces inside
+/// the braces are allowed, and braces around anything but a plain name are left alone
+fn placeholders(text: &str) -> Vec<(usize, String, usize)> {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(open) = text[from..].find("{{").map(|i| from + i) {
+        let Some(close) = text[open + 2..].find("}}").map(|i| open + 2 + i) else { break };
+        let name = text[open + 2..close].trim();
+        if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+            found.push((open, name.to_string(), close + 2));
+            from = close + 2;
+        } else {
+            from = open + 2;
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn review() -> Template {
+        Template::parse("review", r#"
+            system = "You are a careful {{language}} reviewer."
+            prompt = "Review this change, looking for {{ focus }} in {{language}}. Leave {{not a name}} and {{}} alone."
+
+            [defaults]
+            focus = "bugs"
+            depth = 2
+        "#).unwrap()
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn fills_in_variables_then_defaults() {
+        let template = review();
+        assert_eq!(template.variables(), ["language", "focus"]);
+        let prompt = template.prompt.as_deref().unwrap();
+        assert_eq!(
+            template.render(prompt, &vars(&[("language", "Rust")])).unwrap(),
+            "Review this change, looking for bugs in Rust. Leave {{not a name}} and {{}} alone.",
+        );
+        // the last -D of a name wins, and over the default
+        let rendered = template.render(prompt, &vars(&[("language", "Go"), ("focus", "races"), ("language", "Rust")])).unwrap();
+        assert!(rendered.starts_with("Review this change, looking for races in Rust."), "{}", rendered);
+        assert_eq!(template.render("{{depth}} levels", &[]).unwrap(), "2 levels");
+    }
+
+    #[test]
+    fn fails_on_a_variable_without_a_value() {
+        let error = review().render("{{language}}", &[]).unwrap_err().to_string();
+        assert_eq!(error, "The template 'review' needs a value for {{language}}, give one with -D language=...");
+    }
+
+    #[test]
+    fn needs_a_prompt_of_some_kind() {
+        assert!(Template::parse("empty", "description = \"nothing\"").is_err());
+        assert!(Template::parse("number", "prompt = 3").is_err());
+    }
+}
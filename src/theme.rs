@@ -0,0 +1,198 @@
+//! The colours of rendered answers, code, reasoning, stats, and diffs, as ANSI escapes.
+//!
+//! Each can be changed in a `[theme]` table of the config, as words such as
+//! `"bold blue"`, `"bright-black"`, `"on-red"`, a 256-colour number like `"208"`, or
+//! `"#ff8800"`; `""` leaves the text as it is. Colour is off altogether with
+//! `--no-color`, a non-empty `NO_COLOR`, and on a stream that is not a terminal, where
+//! every escape is empty.
+
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+use qllm::Config;
+
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub reset: String,
+    /// a first-level heading
+    pub title: String,
+    /// the other headings
+    pub heading: String,
+    pub bold: String,
+    pub italic: String,
+    /// code spans, and code blocks in a language the highlighter does not know
+    pub code: String,
+    pub link: String,
+    /// fences, rules, quote bars, and the urls of links
+    pub dim: String,
+    /// the parts of highlighted code, which only the `highlight` feature colours
+    #[cfg_attr(not(feature = "highlight"), allow(dead_code))]
+    pub keyword: String,
+    #[cfg_attr(not(feature = "highlight"), allow(dead_code))]
+    pub string: String,
+    #[cfg_attr(not(feature = "highlight"), allow(dead_code))]
+    pub comment: String,
+    #[cfg_attr(not(feature = "highlight"), allow(dead_code))]
+    pub number: String,
+    /// the reasoning of thinking models, on stderr
+    pub reasoning: String,
+    /// the line of `--stats`, on stderr
+    pub stats: String,
+    /// the `---` and `+++` lines of a diff
+    pub diff_header: String,
+    pub removed: String,
+    pub added: String,
+    pub hunk: String,
+    /// tokens of `--logprobs` by how likely they were
+    pub likely: String,
+    pub unsure: String,
+    pub unlikely: String,
+}
+
+/// the names of the `[theme]` table and their default styles
+const DEFAULTS: &[(&str, &str)] = &[
+    ("title", "bold underline"),
+    ("heading", "bold"),
+    ("bold", "bold"),
+    ("italic", "italic"),
+    ("code", "cyan"),
+    ("link", "underline"),
+    ("dim", "dim"),
+    ("keyword", "magenta"),
+    ("string", "green"),
+    ("comment", "bright-black"),
+    ("number", "yellow"),
+    ("reasoning", "dim"),
+    ("stats", ""),
+    ("diff_header", "bold"),
+    ("removed", "red"),
+    ("added", "green"),
+    ("hunk", "cyan"),
+    ("likely", "green"),
+    ("unsure", "yellow"),
+    ("unlikely", "red"),
+];
+
+static STDOUT: OnceLock<Theme> = OnceLock::new();
+static STDERR: OnceLock<Theme> = OnceLock::new();
+
+/// set up the themes of stdout and stderr from `--no-color` and the config; commands
+/// that do not call it get the default colours
+pub fn init(no_color: bool, config: &Config) -> Result<(), qllm::Error> {
+    let theme = Theme::from_config(config)?;
+    let off = no_color || self::no_color();
+    let _ = STDOUT.set(if off || !std::io::stdout().is_terminal() { Theme::plain() } else { theme.clone() });
+    let _ = STDERR.set(if off || !std::io::stderr().is_terminal() { Theme::plain() } else { theme });
+    Ok(())
+}
+
+/// whether NO_COLOR asks for no colour
+fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+/// the theme of what is printed to stdout
+pub fn out() -> &'static Theme {
+    STDOUT.get_or_init(|| Theme::default_for(std::io::stdout().is_terminal()))
+}
+
+/// the theme of what is printed to stderr
+pub fn err() -> &'static Theme {
+    STDERR.get_or_init(|| Theme::default_for(std::io::stderr().is_terminal()))
+}
+
+impl Theme {
+    fn default_for(terminal: bool) -> Self {
+        match terminal && !no_color() {
+            true => Theme::from_config(&Config::default()).unwrap_or_else(|_| Theme::plain()),
+            false => Theme::plain(),
+        }
+    }
+
+    /// no colour at all
+    fn plain() -> Self {
+        Theme::from_styles(|_| Some(String::new()))
+    }
+
+    /// the defaults with the styles of the config's `[theme]` table
+    fn from_config(config: &Config) -> Result<Self, qllm::Error> {
+        let table = config.get("theme").and_then(|theme| theme.as_object());
+        if let Some(table) = table {
+            if let Some(unknown) = table.keys().find(|key| !DEFAULTS.iter().any(|(name, _)| name == key)) {
+                let names: Vec<&str> = DEFAULTS.iter().map(|(name, _)| *name).collect();
+                return Err(format!("The theme has no `{}`, only {}.", unknown, names.join(", ")).into());
+            }
+        }
+        let mut styles = Vec::new();
+        for (name, default) in DEFAULTS {
+            let style = table.and_then(|table| table.get(*name)).map(|value| value.as_str().ok_or_else(|| format!("The theme's `{}` is not a string.", name)));
+            let style = style.transpose()?.unwrap_or(default);
+            styles.push((*name, escape(style).map_err(|word| format!("The theme's `{}` has `{}`, which is not a colour or style.", name, word))?));
+        }
+        Ok(Theme::from_styles(|name| styles.iter().find(|(key, _)| *key == name).map(|(_, escape)| escape.clone())))
+    }
+
+    fn from_styles(style: impl Fn(&str) -> Option<String>) -> Self {
+        let get = |name: &str| style(name).unwrap_or_default();
+        // with nothing coloured there is nothing to reset
+        let reset = if DEFAULTS.iter().all(|(name, _)| get(name).is_empty()) { "" } else { "\x1b[0m" };
+        Theme {
+            reset: reset.to_string(),
+            title: get("title"),
+            heading: get("heading"),
+            bold: get("bold"),
+            italic: get("italic"),
+            code: get("code"),
+            link: get("link"),
+            dim: get("dim"),
+            keyword: get("keyword"),
+            string: get("string"),
+            comment: get("comment"),
+            number: get("number"),
+            reasoning: get("reasoning"),
+            stats: get("stats"),
+            diff_header: get("diff_header"),
+            removed: get("removed"),
+            added: get("added"),
+            hunk: get("hunk"),
+            likely: get("likely"),
+            unsure: get("unsure"),
+            unlikely: get("unlikely"),
+        }
+    }
+}
+
+/// the escape of a style such as `bold bright-red on-black`, or the word that is not
+/// a colour or style
+fn escape(style: &str) -> Result<String, String> {
+    const COLOURS: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+    let mut codes = Vec::new();
+    for word in style.split_whitespace() {
+        let word = word.to_lowercase();
+        let (background, colour) = match word.strip_prefix("on-") {
+            Some(colour) => (true, colour),
+            None => (false, word.as_str()),
+        };
+        let base = if background { 40 } else { 30 };
+        let code = match colour {
+            "bold" if !background => "1".to_string(),
+            "dim" if !background => "2".to_string(),
+            "italic" if !background => "3".to_string(),
+            "underline" if !background => "4".to_string(),
+            "reverse" if !background => "7".to_string(),
+            "gray" | "grey" => (base + 60).to_string(),
+            _ if COLOURS.contains(&colour) => (base + COLOURS.iter().position(|c| *c == colour).unwrap_or_default()).to_string(),
+            _ if colour.starts_with("bright-") && COLOURS.contains(&&colour[7..]) => {
+                (base + 60 + COLOURS.iter().position(|c| *c == &colour[7..]).unwrap_or_default()).to_string()
+            }
+            _ if colour.parse::<u8>().is_ok() => format!("{};5;{}", base + 8, colour),
+            _ if colour.len() == 7 && colour.starts_with('#') && u32::from_str_radix(&colour[1..], 16).is_ok() => {
+                let rgb = u32::from_str_radix(&colour[1..], 16).unwrap_or_default();
+                format!("{};2;{};{};{}", base + 8, rgb >> 16, (rgb >> 8) & 0xff, rgb & 0xff)
+            }
+            _ => return Err(word.to_string()),
+        };
+        codes.push(code);
+    }
+    Ok(if codes.is_empty() { String::new() } else { format!("\x1b[{}m", codes.join(";")) })
+}
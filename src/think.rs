@@ -0,0 +1,149 @@
+//! Split a leading `<think>…</think>` span out of streamed answer text.
+//!
+//! Reasoning models served by llama.cpp, vllm, and Ollama's older releases put
+//! their chain of thought inline in the content, ahead of the answer. Only a tag
+//! at the start of the content counts, so an answer that writes about `<think>`
+//! tags is left as it is. A tag may be split across deltas, so a piece that could
+//! be the start of one is held back until the next delta shows whether it is.
+
+use crate::StreamEvent;
+
+const OPEN: &str = "<think>";
+const CLOSE: &str = "</think>";
+
+/// how far into the content the splitter is
+#[derive(Debug, Default, PartialEq)]
+enum State {
+    /// nothing but whitespace yet, so the content may still open with a tag
+    #[default]
+    Start,
+    /// inside the leading span, until its closing tag
+    Thinking,
+    /// the answer, which is passed on as it is
+    Answer,
+}
+
+/// turns content into `Content` and `Reasoning` events
+#[derive(Debug, Default)]
+pub struct ThinkSplitter {
+    state: State,
+    /// text held back because it may be the start of a tag
+    pending: String,
+}
+
+impl ThinkSplitter {
+    pub fn new() -> Self {
+        ThinkSplitter::default()
+    }
+
+    /// split the next piece of content
+    pub fn push(&mut self, text: &str, events: &mut Vec<StreamEvent>) {
+        self.pending.push_str(text);
+        if self.state == State::Start {
+            let start = self.pending.trim_start();
+            if start.starts_with(OPEN) {
+                let at = self.pending.len() - start.len();
+                self.pending.drain(..at + OPEN.len());
+                self.state = State::Thinking;
+            } else if OPEN.starts_with(start) {
+                return;
+            } else {
+                self.state = State::Answer;
+            }
+        }
+        if self.state == State::Thinking {
+            match self.pending.find(CLOSE) {
+                Some(at) => {
+                    let thought = self.pending[..at].to_string();
+                    self.pending.drain(..at + CLOSE.len());
+                    self.emit(thought, events);
+                    self.state = State::Answer;
+                }
+                None => {
+                    // keep the longest tail that is a prefix of the tag
+                    let keep = (1..CLOSE.len()).rev()
+                        .find(|&n| self.pending.ends_with(&CLOSE[..n]))
+                        .unwrap_or(0);
+                    let thought = self.pending[..self.pending.len() - keep].to_string();
+                    self.pending.drain(..self.pending.len() - keep);
+                    self.emit(thought, events);
+                    return;
+                }
+            }
+        }
+        let text = std::mem::take(&mut self.pending);
+        self.emit(text, events);
+    }
+
+    /// emit whatever was held back, once the stream has ended
+    pub fn finish(&mut self, events: &mut Vec<StreamEvent>) {
+        let text = std::mem::take(&mut self.pending);
+        self.emit(text, events);
+    }
+
+    fn emit(&self, text: String, events: &mut Vec<StreamEvent>) {
+        if text.is_empty() {
+            return;
+        }
+        events.push(if self.state == State::Thinking { StreamEvent::Reasoning(text) } else { StreamEvent::Content(text) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the events of the deltas, with neighbouring events of a kind joined
+    fn split(deltas: &[&str]) -> Vec<StreamEvent> {
+        let mut splitter = ThinkSplitter::new();
+        let mut events = Vec::new();
+        for delta in deltas {
+            splitter.push(delta, &mut events);
+        }
+        splitter.finish(&mut events);
+        let mut joined: Vec<StreamEvent> = Vec::new();
+        for event in events {
+            match (joined.last_mut(), event) {
+                (Some(StreamEvent::Content(last)), StreamEvent::Content(text)) | (Some(StreamEvent::Reasoning(last)), StreamEvent::Reasoning(text)) => last.push_str(&text),
+                (_, event) => joined.push(event),
+            }
+        }
+        joined
+    }
+
+    fn reasoning(text: &str) -> StreamEvent {
+        StreamEvent::Reasoning(text.to_string())
+    }
+
+    fn content(text: &str) -> StreamEvent {
+        StreamEvent::Content(text.to_string())
+    }
+
+    #[test]
+    fn splits_a_leading_span() {
+        assert_eq!(split(&["<think>Easy.</think>4"]), [reasoning("Easy."), content("4")]);
+        assert_eq!(split(&["\n<think>Easy.</think>\n\n4"]), [reasoning("Easy."), content("\n\n4")]);
+    }
+
+    #[test]
+    fn finds_tags_split_across_deltas() {
+        let whole = "<think>Add them.</think>The sum is 4.";
+        for at in 1..whole.len() {
+            let (first, second) = whole.split_at(at);
+            assert_eq!(split(&[first, second]), [reasoning("Add them."), content("The sum is 4.")], "split at {}", at);
+        }
+        assert_eq!(split(&["<", "th", "ink", ">", "Hm.", "</", "thi", "nk", ">", "Yes."]), [reasoning("Hm."), content("Yes.")]);
+    }
+
+    #[test]
+    fn leaves_tags_after_the_start_in_the_answer() {
+        assert_eq!(split(&["Models write <think>", "…</think> first."]), [content("Models write <think>…</think> first.")]);
+        assert_eq!(split(&["<think>A</think>B <think>C</think>"]), [reasoning("A"), content("B <think>C</think>")]);
+    }
+
+    #[test]
+    fn gives_back_what_was_held_when_the_stream_ends() {
+        assert_eq!(split(&["<thi"]), [content("<thi")]);
+        assert_eq!(split(&["<think>Still going</th"]), [reasoning("Still going</th")]);
+    }
+}
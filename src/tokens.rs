@@ -0,0 +1,316 @@
+//! Counting the tokens of text the way a model's tokenizer would.
+//!
+//! OpenAI models are counted with their tiktoken encoding. Other models need a
+//! Hugging Face `tokenizer.json`, named per model in the config:
+//!
+//! ```toml
+//! [tokenizers]
+//! "llama-3.1-8b-instruct" = "~/models/llama-3.1-8b/tokenizer.json"
+//! ```
+//!
+//! Models with neither are estimated at four characters a token.
+
+use serde_json::Value;
+use std::path::PathBuf;
+
+use crate::{dirs, pricing, Config, Result};
+
+/// the tiktoken encodings that can be named instead of a model
+pub const ENCODINGS: &[&str] = &["o200k_base", "o200k_harmony", "cl100k_base", "p50k_base", "p50k_edit", "r50k_base", "gpt2"];
+
+/// which part of a text [`Tokenizer::truncate`] cuts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cut {
+    /// the start, keeping the end
+    Head,
+    /// the end, keeping the start
+    Tail,
+    /// the middle, keeping both ends
+    Middle,
+}
+
+/// stands in for the text cut out of the middle
+pub const ELISION: &str = "\n[...]\n";
+
+/// how the tokens of a model are counted
+pub enum Tokenizer {
+    #[cfg(feature = "tiktoken")]
+    Tiktoken(&'static str, &'static tiktoken_rs::CoreBPE),
+    #[cfg(feature = "tokenizers")]
+    HuggingFace(PathBuf, Box<tokenizers::Tokenizer>),
+    /// four characters a token, for models with no known tokenizer
+    Estimate,
+}
+
+impl Tokenizer {
+    /// the tokenizer named by `name`, either a tiktoken encoding or the path of a
+    /// `tokenizer.json`
+    pub fn named(name: &str) -> Result<Self> {
+        if ENCODINGS.contains(&name) {
+            return tiktoken_encoding(name);
+        }
+        hugging_face(dirs::expand_home(name))
+    }
+
+    /// the tokenizer of `model`: the `tokenizer.json` given for it in the config's
+    /// `[tokenizers]` table, else its tiktoken encoding, else the estimate
+    pub fn for_model(model: &str, config: &Config) -> Result<Self> {
+        if let Some(path) = configured(model, config.get("tokenizers")) {
+            return hugging_face(dirs::expand_home(&path));
+        }
+        // OpenRouter and others put the vendor in front, as in openai/gpt-4o
+        let bare = model.rsplit('/').next().unwrap_or(model);
+        match tiktoken_model(bare) {
+            Some(encoding) => tiktoken_encoding(encoding),
+            None => Ok(Tokenizer::Estimate),
+        }
+    }
+
+    /// a description of the tokenizer, for telling the user what was counted with
+    pub fn describe(&self) -> String {
+        match self {
+            #[cfg(feature = "tiktoken")]
+            Tokenizer::Tiktoken(encoding, _) => format!("tiktoken {}", encoding),
+            #[cfg(feature = "tokenizers")]
+            Tokenizer::HuggingFace(path, _) => path.display().to_string(),
+            Tokenizer::Estimate => "an estimate of four characters a token".to_string(),
+        }
+    }
+
+    /// whether the counts are exact rather than estimated
+    pub fn exact(&self) -> bool {
+        !matches!(self, Tokenizer::Estimate)
+    }
+
+    /// the number of tokens in `text`, without any special tokens around it
+    pub fn count(&self, text: &str) -> u64 {
+        match self {
+            #[cfg(feature = "tiktoken")]
+            Tokenizer::Tiktoken(_, bpe) => bpe.encode_ordinary(text).len() as u64,
+            #[cfg(feature = "tokenizers")]
+            Tokenizer::HuggingFace(_, tokenizer) => match tokenizer.encode(text, false) {
+                Ok(encoding) => encoding.len() as u64,
+                Err(_) => pricing::estimate_tokens(text),
+            },
+            Tokenizer::Estimate => pricing::estimate_tokens(text),
+        }
+    }
+
+    /// `text` split into pieces of at most about `max` tokens, at line breaks unless a
+    /// single line is longer than that
+    pub fn chunks(&self, text: &str, max: u64) -> Vec<String> {
+        let max = max.max(1);
+        let mut chunks = Vec::new();
+        let mut chunk = String::new();
+        let mut tokens = 0;
+        for line in text.split_inclusive('\n') {
+            let count = self.count(line);
+            if tokens + count > max && !chunk.is_empty() {
+                chunks.push(std::mem::take(&mut chunk));
+                tokens = 0;
+            }
+            if count > max {
+                // a line too long for a chunk of its own is cut into pieces by characters
+                let chars: Vec<char> = line.chars().collect();
+                let size = ((chars.len() as u64 * max / count) as usize).max(1);
+                chunks.extend(chars.chunks(size).map(|piece| piece.iter().collect::<String>()));
+                continue;
+            }
+            chunk.push_str(line);
+            tokens += count;
+        }
+        if !chunk.trim().is_empty() {
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    /// `text` cut down to at most `keep` tokens, at line breaks where there are any nearby
+    pub fn truncate(&self, text: &str, keep: u64, cut: Cut) -> String {
+        let mut chars = text.chars().count();
+        let mut kept = text.to_string();
+        for _ in 0..8 {
+            let count = self.count(&kept);
+            if count <= keep {
+                break;
+            }
+            // shrink in proportion to the tokens over, and a little more so that it converges
+            chars = (chars as u64 * keep / count) as usize * 19 / 20;
+            kept = cut_chars(text, chars, cut);
+        }
+        kept
+    }
+}
+
+/// `text` cut down to `keep` characters
+fn cut_chars(text: &str, keep: usize, cut: Cut) -> String {
+    let total = text.chars().count();
+    if keep >= total {
+        return text.to_string();
+    }
+    let byte = |chars: usize| text.char_indices().nth(chars).map(|(i, _)| i).unwrap_or(text.len());
+    match cut {
+        Cut::Head => {
+            let start = byte(total - keep);
+            // start on a line of its own if one begins early in what is kept
+            match text[start..].find('\n') {
+                Some(i) if i < keep / 4 => text[start + i + 1..].to_string(),
+                _ => text[start..].to_string(),
+            }
+        }
+        Cut::Tail => {
+            let end = byte(keep);
+            match text[..end].rfind('\n') {
+                Some(i) if end - i < keep / 4 => text[..i].to_string(),
+                _ => text[..end].to_string(),
+            }
+        }
+        Cut::Middle => {
+            let start = cut_chars(text, keep / 2, Cut::Tail);
+            let end = cut_chars(text, keep - keep / 2, Cut::Head);
+            format!("{}{}{}", start, ELISION, end)
+        }
+    }
+}
+
+/// the `tokenizer.json` configured for a model: an exact name first, then the longest
+/// name contained in the model's, as with prices
+fn configured(model: &str, table: Option<&Value>) -> Option<String> {
+    let table = table?.as_object()?;
+    if let Some(path) = table.get(model).and_then(Value::as_str) {
+        return Some(path.to_string());
+    }
+    table.iter()
+        .filter(|(name, _)| model.contains(name.as_str()))
+        .max_by_key(|(name, _)| name.len())
+        .and_then(|(_, path)| path.as_str())
+        .map(str::to_string)
+}
+
+#[cfg(feature = "tiktoken")]
+fn tiktoken_model(model: &str) -> Option<&'static str> {
+    use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer as Encoding};
+    Some(match get_tokenizer(model)? {
+        Encoding::O200kHarmony => "o200k_harmony",
+        Encoding::O200kBase => "o200k_base",
+        Encoding::Cl100kBase => "cl100k_base",
+        Encoding::P50kBase => "p50k_base",
+        Encoding::R50kBase => "r50k_base",
+        Encoding::P50kEdit => "p50k_edit",
+        Encoding::Gpt2 => "gpt2",
+    })
+}
+
+#[cfg(not(feature = "tiktoken"))]
+fn tiktoken_model(_model: &str) -> Option<&'static str> {
+    None
+}
+
+#[cfg(feature = "tiktoken")]
+fn tiktoken_encoding(name: &str) -> Result<Tokenizer> {
+    use tiktoken_rs::tokenizer::Tokenizer as Encoding;
+    let (name, encoding) = match name {
+        "o200k_harmony" => ("o200k_harmony", Encoding::O200kHarmony),
+        "o200k_base" => ("o200k_base", Encoding::O200kBase),
+        "cl100k_base" => ("cl100k_base", Encoding::Cl100kBase),
+        "p50k_base" => ("p50k_base", Encoding::P50kBase),
+        "p50k_edit" => ("p50k_edit", Encoding::P50kEdit),
+        "r50k_base" => ("r50k_base", Encoding::R50kBase),
+        "gpt2" => ("gpt2", Encoding::Gpt2),
+        _ => return Err(format!("Unknown tiktoken encoding '{}', expected one of: {}.", name, ENCODINGS.join(", ")).into()),
+    };
+    let bpe = tiktoken_rs::bpe_for_tokenizer(encoding).map_err(|e| format!("Could not load {}: {}", name, e))?;
+    Ok(Tokenizer::Tiktoken(name, bpe))
+}
+
+#[cfg(not(feature = "tiktoken"))]
+fn tiktoken_encoding(name: &str) -> Result<Tokenizer> {
+    Err(format!("Counting with {} needs qllm built with the `tiktoken` feature.", name).into())
+}
+
+#[cfg(feature = "tokenizers")]
+fn hugging_face(path: PathBuf) -> Result<Tokenizer> {
+    let tokenizer = tokenizers::Tokenizer::from_file(&path)
+        .map_err(|e| format!("Could not load the tokenizer {}: {}", path.display(), e))?;
+    Ok(Tokenizer::HuggingFace(path, Box::new(tokenizer)))
+}
+
+#[cfg(not(feature = "tokenizers"))]
+fn hugging_face(path: PathBuf) -> Result<Tokenizer> {
+    Err(format!("Counting with {} needs qllm built with the `tokenizers` feature.", path.display()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn finds_the_tokenizer_configured_for_a_model() {
+        let table = json!({ "llama-3.1": "~/llama.json", "llama-3.1-8b": "~/llama-8b.json", "qwen": 3 });
+        assert_eq!(configured("llama-3.1-8b-instruct", Some(&table)).as_deref(), Some("~/llama-8b.json"));
+        assert_eq!(configured("llama-3.1-70b", Some(&table)).as_deref(), Some("~/llama.json"));
+        assert_eq!(configured("qwen2.5", Some(&table)), None);
+        assert_eq!(configured("llama-3.1", None), None);
+    }
+
+    #[test]
+    fn estimates_models_without_a_tokenizer() {
+        let tokenizer = Tokenizer::for_model("mistral-7b-instruct", &Config::default()).unwrap();
+        assert!(!tokenizer.exact());
+        assert_eq!(tokenizer.count("twelve chars"), 3);
+    }
+
+    #[cfg(feature = "tiktoken")]
+    #[test]
+    fn counts_openai_models_with_tiktoken() {
+        let tokenizer = Tokenizer::for_model("openai/gpt-4o-mini", &Config::default()).unwrap();
+        assert_eq!(tokenizer.describe(), "tiktoken o200k_base");
+        assert_eq!(tokenizer.count("hello world"), 2);
+        assert_eq!(Tokenizer::named("cl100k_base").unwrap().count("hello world"), 2);
+        assert!(Tokenizer::named("cl200k_base").is_err());
+    }
+
+    /// twenty numbered lines of twenty characters, about a hundred tokens estimated
+    fn lines() -> String {
+        (1..=20).map(|n| format!("line {:02} of the text\n", n)).collect()
+    }
+
+    #[test]
+    fn cuts_the_head_keeping_whole_lines_at_the_end() {
+        let kept = Tokenizer::Estimate.truncate(&lines(), 30, Cut::Head);
+        assert!(Tokenizer::Estimate.count(&kept) <= 30, "{}", kept);
+        assert!(kept.starts_with("line ") && kept.ends_with("line 20 of the text\n"), "{}", kept);
+    }
+
+    #[test]
+    fn cuts_the_tail_keeping_whole_lines_at_the_start() {
+        let kept = Tokenizer::Estimate.truncate(&lines(), 30, Cut::Tail);
+        assert!(Tokenizer::Estimate.count(&kept) <= 30, "{}", kept);
+        assert!(kept.starts_with("line 01 of the text\n") && kept.ends_with(" of the text"), "{}", kept);
+    }
+
+    #[test]
+    fn cuts_the_middle_keeping_both_ends() {
+        let kept = Tokenizer::Estimate.truncate(&lines(), 30, Cut::Middle);
+        assert!(Tokenizer::Estimate.count(&kept) <= 30, "{}", kept);
+        let (start, end) = kept.split_once(ELISION).unwrap();
+        assert!(start.starts_with("line 01 ") && end.ends_with("line 20 of the text\n"), "{}", kept);
+    }
+
+    #[test]
+    fn leaves_text_that_fits_alone() {
+        for cut in [Cut::Head, Cut::Tail, Cut::Middle] {
+            assert_eq!(Tokenizer::Estimate.truncate(&lines(), 1000, cut), lines());
+        }
+        assert_eq!(cut_chars("naïve café", 4, Cut::Head), "café");
+    }
+
+    #[test]
+    fn chunks_at_line_breaks() {
+        let text = "aaaa\nbbbb\ncccc\ndddd\n";
+        assert_eq!(Tokenizer::Estimate.chunks(text, 4), ["aaaa\nbbbb\n", "cccc\ndddd\n"]);
+        // a line longer than a chunk is cut by characters
+        assert_eq!(Tokenizer::Estimate.chunks("abcdefghijklmnop", 2), ["abcdefgh", "ijklmnop"]);
+    }
+}
@@ -0,0 +1,176 @@
+//! OpenAI-style tool (function) definitions and the calls a model makes to them.
+//!
+//! Streamed tool calls arrive as fragments: the first delta of a call carries its
+//! id and name, later ones append pieces of the JSON arguments. [`ToolCalls`]
+//! stitches the fragments back together by index.
+
+use serde_json::{json, Value};
+use std::path::Path;
+
+use crate::Result;
+
+/// one fragment of a streamed tool call
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ToolCallDelta {
+    /// which call the fragment belongs to when several are made at once
+    pub index: usize,
+    pub id: Option<String>,
+    pub name: Option<String>,
+    /// the next piece of the JSON encoded arguments
+    pub arguments: String,
+}
+
+/// a complete call of a tool by the model
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    /// the arguments as the model wrote them, normally a JSON object
+    pub arguments: String,
+}
+
+impl ToolCall {
+    /// the call in the OpenAI `tool_calls` shape
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "type": "function",
+            "function": { "name": self.name, "arguments": self.arguments },
+        })
+    }
+
+    /// the arguments parsed as JSON, or an empty object when there are none
+    pub fn parsed_arguments(&self) -> Result<Value> {
+        if self.arguments.trim().is_empty() {
+            return Ok(json!({}));
+        }
+        serde_json::from_str(&self.arguments)
+            .map_err(|e| format!("the arguments of the {} call are not JSON ({}): {}", self.name, e, self.arguments).into())
+    }
+}
+
+/// accumulates streamed tool call fragments into whole calls
+#[derive(Clone, Debug, Default)]
+pub struct ToolCalls {
+    calls: Vec<ToolCall>,
+}
+
+impl ToolCalls {
+    pub fn push(&mut self, delta: &ToolCallDelta) {
+        if self.calls.len() <= delta.index {
+            self.calls.resize_with(delta.index + 1, ToolCall::default);
+        }
+        let call = &mut self.calls[delta.index];
+        if let Some(id) = &delta.id {
+            call.id.clone_from(id);
+        }
+        // the name normally arrives whole, but some servers split it too
+        if let Some(name) = &delta.name {
+            call.name.push_str(name);
+        }
+        call.arguments.push_str(&delta.arguments);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// the calls so far, with ids made up for servers that do not send them
+    pub fn calls(&self) -> Vec<ToolCall> {
+        self.calls.iter().enumerate()
+            .filter(|(_, call)| !call.name.is_empty())
+            .map(|(i, call)| ToolCall {
+                id: if call.id.is_empty() { format!("call_{}", i) } else { call.id.clone() },
+                ..call.clone()
+            })
+            .collect()
+    }
+}
+
+/// read tool definitions from a JSON file holding an array of them or a `{"tools": [...]}`
+/// object; bare function definitions are wrapped as `{"type": "function", "function": ...}`
+pub fn load(path: &Path) -> Result<Vec<Value>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read the tools file {}: {}", path.display(), e))?;
+    let parsed: Value = serde_json::from_str(&text)
+        .map_err(|e| format!("the tools file {} is not valid JSON: {}", path.display(), e))?;
+    let tools = match parsed {
+        Value::Array(tools) => tools,
+        Value::Object(ref object) if object.contains_key("tools") => {
+            parsed["tools"].as_array().cloned().ok_or("`tools` in the tools file must be an array")?
+        }
+        tool @ Value::Object(_) => vec![tool],
+        _ => return Err(format!("the tools file {} must hold an array of tool definitions", path.display()).into()),
+    };
+    tools.into_iter()
+        .map(|tool| {
+            if tool["type"].is_string() {
+                Ok(tool)
+            } else if tool["name"].is_string() {
+                Ok(json!({ "type": "function", "function": tool }))
+            } else {
+                Err(format!("{} is not a tool definition", tool).into())
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delta(index: usize, id: Option<&str>, name: Option<&str>, arguments: &str) -> ToolCallDelta {
+        ToolCallDelta { index, id: id.map(str::to_string), name: name.map(str::to_string), arguments: arguments.to_string() }
+    }
+
+    fn call(id: &str, name: &str, arguments: &str) -> ToolCall {
+        ToolCall { id: id.to_string(), name: name.to_string(), arguments: arguments.to_string() }
+    }
+
+    #[test]
+    fn joins_arguments_split_across_deltas() {
+        let mut calls = ToolCalls::default();
+        for fragment in [delta(0, Some("call_a"), Some("get_weather"), ""), delta(0, None, None, "{\"ci"), delta(0, None, None, "ty\": \"Par"), delta(0, None, None, "is\"}")] {
+            calls.push(&fragment);
+        }
+        assert_eq!(calls.calls(), [call("call_a", "get_weather", "{\"city\": \"Paris\"}")]);
+        assert_eq!(calls.calls()[0].parsed_arguments().unwrap(), json!({ "city": "Paris" }));
+    }
+
+    #[test]
+    fn merges_interleaved_calls_by_index() {
+        let mut calls = ToolCalls::default();
+        for fragment in [
+            delta(1, Some("call_b"), Some("get_time"), "{\"zone\":"),
+            delta(0, Some("call_a"), Some("get_weather"), "{\"city\":"),
+            delta(1, None, None, " \"CET\"}"),
+            delta(0, None, None, " \"Oslo\"}"),
+        ] {
+            calls.push(&fragment);
+        }
+        assert_eq!(calls.calls(), [
+            call("call_a", "get_weather", "{\"city\": \"Oslo\"}"),
+            call("call_b", "get_time", "{\"zone\": \"CET\"}"),
+        ]);
+    }
+
+    #[test]
+    fn takes_an_id_and_name_that_arrive_late() {
+        let mut calls = ToolCalls::default();
+        calls.push(&delta(0, None, None, "{\"q\": "));
+        assert!(calls.calls().is_empty(), "a call without a name is not complete yet");
+        calls.push(&delta(0, None, Some("sea"), "\"rust\"}"));
+        calls.push(&delta(0, Some("call_late"), Some("rch"), ""));
+        assert_eq!(calls.calls(), [call("call_late", "search", "{\"q\": \"rust\"}")]);
+    }
+
+    #[test]
+    fn makes_up_ids_the_server_left_out() {
+        let mut calls = ToolCalls::default();
+        calls.push(&delta(0, None, Some("now"), ""));
+        calls.push(&delta(1, None, Some("today"), "{}"));
+        let ids: Vec<String> = calls.calls().into_iter().map(|call| call.id).collect();
+        assert_eq!(ids, ["call_0", "call_1"]);
+        assert_eq!(calls.calls()[0].parsed_arguments().unwrap(), json!({}));
+    }
+}
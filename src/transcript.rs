@@ -0,0 +1,71 @@
+//! A plain-text transcript of a conversation that keeps the role of every message.
+//!
+//! `qllm --recurse` prints one and `qllm -c` reads one back from stdin as the earlier
+//! messages of the conversation, so that qllm can be piped into itself. Every message
+//! starts with its role on a line of its own, e.g. `<|user|>`.
+
+use crate::client::Message;
+
+/// the roles a transcript can hold, tool calls and their results are left out
+const ROLES: &[&str] = &["system", "user", "assistant"];
+
+/// the line that starts a message of `role`
+pub fn marker(role: &str) -> String {
+    format!("<|{}|>", role)
+}
+
+/// the role a marker line starts, if it is one
+fn role_of(line: &str) -> Option<&'static str> {
+    let role = line.trim_end().strip_prefix("<|")?.strip_suffix("|>")?;
+    ROLES.iter().copied().find(|known| *known == role)
+}
+
+/// the transcript of `messages`
+pub fn format(messages: &[Message]) -> String {
+    messages.iter()
+        .filter(|m| ROLES.contains(&m.role.as_str()))
+        .map(|m| format!("{}\n{}\n", marker(&m.role), m.content))
+        .collect()
+}
+
+/// the messages of a transcript, or None if the text is not one, because it does not
+/// start with a marker line
+pub fn parse(text: &str) -> Option<Vec<Message>> {
+    let mut messages: Vec<Message> = Vec::new();
+    for line in text.split_inclusive('\n') {
+        match role_of(line) {
+            Some(role) => messages.push(Message::new(role, "")),
+            None if messages.is_empty() && line.trim().is_empty() => {}
+            None => messages.last_mut()?.content.push_str(line),
+        }
+    }
+    for message in &mut messages {
+        message.content = message.content.trim_end().to_string();
+    }
+    (!messages.is_empty()).then_some(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let messages = vec![Message::system("be brief"), Message::user("two\n\nparagraphs"), Message::assistant("ok")];
+        let text = format(&messages);
+        assert_eq!(text, "<|system|>\nbe brief\n<|user|>\ntwo\n\nparagraphs\n<|assistant|>\nok\n");
+        assert_eq!(parse(&text), Some(messages));
+    }
+
+    #[test]
+    fn leaves_out_tool_messages() {
+        assert_eq!(format(&[Message::user("hi"), Message::tool("1", "result")]), "<|user|>\nhi\n");
+    }
+
+    #[test]
+    fn is_not_a_transcript_without_a_marker_first() {
+        assert_eq!(parse("plain text\n<|user|>\nhi\n"), None);
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("\n<|user|>\n<|tool|>\n"), Some(vec![Message::user("<|tool|>")]));
+    }
+}
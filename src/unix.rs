@@ -0,0 +1,63 @@
+//! HTTP over Unix domain sockets, for local inference servers that listen on a socket
+//! file rather than a port, as they often do in containers.
+//!
+//! `unix:///run/llama.sock/v1` is the socket `/run/llama.sock` and the path `/v1` on
+//! it: the socket is the first part of the path that is not a directory, and requests
+//! are built for `http://localhost/v1` as for any other endpoint, then sent over the
+//! socket by hyper instead of over TCP by reqwest.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::Result;
+
+/// the socket and the http endpoint standing in for a `unix://` endpoint, or None for
+/// any other endpoint
+pub fn split(endpoint: &str) -> Option<(PathBuf, String)> {
+    let path = endpoint.strip_prefix("unix://")?;
+    let parts: Vec<&str> = path.split('/').collect();
+    for end in 1..=parts.len() {
+        let socket = parts[..end].join("/");
+        if !socket.is_empty() && !Path::new(&socket).is_dir() {
+            return Some((PathBuf::from(socket), format!("http://localhost/{}", parts[end..].join("/"))));
+        }
+    }
+    Some((PathBuf::from(path), "http://localhost/".to_string()))
+}
+
+/// send a request over the socket, giving up on connecting after `connect`; a socket
+/// that can not be connected to is an `io::Error`, like a refused TCP connection
+#[cfg(unix)]
+pub async fn execute(socket: &Path, request: reqwest::Request, connect: Option<Duration>) -> Result<reqwest::Response> {
+    let connecting = tokio::net::UnixStream::connect(socket);
+    let stream = match connect {
+        Some(limit) => tokio::time::timeout(limit, connecting).await
+            .unwrap_or_else(|_| Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out"))),
+        None => connecting.await,
+    };
+    let stream = stream.map_err(|e| std::io::Error::new(e.kind(), format!("Could not connect to {}: {}", socket.display(), e)))?;
+    let (mut sender, connection) = hyper::client::conn::handshake(stream).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::debug!("the connection to the socket failed: {}", e);
+        }
+    });
+
+    let url = request.url();
+    let target = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let mut builder = hyper::Request::builder().method(request.method().clone()).uri(target).header("host", "localhost");
+    for (name, value) in request.headers() {
+        builder = builder.header(name, value);
+    }
+    let body = request.body().and_then(reqwest::Body::as_bytes).map(<[u8]>::to_vec).unwrap_or_default();
+    let response = sender.send_request(builder.body(hyper::Body::from(body))?).await?;
+    Ok(reqwest::Response::from(response.map(reqwest::Body::from)))
+}
+
+#[cfg(not(unix))]
+pub async fn execute(_socket: &Path, _request: reqwest::Request, _connect: Option<Duration>) -> Result<reqwest::Response> {
+    Err("Unix sockets are not supported on this platform.".into())
+}
@@ -0,0 +1,89 @@
+//! Incremental UTF-8 decoding of streamed bytes.
+
+/// decodes a byte stream chunk by chunk, carrying a multi-byte character that
+/// is split across chunks over to the next one instead of replacing its halves
+#[derive(Debug, Default)]
+pub struct Utf8Decoder {
+    /// the start of a character whose remaining bytes have not arrived yet
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    pub fn new() -> Self {
+        Utf8Decoder::default()
+    }
+
+    /// decode the next chunk; invalid sequences become U+FFFD
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        let mut input = std::mem::take(&mut self.pending);
+        input.extend_from_slice(bytes);
+        let mut out = String::with_capacity(input.len());
+        let mut rest = input.as_slice();
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    out.push_str(valid);
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    // the prefix was just validated
+                    out.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                    match e.error_len() {
+                        Some(len) => {
+                            out.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[len..];
+                        }
+                        None => {
+                            // an incomplete character at the end, keep it for the next chunk
+                            self.pending = after.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// flush at the end of the stream; a dangling partial character becomes U+FFFD
+    pub fn finish(&mut self) -> String {
+        if std::mem::take(&mut self.pending).is_empty() {
+            String::new()
+        } else {
+            char::REPLACEMENT_CHARACTER.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carries_a_split_character_to_the_next_chunk() {
+        let bytes = "aé€😀".as_bytes();
+        // every way of cutting the text in two
+        for cut in 0..=bytes.len() {
+            let mut decoder = Utf8Decoder::new();
+            let text = decoder.decode(&bytes[..cut]) + &decoder.decode(&bytes[cut..]) + &decoder.finish();
+            assert_eq!(text, "aé€😀", "cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn decodes_one_byte_at_a_time() {
+        let mut decoder = Utf8Decoder::new();
+        let text: String = "日本語".as_bytes().iter().map(|b| decoder.decode(&[*b])).collect();
+        assert_eq!(text, "日本語");
+    }
+
+    #[test]
+    fn replaces_invalid_and_dangling_bytes() {
+        let mut decoder = Utf8Decoder::new();
+        assert_eq!(decoder.decode(b"a\xffb"), "a\u{fffd}b");
+        assert_eq!(decoder.decode(b"c\xe2\x82"), "c");
+        assert_eq!(decoder.finish(), "\u{fffd}");
+        assert_eq!(decoder.finish(), "");
+    }
+}
@@ -0,0 +1,192 @@
+//! Wrap streamed prose at a width for `--wrap`, leaving code blocks and tables as they
+//! are.
+//!
+//! Words are passed on as soon as the whitespace after them arrives, so wrapped answers
+//! still appear progressively. The text may already be rendered: ANSI escapes take no
+//! room, and fences are recognised through them.
+
+use crate::blocks;
+
+#[derive(Debug, Default)]
+pub struct Wrapper {
+    width: usize,
+    /// the columns taken on the line being printed
+    column: usize,
+    /// the word waiting for the whitespace after it
+    word: String,
+    /// the whitespace since the last word, dropped if the next word goes on a new line
+    spaces: String,
+    /// no word of the current line has been seen yet
+    line_start: bool,
+    /// the indent of lines wrapped from the current one, under the text of a list item
+    hang: usize,
+    /// the current line is printed as it is: code, a fence, or a table row
+    verbatim: bool,
+    /// the fence that opened the current code block, if inside one
+    fence: Option<String>,
+}
+
+impl Wrapper {
+    pub fn new(width: usize) -> Self {
+        Wrapper { width, line_start: true, ..Wrapper::default() }
+    }
+
+    /// add streamed text, returning what can be printed of it
+    pub fn push(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        for c in text.chars() {
+            match c {
+                '\n' => {
+                    self.place_word(&mut out);
+                    if self.verbatim {
+                        out.push_str(&self.spaces);
+                    }
+                    out.push('\n');
+                    self.spaces.clear();
+                    self.column = 0;
+                    self.hang = 0;
+                    self.line_start = true;
+                    self.verbatim = false;
+                }
+                _ if self.verbatim => out.push(c),
+                ' ' | '\t' => {
+                    self.place_word(&mut out);
+                    if self.verbatim {
+                        out.push(c);
+                    } else {
+                        self.spaces.push(c);
+                    }
+                }
+                _ => self.word.push(c),
+            }
+        }
+        out
+    }
+
+    /// whatever is left once the stream has ended
+    pub fn finish(&mut self) -> String {
+        let mut out = String::new();
+        self.place_word(&mut out);
+        out
+    }
+
+    /// print the waiting word, on a new line if it would not fit on this one
+    fn place_word(&mut self, out: &mut String) {
+        if self.word.is_empty() {
+            return;
+        }
+        let word = std::mem::take(&mut self.word);
+        let width = width(&word);
+        let gap = width_of_spaces(&self.spaces);
+        if self.line_start {
+            self.line_start = false;
+            let plain = strip_escapes(&word);
+            if let Some(fence) = &self.fence {
+                if blocks::closes(&plain, fence) {
+                    self.fence = None;
+                }
+                self.verbatim = true;
+            } else if let Some((fence, _)) = blocks::fence(&plain) {
+                self.fence = Some(fence);
+                self.verbatim = true;
+            } else if plain.starts_with('|') {
+                self.verbatim = true;
+            }
+            self.hang = gap + if list_marker(&plain) { width + 1 } else { 0 };
+        } else if !self.verbatim && self.column + gap + width > self.width && self.column > self.hang {
+            out.push('\n');
+            out.push_str(&" ".repeat(self.hang));
+            self.column = self.hang;
+            self.spaces.clear();
+        }
+        self.column += width_of_spaces(&self.spaces) + width;
+        out.push_str(&std::mem::take(&mut self.spaces));
+        out.push_str(&word);
+    }
+}
+
+/// whether a word starts a list item, as `-`, `•` or `1.` do
+fn list_marker(word: &str) -> bool {
+    match word {
+        "-" | "*" | "+" | "•" => true,
+        _ => word.len() > 1 && word[..word.len() - 1].chars().all(|c| c.is_ascii_digit()) && (word.ends_with('.') || word.ends_with(')')),
+    }
+}
+
+/// the columns whitespace takes, with tabs as four
+fn width_of_spaces(spaces: &str) -> usize {
+    spaces.chars().map(|c| if c == '\t' { 4 } else { 1 }).sum()
+}
+
+/// the columns text takes, leaving out ANSI escapes
+pub fn width(text: &str) -> usize {
+    strip_escapes(text).chars().count()
+}
+
+/// text without its ANSI escapes
+fn strip_escapes(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // an escape ends at its first letter, as in \x1b[1;31m
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// the width of the terminal stdout goes to, if it goes to one
+pub fn terminal_width() -> Option<usize> {
+    terminal_size::terminal_size().map(|(terminal_size::Width(width), _)| width as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `text` wrapped at `width`, streamed in pieces of `size` characters
+    fn wrap(text: &str, width: usize, size: usize) -> String {
+        let mut wrapper = Wrapper::new(width);
+        let chars: Vec<char> = text.chars().collect();
+        let mut out: String = chars.chunks(size).map(|piece| wrapper.push(&piece.iter().collect::<String>())).collect();
+        out.push_str(&wrapper.finish());
+        out
+    }
+
+    #[test]
+    fn wraps_prose_however_it_is_streamed() {
+        let text = "The quick brown fox jumps over the lazy dog.\n";
+        for size in [1, 4, text.len()] {
+            assert_eq!(wrap(text, 16, size), "The quick brown\nfox jumps over\nthe lazy dog.\n", "pieces of {}", size);
+        }
+    }
+
+    #[test]
+    fn hangs_list_items_under_their_text() {
+        assert_eq!(wrap("- one two three four\n  12. five six seven\n", 12, 3), "- one two\n  three four\n  12. five\n      six\n      seven\n");
+    }
+
+    #[test]
+    fn leaves_code_and_tables_as_they_are() {
+        let text = "```rust\nlet long_name = another_long_name + yet_another;\n```\n| a long cell | another long cell |\n";
+        assert_eq!(wrap(text, 10, 5), text);
+    }
+
+    #[test]
+    fn keeps_a_word_longer_than_the_line_whole() {
+        assert_eq!(wrap("a supercalifragilistic word", 8, 1), "a\nsupercalifragilistic\nword");
+    }
+
+    #[test]
+    fn gives_escapes_no_width() {
+        assert_eq!(width("\x1b[1;31mred\x1b[0m"), 3);
+        assert_eq!(wrap("\x1b[1mbold\x1b[0m text here", 9, 2), "\x1b[1mbold\x1b[0m text\nhere");
+    }
+}